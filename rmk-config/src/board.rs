@@ -28,6 +28,8 @@ impl BoardConfig {
     }
     /// Get the number of encoders for each board
     ///
+    /// An encoder with an alternate action for fast spinning takes two entries in the encoder map.
+    ///
     /// - If the board is the unibody board, the returned vector has only one element.
     /// - If the board is the split board, the number of elements is the number of peripherals + 1 (central),
     ///   where the first element is the number of encoders on the central.
@@ -44,7 +46,9 @@ impl BoardConfig {
                         .unwrap_or_default()
                         .encoder
                         .unwrap_or(Vec::new())
-                        .len(),
+                        .iter()
+                        .map(|e| e.num_slots())
+                        .sum(),
                 );
 
                 // Peripheral's encoders
@@ -55,18 +59,82 @@ impl BoardConfig {
                             .unwrap_or_default()
                             .encoder
                             .unwrap_or(Vec::new())
-                            .len(),
+                            .iter()
+                            .map(|e| e.num_slots())
+                            .sum(),
                     );
                 }
             }
             BoardConfig::UniBody(uni_body_config) => {
-                num_encoder.push(uni_body_config.input_device.encoder.clone().unwrap_or(Vec::new()).len());
+                num_encoder.push(
+                    uni_body_config
+                        .input_device
+                        .encoder
+                        .clone()
+                        .unwrap_or(Vec::new())
+                        .iter()
+                        .map(|e| e.num_slots())
+                        .sum(),
+                );
             }
         };
         num_encoder
     }
 }
 
+impl MatrixConfig {
+    /// Check that every side of the shift register matrix is either shift registers or pins
+    pub fn check_shift_register(&self) -> Result<(), String> {
+        if self.shift_register_out.is_none() && self.shift_register_in.is_none() {
+            return Err(
+                "`shift_register_out` or `shift_register_in` is required for shift register matrix".to_string(),
+            );
+        }
+        let (input_pins, output_pins) = if self.row2col {
+            (&self.col_pins, &self.row_pins)
+        } else {
+            (&self.row_pins, &self.col_pins)
+        };
+        if self.shift_register_out.is_none() && output_pins.is_none() {
+            return Err("Output pins are required for shift register matrix without `shift_register_out`".to_string());
+        }
+        if self.shift_register_in.is_none() && input_pins.is_none() {
+            return Err("Input pins are required for shift register matrix without `shift_register_in`".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check that the charlieplex matrix has enough pins
+    pub fn check_charlieplex(&self) -> Result<(), String> {
+        match &self.charlieplex_pins {
+            Some(pins) if pins.len() >= 2 => Ok(()),
+            Some(_) => Err("At least 2 `charlieplex_pins` are required for charlieplex matrix".to_string()),
+            None => Err("`charlieplex_pins` is required for charlieplex matrix".to_string()),
+        }
+    }
+
+    /// Check the I/O expander matrix, expander pins are named as `EXP0`..`EXP15`
+    pub fn check_io_expander(&self) -> Result<(), String> {
+        if self.io_expander.is_none() {
+            return Err("`io_expander` is required for I/O expander matrix".to_string());
+        }
+        let (Some(row_pins), Some(col_pins)) = (&self.row_pins, &self.col_pins) else {
+            return Err("`row_pins` and `col_pins` is required for I/O expander matrix".to_string());
+        };
+        let mut used = 0u16;
+        for pin in row_pins.iter().chain(col_pins.iter()) {
+            if let Some(num) = pin.strip_prefix("EXP") {
+                match num.parse::<u8>() {
+                    Ok(num) if num < 16 && used & (1 << num) == 0 => used |= 1 << num,
+                    Ok(num) if num < 16 => return Err(format!("I/O expander pin {} is used more than once", pin)),
+                    _ => return Err(format!("Invalid I/O expander pin: {}, it should be EXP0..EXP15", pin)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl KeyboardTomlConfig {
     pub fn get_board_config(&self) -> Result<BoardConfig, String> {
         let matrix = self.matrix.clone();
@@ -77,6 +145,12 @@ impl KeyboardTomlConfig {
                 Ok(BoardConfig::Split(s))
             },
             (Some(m), None) => {
+                if m.ghost_filter && !matches!(m.matrix_type, MatrixType::normal) {
+                    return Err("`ghost_filter` is only supported by normal matrix".to_string());
+                }
+                if m.scan_throttle.is_some() && !matches!(m.matrix_type, MatrixType::normal) {
+                    return Err("`scan_throttle` is only supported by normal matrix".to_string());
+                }
                 match m.matrix_type {
                     MatrixType::normal => {
                         if m.row_pins.is_none() || m.col_pins.is_none() {
@@ -88,6 +162,9 @@ impl KeyboardTomlConfig {
                             return Err("`direct_pins` is required for direct pin matrix".to_string());
                         }
                     },
+                    MatrixType::shift_register => m.check_shift_register()?,
+                    MatrixType::io_expander => m.check_io_expander()?,
+                    MatrixType::charlieplex => m.check_charlieplex()?,
                 }
                 // FIXME: input device for split keyboard is not supported yet
                 Ok(BoardConfig::UniBody(UniBodyConfig{matrix: m, input_device: input_device.unwrap_or_default()}))
@@ -244,6 +244,9 @@ pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
         "mouse_acceleration_2",
         "ms_acl2"
     );
+    add_alias!("CpiUp" = "cpi_up", "dpi_up");
+    add_alias!("CpiDown" = "cpi_down", "dpi_down");
+    add_alias!("CpiCycle" = "cpi_cycle", "dpi_cycle");
     add_alias!("LCtrl" = "l_ctrl", "leftctrl", "left_ctrl", "lctl");
     add_alias!("LShift" = "l_shift", "leftshift", "left_shift", "lsft");
     add_alias!("LAlt" = "l_alt", "leftalt", "left_alt", "lopt");
@@ -450,6 +450,12 @@ impl KeyboardTomlConfig {
                                     key_action_sequence.push(action);
                                 }
 
+                                // gamepad actions:
+                                Rule::gamepad_button_action | Rule::hat_action | Rule::keymap_profile_action => {
+                                    let action = inner_pair.as_str().to_string();
+                                    key_action_sequence.push(action);
+                                }
+
                                 Rule::EOI | Rule::WHITESPACE => {
                                     // Ignore End of input marker
                                 }
@@ -682,4 +688,49 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_gamepad_grammar() {
+        let test_cases = vec![
+            ("GP(0)", Rule::gamepad_button_action),
+            ("gp(31)", Rule::gamepad_button_action), // Case insensitive
+            ("HAT(Up)", Rule::hat_action),
+            ("hat(left)", Rule::hat_action), // Case insensitive
+        ];
+
+        for (input, expected_rule) in test_cases {
+            let result = ConfigParser::parse(Rule::key_map, input);
+            assert!(result.is_ok(), "Failed to parse: {}", input);
+
+            let found_rule = result
+                .unwrap()
+                .flat_map(|pair| pair.into_inner())
+                .map(|pair| pair.as_rule())
+                .find(|rule| *rule == Rule::gamepad_button_action || *rule == Rule::hat_action);
+            assert_eq!(
+                found_rule,
+                Some(expected_rule),
+                "Input: {} should be parsed as {:?}",
+                input,
+                expected_rule
+            );
+        }
+
+        // Invalid hat direction
+        assert!(ConfigParser::parse(Rule::key_map, "HAT(Forward)").is_err());
+    }
+
+    #[test]
+    fn test_keymap_profile_grammar() {
+        for input in ["KMP(0)", "kmp(7)"] {
+            let result = ConfigParser::parse(Rule::key_map, input);
+            assert!(result.is_ok(), "Failed to parse: {}", input);
+
+            let found = result
+                .unwrap()
+                .flat_map(|pair| pair.into_inner())
+                .any(|pair| pair.as_rule() == Rule::keymap_profile_action);
+            assert!(found, "Input: {} should be parsed as keymap_profile_action", input);
+        }
+    }
 }
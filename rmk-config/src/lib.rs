@@ -172,6 +172,10 @@ pub struct RmkConstantsConfig {
     /// Macro space size in bytes for storing sequences
     #[serde_inline_default(256)]
     pub macro_space_size: usize,
+    /// Number of keymap profiles stored in the storage
+    #[serde_inline_default(1)]
+    #[serde(deserialize_with = "check_keymap_profiles_num")]
+    pub keymap_profiles_num: usize,
     /// Default debounce time in ms
     #[serde_inline_default(20)]
     pub debounce_time: u16,
@@ -240,6 +244,17 @@ where
     Ok(value)
 }
 
+fn check_keymap_profiles_num<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let value = SerdeDeserialize::deserialize(deserializer)?;
+    if !(1..=8).contains(&value) {
+        panic!("❌ Parse `keyboard.toml` error: keymap_profiles_num must be between 1 and 8, got {value}");
+    }
+    Ok(value)
+}
+
 fn check_fork_max_num<'de, D>(deserializer: D) -> Result<usize, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -263,6 +278,7 @@ impl Default for RmkConstantsConfig {
             morse_max_num: 8,
             max_patterns_per_key: 8,
             macro_space_size: 256,
+            keymap_profiles_num: 1,
             debounce_time: 20,
             event_channel_size: 16,
             controller_channel_size: 16,
@@ -327,6 +343,9 @@ pub enum MatrixType {
     #[default]
     normal,
     direct_pin,
+    shift_register,
+    io_expander,
+    charlieplex,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -336,15 +355,98 @@ pub struct MatrixConfig {
     pub row_pins: Option<Vec<String>>,
     pub col_pins: Option<Vec<String>>,
     pub direct_pins: Option<Vec<Vec<String>>>,
+    /// Pins of `charlieplex` matrix, any pin can drive or sense
+    pub charlieplex_pins: Option<Vec<String>>,
     #[serde(default = "default_true")]
     pub direct_pin_low_active: bool,
     #[serde(default = "default_false")]
     pub row2col: bool,
+    /// Block the ambiguous presses of a normal matrix without diodes
+    #[serde(default = "default_false")]
+    pub ghost_filter: bool,
+    /// Reduce the scan rate of a normal matrix when idle
+    pub scan_throttle: Option<ScanThrottleConfig>,
     pub debouncer: Option<String>,
+    /// Debounce time of pressing a key in ms, defaults to `rmk.debounce_time`
+    pub debounce_press_time: Option<u16>,
+    /// Debounce time of releasing a key in ms, defaults to `rmk.debounce_time`
+    pub debounce_release_time: Option<u16>,
+    /// Debounce time of individual keys
+    pub debounce_overrides: Option<Vec<DebounceOverrideConfig>>,
+    /// Enable chatter detection, a press within this time in ms after the last release of the same key is counted as chatter
+    pub chatter_window: Option<u16>,
+    /// Ignore the detected chatter
+    #[serde(default = "default_false")]
+    pub chatter_suppress: bool,
+    /// 74HC595 shift registers driving the output lines of `shift_register` matrix, replacing the output pins
+    pub shift_register_out: Option<ShiftRegisterConfig>,
+    /// 74HC165 shift registers reading the input lines of `shift_register` matrix, replacing the input pins
+    pub shift_register_in: Option<ShiftRegisterConfig>,
+    /// I2C GPIO expander of `io_expander` matrix, whose pins are referred as `EXP0`..`EXP15` in the row/col pins
+    pub io_expander: Option<IoExpanderConfig>,
+    /// Keys held at boot to jump to the bootloader, as [row, col] in the keymap
+    pub bootloader_keys: Option<Vec<[u8; 2]>>,
+    /// Keys held at boot to erase the storage and restore the default keymap, as [row, col] in the keymap
+    pub clear_storage_keys: Option<Vec<[u8; 2]>>,
+}
+
+/// Pins of chained shift registers
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShiftRegisterConfig {
+    /// Serial data pin, SER of 74HC595 or QH of 74HC165
+    pub data: String,
+    /// Shift clock pin, SRCLK of 74HC595 or CLK of 74HC165
+    pub clock: String,
+    /// Latch pin, RCLK of 74HC595 or SH/LD of 74HC165
+    pub latch: String,
+}
+
+/// I2C GPIO expander used by the matrix
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoExpanderConfig {
+    pub chip: IoExpanderChip,
+    pub i2c: I2cConfig,
+    /// Pin connected to the interrupt output of the expander, used to wake up the matrix
+    pub interrupt: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum IoExpanderChip {
+    mcp23017,
+    pca9555,
+}
+
+/// Adaptive scan rate of the matrix, the omitted durations use RMK's defaults
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanThrottleConfig {
+    /// After no key changes for this time, the matrix is scanned every `idle_interval`
+    pub idle_timeout: Option<DurationMillis>,
+    /// Interval between two scans when idle
+    pub idle_interval: Option<DurationMillis>,
+    /// After no key changes for this time, the matrix sleeps
+    pub sleep_timeout: Option<DurationMillis>,
+    /// Interval between two scans when sleeping, used without `async_matrix`
+    pub sleep_interval: Option<DurationMillis>,
+}
+
+/// Debounce time of a single key, e.g. for a worn switch
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DebounceOverrideConfig {
+    pub row: u8,
+    pub col: u8,
+    /// Debounce time of pressing the key in ms, defaults to the matrix's press debounce time
+    pub press_time: Option<u16>,
+    /// Debounce time of releasing the key in ms, defaults to the matrix's release debounce time
+    pub release_time: Option<u16>,
 }
 
 /// Config for storage
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct StorageConfig {
     /// Start address of local storage, MUST BE start of a sector.
@@ -358,6 +460,20 @@ pub struct StorageConfig {
     pub clear_storage: Option<bool>,
     // Clear on the layout at reboot, set this to true if you want to reset the layout
     pub clear_layout: Option<bool>,
+    /// Runtime states which are saved and restored after reboot
+    pub persisted_state: Option<Vec<PersistedState>>,
+}
+
+/// Runtime state which can be saved across reboots
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum PersistedState {
+    /// The layers toggled by `TG`, the default layer is always saved
+    layers,
+    /// Combo on/off state
+    combo,
+    /// Mouse key speed preset selected by `MouseAccel0`-`MouseAccel2`
+    mouse_speed,
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
@@ -707,6 +823,9 @@ pub struct InputDeviceConfig {
     pub pointing: Option<Vec<PointingDeviceConfig>>,
     pub joystick: Option<Vec<JoystickConfig>>,
     pub pmw3610: Option<Vec<Pmw3610Config>>,
+    pub pmw3360: Option<Vec<OpticalSensorConfig>>,
+    pub pmw3389: Option<Vec<OpticalSensorConfig>>,
+    pub paw3395: Option<Vec<OpticalSensorConfig>>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -724,6 +843,65 @@ pub struct JoystickConfig {
     pub transform: Vec<Vec<i16>>,
     pub bias: Vec<i16>,
     pub resolution: u16,
+    /// Calibrate the center at boot and save it to the storage
+    #[serde(default)]
+    pub calibrate: bool,
+    /// Number of readings used by the center calibration
+    pub calibration_samples: Option<u16>,
+    /// Maximum spread of the readings during the calibration, the calibration is skipped if the stick is moved
+    pub calibration_tolerance: Option<u16>,
+    /// Deadzone applied to the length of the stick vector
+    #[serde(default)]
+    pub radial_deadzone: u16,
+    /// Deadzone applied to each axis
+    #[serde(default)]
+    pub axial_deadzone: u16,
+    /// Response curve, defaults to linear
+    #[serde(default)]
+    pub curve: JoystickCurve,
+    /// Maximum deflection of the centered axis value, used by deadzones and curves
+    pub range: Option<u16>,
+    /// Output mode, defaults to mouse. Gamepad modes require the `gamepad` feature of RMK
+    #[serde(default)]
+    pub mode: JoystickMode,
+    /// Keymap positions triggered by stick directions in digital mode, in `[row, col]`
+    pub keys: Option<JoystickKeysConfig>,
+    /// Threshold of pressing a key in digital mode
+    pub threshold: Option<u16>,
+    /// Threshold of releasing a key in digital mode
+    pub release_threshold: Option<u16>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum JoystickCurve {
+    #[default]
+    linear,
+    quadratic,
+    cubic,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum JoystickMode {
+    #[default]
+    mouse,
+    digital,
+    /// X/Y/Z axes of the gamepad
+    gamepad,
+    /// Rx/Ry/Rz axes of the gamepad
+    gamepad_right,
+}
+
+/// Keymap positions of the joystick directions in digital mode
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(unused)]
+#[serde(deny_unknown_fields)]
+pub struct JoystickKeysConfig {
+    pub up: Option<[u8; 2]>,
+    pub down: Option<[u8; 2]>,
+    pub left: Option<[u8; 2]>,
+    pub right: Option<[u8; 2]>,
 }
 
 /// PMW3610 optical mouse sensor configuration
@@ -754,6 +932,44 @@ pub struct Pmw3610Config {
     /// Enable smart mode for better tracking on shiny surfaces
     #[serde(default)]
     pub smart_mode: bool,
+    /// CPI steps used by CPI cycling keycodes
+    pub cpi_steps: Option<Vec<u16>>,
+}
+
+/// PMW3360/PMW3389/PAW3395 optical mouse sensor configuration
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(unused)]
+#[serde(deny_unknown_fields)]
+pub struct OpticalSensorConfig {
+    /// Name of the sensor (used for variable naming)
+    pub name: String,
+    /// SPI pins
+    pub spi: SpiConfig,
+    /// Optional motion interrupt pin
+    pub motion: Option<String>,
+    /// CPI resolution. Optional, uses sensor default if not set.
+    pub cpi: Option<u16>,
+    /// CPI steps used by CPI cycling keycodes
+    pub cpi_steps: Option<Vec<u16>>,
+    /// Invert X axis
+    #[serde(default)]
+    pub invert_x: bool,
+    /// Invert Y axis
+    #[serde(default)]
+    pub invert_y: bool,
+    /// Swap X and Y axes
+    #[serde(default)]
+    pub swap_xy: bool,
+    /// Force awake mode (disable power saving), PMW3360/PMW3389 only
+    #[serde(default)]
+    pub force_awake: bool,
+    /// Use 3mm lift-off distance instead of 2mm, PMW3360/PMW3389 only
+    #[serde(default)]
+    pub lift_off_3mm: bool,
+    /// Path of the SROM firmware file, relative to the crate root. PMW3360/PMW3389 only
+    pub srom: Option<String>,
+    /// Power-up register settings as (register, value) pairs, PAW3395 only
+    pub init_sequence: Option<Vec<(u8, u8)>>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -782,6 +998,33 @@ pub struct EncoderConfig {
     // Use MCU's internal pull-up resistor or not, defaults to false, the external pull-up resistor is needed
     #[serde(default = "default_false")]
     pub internal_pullup: bool,
+    // Velocity-sensitive output
+    pub acceleration: Option<EncoderAccelerationConfig>,
+    // Ignore a single reverse step within this time(ms) after the previous step
+    pub reverse_debounce: Option<u16>,
+}
+
+impl EncoderConfig {
+    /// Number of entries used by this encoder in the encoder map
+    pub fn num_slots(&self) -> usize {
+        match &self.acceleration {
+            Some(acceleration) if acceleration.alternate => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(unused)]
+#[serde(deny_unknown_fields)]
+pub struct EncoderAccelerationConfig {
+    // A step within this time(ms) after the previous step is a fast step
+    pub interval: u16,
+    // Number of actions triggered by a fast step, defaults to 1
+    pub multiplier: Option<u8>,
+    // Use the next entry in the encoder map for fast steps
+    #[serde(default = "default_false")]
+    pub alternate: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -803,7 +1046,53 @@ impl Default for EncoderResolution {
 #[allow(unused)]
 #[serde(deny_unknown_fields)]
 pub struct PointingDeviceConfig {
+    /// Name of the pointing device (used for variable naming)
+    #[serde(default)]
+    pub name: String,
     pub interface: Option<CommunicationProtocol>,
+    /// Optional data ready pin. If omitted, the device is polled.
+    pub data_ready: Option<String>,
+    /// Cirque Pinnacle trackpad config
+    pub pinnacle: Option<PinnacleConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum PinnacleMode {
+    #[default]
+    absolute,
+    relative,
+}
+
+/// Cirque Pinnacle trackpad configuration
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(unused)]
+#[serde(deny_unknown_fields)]
+pub struct PinnacleConfig {
+    /// Report absolute positions or relative motion, defaults to absolute
+    #[serde(default)]
+    pub mode: PinnacleMode,
+    /// ADC attenuation(1, 2, 3 or 4), defaults to 4. Thicker overlays need less attenuation.
+    pub attenuation: Option<u8>,
+    /// Tune edge sensitivity for curved overlays
+    #[serde(default)]
+    pub curved_overlay: bool,
+    /// Diameter of the trackpad in millimeters, defaults to 35
+    pub diameter_mm: Option<u8>,
+    /// Resolution of absolute mode output, defaults to 800
+    pub cpi: Option<u16>,
+    /// Enable the trackpad's compensations during calibration, defaults to true
+    #[serde(default = "default_true")]
+    pub compensation: bool,
+    /// Invert X axis
+    #[serde(default)]
+    pub invert_x: bool,
+    /// Invert Y axis
+    #[serde(default)]
+    pub invert_y: bool,
+    /// Swap X and Y axes
+    #[serde(default)]
+    pub swap_xy: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -531,6 +531,7 @@ pub(crate) fn expand_behavior_config(keyboard_config: &KeyboardTomlConfig) -> pr
             morse: #morse,
             keyboard_macros: #macros,
             mouse_key: ::rmk::config::MouseKeyConfig::default(),
+            caps_word: ::rmk::config::CapsWordConfig::default(),
             tap: ::rmk::config::TapConfig::default(),
         };
     }
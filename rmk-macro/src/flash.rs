@@ -3,7 +3,7 @@
 
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use rmk_config::{ChipSeries, KeyboardTomlConfig, StorageConfig};
+use rmk_config::{ChipSeries, KeyboardTomlConfig, PersistedState, StorageConfig};
 
 pub(crate) fn expand_flash_init(keyboard_config: &KeyboardTomlConfig) -> TokenStream2 {
     if !keyboard_config.get_storage_config().enabled {
@@ -45,12 +45,21 @@ fn expand_storage_config(storage_config: &StorageConfig) -> TokenStream2 {
     let start_addr = storage_config.start_addr.unwrap_or(0);
     let clear_storage = storage_config.clear_storage.unwrap_or(false);
     let clear_layout = storage_config.clear_layout.unwrap_or(false);
+    let persisted_state = storage_config.persisted_state.clone().unwrap_or_default();
+    let layers = persisted_state.contains(&PersistedState::layers);
+    let combo = persisted_state.contains(&PersistedState::combo);
+    let mouse_speed = persisted_state.contains(&PersistedState::mouse_speed);
     quote! {
         let storage_config = ::rmk::config::StorageConfig {
             num_sectors: #num_sectors,
             start_addr: #start_addr,
             clear_storage: #clear_storage,
-            clear_layout: #clear_layout
+            clear_layout: #clear_layout,
+            persisted_state: ::rmk::config::PersistedStateConfig {
+                layers: #layers,
+                combo: #combo,
+                mouse_speed: #mouse_speed,
+            },
         };
     }
 }
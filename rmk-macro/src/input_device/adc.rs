@@ -1,5 +1,5 @@
 use quote::{format_ident, quote};
-use rmk_config::{BleConfig, ChipSeries, JoystickConfig};
+use rmk_config::{BleConfig, ChipSeries, JoystickConfig, JoystickCurve, JoystickMode};
 
 use crate::input_device::Initializer;
 
@@ -64,7 +64,9 @@ pub(crate) fn expand_adc_device(
                 light_sleep = Some(350);
             }
 
-            for joystick in joystick_config {
+            for (id, joystick) in joystick_config.into_iter().enumerate() {
+                let options = expand_joystick_options(&joystick);
+                let id = id as u8;
                 let mut cnt = 0u8;
                 for pin in [joystick.pin_x, joystick.pin_y, joystick.pin_z].iter() {
                     if pin == "_" {
@@ -89,7 +91,9 @@ pub(crate) fn expand_adc_device(
                 } = joystick;
                 let joystick_processor = Initializer {
                     initializer: quote! {
-                        let mut #joy_ident = rmk::input_device::joystick::JoystickProcessor::new([#([#(#transform),*]),*], [#(#bias),*], #resolution, &keymap);
+                        let mut #joy_ident = rmk::input_device::joystick::JoystickProcessor::new([#([#(#transform),*]),*], [#(#bias),*], #resolution, &keymap)
+                            .with_id(#id)
+                            #options;
                     },
                     var_name: joy_ident,
                 };
@@ -135,3 +139,67 @@ pub(crate) fn expand_adc_device(
         _ => (Vec::new(), Vec::new()),
     }
 }
+
+/// Expand the builder calls of the calibration, deadzone, curve and mode of the joystick processor
+fn expand_joystick_options(joystick: &JoystickConfig) -> proc_macro2::TokenStream {
+    let mut options = quote! {};
+
+    if joystick.calibrate {
+        let samples = joystick.calibration_samples.unwrap_or(32);
+        let tolerance = joystick.calibration_tolerance.unwrap_or(500);
+        options.extend(quote! { .with_calibration(#samples, #tolerance) });
+    }
+
+    let radial = joystick.radial_deadzone;
+    let axial = joystick.axial_deadzone;
+    if radial > 0 || axial > 0 {
+        options.extend(quote! { .with_deadzone(#radial, #axial) });
+    }
+
+    let range = joystick.range.unwrap_or(i16::MAX as u16);
+    if joystick.curve != JoystickCurve::linear || joystick.range.is_some() {
+        let curve = match joystick.curve {
+            JoystickCurve::linear => quote! { Linear },
+            JoystickCurve::quadratic => quote! { Quadratic },
+            JoystickCurve::cubic => quote! { Cubic },
+        };
+        options.extend(quote! { .with_curve(::rmk::input_device::joystick::ResponseCurve::#curve, #range) });
+    }
+
+    if joystick.mode == JoystickMode::gamepad || joystick.mode == JoystickMode::gamepad_right {
+        let stick = if joystick.mode == JoystickMode::gamepad {
+            quote! { Left }
+        } else {
+            quote! { Right }
+        };
+        options.extend(quote! {
+            .with_mode(::rmk::input_device::joystick::JoystickMode::Gamepad(::rmk::input_device::joystick::GamepadStick::#stick))
+        });
+    }
+
+    if joystick.mode == JoystickMode::digital {
+        let keys = joystick
+            .keys
+            .clone()
+            .unwrap_or_else(|| panic!("`keys` is required for joystick {} in digital mode", joystick.name));
+        let pos = |key: Option<[u8; 2]>| match key {
+            Some([row, col]) => quote! { Some(::rmk::event::KeyPos { row: #row, col: #col }) },
+            None => quote! { None },
+        };
+        let (up, down, left, right) = (pos(keys.up), pos(keys.down), pos(keys.left), pos(keys.right));
+        let threshold = joystick.threshold.unwrap_or(range / 2);
+        let release_threshold = joystick.release_threshold.unwrap_or(threshold / 4 * 3);
+        options.extend(quote! {
+            .with_mode(::rmk::input_device::joystick::JoystickMode::Digital(::rmk::input_device::joystick::DigitalKeys {
+                up: #up,
+                down: #down,
+                left: #left,
+                right: #right,
+                threshold: #threshold,
+                release_threshold: #release_threshold,
+            }))
+        });
+    }
+
+    options
+}
@@ -17,10 +17,12 @@ pub(crate) fn expand_encoder_device(
     }
 
     let mut device_initializer = vec![];
+    // Id of the next encoder, an encoder with an alternate action for fast spinning takes two ids
+    let mut encoder_id = id_offset as u8;
 
     // Create rotary encoders
-    for (idx, encoder) in encoder_config.iter().enumerate() {
-        let encoder_id = idx as u8 + id_offset as u8;
+    for encoder in encoder_config.iter() {
+        let options = expand_encoder_options(encoder, encoder_id);
 
         let pull = if encoder.internal_pullup { Some(true) } else { None };
 
@@ -40,7 +42,7 @@ pub(crate) fn expand_encoder_device(
                         #pin_b,
                         ::rmk::input_device::rotary_encoder::E8H7Phase,
                         #encoder_id
-                    );
+                    )#options;
                 }
             }
             Some("resolution") => {
@@ -62,7 +64,7 @@ pub(crate) fn expand_encoder_device(
                         #resolution,
                         #reverse,
                         #encoder_id
-                    );
+                    )#options;
                 }
             }
             Some("default") => {
@@ -73,7 +75,7 @@ pub(crate) fn expand_encoder_device(
                         #pin_b,
                         ::rmk::input_device::rotary_encoder::DefaultPhase,
                         #encoder_id
-                    );
+                    )#options;
                 }
             }
             _ => {
@@ -85,7 +87,40 @@ pub(crate) fn expand_encoder_device(
             initializer: encoder_device,
             var_name: encoder_name,
         });
+        encoder_id += encoder.num_slots() as u8;
     }
 
     (device_initializer, vec![])
 }
+
+/// Expand the builder calls of acceleration and reverse debouncing
+fn expand_encoder_options(encoder: &EncoderConfig, encoder_id: u8) -> proc_macro2::TokenStream {
+    let mut options = quote! {};
+    if let Some(acceleration) = &encoder.acceleration {
+        let interval = acceleration.interval as u64;
+        let multiplier = acceleration.multiplier.unwrap_or(1);
+        if multiplier == 0 {
+            panic!("`acceleration.multiplier` of the encoder must be greater than 0");
+        }
+        let alternate_id = if acceleration.alternate {
+            let id = encoder_id + 1;
+            quote! { Some(#id) }
+        } else {
+            quote! { None }
+        };
+        options.extend(quote! {
+            .with_acceleration(::rmk::input_device::rotary_encoder::EncoderAcceleration {
+                interval: ::embassy_time::Duration::from_millis(#interval),
+                multiplier: #multiplier,
+                alternate_id: #alternate_id,
+            })
+        });
+    }
+    if let Some(debounce) = encoder.reverse_debounce {
+        let debounce = debounce as u64;
+        options.extend(quote! {
+            .with_reverse_debounce(::embassy_time::Duration::from_millis(#debounce))
+        });
+    }
+    options
+}
@@ -1,13 +1,17 @@
 use adc::expand_adc_device;
 use encoder::expand_encoder_device;
+use optical_sensor::{OpticalSensorKind, expand_optical_sensor_device};
 use pmw3610::expand_pmw3610_device;
+use pointing::expand_pointing_device;
 use proc_macro2::{Ident, TokenStream};
 use quote::quote;
 use rmk_config::{BoardConfig, CommunicationConfig, InputDeviceConfig, KeyboardTomlConfig, UniBodyConfig};
 
 pub(crate) mod adc;
 pub(crate) mod encoder;
+pub(crate) mod optical_sensor;
 pub(crate) mod pmw3610;
+pub(crate) mod pointing;
 
 /// Initializer struct for input devices
 pub(crate) struct Initializer {
@@ -95,7 +99,7 @@ pub(crate) fn expand_input_device_config(
     // generate PMW3610 configuration
     let (pmw3610_device_initializers, pmw3610_processor_initializers) = match &board {
         BoardConfig::UniBody(UniBodyConfig { input_device, .. }) => {
-            expand_pmw3610_device(input_device.clone().pmw3610.unwrap_or(Vec::new()), &chip)
+            expand_pmw3610_device(input_device.clone().pmw3610.unwrap_or(Vec::new()), &chip, 0)
         }
         BoardConfig::Split(split_config) => expand_pmw3610_device(
             split_config
@@ -106,8 +110,10 @@ pub(crate) fn expand_input_device_config(
                 .pmw3610
                 .unwrap_or(Vec::new()),
             &chip,
+            0,
         ),
     };
+    let mut motion_sensor_num = pmw3610_device_initializers.len() as u8;
 
     for initializer in pmw3610_device_initializers {
         initialization.extend(initializer.initializer);
@@ -121,7 +127,59 @@ pub(crate) fn expand_input_device_config(
         processors.push(quote! { #processor_name });
     }
 
-    // For split keyboards, also generate processors for PMW3610 devices on peripherals
+    // generate PMW3360/PMW3389/PAW3395 configuration
+    let central_input_device = match &board {
+        BoardConfig::UniBody(UniBodyConfig { input_device, .. }) => input_device.clone(),
+        BoardConfig::Split(split_config) => split_config.central.input_device.clone().unwrap_or_default(),
+    };
+    for (kind, sensor_config) in optical_sensor_configs(&central_input_device) {
+        let (device_initializers, processor_initializers) =
+            expand_optical_sensor_device(kind, sensor_config, &chip, motion_sensor_num);
+        motion_sensor_num += device_initializers.len() as u8;
+
+        for initializer in device_initializers {
+            initialization.extend(initializer.initializer);
+            let device_name = initializer.var_name;
+            devices.push(quote! { #device_name });
+        }
+
+        for initializer in processor_initializers {
+            initialization.extend(initializer.initializer);
+            let processor_name = initializer.var_name;
+            processors.push(quote! { #processor_name });
+        }
+    }
+
+    // generate pointing device configuration
+    let (pointing_device_initializers, pointing_processor_initializers) = match &board {
+        BoardConfig::UniBody(UniBodyConfig { input_device, .. }) => {
+            expand_pointing_device(input_device.clone().pointing.unwrap_or(Vec::new()), &chip)
+        }
+        BoardConfig::Split(split_config) => expand_pointing_device(
+            split_config
+                .central
+                .input_device
+                .clone()
+                .unwrap_or(InputDeviceConfig::default())
+                .pointing
+                .unwrap_or(Vec::new()),
+            &chip,
+        ),
+    };
+
+    for initializer in pointing_device_initializers {
+        initialization.extend(initializer.initializer);
+        let device_name = initializer.var_name;
+        devices.push(quote! { #device_name });
+    }
+
+    for initializer in pointing_processor_initializers {
+        initialization.extend(initializer.initializer);
+        let processor_name = initializer.var_name;
+        processors.push(quote! { #processor_name });
+    }
+
+    // For split keyboards, also generate processors for PMW3610 and pointing devices on peripherals
     // The devices run on peripherals, but processors need to run on central to handle the events
     if let BoardConfig::Split(split_config) = &board {
         for peripheral in &split_config.peripheral {
@@ -133,15 +191,63 @@ pub(crate) fn expand_input_device_config(
                 .unwrap_or(Vec::new());
 
             // Only generate processors (not devices) for peripheral PMW3610
-            let (_, peripheral_pmw3610_processors) = expand_pmw3610_device(peripheral_pmw3610_config, &chip);
+            let (_, peripheral_pmw3610_processors) = expand_pmw3610_device(peripheral_pmw3610_config, &chip, 0);
 
             for initializer in peripheral_pmw3610_processors {
                 initialization.extend(initializer.initializer);
                 let processor_name = initializer.var_name;
                 processors.push(quote! { #processor_name });
             }
+
+            // Only generate processors (not devices) for peripheral PMW3360/PMW3389/PAW3395
+            let peripheral_input_device = peripheral.input_device.clone().unwrap_or_default();
+            for (kind, sensor_config) in optical_sensor_configs(&peripheral_input_device) {
+                let (_, peripheral_sensor_processors) = expand_optical_sensor_device(kind, sensor_config, &chip, 0);
+
+                for initializer in peripheral_sensor_processors {
+                    initialization.extend(initializer.initializer);
+                    let processor_name = initializer.var_name;
+                    processors.push(quote! { #processor_name });
+                }
+            }
+
+            let peripheral_pointing_config = peripheral
+                .input_device
+                .clone()
+                .unwrap_or(InputDeviceConfig::default())
+                .pointing
+                .unwrap_or(Vec::new());
+
+            // Only generate processors (not devices) for peripheral pointing devices
+            let (_, peripheral_pointing_processors) = expand_pointing_device(peripheral_pointing_config, &chip);
+
+            for initializer in peripheral_pointing_processors {
+                initialization.extend(initializer.initializer);
+                let processor_name = initializer.var_name;
+                processors.push(quote! { #processor_name });
+            }
         }
     }
 
     (initialization, devices, processors)
 }
+
+/// Get all PMW3360/PMW3389/PAW3395 configurations of the given input device config
+pub(crate) fn optical_sensor_configs(
+    input_device: &InputDeviceConfig,
+) -> Vec<(OpticalSensorKind, Vec<rmk_config::OpticalSensorConfig>)> {
+    vec![
+        (
+            OpticalSensorKind::Pmw3360,
+            input_device.pmw3360.clone().unwrap_or_default(),
+        ),
+        (
+            OpticalSensorKind::Pmw3389,
+            input_device.pmw3389.clone().unwrap_or_default(),
+        ),
+        (
+            OpticalSensorKind::Paw3395,
+            input_device.paw3395.clone().unwrap_or_default(),
+        ),
+    ]
+}
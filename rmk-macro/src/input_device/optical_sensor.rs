@@ -0,0 +1,205 @@
+use quote::{format_ident, quote};
+use rmk_config::{ChipModel, ChipSeries, OpticalSensorConfig};
+
+use super::Initializer;
+
+/// Optical sensors using 4-wire SPI
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OpticalSensorKind {
+    Pmw3360,
+    Pmw3389,
+    Paw3395,
+}
+
+impl OpticalSensorKind {
+    fn name(&self) -> &'static str {
+        match self {
+            OpticalSensorKind::Pmw3360 => "pmw3360",
+            OpticalSensorKind::Pmw3389 => "pmw3389",
+            OpticalSensorKind::Paw3395 => "paw3395",
+        }
+    }
+}
+
+/// Expand PMW3360/PMW3389/PAW3395 device configuration.
+/// `id_offset` is the id of the first sensor, which is used to save the CPI to storage.
+/// Returns (device initializers, processor initializers)
+pub(crate) fn expand_optical_sensor_device(
+    kind: OpticalSensorKind,
+    sensor_config: Vec<OpticalSensorConfig>,
+    chip: &ChipModel,
+    id_offset: u8,
+) -> (Vec<Initializer>, Vec<Initializer>) {
+    if sensor_config.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+
+    // Hardware SPI is only supported on nRF52 now
+    if chip.series != ChipSeries::Nrf52 {
+        panic!("{} is only supported on nRF52 chips", kind.name());
+    }
+
+    let mut device_initializers = vec![];
+    let mut processor_initializers = vec![];
+
+    for (idx, sensor) in sensor_config.iter().enumerate() {
+        let sensor_name = if sensor.name.is_empty() {
+            format!("{}_{}", kind.name(), idx)
+        } else {
+            sensor.name.clone()
+        };
+
+        let device_ident = format_ident!("{}_device", sensor_name);
+        let processor_ident = format_ident!("{}_processor", sensor_name);
+
+        let spi = &sensor.spi;
+        if spi.mosi.is_empty() || spi.miso.is_empty() {
+            panic!("{} requires both spi.mosi and spi.miso", kind.name());
+        }
+        let instance_ident = format_ident!("{}", spi.instance);
+        // On nRF52840, the interrupt of SPI3 is SPIM3
+        let irq_ident = if spi.instance == "SPI3" {
+            format_ident!("SPIM3")
+        } else {
+            format_ident!("{}", spi.instance)
+        };
+        let sck_ident = format_ident!("{}", spi.sck);
+        let mosi_ident = format_ident!("{}", spi.mosi);
+        let miso_ident = format_ident!("{}", spi.miso);
+        let cs_ident = format_ident!(
+            "{}",
+            spi.cs
+                .as_ref()
+                .unwrap_or_else(|| panic!("{} requires `cs` in spi config", kind.name()))
+        );
+
+        let cpi = sensor.cpi.unwrap_or(0);
+        let invert_x = sensor.invert_x;
+        let invert_y = sensor.invert_y;
+        let swap_xy = sensor.swap_xy;
+        let id = id_offset + idx as u8;
+        let cpi_steps = match &sensor.cpi_steps {
+            Some(steps) => quote! { &[#(#steps),*] },
+            None => quote! { ::rmk::input_device::motion_sensor::DEFAULT_CPI_STEPS },
+        };
+
+        let motion_pin_init = if let Some(motion_pin) = &sensor.motion {
+            let motion_ident = format_ident!("{}", motion_pin);
+            quote! {
+                Some(::embassy_nrf::gpio::Input::new(p.#motion_ident, ::embassy_nrf::gpio::Pull::Up))
+            }
+        } else {
+            quote! {
+                None::<::embassy_nrf::gpio::Input<'static>>
+            }
+        };
+
+        let sensor_init = match kind {
+            OpticalSensorKind::Pmw3360 | OpticalSensorKind::Pmw3389 => {
+                if sensor.init_sequence.is_some() {
+                    panic!("`init_sequence` is only available for paw3395");
+                }
+                let driver = if kind == OpticalSensorKind::Pmw3360 {
+                    quote! { ::rmk::input_device::pmw33xx::Pmw3360 }
+                } else {
+                    quote! { ::rmk::input_device::pmw33xx::Pmw3389 }
+                };
+                let rest_mode = if sensor.force_awake {
+                    quote! { ::rmk::input_device::motion_sensor::RestMode::ForceAwake }
+                } else {
+                    quote! { ::rmk::input_device::motion_sensor::RestMode::Normal }
+                };
+                let lift_off_3mm = sensor.lift_off_3mm;
+                let srom = match &sensor.srom {
+                    Some(path) => quote! { Some(include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", #path))) },
+                    None => quote! { None },
+                };
+                quote! {
+                    #driver::new(
+                        spi_bus,
+                        cs,
+                        motion,
+                        ::rmk::input_device::pmw33xx::Pmw33xxConfig {
+                            cpi: #cpi,
+                            invert_x: #invert_x,
+                            invert_y: #invert_y,
+                            swap_xy: #swap_xy,
+                            rest_mode: #rest_mode,
+                            lift_off_3mm: #lift_off_3mm,
+                            srom: #srom,
+                        },
+                    )
+                }
+            }
+            OpticalSensorKind::Paw3395 => {
+                if sensor.srom.is_some() || sensor.force_awake || sensor.lift_off_3mm {
+                    panic!("`srom`, `force_awake` and `lift_off_3mm` are not available for paw3395");
+                }
+                let init_sequence = sensor
+                    .init_sequence
+                    .clone()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(reg, value)| quote! { (#reg, #value) });
+                quote! {
+                    ::rmk::input_device::paw3395::Paw3395::new(
+                        spi_bus,
+                        cs,
+                        motion,
+                        ::rmk::input_device::paw3395::Paw3395Config {
+                            cpi: #cpi,
+                            invert_x: #invert_x,
+                            invert_y: #invert_y,
+                            swap_xy: #swap_xy,
+                            init_sequence: &[#(#init_sequence),*],
+                        },
+                    )
+                }
+            }
+        };
+
+        let device_init = quote! {
+            let mut #device_ident = {
+                use ::embassy_nrf::gpio::{Level, Output, OutputDrive};
+                use ::rmk::input_device::motion_sensor::MotionSensorDevice;
+                ::embassy_nrf::bind_interrupts!(struct SpimIrqs {
+                    #irq_ident => ::embassy_nrf::spim::InterruptHandler<::embassy_nrf::peripherals::#instance_ident>;
+                });
+
+                let mut spi_config = ::embassy_nrf::spim::Config::default();
+                spi_config.frequency = ::embassy_nrf::spim::Frequency::M2;
+                spi_config.mode = ::embassy_nrf::spim::MODE_3;
+                let spi_bus = ::embassy_nrf::spim::Spim::new(
+                    p.#instance_ident,
+                    SpimIrqs,
+                    p.#sck_ident,
+                    p.#miso_ident,
+                    p.#mosi_ident,
+                    spi_config,
+                );
+                let cs = Output::new(p.#cs_ident, Level::High, OutputDrive::Standard);
+                let motion = #motion_pin_init;
+
+                MotionSensorDevice::new(#sensor_init)
+                    .with_id(#id)
+                    .with_cpi_steps(#cpi_steps)
+            };
+        };
+
+        device_initializers.push(Initializer {
+            initializer: device_init,
+            var_name: device_ident,
+        });
+
+        let processor_init = quote! {
+            let mut #processor_ident = ::rmk::input_device::motion_sensor::MotionSensorProcessor::new(&keymap);
+        };
+
+        processor_initializers.push(Initializer {
+            initializer: processor_init,
+            var_name: processor_ident,
+        });
+    }
+
+    (device_initializers, processor_initializers)
+}
@@ -4,10 +4,12 @@ use rmk_config::{ChipModel, ChipSeries, Pmw3610Config};
 use super::Initializer;
 
 /// Expand PMW3610 device configuration.
+/// `id_offset` is the id of the first sensor, which is used to save the CPI to storage.
 /// Returns (device initializers, processor initializers)
 pub(crate) fn expand_pmw3610_device(
     pmw3610_config: Vec<Pmw3610Config>,
     chip: &ChipModel,
+    id_offset: u8,
 ) -> (Vec<Initializer>, Vec<Initializer>) {
     if pmw3610_config.is_empty() {
         return (Vec::new(), Vec::new());
@@ -59,6 +61,11 @@ pub(crate) fn expand_pmw3610_device(
         let swap_xy = sensor.swap_xy;
         let force_awake = sensor.force_awake;
         let smart_mode = sensor.smart_mode;
+        let id = id_offset + idx as u8;
+        let cpi_steps = match &sensor.cpi_steps {
+            Some(steps) => quote! { &[#(#steps),*] },
+            None => quote! { ::rmk::input_device::motion_sensor::DEFAULT_CPI_STEPS },
+        };
 
         // Generate motion pin initialization (optional)
         let motion_pin_init = if let Some(motion_pin) = &sensor.motion {
@@ -89,7 +96,8 @@ pub(crate) fn expand_pmw3610_device(
             ChipSeries::Nrf52 => quote! {
                 let mut #device_ident = {
                     use ::embassy_nrf::gpio::{Output, Flex, Level, OutputDrive};
-                    use ::rmk::input_device::pmw3610::{BitBangSpiBus, Pmw3610Config, Pmw3610Device};
+                    use ::rmk::input_device::motion_sensor::MotionSensorDevice;
+                    use ::rmk::input_device::pmw3610::{BitBangSpiBus, Pmw3610, Pmw3610Config};
 
                     let sck = Output::new(p.#sck_ident, Level::High, OutputDrive::Standard);
                     let sdio = Flex::new(p.#sdio_ident);
@@ -107,13 +115,16 @@ pub(crate) fn expand_pmw3610_device(
                         smart_mode: #smart_mode,
                     };
 
-                    Pmw3610Device::new(spi_bus, cs, motion, config)
+                    MotionSensorDevice::new(Pmw3610::new(spi_bus, cs, motion, config))
+                        .with_id(#id)
+                        .with_cpi_steps(#cpi_steps)
                 };
             },
             ChipSeries::Rp2040 => quote! {
                 let mut #device_ident = {
                     use ::embassy_rp::gpio::{Output, Flex, Level};
-                    use ::rmk::input_device::pmw3610::{BitBangSpiBus, Pmw3610Config, Pmw3610Device};
+                    use ::rmk::input_device::motion_sensor::MotionSensorDevice;
+                    use ::rmk::input_device::pmw3610::{BitBangSpiBus, Pmw3610, Pmw3610Config};
 
                     let sck = Output::new(p.#sck_ident, Level::High);
                     let sdio = Flex::new(p.#sdio_ident);
@@ -131,7 +142,9 @@ pub(crate) fn expand_pmw3610_device(
                         smart_mode: #smart_mode,
                     };
 
-                    Pmw3610Device::new(spi_bus, cs, motion, config)
+                    MotionSensorDevice::new(Pmw3610::new(spi_bus, cs, motion, config))
+                        .with_id(#id)
+                        .with_cpi_steps(#cpi_steps)
                 };
             },
             _ => unreachable!(),
@@ -144,7 +157,7 @@ pub(crate) fn expand_pmw3610_device(
 
         // Generate processor initialization
         let processor_init = quote! {
-            let mut #processor_ident = ::rmk::input_device::pmw3610::Pmw3610Processor::new(&keymap);
+            let mut #processor_ident = ::rmk::input_device::motion_sensor::MotionSensorProcessor::new(&keymap);
         };
 
         processor_initializers.push(Initializer {
@@ -0,0 +1,176 @@
+use quote::{format_ident, quote};
+use rmk_config::{ChipModel, ChipSeries, CommunicationProtocol, PinnacleMode, PointingDeviceConfig};
+
+use super::Initializer;
+
+/// Expand pointing device configuration.
+/// Returns (device initializers, processor initializers)
+pub(crate) fn expand_pointing_device(
+    pointing_config: Vec<PointingDeviceConfig>,
+    chip: &ChipModel,
+) -> (Vec<Initializer>, Vec<Initializer>) {
+    if pointing_config.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+
+    // I2C pointing devices are only supported on nRF52 and RP2040
+    match chip.series {
+        ChipSeries::Nrf52 | ChipSeries::Rp2040 => {}
+        _ => {
+            panic!("Pointing devices are only supported on nRF52 and RP2040 chips");
+        }
+    }
+
+    let mut device_initializers = vec![];
+    let mut processor_initializers = vec![];
+
+    for (idx, pointing) in pointing_config.iter().enumerate() {
+        let pinnacle = pointing
+            .pinnacle
+            .as_ref()
+            .expect("Only Cirque Pinnacle is supported as pointing device now, add `pinnacle` config to the `[[input_device.pointing]]`");
+
+        let device_name = if pointing.name.is_empty() {
+            format!("pointing_{}", idx)
+        } else {
+            pointing.name.clone()
+        };
+
+        let device_ident = format_ident!("{}_device", device_name);
+        let processor_ident = format_ident!("{}_processor", device_name);
+
+        let i2c = match &pointing.interface {
+            Some(CommunicationProtocol::I2c(i2c)) => i2c,
+            Some(CommunicationProtocol::Spi(_)) => {
+                panic!("Cirque Pinnacle over SPI is not supported in keyboard.toml, use I2C")
+            }
+            None => panic!("`interface` is required for pointing device {}", device_name),
+        };
+        let instance_ident = format_ident!("{}", i2c.instance);
+        let sda_ident = format_ident!("{}", i2c.sda);
+        let scl_ident = format_ident!("{}", i2c.scl);
+        let address = i2c.address;
+
+        // Generate config values
+        let mode = match pinnacle.mode {
+            PinnacleMode::absolute => quote! { PinnacleMode::Absolute },
+            PinnacleMode::relative => quote! { PinnacleMode::Relative },
+        };
+        let attenuation = match pinnacle.attenuation.unwrap_or(4) {
+            1 => quote! { PinnacleAttenuation::X1 },
+            2 => quote! { PinnacleAttenuation::X2 },
+            3 => quote! { PinnacleAttenuation::X3 },
+            4 => quote! { PinnacleAttenuation::X4 },
+            other => panic!("Invalid pinnacle attenuation {}, available values: 1, 2, 3, 4", other),
+        };
+        let curved_overlay = pinnacle.curved_overlay;
+        let diameter_mm = pinnacle.diameter_mm.unwrap_or(35);
+        let cpi = pinnacle.cpi.unwrap_or(800);
+        let compensation = pinnacle.compensation;
+        let invert_x = pinnacle.invert_x;
+        let invert_y = pinnacle.invert_y;
+        let swap_xy = pinnacle.swap_xy;
+
+        let config = quote! {
+            PinnacleConfig {
+                mode: #mode,
+                attenuation: #attenuation,
+                curved_overlay: #curved_overlay,
+                diameter_mm: #diameter_mm,
+                cpi: #cpi,
+                compensation: #compensation,
+                invert_x: #invert_x,
+                invert_y: #invert_y,
+                swap_xy: #swap_xy,
+            }
+        };
+
+        // Generate data ready pin initialization (optional)
+        let data_ready_init = if let Some(data_ready_pin) = &pointing.data_ready {
+            let data_ready_ident = format_ident!("{}", data_ready_pin);
+            match chip.series {
+                ChipSeries::Nrf52 => quote! {
+                    Some(::embassy_nrf::gpio::Input::new(p.#data_ready_ident, ::embassy_nrf::gpio::Pull::None))
+                },
+                ChipSeries::Rp2040 => quote! {
+                    Some(::embassy_rp::gpio::Input::new(p.#data_ready_ident, ::embassy_rp::gpio::Pull::None))
+                },
+                _ => unreachable!(),
+            }
+        } else {
+            match chip.series {
+                ChipSeries::Nrf52 => quote! {
+                    None::<::embassy_nrf::gpio::Input<'static>>
+                },
+                ChipSeries::Rp2040 => quote! {
+                    None::<::embassy_rp::gpio::Input<'static>>
+                },
+                _ => unreachable!(),
+            }
+        };
+
+        // Generate device initialization based on chip series
+        let device_init = match chip.series {
+            ChipSeries::Nrf52 => quote! {
+                let mut #device_ident = {
+                    use ::rmk::input_device::pinnacle::{Pinnacle, PinnacleAttenuation, PinnacleConfig, PinnacleDevice, PinnacleMode};
+                    ::embassy_nrf::bind_interrupts!(struct TwimIrqs {
+                        #instance_ident => ::embassy_nrf::twim::InterruptHandler<::embassy_nrf::peripherals::#instance_ident>;
+                    });
+
+                    let i2c = ::embassy_nrf::twim::Twim::new(
+                        p.#instance_ident,
+                        TwimIrqs,
+                        p.#sda_ident,
+                        p.#scl_ident,
+                        ::embassy_nrf::twim::Config::default(),
+                        &mut [],
+                    );
+                    let data_ready = #data_ready_init;
+
+                    PinnacleDevice::new(Pinnacle::new_i2c(i2c, #address, #config), data_ready)
+                };
+            },
+            ChipSeries::Rp2040 => {
+                let irq_ident = format_ident!("{}_IRQ", i2c.instance);
+                quote! {
+                    let mut #device_ident = {
+                        use ::rmk::input_device::pinnacle::{Pinnacle, PinnacleAttenuation, PinnacleConfig, PinnacleDevice, PinnacleMode};
+                        ::embassy_rp::bind_interrupts!(struct I2cIrqs {
+                            #irq_ident => ::embassy_rp::i2c::InterruptHandler<::embassy_rp::peripherals::#instance_ident>;
+                        });
+
+                        let i2c = ::embassy_rp::i2c::I2c::new_async(
+                            p.#instance_ident,
+                            p.#scl_ident,
+                            p.#sda_ident,
+                            I2cIrqs,
+                            ::embassy_rp::i2c::Config::default(),
+                        );
+                        let data_ready = #data_ready_init;
+
+                        PinnacleDevice::new(Pinnacle::new_i2c(i2c, #address, #config), data_ready)
+                    };
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        device_initializers.push(Initializer {
+            initializer: device_init,
+            var_name: device_ident,
+        });
+
+        // Generate processor initialization
+        let processor_init = quote! {
+            let mut #processor_ident = ::rmk::input_device::pinnacle::PinnacleProcessor::new(&keymap);
+        };
+
+        processor_initializers.push(Initializer {
+            initializer: processor_init,
+            var_name: processor_ident,
+        });
+    }
+
+    (device_initializers, processor_initializers)
+}
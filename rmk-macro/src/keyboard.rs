@@ -142,7 +142,7 @@ fn expand_main(
     let keymap_and_storage = expand_keymap_and_storage(keyboard_config);
     let split_central_config = expand_split_central_config(keyboard_config);
     let (input_device_config, devices, processors) = expand_input_device_config(keyboard_config);
-    let matrix_and_keyboard = expand_matrix_and_keyboard_init(keyboard_config);
+    let matrix_init = expand_matrix_init(keyboard_config);
     let (controller_initializers, controllers) = expand_controller_init(keyboard_config, &item_mod);
     let run_rmk = expand_rmk_entry(keyboard_config, &item_mod, devices, processors, controllers);
 
@@ -211,6 +211,9 @@ fn expand_main(
             // Initialize flash driver as `flash` and storage config as `storage_config`
             #flash_init
 
+            // Initialize the matrix as `matrix`, and check the keys held at boot
+            #matrix_init
+
             // Initialize ble config as `ble_battery_config`
             #ble_config
 
@@ -223,8 +226,8 @@ fn expand_main(
             // Initialize the storage and keymap, as `storage` and `keymap`
             #keymap_and_storage
 
-            // Initialize the matrix + keyboard, as `matrix` and `keyboard`
-            #matrix_and_keyboard
+            // Initialize the keyboard, as `keyboard`
+            let mut keyboard = ::rmk::keyboard::Keyboard::new(&keymap);
 
             // Initialize input device config as `input_device_config` and processor as `processor`
             #input_device_config
@@ -259,6 +262,15 @@ pub(crate) fn expand_keymap_and_storage(keyboard_config: &KeyboardTomlConfig) ->
     };
 
     if keyboard_config.get_storage_config().enabled {
+        // The page cache covers all sectors, and the key cache is sized from the keymap
+        let num_sectors = keyboard_config.get_storage_config().num_sectors.unwrap_or(2) as usize;
+        let flash_backend = quote! {
+            let flash = ::rmk::storage::backend::FlashBackend::<
+                _,
+                #num_sectors,
+                { ::rmk::storage::backend::cache_keys(ROW, COL) },
+            >::new(flash, &rmk_config.storage_config);
+        };
         let num_encoders = keyboard_config.get_board_config().unwrap().get_num_encoder();
         let total_num_encoders = num_encoders.iter().sum::<usize>();
         let keymap_storage_init = if total_num_encoders == 0 {
@@ -297,6 +309,7 @@ pub(crate) fn expand_keymap_and_storage(keyboard_config: &KeyboardTomlConfig) ->
             #initialize_positional_config
             let mut default_keymap = get_default_keymap();
             #default_encoder_keymap
+            #flash_backend
             let (keymap, mut storage) =  #keymap_storage_init.await;
         }
     } else {
@@ -313,7 +326,8 @@ pub(crate) fn expand_keymap_and_storage(keyboard_config: &KeyboardTomlConfig) ->
     }
 }
 
-pub(crate) fn expand_matrix_and_keyboard_init(keyboard_config: &KeyboardTomlConfig) -> TokenStream2 {
+pub(crate) fn expand_matrix_init(keyboard_config: &KeyboardTomlConfig) -> TokenStream2 {
+    let storage = keyboard_config.get_storage_config().enabled;
     let matrix = match keyboard_config.get_board_config().unwrap() {
         BoardConfig::UniBody(UniBodyConfig {
             matrix: matrix_config,
@@ -321,20 +335,38 @@ pub(crate) fn expand_matrix_and_keyboard_init(keyboard_config: &KeyboardTomlConf
         }) => match matrix_config.matrix_type {
             MatrixType::normal => {
                 let col2row = !matrix_config.row2col;
-                let debouncer_type = get_debouncer_type(&matrix_config);
+                let ghost_filter = matrix_config.ghost_filter;
+                let scan_throttle = expand_scan_throttle(&matrix_config);
+                let debouncer = expand_debouncer(&matrix_config, keyboard_config.rmk.debounce_time, (0, 0));
                 quote! {
-                    let debouncer = #debouncer_type::new();
-                    let mut matrix = ::rmk::matrix::Matrix::<_, _, _, ROW, COL, #col2row>::new(row_pins, col_pins, debouncer);
+                    let debouncer = #debouncer;
+                    let mut matrix = ::rmk::matrix::Matrix::<_, _, _, ROW, COL, #col2row>::new(row_pins, col_pins, debouncer).with_ghost_filter(#ghost_filter)#scan_throttle;
                 }
             }
             MatrixType::direct_pin => {
                 let low_active = matrix_config.direct_pin_low_active;
-                let debouncer_type = get_debouncer_type(&matrix_config);
+                let debouncer = expand_debouncer(&matrix_config, keyboard_config.rmk.debounce_time, (0, 0));
                 quote! {
-                    let debouncer = #debouncer_type::new();
+                    let debouncer = #debouncer;
                     let mut matrix = ::rmk::direct_pin::DirectPinMatrix::<_, _, ROW, COL, SIZE>::new(direct_pins, debouncer, #low_active);
                 }
             }
+            MatrixType::shift_register | MatrixType::io_expander => {
+                let col2row = !matrix_config.row2col;
+                let debouncer = expand_debouncer(&matrix_config, keyboard_config.rmk.debounce_time, (0, 0));
+                quote! {
+                    let debouncer = #debouncer;
+                    let mut matrix = ::rmk::matrix::lines::LineMatrix::<_, _, _, ROW, COL, #col2row>::new(matrix_outputs, matrix_inputs, debouncer);
+                }
+            }
+            MatrixType::charlieplex => {
+                let pin_num = matrix_config.charlieplex_pins.as_ref().map_or(0, |pins| pins.len());
+                let debouncer = expand_debouncer(&matrix_config, keyboard_config.rmk.debounce_time, (0, 0));
+                quote! {
+                    let debouncer = #debouncer;
+                    let mut matrix = ::rmk::matrix::charlieplex::CharlieplexMatrix::<_, _, #pin_num, ROW, COL>::new(charlieplex_pins, debouncer, scan_map);
+                }
+            }
         },
         BoardConfig::Split(split_config) => {
             // Matrix config for split central
@@ -345,29 +377,152 @@ pub(crate) fn expand_matrix_and_keyboard_init(keyboard_config: &KeyboardTomlConf
             let col2row = !split_config.central.matrix.row2col;
             match split_config.central.matrix.matrix_type {
                 MatrixType::normal => {
-                    let debouncer_type = get_debouncer_type(&split_config.central.matrix);
+                    let ghost_filter = split_config.central.matrix.ghost_filter;
+                    let scan_throttle = expand_scan_throttle(&split_config.central.matrix);
+                    let debouncer = expand_debouncer(
+                        &split_config.central.matrix,
+                        keyboard_config.rmk.debounce_time,
+                        (central_row_offset, central_col_offset),
+                    );
                     quote! {
-                        let debouncer = #debouncer_type::new();
-                        let matrix = ::rmk::matrix::Matrix::<_, _, _, #central_row, #central_col, #col2row>::new(row_pins, col_pins, debouncer);
+                        let debouncer = #debouncer;
+                        let matrix = ::rmk::matrix::Matrix::<_, _, _, #central_row, #central_col, #col2row>::new(row_pins, col_pins, debouncer).with_ghost_filter(#ghost_filter)#scan_throttle;
                         let mut matrix = ::rmk::matrix::OffsetMatrixWrapper::<_, _, _, #central_row_offset, #central_col_offset>(matrix);
                     }
                 }
                 MatrixType::direct_pin => {
                     let low_active = split_config.central.matrix.direct_pin_low_active;
                     let size = split_config.central.rows * split_config.central.cols;
-                    let debouncer_type = get_debouncer_type(&split_config.central.matrix);
+                    let debouncer = expand_debouncer(
+                        &split_config.central.matrix,
+                        keyboard_config.rmk.debounce_time,
+                        (central_row_offset, central_col_offset),
+                    );
                     quote! {
-                        let debouncer = #debouncer_type::new();
+                        let debouncer = #debouncer;
                         let matrix = ::rmk::direct_pin::DirectPinMatrix::<_, _, #central_row, #central_col, #size>::new(direct_pins, debouncer, #low_active);
                         let mut matrix = ::rmk::matrix::OffsetMatrixWrapper::<_, _, _, #central_row_offset, #central_col_offset>(matrix);
                     }
                 }
+                MatrixType::shift_register | MatrixType::io_expander => {
+                    let debouncer = expand_debouncer(
+                        &split_config.central.matrix,
+                        keyboard_config.rmk.debounce_time,
+                        (central_row_offset, central_col_offset),
+                    );
+                    quote! {
+                        let debouncer = #debouncer;
+                        let matrix = ::rmk::matrix::lines::LineMatrix::<_, _, _, #central_row, #central_col, #col2row>::new(matrix_outputs, matrix_inputs, debouncer);
+                        let mut matrix = ::rmk::matrix::OffsetMatrixWrapper::<_, _, _, #central_row_offset, #central_col_offset>(matrix);
+                    }
+                }
+                MatrixType::charlieplex => {
+                    let pin_num = split_config
+                        .central
+                        .matrix
+                        .charlieplex_pins
+                        .as_ref()
+                        .map_or(0, |pins| pins.len());
+                    let debouncer = expand_debouncer(
+                        &split_config.central.matrix,
+                        keyboard_config.rmk.debounce_time,
+                        (central_row_offset, central_col_offset),
+                    );
+                    quote! {
+                        let debouncer = #debouncer;
+                        let matrix = ::rmk::matrix::charlieplex::CharlieplexMatrix::<_, _, #pin_num, #central_row, #central_col>::new(charlieplex_pins, debouncer, scan_map);
+                        let mut matrix = ::rmk::matrix::OffsetMatrixWrapper::<_, _, _, #central_row_offset, #central_col_offset>(matrix);
+                    }
+                }
             }
         }
     };
+    let bootmagic = match keyboard_config.get_board_config().unwrap() {
+        BoardConfig::UniBody(UniBodyConfig { matrix, .. }) => {
+            let (layout, _) = keyboard_config.get_layout_config().unwrap();
+            expand_bootmagic(
+                &matrix,
+                (0, 0),
+                (layout.rows as usize, layout.cols as usize),
+                false,
+                storage,
+            )
+        }
+        BoardConfig::Split(split_config) => {
+            let central = &split_config.central;
+            // The matrix of the central reports the positions in the keymap
+            expand_bootmagic(
+                &central.matrix,
+                (central.row_offset, central.col_offset),
+                (central.rows, central.cols),
+                false,
+                storage,
+            )
+        }
+    };
     quote! {
-        let mut keyboard = ::rmk::keyboard::Keyboard::new(&keymap);
         #matrix
+        #bootmagic
+    }
+}
+
+/// Expand the check of the keys held at boot, `offset` and `size` are the part of the keymap scanned by the matrix.
+///
+/// If `local` is true, the matrix reports the positions without the offset, e.g. the matrix of split peripherals.
+/// The storage config is updated if the storage is enabled.
+pub(crate) fn expand_bootmagic(
+    matrix_config: &MatrixConfig,
+    offset: (usize, usize),
+    size: (usize, usize),
+    local: bool,
+    storage: bool,
+) -> TokenStream2 {
+    let expand_keys = |keys: &Option<Vec<[u8; 2]>>| {
+        let keys = keys.iter().flatten().map(|[row, col]| {
+            let (row, col) = (*row as usize, *col as usize);
+            if row < offset.0 || row >= offset.0 + size.0 || col < offset.1 || col >= offset.1 + size.1 {
+                panic!(
+                    "Bootmagic key [{}, {}] isn't scanned by the matrix of this board",
+                    row, col
+                );
+            }
+            let (row, col) = if local {
+                ((row - offset.0) as u8, (col - offset.1) as u8)
+            } else {
+                (row as u8, col as u8)
+            };
+            quote! { (#row, #col) }
+        });
+        quote! { &[#(#keys),*] }
+    };
+    if matrix_config.bootloader_keys.is_none() && matrix_config.clear_storage_keys.is_none() {
+        return quote! {};
+    }
+    if matrix_config.clear_storage_keys.is_some() && !storage {
+        panic!("`clear_storage_keys` requires the storage");
+    }
+    let bootloader_keys = expand_keys(&matrix_config.bootloader_keys);
+    let clear_storage_keys = expand_keys(&matrix_config.clear_storage_keys);
+    let check = quote! {
+        ::rmk::bootmagic::check_bootmagic(
+            &mut matrix,
+            &::rmk::config::BootMagicConfig {
+                bootloader_keys: #bootloader_keys,
+                clear_storage_keys: #clear_storage_keys,
+            },
+        )
+        .await
+    };
+    if storage {
+        quote! {
+            let clear_storage = #check;
+            let storage_config = ::rmk::config::StorageConfig {
+                clear_storage: storage_config.clear_storage || clear_storage,
+                ..storage_config
+            };
+        }
+    } else {
+        quote! { #check; }
     }
 }
 
@@ -394,11 +549,111 @@ fn expand_key_info_row(row: &Vec<KeyInfo>) -> proc_macro2::TokenStream {
     quote! { [#(#key_info), *] }
 }
 
-/// Get debouncer type
-pub(crate) fn get_debouncer_type(matrix_config: &MatrixConfig) -> TokenStream2 {
-    match matrix_config.debouncer.clone().unwrap_or("default".to_string()) {
+/// Expand the `with_scan_throttle` call of the normal matrix, or nothing if the scan rate isn't throttled
+pub(crate) fn expand_scan_throttle(matrix_config: &MatrixConfig) -> TokenStream2 {
+    let Some(throttle) = &matrix_config.scan_throttle else {
+        return quote! {};
+    };
+    let fields = [
+        (quote! { idle_timeout }, &throttle.idle_timeout),
+        (quote! { idle_interval }, &throttle.idle_interval),
+        (quote! { sleep_timeout }, &throttle.sleep_timeout),
+        (quote! { sleep_interval }, &throttle.sleep_interval),
+    ]
+    .into_iter()
+    .filter_map(|(name, duration)| {
+        duration.as_ref().map(|d| {
+            let ms = d.0;
+            quote! { #name: ::embassy_time::Duration::from_millis(#ms), }
+        })
+    });
+    quote! {
+        .with_scan_throttle(::rmk::matrix::scan_throttle::ScanThrottleConfig {
+            #(#fields)*
+            ..Default::default()
+        })
+    }
+}
+
+/// Expand the debouncer initialization, `default_time` is the default debounce time in ms
+/// Expand the debouncer constructor, `offset` is the (row, col) offset of the matrix in the whole keyboard
+pub(crate) fn expand_debouncer(
+    matrix_config: &MatrixConfig,
+    default_time: u16,
+    offset: (usize, usize),
+) -> TokenStream2 {
+    let debouncer = expand_debounce_algorithm(matrix_config, default_time);
+    match matrix_config.chatter_window {
+        Some(window) => {
+            let suppress = matrix_config.chatter_suppress;
+            let row_offset = offset.0 as u8;
+            let col_offset = offset.1 as u8;
+            quote! {
+                ::rmk::debounce::chatter::ChatterDetector::new(#debouncer, #window)
+                    .with_suppression(#suppress)
+                    .with_offset(#row_offset, #col_offset)
+            }
+        }
+        None => {
+            if matrix_config.chatter_suppress {
+                panic!("`chatter_suppress` requires `chatter_window`");
+            }
+            debouncer
+        }
+    }
+}
+
+fn expand_debounce_algorithm(matrix_config: &MatrixConfig, default_time: u16) -> TokenStream2 {
+    let debouncer_type = match matrix_config.debouncer.clone().unwrap_or("default".to_string()) {
         s if s == "fast" => quote! { ::rmk::debounce::fast_debouncer::FastDebouncer },
         s if s == "default" => quote! { ::rmk::debounce::default_debouncer::DefaultDebouncer },
-        _ => panic!("Invalid debouncer type, supported debouncer types are `default` and `fast`"),
+        s if s == "sym_defer_pk" => quote! { ::rmk::debounce::sym_defer_pk::SymDeferPkDebouncer },
+        s if s == "sym_eager_pk" => quote! { ::rmk::debounce::sym_eager_pk::SymEagerPkDebouncer },
+        s if s == "asym_eager_defer_pk" => quote! { ::rmk::debounce::asym_eager_defer_pk::AsymEagerDeferPkDebouncer },
+        s if s == "sym_defer_pr" => quote! { ::rmk::debounce::sym_defer_pr::SymDeferPrDebouncer },
+        _ => panic!(
+            "Invalid debouncer type, supported debouncer types are `default`, `fast`, `sym_defer_pk`, `sym_eager_pk`, `asym_eager_defer_pk` and `sym_defer_pr`"
+        ),
+    };
+
+    let has_thresholds = matrix_config.debounce_press_time.is_some()
+        || matrix_config.debounce_release_time.is_some()
+        || matrix_config.debounce_overrides.is_some();
+    if !has_thresholds {
+        return quote! { #debouncer_type::new() };
+    }
+    if matches!(
+        matrix_config.debouncer.as_deref(),
+        None | Some("default") | Some("fast")
+    ) {
+        panic!(
+            "`debounce_press_time`, `debounce_release_time` and `debounce_overrides` are not supported by the `default` and `fast` debouncers"
+        );
+    }
+
+    let press = matrix_config.debounce_press_time.unwrap_or(default_time);
+    let release = matrix_config.debounce_release_time.unwrap_or(default_time);
+    let overrides = matrix_config
+        .debounce_overrides
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|o| {
+            let row = o.row;
+            let col = o.col;
+            let key_press = o.press_time.unwrap_or(press);
+            let key_release = o.release_time.unwrap_or(release);
+            quote! {
+                ::rmk::debounce::KeyDebounceOverride {
+                    row: #row,
+                    col: #col,
+                    threshold: ::rmk::debounce::DebounceThreshold { press: #key_press, release: #key_release },
+                }
+            }
+        });
+    quote! {
+        #debouncer_type::new()
+            .with_threshold(#press, #release)
+            .with_key_overrides(&[#(#overrides),*])
     }
 }
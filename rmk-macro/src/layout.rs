@@ -36,7 +36,7 @@ pub(crate) fn expand_default_keymap(keyboard_config: &KeyboardTomlConfig) -> Tok
     }
     encoder_map.resize(
         layout.keymap.len(),
-        quote! { [::rmk::encoder!(::rmk::k!(No), ::rmk::k!(No)); NUM_ENCODER] }
+        quote! { [::rmk::encoder!(::rmk::k!(No), ::rmk::k!(No)); NUM_ENCODER] },
     );
 
     quote! {
@@ -83,10 +83,7 @@ fn expand_encoder_layer(
     }
 
     // Make sure it configures correct number of encoders
-    encoders.resize(
-        num_encoder,
-        quote! { ::rmk::encoder!(::rmk::k!(No), ::rmk::k!(No)) }
-    );
+    encoders.resize(num_encoder, quote! { ::rmk::encoder!(::rmk::k!(No), ::rmk::k!(No)) });
 
     quote! { [#(#encoders), *] }
 }
@@ -434,6 +431,39 @@ pub(crate) fn parse_key(key: String, profiles: &Option<HashMap<String, MorseProf
                 ::rmk::td!(#index)
             }
         }
+        s if s.to_lowercase().starts_with("gp(") => {
+            let button = get_number(s.clone(), s.get(0..3).unwrap(), ")");
+            if button >= 32 {
+                panic!("\n❌ keyboard.toml: GP(n) invalid, the gamepad button should be in 0..=31");
+            }
+            quote! {
+                ::rmk::gp!(#button)
+            }
+        }
+        s if s.to_lowercase().starts_with("kmp(") => {
+            let profile = get_number(s.clone(), s.get(0..4).unwrap(), ")");
+            if profile >= 8 {
+                panic!("\n❌ keyboard.toml: KMP(n) invalid, the keymap profile should be in 0..=7");
+            }
+            quote! {
+                ::rmk::kmp!(#profile)
+            }
+        }
+        s if s.to_lowercase().starts_with("hat(") => {
+            let direction = s.get(4..).unwrap().trim_end_matches(")").trim().to_lowercase();
+            let direction = match direction.as_str() {
+                "up" => quote! { Up },
+                "down" => quote! { Down },
+                "left" => quote! { Left },
+                "right" => quote! { Right },
+                _ => panic!(
+                    "\n❌ keyboard.toml: HAT(direction) invalid, the direction should be Up, Down, Left or Right"
+                ),
+            };
+            quote! {
+                ::rmk::hat!(#direction)
+            }
+        }
         s if s.to_lowercase().starts_with("m(") => {
             let index = get_number(s.clone(), s.get(0..2).unwrap(), ")");
             quote! {
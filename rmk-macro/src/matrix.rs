@@ -1,12 +1,15 @@
 //! Initialize matrix initialization boilerplate of RMK
 //!
-use quote::quote;
-use rmk_config::{BoardConfig, ChipModel, ChipSeries, KeyboardTomlConfig, MatrixType, UniBodyConfig};
+use quote::{format_ident, quote};
+use rmk_config::{
+    BoardConfig, ChipModel, ChipSeries, IoExpanderChip, KeyboardTomlConfig, MatrixConfig, MatrixType,
+    ShiftRegisterConfig, UniBodyConfig,
+};
 
 use crate::feature::is_feature_enabled;
 use crate::gpio_config::{
-    convert_direct_pins_to_initializers, convert_input_pins_to_initializers, convert_output_pins_to_initializers,
-    get_input_pin_type, get_output_pin_type,
+    convert_direct_pins_to_initializers, convert_gpio_str_to_input_pin, convert_gpio_str_to_output_pin,
+    convert_input_pins_to_initializers, convert_output_pins_to_initializers, get_input_pin_type, get_output_pin_type,
 };
 
 pub(crate) fn expand_matrix_config(
@@ -49,6 +52,30 @@ pub(crate) fn expand_matrix_config(
                     let low_active = #low_active;
                 });
             }
+            MatrixType::shift_register => {
+                let (layout, _) = keyboard_config.get_layout_config().unwrap();
+                matrix_config.extend(expand_shift_register_matrix_lines(
+                    &keyboard_config.get_chip_model().unwrap(),
+                    matrix,
+                    layout.rows as usize,
+                    layout.cols as usize,
+                ));
+            }
+            MatrixType::io_expander => {
+                matrix_config.extend(expand_io_expander_matrix_lines(
+                    &keyboard_config.get_chip_model().unwrap(),
+                    matrix,
+                ));
+            }
+            MatrixType::charlieplex => {
+                let (layout, _) = keyboard_config.get_layout_config().unwrap();
+                matrix_config.extend(expand_charlieplex_matrix(
+                    &keyboard_config.get_chip_model().unwrap(),
+                    matrix,
+                    layout.rows as usize,
+                    layout.cols as usize,
+                ));
+            }
         },
         BoardConfig::Split(split_config) => {
             // Matrix config for split central
@@ -66,6 +93,22 @@ pub(crate) fn expand_matrix_config(
                     async_matrix,
                     split_config.central.matrix.direct_pin_low_active,
                 )),
+                MatrixType::shift_register => matrix_config.extend(expand_shift_register_matrix_lines(
+                    &keyboard_config.get_chip_model().unwrap(),
+                    &split_config.central.matrix,
+                    split_config.central.rows,
+                    split_config.central.cols,
+                )),
+                MatrixType::io_expander => matrix_config.extend(expand_io_expander_matrix_lines(
+                    &keyboard_config.get_chip_model().unwrap(),
+                    &split_config.central.matrix,
+                )),
+                MatrixType::charlieplex => matrix_config.extend(expand_charlieplex_matrix(
+                    &keyboard_config.get_chip_model().unwrap(),
+                    &split_config.central.matrix,
+                    split_config.central.rows,
+                    split_config.central.cols,
+                )),
             }
         }
     };
@@ -150,3 +193,234 @@ pub(crate) fn expand_matrix_input_output_pins(
         };
     }
 }
+
+/// Expand the output lines and input lines of a shift register matrix, as `matrix_outputs` and `matrix_inputs`.
+///
+/// The shift registers are driven by bit-banged SPI, the sides without shift registers use the row/col pins.
+pub(crate) fn expand_shift_register_matrix_lines(
+    chip: &ChipModel,
+    matrix: &MatrixConfig,
+    rows: usize,
+    cols: usize,
+) -> proc_macro2::TokenStream {
+    if let Err(e) = matrix.check_shift_register() {
+        panic!("\n❌ keyboard.toml: {}", e);
+    }
+    let (input_pins, output_pins, input_len, output_len) = if matrix.row2col {
+        (matrix.col_pins.clone(), matrix.row_pins.clone(), cols, rows)
+    } else {
+        (matrix.row_pins.clone(), matrix.col_pins.clone(), rows, cols)
+    };
+
+    let outputs = match &matrix.shift_register_out {
+        Some(shift_register) => {
+            let (spi_bus, latch) = expand_shift_register_bus(chip, shift_register, false);
+            quote! {
+                ::rmk::matrix::shift_register::ShiftRegisterOutputs::new(#spi_bus, #latch, #output_len)
+            }
+        }
+        None => {
+            let pin_initialization = convert_output_pins_to_initializers(chip, output_pins.unwrap());
+            quote! {
+                ::rmk::matrix::lines::PinOutputLines({
+                    #pin_initialization
+                    output_pins
+                })
+            }
+        }
+    };
+    let inputs = match &matrix.shift_register_in {
+        Some(shift_register) => {
+            let (spi_bus, load) = expand_shift_register_bus(chip, shift_register, true);
+            quote! {
+                ::rmk::matrix::shift_register::ShiftRegisterInputs::new(
+                    #spi_bus.with_phase(::rmk::driver::bitbang_spi::Phase::CaptureOnFirstTransition),
+                    #load,
+                    #input_len,
+                )
+            }
+        }
+        None => {
+            // Input lines are polled, so async input pins are not needed
+            let pin_initialization = convert_input_pins_to_initializers(chip, input_pins.unwrap(), false);
+            quote! {
+                ::rmk::matrix::lines::PinInputLines({
+                    #pin_initialization
+                    input_pins
+                })
+            }
+        }
+    };
+
+    quote! {
+        let matrix_outputs = #outputs;
+        let matrix_inputs = #inputs;
+    }
+}
+
+/// Expand the bit-banged SPI bus and the latch pin of chained shift registers
+fn expand_shift_register_bus(
+    chip: &ChipModel,
+    shift_register: &ShiftRegisterConfig,
+    latch_high: bool,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let data_ident = format_ident!("{}", shift_register.data);
+    let data = match chip.series {
+        ChipSeries::Nrf52 => quote! { ::embassy_nrf::gpio::Flex::new(p.#data_ident) },
+        ChipSeries::Rp2040 => quote! { ::embassy_rp::gpio::Flex::new(p.#data_ident) },
+        _ => panic!("\n❌ keyboard.toml: Shift register matrix is only supported on nRF52 and RP2040 now"),
+    };
+    let clock = convert_gpio_str_to_output_pin(chip, shift_register.clock.clone(), true);
+    let latch = convert_gpio_str_to_output_pin(chip, shift_register.latch.clone(), latch_high);
+    (
+        quote! { ::rmk::driver::bitbang_spi::BitBangSpiBus::new(#clock, #data) },
+        latch,
+    )
+}
+
+/// Expand the pins and the scan map of a charlieplex matrix, as `charlieplex_pins` and `scan_map`.
+///
+/// Keys are numbered row by row, key `k` is sensed by the `k % (N - 1)`th pin among the other pins
+/// when the `k / (N - 1)`th pin is driven. So a `N` rows x `N - 1` cols layout maps rows to the driving pins.
+pub(crate) fn expand_charlieplex_matrix(
+    chip: &ChipModel,
+    matrix: &MatrixConfig,
+    rows: usize,
+    cols: usize,
+) -> proc_macro2::TokenStream {
+    if let Err(e) = matrix.check_charlieplex() {
+        panic!("\n❌ keyboard.toml: {}", e);
+    }
+    let pins = matrix.charlieplex_pins.clone().unwrap();
+    let pin_num = pins.len();
+    if rows * cols > pin_num * (pin_num - 1) {
+        panic!(
+            "\n❌ keyboard.toml: {} charlieplex pins can only address {} keys, but the matrix has {} keys",
+            pin_num,
+            pin_num * (pin_num - 1),
+            rows * cols
+        );
+    }
+    let pin_initializers = pins.iter().map(|pin| {
+        let pin_ident = format_ident!("{}", pin);
+        match chip.series {
+            ChipSeries::Nrf52 => quote! { ::embassy_nrf::gpio::Flex::new(p.#pin_ident) },
+            ChipSeries::Rp2040 => quote! { ::embassy_rp::gpio::Flex::new(p.#pin_ident) },
+            _ => panic!("\n❌ keyboard.toml: Charlieplex matrix is only supported on nRF52 and RP2040 now"),
+        }
+    });
+    let scan_map_rows = (0..rows).map(|row| {
+        let locations = (0..cols).map(|col| {
+            let key = row * cols + col;
+            let out_idx = key / (pin_num - 1);
+            let sense = key % (pin_num - 1);
+            let in_idx = if sense < out_idx { sense } else { sense + 1 };
+            quote! { ::rmk::matrix::bidirectional_matrix::ScanLocation::Pins(#in_idx, #out_idx) }
+        });
+        quote! { [#(#locations),*] }
+    });
+
+    quote! {
+        let charlieplex_pins = [#(#pin_initializers),*];
+        let scan_map = [#(#scan_map_rows),*];
+    }
+}
+
+/// Expand the output lines and input lines of an I/O expander matrix, as `matrix_outputs` and `matrix_inputs`.
+///
+/// Pins named `EXP0`..`EXP15` are pins of the expander, others are native pins.
+pub(crate) fn expand_io_expander_matrix_lines(chip: &ChipModel, matrix: &MatrixConfig) -> proc_macro2::TokenStream {
+    if let Err(e) = matrix.check_io_expander() {
+        panic!("\n❌ keyboard.toml: {}", e);
+    }
+    let io_expander = matrix.io_expander.as_ref().unwrap();
+    let (input_pins, output_pins) = if matrix.row2col {
+        (matrix.col_pins.clone().unwrap(), matrix.row_pins.clone().unwrap())
+    } else {
+        (matrix.row_pins.clone().unwrap(), matrix.col_pins.clone().unwrap())
+    };
+    let input_len = input_pins.len();
+    let output_len = output_pins.len();
+    let input_pin_type = get_input_pin_type(chip, false);
+    let output_pin_type = get_output_pin_type(chip);
+
+    // Lines are active low, unselected outputs are high and inputs are pulled up
+    let expand_line = |pin: String, native: proc_macro2::TokenStream| match pin.strip_prefix("EXP") {
+        Some(num) => {
+            let num: u8 = num.parse().unwrap();
+            quote! { ::rmk::matrix::io_expander::ExpanderLine::Expander(#num) }
+        }
+        None => quote! { ::rmk::matrix::io_expander::ExpanderLine::Pin(#native) },
+    };
+    let output_lines = output_pins.into_iter().map(|pin| {
+        let native = convert_gpio_str_to_output_pin(chip, pin.clone(), true);
+        expand_line(pin, native)
+    });
+    let input_lines = input_pins.into_iter().map(|pin| {
+        let native = convert_gpio_str_to_input_pin(chip, pin.clone(), false, Some(true));
+        expand_line(pin, native)
+    });
+    let interrupt = match &io_expander.interrupt {
+        Some(pin) => {
+            let pin = convert_gpio_str_to_input_pin(chip, pin.clone(), false, Some(true));
+            quote! { Some(#pin) }
+        }
+        None => quote! { None::<#input_pin_type<'static>> },
+    };
+
+    let instance_ident = format_ident!("{}", io_expander.i2c.instance);
+    let sda_ident = format_ident!("{}", io_expander.i2c.sda);
+    let scl_ident = format_ident!("{}", io_expander.i2c.scl);
+    let i2c = match chip.series {
+        ChipSeries::Nrf52 => quote! {
+            ::embassy_nrf::bind_interrupts!(struct IoExpanderIrqs {
+                #instance_ident => ::embassy_nrf::twim::InterruptHandler<::embassy_nrf::peripherals::#instance_ident>;
+            });
+            let i2c = ::embassy_nrf::twim::Twim::new(
+                p.#instance_ident,
+                IoExpanderIrqs,
+                p.#sda_ident,
+                p.#scl_ident,
+                ::embassy_nrf::twim::Config::default(),
+                &mut [],
+            );
+        },
+        ChipSeries::Rp2040 => {
+            let irq_ident = format_ident!("{}_IRQ", io_expander.i2c.instance);
+            quote! {
+                ::embassy_rp::bind_interrupts!(struct IoExpanderIrqs {
+                    #irq_ident => ::embassy_rp::i2c::InterruptHandler<::embassy_rp::peripherals::#instance_ident>;
+                });
+                let i2c = ::embassy_rp::i2c::I2c::new_async(
+                    p.#instance_ident,
+                    p.#scl_ident,
+                    p.#sda_ident,
+                    IoExpanderIrqs,
+                    ::embassy_rp::i2c::Config::default(),
+                );
+            }
+        }
+        _ => panic!("\n❌ keyboard.toml: I/O expander matrix is only supported on nRF52 and RP2040 now"),
+    };
+    let address = io_expander.i2c.address;
+    let expander = match io_expander.chip {
+        IoExpanderChip::mcp23017 => quote! { ::rmk::matrix::io_expander::Mcp23017::new(i2c, #address) },
+        IoExpanderChip::pca9555 => quote! { ::rmk::matrix::io_expander::Pca9555::new(i2c, #address) },
+    };
+
+    quote! {
+        let matrix_io_expander = {
+            #i2c
+            ::rmk::matrix::io_expander::SharedIoExpander::new(#expander)
+        };
+        let matrix_outputs = ::rmk::matrix::io_expander::ExpanderOutputLines::<_, #output_pin_type, #output_len>::new(
+            &matrix_io_expander,
+            [#(#output_lines),*],
+        );
+        let matrix_inputs = ::rmk::matrix::io_expander::ExpanderInputLines::<_, #input_pin_type, _, #input_len>::new(
+            &matrix_io_expander,
+            [#(#input_lines),*],
+            #interrupt,
+        );
+    }
+}
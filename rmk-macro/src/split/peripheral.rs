@@ -15,10 +15,16 @@ use crate::gpio_config::expand_output_initialization;
 use crate::import::expand_custom_imports;
 use crate::input_device::adc::expand_adc_device;
 use crate::input_device::encoder::expand_encoder_device;
+use crate::input_device::optical_sensor::expand_optical_sensor_device;
+use crate::input_device::optical_sensor_configs;
 use crate::input_device::pmw3610::expand_pmw3610_device;
-use crate::keyboard::get_debouncer_type;
+use crate::input_device::pointing::expand_pointing_device;
+use crate::keyboard::{expand_bootmagic, expand_debouncer, expand_scan_throttle};
 use crate::keyboard_config::read_keyboard_toml_config;
-use crate::matrix::{expand_matrix_direct_pins, expand_matrix_input_output_pins};
+use crate::matrix::{
+    expand_charlieplex_matrix, expand_io_expander_matrix_lines, expand_matrix_direct_pins,
+    expand_matrix_input_output_pins, expand_shift_register_matrix_lines,
+};
 use crate::split::central::expand_serial_init;
 
 /// Parse split peripheral mod and generate a valid RMK main function with all needed code
@@ -159,14 +165,16 @@ fn expand_split_peripheral(
 
     let imports = expand_custom_imports(&item_mod);
     let mut chip_init = expand_chip_init(keyboard_config, Some(id), &item_mod);
-    if split_config.connection == "ble" {
-        // Add storage when using BLE split
-        let flash_init = expand_flash_init(keyboard_config);
-        chip_init.extend(quote! {
-            #flash_init
+    // Add storage when using BLE split, it's initialized after the bootmagic check of the matrix
+    let storage = split_config.connection == "ble";
+    let storage_init = if storage {
+        chip_init.extend(expand_flash_init(keyboard_config));
+        quote! {
             let mut storage = ::rmk::storage::new_storage_for_split_peripheral(flash, storage_config).await;
-        });
-    }
+        }
+    } else {
+        quote! {}
+    };
 
     // Debouncer config
     let col = peripheral_config.cols;
@@ -193,14 +201,20 @@ fn expand_split_peripheral(
                 peripheral_config.matrix.row2col,
                 async_matrix,
             ));
-            let debouncer_type = get_debouncer_type(&peripheral_config.matrix);
+            let debouncer = expand_debouncer(
+                &peripheral_config.matrix,
+                keyboard_config.rmk.debounce_time,
+                (peripheral_config.row_offset, peripheral_config.col_offset),
+            );
             let col2row = !peripheral_config.matrix.row2col;
+            let ghost_filter = peripheral_config.matrix.ghost_filter;
+            let scan_throttle = expand_scan_throttle(&peripheral_config.matrix);
             let num_row = peripheral_config.rows;
             let num_col = peripheral_config.cols;
 
             matrix_config.extend(quote! {
-                let debouncer = #debouncer_type::new();
-                let mut matrix = ::rmk::matrix::Matrix::<_, _, _, #num_row, #num_col, #col2row>::new(row_pins, col_pins, debouncer);
+                let debouncer = #debouncer;
+                let mut matrix = ::rmk::matrix::Matrix::<_, _, _, #num_row, #num_col, #col2row>::new(row_pins, col_pins, debouncer).with_ghost_filter(#ghost_filter)#scan_throttle;
             });
         }
         MatrixType::direct_pin => {
@@ -218,15 +232,65 @@ fn expand_split_peripheral(
             // So we need to declaring them in advance.
             let size = row * col;
             let low_active = peripheral_config.matrix.direct_pin_low_active;
-            let debouncer_type = get_debouncer_type(&peripheral_config.matrix);
+            let debouncer = expand_debouncer(
+                &peripheral_config.matrix,
+                keyboard_config.rmk.debounce_time,
+                (peripheral_config.row_offset, peripheral_config.col_offset),
+            );
 
             matrix_config.extend(quote! {
-                let debouncer = #debouncer_type::new();
+                let debouncer = #debouncer;
                 let mut matrix = ::rmk::direct_pin::DirectPinMatrix::<_, _, #row, #col, #size>::new(direct_pins, debouncer, #low_active);
             });
         }
+        MatrixType::shift_register | MatrixType::io_expander => {
+            // Both are scanned by `LineMatrix`, only the lines are different
+            matrix_config.extend(match peripheral_config.matrix.matrix_type {
+                MatrixType::io_expander => expand_io_expander_matrix_lines(&chip, &peripheral_config.matrix),
+                _ => expand_shift_register_matrix_lines(&chip, &peripheral_config.matrix, row, col),
+            });
+            let debouncer = expand_debouncer(
+                &peripheral_config.matrix,
+                keyboard_config.rmk.debounce_time,
+                (peripheral_config.row_offset, peripheral_config.col_offset),
+            );
+            let col2row = !peripheral_config.matrix.row2col;
+
+            matrix_config.extend(quote! {
+                let debouncer = #debouncer;
+                let mut matrix = ::rmk::matrix::lines::LineMatrix::<_, _, _, #row, #col, #col2row>::new(matrix_outputs, matrix_inputs, debouncer);
+            });
+        }
+        MatrixType::charlieplex => {
+            matrix_config.extend(expand_charlieplex_matrix(&chip, &peripheral_config.matrix, row, col));
+            let pin_num = peripheral_config
+                .matrix
+                .charlieplex_pins
+                .as_ref()
+                .map_or(0, |pins| pins.len());
+            let debouncer = expand_debouncer(
+                &peripheral_config.matrix,
+                keyboard_config.rmk.debounce_time,
+                (peripheral_config.row_offset, peripheral_config.col_offset),
+            );
+
+            matrix_config.extend(quote! {
+                let debouncer = #debouncer;
+                let mut matrix = ::rmk::matrix::charlieplex::CharlieplexMatrix::<_, _, #pin_num, #row, #col>::new(charlieplex_pins, debouncer, scan_map);
+            });
+        }
     }
 
+    // The matrix of the peripheral reports the positions without the offset
+    matrix_config.extend(expand_bootmagic(
+        &peripheral_config.matrix,
+        (peripheral_config.row_offset, peripheral_config.col_offset),
+        (row, col),
+        true,
+        storage,
+    ));
+    matrix_config.extend(storage_init);
+
     let output_config = expand_output_initialization(peripheral_config.output.clone().unwrap_or_default(), &chip);
 
     // Peripherals don't need to run processors
@@ -400,9 +464,11 @@ pub(crate) fn expand_peripheral_input_device_config(
                 .pmw3610
                 .unwrap_or(Vec::new()),
             &chip,
+            0,
         ),
         _ => (vec![], vec![]),
     };
+    let mut motion_sensor_num = pmw3610_devices.len() as u8;
 
     for initializer in pmw3610_devices {
         initializations.extend(initializer.initializer);
@@ -410,5 +476,41 @@ pub(crate) fn expand_peripheral_input_device_config(
         devices.push(quote! { #device_name });
     }
 
+    // generate PMW3360/PMW3389/PAW3395 configuration
+    if let BoardConfig::Split(split_config) = &board {
+        let input_device = split_config.peripheral[id].input_device.clone().unwrap_or_default();
+        for (kind, sensor_config) in optical_sensor_configs(&input_device) {
+            let (sensor_devices, _sensor_processors) =
+                expand_optical_sensor_device(kind, sensor_config, &chip, motion_sensor_num);
+            motion_sensor_num += sensor_devices.len() as u8;
+
+            for initializer in sensor_devices {
+                initializations.extend(initializer.initializer);
+                let device_name = initializer.var_name;
+                devices.push(quote! { #device_name });
+            }
+        }
+    }
+
+    // generate pointing device configuration
+    let (pointing_devices, _pointing_processors) = match &board {
+        BoardConfig::Split(split_config) => expand_pointing_device(
+            split_config.peripheral[id]
+                .input_device
+                .clone()
+                .unwrap_or(InputDeviceConfig::default())
+                .pointing
+                .unwrap_or(Vec::new()),
+            &chip,
+        ),
+        _ => (vec![], vec![]),
+    };
+
+    for initializer in pointing_devices {
+        initializations.extend(initializer.initializer);
+        let device_name = initializer.var_name;
+        devices.push(quote! { #device_name });
+    }
+
     (initializations, devices, processors)
 }
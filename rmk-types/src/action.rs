@@ -325,6 +325,23 @@ pub enum Action {
     OneShotModifier(ModifierCombination),
     /// Oneshot key, keep the key active until the next key is triggered.
     OneShotKey(KeyCode),
+    /// Gamepad button, 0 ~ 31
+    GamepadButton(u8),
+    /// Gamepad hat switch direction. Directions of multiple pressed keys are combined, e.g. Up + Left = UpLeft
+    GamepadHat(HatDirection),
+    /// Switch to the keymap profile, which is loaded from the storage
+    SwitchKeymapProfile(u8),
+}
+
+/// Direction of the gamepad hat switch
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(postcard::experimental::max_size::MaxSize)]
+pub enum HatDirection {
+    Up,
+    Down,
+    Left,
+    Right,
 }
 
 #[cfg(test)]
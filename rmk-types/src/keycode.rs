@@ -794,6 +794,10 @@ pub enum KeyCode {
     TriLayerUpper = 0x778,
     RepeatKey = 0x779,
     AltRepeatKey = 0x77A,
+    // Motion sensor keycodes, not in vial
+    CpiUp = 0x790,
+    CpiDown = 0x791,
+    CpiCycle = 0x792,
     // Kb keycodes, use 0x800 ~ 0x81F
     Kb0 = 0x800,
     Kb1 = 0x801,
@@ -992,7 +996,7 @@ impl KeyCode {
 
     /// Returns `true` if the keycode is defined by rmk to achieve special functionalities, such as reboot keyboard, goto bootloader, etc.
     pub fn is_rmk(self) -> bool {
-        KeyCode::Bootloader <= self && self <= KeyCode::AltRepeatKey
+        KeyCode::Bootloader <= self && self <= KeyCode::CpiCycle
     }
 
     /// Returns `true` if the keycode is a combo keycode
@@ -1000,6 +1004,11 @@ impl KeyCode {
         KeyCode::ComboOn <= self && self <= KeyCode::ComboToggle
     }
 
+    /// Returns `true` if the keycode is a motion sensor CPI keycode
+    pub fn is_cpi(self) -> bool {
+        KeyCode::CpiUp <= self && self <= KeyCode::CpiCycle
+    }
+
     /// Returns `true` if the keycode is a boot keycode
     pub fn is_boot(self) -> bool {
         KeyCode::Bootloader <= self && self <= KeyCode::Reboot
@@ -64,6 +64,38 @@ impl TryFrom<u8> for ViaKeyboardInfo {
     }
 }
 
+/// Channel id of the keyboard's own values in VIA's custom value commands.
+pub const VIA_CUSTOM_CHANNEL_KEYBOARD: u8 = 0x00;
+
+/// Value ids of the keyboard's own values in VIA's custom value commands.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, FromRepr)]
+#[repr(u8)]
+pub enum ViaCustomValue {
+    /// Get: read the chatter counters starting from the given index. Set: clear all chatter counters
+    ChatterCounters = 0x01,
+    /// Get: read the instrumentation of the scan rate, queue depth, processing time and report latency.
+    /// Set: reset the peaks and statistics. Requires the `instrumentation` feature of rmk
+    Instrumentation = 0x02,
+    /// Get: read the number of keymap profiles, the active profile and the bindings.
+    /// Set: switch, copy, clear or bind the keymap profiles. Requires the `storage` feature of rmk
+    KeymapProfile = 0x03,
+    /// Get: read a chunk of the configuration backup at the given offset.
+    /// Set: begin, write a chunk of and end the import of a backup. Requires the `storage` feature of rmk
+    Backup = 0x04,
+    /// Get: read the erase cycles, free space and capacity of the storage, and the number of pending writes.
+    /// Set: save the pending writes immediately. Requires the `storage` feature of rmk
+    StorageStats = 0x05,
+}
+
+impl TryFrom<u8> for ViaCustomValue {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        // Return original value when there's an error
+        Self::from_repr(value).ok_or(value)
+    }
+}
+
 /// Vial communication commands.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, FromRepr)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -101,12 +133,58 @@ pub enum SettingKey {
     ComboTimeout = 0x02,
     OneShotTimeout = 0x06,
     MorseTimeout = 0x07,
+    MouseKeyDelay = 0x09,
+    MouseKeyInterval = 0x0A,
+    MouseKeyMoveDelta = 0x0B,
+    MouseKeyMaxSpeed = 0x0C,
+    MouseKeyTimeToMax = 0x0D,
+    MouseKeyWheelDelay = 0x0E,
+    MouseKeyWheelInterval = 0x0F,
+    MouseKeyWheelMaxSpeed = 0x10,
+    MouseKeyWheelTimeToMax = 0x11,
     TapInterval = 0x12,
     TapCapslockInterval = 0x13,
     PermissiveHold = 0x16,
     HoldOnOtherKeyPress = 0x17,
     UnilateralTap = 0x1A,
     PriorIdleTime = 0x1B,
+    // RMK specific settings, which aren't shown by Vial
+    FlowTap = 0x100,
+    TriLayer = 0x101,
+    CapsWordTimeout = 0x102,
+    MouseKeyWheelDelta = 0x103,
+    MouseKeyMoveMax = 0x104,
+    MouseKeyWheelMax = 0x105,
+}
+
+impl SettingKey {
+    /// All supported settings, in ascending order
+    pub const ALL: [SettingKey; 24] = [
+        SettingKey::ComboTimeout,
+        SettingKey::OneShotTimeout,
+        SettingKey::MorseTimeout,
+        SettingKey::MouseKeyDelay,
+        SettingKey::MouseKeyInterval,
+        SettingKey::MouseKeyMoveDelta,
+        SettingKey::MouseKeyMaxSpeed,
+        SettingKey::MouseKeyTimeToMax,
+        SettingKey::MouseKeyWheelDelay,
+        SettingKey::MouseKeyWheelInterval,
+        SettingKey::MouseKeyWheelMaxSpeed,
+        SettingKey::MouseKeyWheelTimeToMax,
+        SettingKey::TapInterval,
+        SettingKey::TapCapslockInterval,
+        SettingKey::PermissiveHold,
+        SettingKey::HoldOnOtherKeyPress,
+        SettingKey::UnilateralTap,
+        SettingKey::PriorIdleTime,
+        SettingKey::FlowTap,
+        SettingKey::TriLayer,
+        SettingKey::CapsWordTimeout,
+        SettingKey::MouseKeyWheelDelta,
+        SettingKey::MouseKeyMoveMax,
+        SettingKey::MouseKeyWheelMax,
+    ];
 }
 
 impl From<u16> for SettingKey {
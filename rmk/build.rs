@@ -2,7 +2,6 @@
 mod common;
 
 use std::path::Path;
-use std::process::Command;
 use std::{env, fs};
 
 use const_gen::*;
@@ -48,8 +47,6 @@ fn main() {
 }
 
 fn get_constants_str(constants: RmkConstantsConfig) -> String {
-    // Compute build hash according to the latest git commit
-    let build_hash = compute_build_hash();
     // Add other constants
     [
         const_declaration!(pub(crate) MOUSE_KEY_INTERVAL = constants.mouse_key_interval),
@@ -57,6 +54,7 @@ fn get_constants_str(constants: RmkConstantsConfig) -> String {
         const_declaration!(pub(crate) COMBO_MAX_NUM = constants.combo_max_num),
         const_declaration!(pub(crate) COMBO_MAX_LENGTH = constants.combo_max_length),
         const_declaration!(pub(crate) MACRO_SPACE_SIZE = constants.macro_space_size),
+        const_declaration!(pub(crate) KEYMAP_PROFILE_NUM = constants.keymap_profiles_num),
         const_declaration!(pub(crate) FORK_MAX_NUM = constants.fork_max_num),
         const_declaration!(pub(crate) DEBOUNCE_THRESHOLD = constants.debounce_time),
         const_declaration!(pub(crate) EVENT_CHANNEL_SIZE = constants.event_channel_size),
@@ -71,33 +69,7 @@ fn get_constants_str(constants: RmkConstantsConfig) -> String {
         const_declaration!(pub(crate) SPLIT_CENTRAL_SLEEP_TIMEOUT_SECONDS = constants.split_central_sleep_timeout_seconds),
         const_declaration!(pub(crate) MORSE_MAX_NUM = constants.morse_max_num),
         const_declaration!(pub(crate) MAX_PATTERNS_PER_KEY = constants.max_patterns_per_key),
-        format!("pub(crate) const BUILD_HASH: u32 = {build_hash:#010x};\n"),
     ]
     .map(|s| "#[allow(clippy::redundant_static_lifetimes)]\n".to_owned() + s.as_str())
     .join("\n")
 }
-
-fn compute_build_hash() -> u32 {
-    // Get the short hash of the latest Git commit. Use "unknown" if it fails
-    let commit_id = Command::new("git")
-        .args(["rev-parse", "--short", "HEAD"])
-        .output()
-        .ok()
-        .and_then(|output| {
-            if output.status.success() {
-                Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
-            } else {
-                None
-            }
-        })
-        .unwrap_or_else(|| "unknown".to_string());
-
-    // Get and format current local time
-    let now = chrono::Local::now();
-
-    // Combine data and compute CRC32
-    let combined = format!("{commit_id}_{now}");
-    let mut hasher = crc32fast::Hasher::new();
-    hasher.update(combined.as_bytes());
-    hasher.finalize()
-}
@@ -7,6 +7,8 @@ use super::device_info::DeviceConfigrmationService;
 #[cfg(feature = "host")]
 use super::host_service::HostService;
 use crate::channel::KEYBOARD_REPORT_CHANNEL;
+#[cfg(feature = "gamepad")]
+use crate::descriptor::GamepadReport;
 use crate::descriptor::{CompositeReport, CompositeReportType, KeyboardReport};
 use crate::hid::{HidError, HidWriterTrait, Report, RunnableHidWriter};
 
@@ -17,9 +19,9 @@ pub(crate) const CCCD_TABLE_SIZE: usize = _CCCD_TABLE_SIZE;
 // NOTE: ideally we would conditionally add the `via_service` member, based on the
 // `vial` feature flag. But when doing that, rust still compiles the member as if
 // the flag was on, for some reason. I suspect it might have something to do with
-// the `gatt_server` macro, but I'm not sure. So we need 2 versions of the Server
-// struct, one with vial support, and one without.
-#[cfg(feature = "host")]
+// the `gatt_server` macro, but I'm not sure. So we need 4 versions of the Server
+// struct, with or without vial support and gamepad support.
+#[cfg(all(feature = "host", not(feature = "gamepad")))]
 #[gatt_server]
 pub(crate) struct Server {
     pub(crate) battery_service: BatteryService,
@@ -29,7 +31,7 @@ pub(crate) struct Server {
     pub(crate) device_config_service: DeviceConfigrmationService,
 }
 
-#[cfg(not(feature = "host"))]
+#[cfg(all(not(feature = "host"), not(feature = "gamepad")))]
 #[gatt_server]
 pub(crate) struct Server {
     pub(crate) battery_service: BatteryService,
@@ -38,6 +40,27 @@ pub(crate) struct Server {
     pub(crate) device_config_service: DeviceConfigrmationService,
 }
 
+#[cfg(all(feature = "host", feature = "gamepad"))]
+#[gatt_server]
+pub(crate) struct Server {
+    pub(crate) battery_service: BatteryService,
+    pub(crate) hid_service: HidService,
+    pub(crate) host_service: HostService,
+    pub(crate) composite_service: CompositeService,
+    pub(crate) gamepad_service: GamepadService,
+    pub(crate) device_config_service: DeviceConfigrmationService,
+}
+
+#[cfg(all(not(feature = "host"), feature = "gamepad"))]
+#[gatt_server]
+pub(crate) struct Server {
+    pub(crate) battery_service: BatteryService,
+    pub(crate) hid_service: HidService,
+    pub(crate) composite_service: CompositeService,
+    pub(crate) gamepad_service: GamepadService,
+    pub(crate) device_config_service: DeviceConfigrmationService,
+}
+
 #[gatt_service(uuid = service::HUMAN_INTERFACE_DEVICE)]
 pub(crate) struct HidService {
     #[characteristic(uuid = "2a4a", read, value = [0x01, 0x01, 0x00, 0x03])]
@@ -77,11 +100,29 @@ pub(crate) struct CompositeService {
     pub(crate) system_report: [u8; 1],
 }
 
+#[cfg(feature = "gamepad")]
+#[gatt_service(uuid = service::HUMAN_INTERFACE_DEVICE)]
+pub(crate) struct GamepadService {
+    #[characteristic(uuid = "2a4a", read, value = [0x01, 0x01, 0x00, 0x03])]
+    pub(crate) hid_info: [u8; 4],
+    #[characteristic(uuid = "2a4b", read, value = GamepadReport::DESCRIPTOR)]
+    pub(crate) report_map: [u8; 76],
+    #[characteristic(uuid = "2a4c", write_without_response)]
+    pub(crate) hid_control_point: u8,
+    #[characteristic(uuid = "2a4e", read, write_without_response, value = 1)]
+    pub(crate) protocol_mode: u8,
+    #[descriptor(uuid = "2908", read, value = [0u8, 1u8])]
+    #[characteristic(uuid = "2a4d", read, notify)]
+    pub(crate) gamepad_report: [u8; 11],
+}
+
 pub(crate) struct BleHidServer<'stack, 'server, 'conn, P: PacketPool> {
     pub(crate) input_keyboard: Characteristic<[u8; 8]>,
     pub(crate) mouse_report: Characteristic<[u8; 5]>,
     pub(crate) media_report: Characteristic<[u8; 2]>,
     pub(crate) system_report: Characteristic<[u8; 1]>,
+    #[cfg(feature = "gamepad")]
+    pub(crate) gamepad_report: Characteristic<[u8; 11]>,
     pub(crate) conn: &'conn GattConnection<'stack, 'server, P>,
 }
 
@@ -92,6 +133,8 @@ impl<'stack, 'server, 'conn, P: PacketPool> BleHidServer<'stack, 'server, 'conn,
             mouse_report: server.composite_service.mouse_report,
             media_report: server.composite_service.media_report,
             system_report: server.composite_service.system_report,
+            #[cfg(feature = "gamepad")]
+            gamepad_report: server.gamepad_service.gamepad_report,
             conn,
         }
     }
@@ -138,6 +181,16 @@ impl<P: PacketPool> HidWriterTrait for BleHidServer<'_, '_, '_, P> {
                 })?;
                 Ok(n)
             }
+            #[cfg(feature = "gamepad")]
+            Report::GamepadReport(gamepad_report) => {
+                let mut buf = [0u8; GamepadReport::SIZE];
+                let n = serialize(&mut buf, &gamepad_report).map_err(|_| HidError::ReportSerializeError)?;
+                self.gamepad_report.notify(self.conn, &buf).await.map_err(|e| {
+                    error!("Failed to notify gamepad report: {:?}", e);
+                    HidError::BleError
+                })?;
+                Ok(n)
+            }
         }
     }
 }
@@ -1,5 +1,7 @@
 use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
+#[cfg(all(feature = "gamepad", not(feature = "_no_usb")))]
+use crate::descriptor::GamepadReport;
 use bt_hci::cmd::le::{LeReadLocalSupportedFeatures, LeSetPhy};
 use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
 use embassy_futures::join::join;
@@ -32,9 +34,9 @@ use {
 };
 #[cfg(feature = "storage")]
 use {
+    crate::state::CONNECTION_TYPE,
+    crate::storage::backend::StorageBackend,
     crate::storage::{Storage, StorageData, StorageKeys},
-    crate::{read_storage, state::CONNECTION_TYPE},
-    embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash,
 };
 
 use crate::ble::battery_service::BleBatteryServer;
@@ -108,7 +110,7 @@ pub(crate) async fn run_ble<
     'a,
     'b,
     C: Controller + ControllerCmdAsync<LeSetPhy> + ControllerCmdSync<LeReadLocalSupportedFeatures>,
-    #[cfg(feature = "storage")] F: AsyncNorFlash,
+    #[cfg(feature = "storage")] B: StorageBackend,
     #[cfg(not(feature = "_no_usb"))] D: Driver<'static>,
     #[cfg(any(feature = "storage", feature = "host"))] const ROW: usize,
     #[cfg(any(feature = "storage", feature = "host"))] const COL: usize,
@@ -118,7 +120,7 @@ pub(crate) async fn run_ble<
     #[cfg(feature = "host")] keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     #[cfg(not(feature = "_no_usb"))] usb_driver: D,
     stack: &'b Stack<'b, C, DefaultPacketPool>,
-    #[cfg(feature = "storage")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    #[cfg(feature = "storage")] storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
     #[cfg_attr(not(feature = "_nrf_ble"), allow(unused_mut))] mut rmk_config: RmkConfig<'static>,
 ) {
     #[cfg(feature = "_nrf_ble")]
@@ -136,6 +138,9 @@ pub(crate) async fn run_ble<
         (usb_builder, keyboard_reader, keyboard_writer, other_writer)
     };
 
+    #[cfg(all(not(feature = "_no_usb"), feature = "gamepad"))]
+    let mut gamepad_writer = add_usb_writer!(&mut _usb_builder, GamepadReport, { GamepadReport::SIZE });
+
     #[cfg(all(not(feature = "_no_usb"), feature = "host"))]
     let mut host_reader_writer = add_usb_reader_writer!(&mut _usb_builder, ViaReport, 32, 32);
 
@@ -149,9 +154,8 @@ pub(crate) async fn run_ble<
     // Load current connection type
     #[cfg(feature = "storage")]
     {
-        let mut buf: [u8; 16] = [0; 16];
         if let Ok(Some(StorageData::ConnectionType(conn_type))) =
-            read_storage!(storage, &(StorageKeys::ConnectionType as u32), buf)
+            storage.fetch_item(StorageKeys::ConnectionType as u32).await
         {
             CONNECTION_TYPE.store(conn_type, Ordering::SeqCst);
         } else {
@@ -264,7 +268,11 @@ pub(crate) async fn run_ble<
                             USB_ENABLED.wait(),
                             adv_fut,
                             #[cfg(feature = "storage")]
-                            run_dummy_keyboard(storage),
+                            run_dummy_keyboard(
+                                #[cfg(feature = "host")]
+                                keymap,
+                                storage,
+                            ),
                             #[cfg(not(feature = "storage"))]
                             run_dummy_keyboard(),
                             profile_manager.update_profile(),
@@ -291,7 +299,12 @@ pub(crate) async fn run_ble<
                                     rmk_config.vial_config,
                                     USB_SUSPENDED.wait(),
                                     UsbLedReader::new(&mut keyboard_reader),
-                                    UsbKeyboardWriter::new(&mut keyboard_writer, &mut other_writer),
+                                    UsbKeyboardWriter::new(
+                                        &mut keyboard_writer,
+                                        &mut other_writer,
+                                        #[cfg(feature = "gamepad")]
+                                        &mut gamepad_writer,
+                                    ),
                                 );
                                 select(usb_fut, profile_manager.update_profile()).await;
                             }
@@ -352,7 +365,12 @@ pub(crate) async fn run_ble<
                             rmk_config.vial_config,
                             core::future::pending::<()>(), // Run forever until BLE connected
                             UsbLedReader::new(&mut keyboard_reader),
-                            UsbKeyboardWriter::new(&mut keyboard_writer, &mut other_writer),
+                            UsbKeyboardWriter::new(
+                                &mut keyboard_writer,
+                                &mut other_writer,
+                                #[cfg(feature = "gamepad")]
+                                &mut gamepad_writer,
+                            ),
                         );
                         match select3(adv_fut, usb_fut, profile_manager.update_profile()).await {
                             Either3::First(Ok(conn)) => {
@@ -777,17 +795,21 @@ async fn advertise<'a, 'b, C: Controller>(
 // Dummy keyboard service is used to monitoring keys when there's no actual connection.
 // It's useful for functions like switching active profiles when there's no connection.
 pub(crate) async fn run_dummy_keyboard<
-    #[cfg(feature = "storage")] F: AsyncNorFlash,
+    #[cfg(feature = "storage")] B: StorageBackend,
     #[cfg(feature = "storage")] const ROW: usize,
     #[cfg(feature = "storage")] const COL: usize,
     #[cfg(feature = "storage")] const NUM_LAYER: usize,
     #[cfg(feature = "storage")] const NUM_ENCODER: usize,
 >(
-    #[cfg(feature = "storage")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    #[cfg(all(feature = "storage", feature = "host"))] keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    #[cfg(feature = "storage")] storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
 ) {
     CONNECTION_STATE.store(ConnectionState::Disconnected.into(), Ordering::Release);
     #[cfg(feature = "storage")]
-    let storage_fut = storage.run();
+    let storage_fut = storage.run(
+        #[cfg(feature = "host")]
+        Some(keymap),
+    );
     let mut dummy_writer = DummyWriter {};
     #[cfg(feature = "storage")]
     select(storage_fut, dummy_writer.run_writer()).await;
@@ -852,7 +874,7 @@ async fn run_ble_keyboard<
     'c,
     'd,
     C: Controller + ControllerCmdAsync<LeSetPhy> + ControllerCmdSync<LeReadLocalSupportedFeatures>,
-    #[cfg(feature = "storage")] F: AsyncNorFlash,
+    #[cfg(feature = "storage")] B: StorageBackend,
     #[cfg(any(feature = "storage", feature = "host"))] const ROW: usize,
     #[cfg(any(feature = "storage", feature = "host"))] const COL: usize,
     #[cfg(any(feature = "storage", feature = "host"))] const NUM_LAYER: usize,
@@ -863,7 +885,7 @@ async fn run_ble_keyboard<
     stack: &Stack<'_, C, DefaultPacketPool>,
     #[cfg(feature = "host")] keymap: &'c RefCell<KeyMap<'c, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     #[cfg(feature = "host")] rmk_config: &'d mut RmkConfig<'static>,
-    #[cfg(feature = "storage")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    #[cfg(feature = "storage")] storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
 ) {
     let ble_hid_server = BleHidServer::new(server, conn);
     #[cfg(feature = "host")]
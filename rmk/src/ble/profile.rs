@@ -203,16 +203,15 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
     /// Load stored bonding information
     #[cfg(feature = "storage")]
     pub async fn load_bonded_devices<
-        F: embedded_storage_async::nor_flash::NorFlash,
+        B: crate::storage::backend::StorageBackend,
         const ROW: usize,
         const COL: usize,
         const NUM_LAYER: usize,
         const NUM_ENCODER: usize,
     >(
         &mut self,
-        storage: &mut crate::storage::Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+        storage: &mut crate::storage::Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
     ) {
-        use crate::read_storage;
         use crate::storage::{StorageData, StorageKeys};
 
         self.bonded_devices.clear();
@@ -226,11 +225,9 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
         }
         debug!("Loaded {} bond info", self.bonded_devices.len());
 
-        let mut buf: [u8; 128] = [0; 128];
-
         // Load current active profile, save to `ACTIVE_PROFILE`
         if let Ok(Some(StorageData::ActiveBleProfile(profile))) =
-            read_storage!(storage, &(StorageKeys::ActiveBleProfile as u32), buf)
+            storage.fetch_item(StorageKeys::ActiveBleProfile as u32).await
         {
             debug!("Loaded active profile: {}", profile);
             ACTIVE_PROFILE.store(profile, Ordering::SeqCst);
@@ -0,0 +1,137 @@
+//! Bootmagic, the keys held at boot to recover the keyboard without reflashing.
+//!
+//! The matrix is scanned for a short time before the storage is initialized. Holding the bootloader keys jumps to
+//! the bootloader, and holding the clear storage keys erases the storage, so that a broken keymap saved in the
+//! storage is replaced by the default keymap.
+
+use embassy_time::{Duration, Instant, with_deadline};
+
+use crate::boot;
+use crate::config::BootMagicConfig;
+use crate::event::{Event, KeyboardEventPos};
+use crate::input_device::InputDevice;
+
+/// Time of collecting the held keys, which covers the debounce time
+const BOOTMAGIC_SCAN_TIME: Duration = Duration::from_millis(100);
+
+/// Max number of the tracked held keys
+const BOOTMAGIC_MAX_HELD_KEYS: usize = 8;
+
+/// Check the keys held at boot, before the storage is initialized.
+///
+/// It jumps to the bootloader if the bootloader keys are held, and returns `true` if the clear storage keys are
+/// held, then `StorageConfig::clear_storage` should be set. If both combinations are held, e.g. the same key with
+/// and without a modifier, the longer one is used.
+///
+/// The held keys are ignored by the keyboard until they're released.
+///
+/// # Example
+/// ```rust
+/// let bootmagic_config = BootMagicConfig {
+///     bootloader_keys: &[(0, 0)],
+///     clear_storage_keys: &[(0, 0), (3, 0)],
+/// };
+/// storage_config.clear_storage |= check_bootmagic(&mut matrix, &bootmagic_config).await;
+/// ```
+pub async fn check_bootmagic<D: InputDevice>(device: &mut D, config: &BootMagicConfig<'_>) -> bool {
+    if config.bootloader_keys.is_empty() && config.clear_storage_keys.is_empty() {
+        return false;
+    }
+
+    let mut held: heapless::Vec<(u8, u8), BOOTMAGIC_MAX_HELD_KEYS> = heapless::Vec::new();
+    let deadline = Instant::now() + BOOTMAGIC_SCAN_TIME;
+    while let Ok(event) = with_deadline(deadline, device.read_event()).await {
+        if let Event::Key(event) = event
+            && let KeyboardEventPos::Key(pos) = event.pos
+        {
+            let key = (pos.row, pos.col);
+            if !event.pressed {
+                held.retain(|k| *k != key);
+            } else if !held.contains(&key) {
+                held.push(key).ok();
+            }
+        }
+    }
+
+    let is_held = |keys: &[(u8, u8)]| !keys.is_empty() && keys.iter().all(|k| held.contains(k));
+    let clear_storage = is_held(config.clear_storage_keys);
+    if is_held(config.bootloader_keys)
+        && !(clear_storage && config.clear_storage_keys.len() > config.bootloader_keys.len())
+    {
+        info!("Bootmagic: jump to bootloader");
+        boot::jump_to_bootloader();
+        return false;
+    }
+    if clear_storage {
+        info!("Bootmagic: clear storage");
+    }
+    clear_storage
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::event::KeyboardEvent;
+
+    /// Device which reports the events once, then nothing
+    struct TestDevice(std::vec::Vec<Event>);
+
+    impl InputDevice for TestDevice {
+        async fn read_event(&mut self) -> Event {
+            match self.0.pop() {
+                Some(event) => event,
+                None => core::future::pending().await,
+            }
+        }
+    }
+
+    fn device(events: &[(u8, u8, bool)]) -> TestDevice {
+        TestDevice(
+            events
+                .iter()
+                .rev()
+                .map(|(row, col, pressed)| Event::Key(KeyboardEvent::key(*row, *col, *pressed)))
+                .collect(),
+        )
+    }
+
+    const CONFIG: BootMagicConfig = BootMagicConfig {
+        bootloader_keys: &[(0, 0)],
+        clear_storage_keys: &[(0, 0), (3, 0)],
+    };
+
+    #[test]
+    fn test_bootmagic_clear_storage() {
+        assert!(block_on(check_bootmagic(
+            &mut device(&[(3, 0, true), (0, 0, true)]),
+            &CONFIG
+        )));
+        // Released before the end of the check
+        assert!(!block_on(check_bootmagic(
+            &mut device(&[(3, 0, true), (0, 0, true), (3, 0, false)]),
+            &CONFIG
+        )));
+        assert!(!block_on(check_bootmagic(&mut device(&[(3, 0, true)]), &CONFIG)));
+        assert!(!block_on(check_bootmagic(
+            &mut device(&[(3, 0, true), (0, 0, true)]),
+            &BootMagicConfig::default()
+        )));
+    }
+
+    #[test]
+    fn test_bootmagic_bootloader() {
+        // The bootloader can't be entered in tests, the storage isn't cleared
+        assert!(!block_on(check_bootmagic(&mut device(&[(0, 0, true)]), &CONFIG)));
+        let config = BootMagicConfig {
+            bootloader_keys: &[(0, 0), (3, 0)],
+            clear_storage_keys: &[(0, 0)],
+        };
+        assert!(!block_on(check_bootmagic(
+            &mut device(&[(3, 0, true), (0, 0, true)]),
+            &config
+        )));
+        assert!(block_on(check_bootmagic(&mut device(&[(0, 0, true)]), &config)));
+    }
+}
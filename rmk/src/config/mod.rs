@@ -51,6 +51,7 @@ pub struct BehaviorConfig {
     pub morse: MorsesConfig,
     pub keyboard_macros: KeyboardMacrosConfig,
     pub mouse_key: MouseKeyConfig,
+    pub caps_word: CapsWordConfig,
 }
 
 /// Configurations for morse behavior
@@ -134,6 +135,21 @@ impl Default for OneShotConfig {
     }
 }
 
+/// Config for Caps Word
+#[derive(Clone, Copy, Debug)]
+pub struct CapsWordConfig {
+    /// Caps Word is deactivated when no key is pressed for this time
+    pub timeout: Duration,
+}
+
+impl Default for CapsWordConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
 /// Config for combo behavior
 #[derive(Clone, Debug)]
 pub struct CombosConfig {
@@ -172,6 +188,8 @@ pub struct StorageConfig {
     pub num_sectors: u8,
     pub clear_storage: bool,
     pub clear_layout: bool,
+    /// Runtime states which are saved to the storage and restored after reboot
+    pub persisted_state: PersistedStateConfig,
 }
 
 impl Default for StorageConfig {
@@ -181,10 +199,27 @@ impl Default for StorageConfig {
             num_sectors: 2,
             clear_storage: false,
             clear_layout: false,
+            persisted_state: PersistedStateConfig::default(),
         }
     }
 }
 
+/// Runtime states which are saved to the storage, all of them are lost after reboot by default.
+///
+/// The states are saved after the storage is idle for a while, or when [`crate::storage::flush`] is called.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PersistedStateConfig {
+    /// The layers toggled by `TG`, the default layer is always saved
+    pub layers: bool,
+    /// Whether combos are on, which is changed by `ComboOn`, `ComboOff` and `ComboToggle`
+    pub combo: bool,
+    /// The mouse key speed preset selected by `MouseAccel0`-`MouseAccel2`.
+    ///
+    /// When it's persisted, an acceleration key selects its preset until it's pressed again, instead of only while
+    /// it's held.
+    pub mouse_speed: bool,
+}
+
 /// Config for [vial](https://get.vial.today/).
 ///
 /// You can generate automatically using [`build.rs`](https://github.com/HaoboGu/rmk/blob/main/examples/use_rust/stm32h7/build.rs).
@@ -205,6 +240,18 @@ impl<'a> VialConfig<'a> {
     }
 }
 
+/// Keys held at boot, see [`crate::bootmagic::check_bootmagic`].
+///
+/// Each field is a combination of (row, col) positions reported by the matrix, all of them must be held. An empty
+/// combination is disabled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BootMagicConfig<'a> {
+    /// Keys to jump to the bootloader
+    pub bootloader_keys: &'a [(u8, u8)],
+    /// Keys to erase the storage, which restores the default keymap
+    pub clear_storage_keys: &'a [(u8, u8)],
+}
+
 /// Configurations for usb
 #[derive(Clone, Copy, Debug)]
 pub struct DeviceConfig<'a> {
@@ -233,7 +280,10 @@ impl Default for DeviceConfig<'_> {
 }
 
 /// Config for mouse key behavior
-#[derive(Clone, Copy, Debug)]
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, postcard::experimental::max_size::MaxSize,
+)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MouseKeyConfig {
     // Accelerated mode parameters
     /// Initial delay between pressing a movement key and first cursor movement (in milliseconds)
@@ -0,0 +1,140 @@
+use super::{DebounceState, DebouncerTrait, KeyThresholds, elapsed, impl_threshold_builder, now_ms};
+use crate::matrix::KeyState;
+
+/// Timer of a key
+#[derive(Clone, Copy, Debug)]
+enum KeyTimer {
+    /// The press has been reported, the key ignores any change until the press debounce time elapsed
+    Locked(u16),
+    /// The key is being released, waiting for the release debounce time
+    Releasing(u16),
+}
+
+/// Asymmetric per-key debouncer, same as QMK's `asym_eager_defer_pk`.
+///
+/// A press is reported immediately and the key ignores any change for the press debounce time.
+/// A release is reported after the pin state has been stable for the release debounce time.
+pub struct AsymEagerDeferPkDebouncer<const ROW: usize, const COL: usize> {
+    thresholds: KeyThresholds,
+    timers: [[Option<KeyTimer>; ROW]; COL],
+}
+
+impl<const ROW: usize, const COL: usize> Default for AsymEagerDeferPkDebouncer<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROW: usize, const COL: usize> AsymEagerDeferPkDebouncer<ROW, COL> {
+    /// Create a debouncer with the default debounce time
+    pub fn new() -> Self {
+        Self {
+            thresholds: KeyThresholds::default(),
+            timers: [[None; ROW]; COL],
+        }
+    }
+
+    fn detect(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+        now: u16,
+    ) -> DebounceState {
+        let threshold = self.thresholds.get(row_idx, col_idx);
+        let timer = &mut self.timers[col_idx][row_idx];
+
+        if let Some(KeyTimer::Locked(start)) = *timer {
+            if elapsed(start, now) < threshold.press {
+                return DebounceState::InProgress;
+            }
+            *timer = None;
+        }
+
+        if key_state.pressed == pin_state {
+            // Bounced back during releasing
+            return match timer.take() {
+                Some(_) => DebounceState::InProgress,
+                None => DebounceState::Ignored,
+            };
+        }
+
+        if pin_state {
+            // Press eagerly
+            *timer = Some(KeyTimer::Locked(now));
+            return DebounceState::Debounced;
+        }
+
+        // Release with deferring
+        match *timer {
+            Some(KeyTimer::Releasing(start)) if elapsed(start, now) >= threshold.release => {
+                *timer = None;
+                DebounceState::Debounced
+            }
+            Some(_) => DebounceState::InProgress,
+            None => {
+                *timer = Some(KeyTimer::Releasing(now));
+                DebounceState::InProgress
+            }
+        }
+    }
+}
+
+impl_threshold_builder!(AsymEagerDeferPkDebouncer);
+
+impl<const ROW: usize, const COL: usize> DebouncerTrait<ROW, COL> for AsymEagerDeferPkDebouncer<ROW, COL> {
+    fn detect_change_with_debounce(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        self.detect(row_idx, col_idx, pin_state, key_state, now_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::{DebounceCase, simulate};
+
+    #[test]
+    fn test_asym_eager_defer_pk() {
+        let cases: &[DebounceCase] = &[
+            // Clean press and release
+            (5, 5, &[(0, true), (10, false), (12, false), (15, false)], &[0, 15]),
+            // Bounces after the press are ignored
+            (5, 5, &[(0, true), (1, false), (2, true), (4, false), (6, true)], &[0]),
+            // Bounces during the release restart the timer
+            (
+                5,
+                5,
+                &[
+                    (0, true),
+                    (10, false),
+                    (12, true),
+                    (13, false),
+                    (17, false),
+                    (18, false),
+                ],
+                &[0, 18],
+            ),
+            // A short glitch in a held key is filtered out
+            (5, 5, &[(0, true), (10, false), (11, true), (20, true)], &[0]),
+            // Asymmetric thresholds
+            (
+                1,
+                20,
+                &[(0, true), (5, false), (20, false), (25, false), (26, true)],
+                &[0, 25, 26],
+            ),
+        ];
+        for (press, release, steps, expected) in cases {
+            let mut debouncer = AsymEagerDeferPkDebouncer::<1, 1>::new().with_threshold(*press, *release);
+            let changes = simulate(steps, |pin, key, now| debouncer.detect(0, 0, pin, key, now));
+            assert_eq!(changes.as_slice(), *expected, "steps: {:?}", steps);
+        }
+    }
+}
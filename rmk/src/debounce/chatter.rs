@@ -0,0 +1,233 @@
+//! Key chatter detection.
+//!
+//! A worn switch may bounce for longer than the debounce time, which shows up as a quick
+//! press-release-press burst of a key, e.g. a double letter.
+//! [`ChatterDetector`] wraps any debouncer, counts such bursts of every key and optionally suppresses them.
+//! The counters can be read over the host protocol, so a configurator can show which switch should be replaced.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::Vec;
+
+use super::{DebounceState, DebouncerTrait, elapsed, now_ms};
+use crate::RawMutex;
+#[cfg(feature = "controller")]
+use crate::channel::send_controller_event_new;
+#[cfg(feature = "controller")]
+use crate::event::ControllerEvent;
+use crate::matrix::KeyState;
+
+/// Max number of chattering keys whose counters are recorded
+pub const MAX_CHATTER_KEYS: usize = 16;
+
+/// Number of detected chatter bursts of a key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChatterCounter {
+    pub row: u8,
+    pub col: u8,
+    pub count: u16,
+}
+
+/// Counters of all chattering keys, only keys that have chattered are recorded
+static CHATTER_COUNTERS: Mutex<RawMutex, RefCell<Vec<ChatterCounter, MAX_CHATTER_KEYS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Get the chatter counters of all chattering keys
+pub fn chatter_counters() -> Vec<ChatterCounter, MAX_CHATTER_KEYS> {
+    CHATTER_COUNTERS.lock(|counters| counters.borrow().clone())
+}
+
+/// Reset the chatter counters
+pub fn clear_chatter_counters() {
+    CHATTER_COUNTERS.lock(|counters| counters.borrow_mut().clear());
+}
+
+/// Increase the chatter counter of the given key, returns the new count
+fn record_chatter(row: u8, col: u8) -> u16 {
+    CHATTER_COUNTERS.lock(|counters| {
+        let mut counters = counters.borrow_mut();
+        if let Some(counter) = counters.iter_mut().find(|c| c.row == row && c.col == col) {
+            counter.count = counter.count.saturating_add(1);
+            return counter.count;
+        }
+        if counters.push(ChatterCounter { row, col, count: 1 }).is_err() {
+            warn!(
+                "Too many chattering keys, the counter of ({}, {}) is not recorded",
+                row, col
+            );
+        }
+        1
+    })
+}
+
+/// Debouncer wrapper which detects key chatter.
+///
+/// A press which is reported within `window` ms after the last release of the same key is counted as chatter.
+/// If suppression is enabled, the press is ignored until the key is released.
+pub struct ChatterDetector<const ROW: usize, const COL: usize, D: DebouncerTrait<ROW, COL>> {
+    debouncer: D,
+    /// Max time in ms between a release and the next press which is counted as chatter
+    window: u16,
+    /// Ignore the chattering press
+    suppress: bool,
+    /// Offset of the matrix in the whole keyboard, used for the reported key position
+    row_offset: u8,
+    col_offset: u8,
+    /// Time when the release of the key was reported
+    released_at: [[Option<u16>; ROW]; COL],
+    /// The key is chattering, its press is ignored until the key is released
+    suppressed: [[bool; ROW]; COL],
+}
+
+impl<const ROW: usize, const COL: usize, D: DebouncerTrait<ROW, COL>> ChatterDetector<ROW, COL, D> {
+    /// Wrap the `debouncer`, a press within `window` ms after the last release of the key is counted as chatter
+    pub fn new(debouncer: D, window: u16) -> Self {
+        Self {
+            debouncer,
+            window,
+            suppress: false,
+            row_offset: 0,
+            col_offset: 0,
+            released_at: [[None; ROW]; COL],
+            suppressed: [[false; ROW]; COL],
+        }
+    }
+
+    /// Ignore the chattering presses
+    pub fn with_suppression(mut self, suppress: bool) -> Self {
+        self.suppress = suppress;
+        self
+    }
+
+    /// Set the offset of the matrix, for split keyboards
+    pub fn with_offset(mut self, row_offset: u8, col_offset: u8) -> Self {
+        self.row_offset = row_offset;
+        self.col_offset = col_offset;
+        self
+    }
+
+    fn detect(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+        now: u16,
+    ) -> DebounceState {
+        if self.suppressed[col_idx][row_idx] {
+            if !pin_state {
+                // The burst continues if the key is pressed again within the window
+                self.suppressed[col_idx][row_idx] = false;
+                self.released_at[col_idx][row_idx] = Some(now);
+            }
+            return DebounceState::Ignored;
+        }
+
+        let state = self
+            .debouncer
+            .detect_change_with_debounce(row_idx, col_idx, pin_state, key_state);
+        if !matches!(state, DebounceState::Debounced) {
+            return state;
+        }
+
+        if key_state.pressed {
+            // Releasing
+            self.released_at[col_idx][row_idx] = Some(now);
+            return state;
+        }
+
+        match self.released_at[col_idx][row_idx].take() {
+            Some(released_at) if elapsed(released_at, now) <= self.window => {
+                self.on_chatter(row_idx, col_idx);
+                if self.suppress {
+                    self.suppressed[col_idx][row_idx] = true;
+                    DebounceState::Ignored
+                } else {
+                    state
+                }
+            }
+            _ => state,
+        }
+    }
+
+    fn on_chatter(&mut self, row_idx: usize, col_idx: usize) {
+        let row = row_idx as u8 + self.row_offset;
+        let col = col_idx as u8 + self.col_offset;
+        let count = record_chatter(row, col);
+        warn!("Key ({}, {}) is chattering, count: {}", row, col, count);
+        #[cfg(feature = "controller")]
+        send_controller_event_new(ControllerEvent::KeyChatter(row, col, count));
+    }
+}
+
+impl<const ROW: usize, const COL: usize, D: DebouncerTrait<ROW, COL>> DebouncerTrait<ROW, COL>
+    for ChatterDetector<ROW, COL, D>
+{
+    fn detect_change_with_debounce(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        self.detect(row_idx, col_idx, pin_state, key_state, now_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::{NoDebounce, simulate};
+
+    fn count_of(row: u8, col: u8) -> u16 {
+        chatter_counters()
+            .iter()
+            .find(|c| c.row == row && c.col == col)
+            .map_or(0, |c| c.count)
+    }
+
+    #[test]
+    fn test_chatter_detection() {
+        // Each test uses its own key position, because the counters are shared
+        let mut detector = ChatterDetector::<2, 2, _>::new(NoDebounce, 30).with_offset(4, 8);
+        let steps = &[
+            // Normal typing
+            (0, true),
+            (50, false),
+            (100, true),
+            (150, false),
+            // Chatter burst
+            (160, true),
+            (170, false),
+            (180, true),
+            (220, false),
+        ];
+        let changes = simulate(steps, |pin, key, now| detector.detect(1, 0, pin, key, now));
+        assert_eq!(changes.as_slice(), &[0, 50, 100, 150, 160, 170, 180, 220]);
+        assert_eq!(count_of(5, 8), 2);
+    }
+
+    #[test]
+    fn test_chatter_suppression() {
+        let mut detector = ChatterDetector::<2, 2, _>::new(NoDebounce, 30)
+            .with_suppression(true)
+            .with_offset(6, 8);
+        let steps = &[
+            (0, true),
+            (50, false),
+            // Chattering press and its bounces are ignored until the burst ends
+            (60, true),
+            (65, false),
+            (70, true),
+            (80, false),
+            // A press after the window is reported
+            (200, true),
+            (250, false),
+        ];
+        let changes = simulate(steps, |pin, key, now| detector.detect(0, 1, pin, key, now));
+        assert_eq!(changes.as_slice(), &[0, 50, 200, 250]);
+        assert_eq!(count_of(6, 9), 2);
+    }
+}
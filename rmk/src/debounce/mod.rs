@@ -1,7 +1,13 @@
+use crate::DEBOUNCE_THRESHOLD;
 use crate::matrix::KeyState;
 
+pub mod asym_eager_defer_pk;
+pub mod chatter;
 pub mod default_debouncer;
 pub mod fast_debouncer;
+pub mod sym_defer_pk;
+pub mod sym_defer_pr;
+pub mod sym_eager_pk;
 
 pub trait DebouncerTrait<const ROW: usize, const COL: usize> {
     fn detect_change_with_debounce(
@@ -19,3 +25,158 @@ pub enum DebounceState {
     InProgress,
     Ignored,
 }
+
+/// Debounce time in ms, for the press and the release separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DebounceThreshold {
+    /// Debounce time of pressing a key
+    pub press: u16,
+    /// Debounce time of releasing a key
+    pub release: u16,
+}
+
+impl Default for DebounceThreshold {
+    fn default() -> Self {
+        Self::symmetric(DEBOUNCE_THRESHOLD)
+    }
+}
+
+impl DebounceThreshold {
+    pub const fn new(press: u16, release: u16) -> Self {
+        Self { press, release }
+    }
+
+    /// Same debounce time for press and release
+    pub const fn symmetric(threshold: u16) -> Self {
+        Self {
+            press: threshold,
+            release: threshold,
+        }
+    }
+
+    /// Get the debounce time of the key change, `pressing` is true if the key is being pressed
+    pub(crate) fn get(&self, pressing: bool) -> u16 {
+        if pressing { self.press } else { self.release }
+    }
+}
+
+/// Debounce time of a single key, which overrides the debouncer's default, e.g. for a worn switch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyDebounceOverride {
+    pub row: u8,
+    pub col: u8,
+    pub threshold: DebounceThreshold,
+}
+
+/// Debounce thresholds of all keys
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct KeyThresholds {
+    default: DebounceThreshold,
+    overrides: &'static [KeyDebounceOverride],
+}
+
+impl KeyThresholds {
+    /// Get the debounce threshold of the given key
+    pub(crate) fn get(&self, row_idx: usize, col_idx: usize) -> DebounceThreshold {
+        self.overrides
+            .iter()
+            .find(|o| o.row as usize == row_idx && o.col as usize == col_idx)
+            .map_or(self.default, |o| o.threshold)
+    }
+}
+
+/// Implement the builder methods of the debounce thresholds
+macro_rules! impl_threshold_builder {
+    ($debouncer:ident) => {
+        impl<const ROW: usize, const COL: usize> $debouncer<ROW, COL> {
+            /// Set the debounce time of press and release, in ms
+            pub fn with_threshold(mut self, press: u16, release: u16) -> Self {
+                self.thresholds.default = $crate::debounce::DebounceThreshold::new(press, release);
+                self
+            }
+
+            /// Set the debounce time of individual keys
+            pub fn with_key_overrides(mut self, overrides: &'static [$crate::debounce::KeyDebounceOverride]) -> Self {
+                self.thresholds.overrides = overrides;
+                self
+            }
+        }
+    };
+}
+pub(crate) use impl_threshold_builder;
+
+/// Elapsed time in ms since `start`, the timestamps are wrapped around u16
+fn elapsed(start: u16, now: u16) -> u16 {
+    now.wrapping_sub(start)
+}
+
+/// Current time in ms, wrapped around u16
+fn now_ms() -> u16 {
+    embassy_time::Instant::now().as_millis() as u16
+}
+
+/// A test case of debouncers: (press threshold, release threshold, pin states, expected key changes)
+#[cfg(test)]
+pub(crate) type DebounceCase = (u16, u16, &'static [(u16, bool)], &'static [u16]);
+
+/// Feed the pin states of a single key to a debouncer, returns the time of reported key changes.
+///
+/// Each step is (time in ms, pin state), the key state is toggled when the debouncer reports a change.
+#[cfg(test)]
+pub(crate) fn simulate(
+    steps: &[(u16, bool)],
+    mut detect: impl FnMut(bool, &KeyState, u16) -> DebounceState,
+) -> heapless::Vec<u16, 16> {
+    let mut key_state = KeyState::new();
+    let mut changes = heapless::Vec::new();
+    for &(time, pin_state) in steps {
+        if let DebounceState::Debounced = detect(pin_state, &key_state, time) {
+            key_state.toggle_pressed();
+            changes.push(time).unwrap();
+        }
+    }
+    changes
+}
+
+/// Debouncer which reports every change immediately, used by the tests of the matrices and the chatter detection
+#[cfg(test)]
+pub(crate) struct NoDebounce;
+
+#[cfg(test)]
+impl<const ROW: usize, const COL: usize> DebouncerTrait<ROW, COL> for NoDebounce {
+    fn detect_change_with_debounce(
+        &mut self,
+        _row_idx: usize,
+        _col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        if pin_state != key_state.pressed {
+            DebounceState::Debounced
+        } else {
+            DebounceState::Ignored
+        }
+    }
+}
+
+/// Read the next key event of a matrix, `None` if there's no event in 20ms
+#[cfg(test)]
+pub(crate) fn next_key<M: crate::input_device::InputDevice>(matrix: &mut M) -> Option<(u8, u8, bool)> {
+    use embassy_futures::select::{Either, select};
+
+    use crate::event::{Event, KeyPos, KeyboardEvent, KeyboardEventPos};
+
+    embassy_futures::block_on(async {
+        match select(matrix.read_event(), embassy_time::Timer::after_millis(20)).await {
+            Either::First(Event::Key(KeyboardEvent {
+                pos: KeyboardEventPos::Key(KeyPos { row, col }),
+                pressed,
+                ..
+            })) => Some((row, col, pressed)),
+            Either::First(_) => panic!("Unexpected event"),
+            Either::Second(_) => None,
+        }
+    })
+}
@@ -0,0 +1,131 @@
+use super::{DebounceState, DebouncerTrait, KeyThresholds, elapsed, impl_threshold_builder, now_ms};
+use crate::matrix::KeyState;
+
+/// Symmetric deferred per-key debouncer, same as QMK's `sym_defer_pk`.
+///
+/// A key change is reported after the pin state of the key has been stable for the debounce time.
+/// Any bounce restarts the timer of the key.
+pub struct SymDeferPkDebouncer<const ROW: usize, const COL: usize> {
+    thresholds: KeyThresholds,
+    /// Time when the pin state of the key became different from the key state
+    changed_at: [[Option<u16>; ROW]; COL],
+}
+
+impl<const ROW: usize, const COL: usize> Default for SymDeferPkDebouncer<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROW: usize, const COL: usize> SymDeferPkDebouncer<ROW, COL> {
+    /// Create a debouncer with the default debounce time
+    pub fn new() -> Self {
+        Self {
+            thresholds: KeyThresholds::default(),
+            changed_at: [[None; ROW]; COL],
+        }
+    }
+
+    fn detect(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+        now: u16,
+    ) -> DebounceState {
+        let changed_at = &mut self.changed_at[col_idx][row_idx];
+        if key_state.pressed == pin_state {
+            // Bounced back, restart on the next change
+            return match changed_at.take() {
+                Some(_) => DebounceState::InProgress,
+                None => DebounceState::Ignored,
+            };
+        }
+
+        let threshold = self.thresholds.get(row_idx, col_idx).get(pin_state);
+        match *changed_at {
+            Some(start) if elapsed(start, now) >= threshold => {
+                *changed_at = None;
+                DebounceState::Debounced
+            }
+            Some(_) => DebounceState::InProgress,
+            None => {
+                *changed_at = Some(now);
+                DebounceState::InProgress
+            }
+        }
+    }
+}
+
+impl_threshold_builder!(SymDeferPkDebouncer);
+
+impl<const ROW: usize, const COL: usize> DebouncerTrait<ROW, COL> for SymDeferPkDebouncer<ROW, COL> {
+    fn detect_change_with_debounce(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        self.detect(row_idx, col_idx, pin_state, key_state, now_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::{DebounceCase, DebounceThreshold, KeyDebounceOverride, simulate};
+
+    #[test]
+    fn test_sym_defer_pk() {
+        let cases: &[DebounceCase] = &[
+            // Clean press and release
+            (
+                5,
+                5,
+                &[(0, true), (3, true), (5, true), (10, false), (15, false)],
+                &[5, 15],
+            ),
+            // Bounces restart the timer
+            (
+                5,
+                5,
+                &[(0, true), (1, false), (2, true), (4, true), (6, true), (7, true)],
+                &[7],
+            ),
+            // A short glitch is filtered out
+            (5, 5, &[(0, true), (2, false), (10, false)], &[]),
+            // Asymmetric thresholds
+            (
+                2,
+                10,
+                &[(0, true), (2, true), (10, false), (15, false), (20, false)],
+                &[2, 20],
+            ),
+            // Timestamps wrap around
+            (5, 5, &[(u16::MAX - 1, true), (3, true)], &[3]),
+        ];
+        for (press, release, steps, expected) in cases {
+            let mut debouncer = SymDeferPkDebouncer::<1, 1>::new().with_threshold(*press, *release);
+            let changes = simulate(steps, |pin, key, now| debouncer.detect(0, 0, pin, key, now));
+            assert_eq!(changes.as_slice(), *expected, "steps: {:?}", steps);
+        }
+    }
+
+    #[test]
+    fn test_sym_defer_pk_override() {
+        let mut debouncer = SymDeferPkDebouncer::<1, 2>::new()
+            .with_threshold(5, 5)
+            .with_key_overrides(&[KeyDebounceOverride {
+                row: 0,
+                col: 1,
+                threshold: DebounceThreshold { press: 20, release: 20 },
+            }]);
+        let steps = &[(0, true), (5, true), (10, true), (20, true)];
+        let changes = simulate(steps, |pin, key, now| debouncer.detect(0, 0, pin, key, now));
+        assert_eq!(changes.as_slice(), &[5]);
+        let changes = simulate(steps, |pin, key, now| debouncer.detect(0, 1, pin, key, now));
+        assert_eq!(changes.as_slice(), &[20]);
+    }
+}
@@ -0,0 +1,143 @@
+use super::{DebounceState, DebouncerTrait, KeyThresholds, elapsed, impl_threshold_builder, now_ms};
+use crate::matrix::KeyState;
+
+/// Symmetric deferred per-row debouncer, same as QMK's `sym_defer_pr`.
+///
+/// Any pin change in a row restarts the timer of the whole row.
+/// Key changes in the row are reported after the row has been stable for the debounce time.
+/// It uses less memory than the per-key debouncers.
+pub struct SymDeferPrDebouncer<const ROW: usize, const COL: usize> {
+    thresholds: KeyThresholds,
+    /// Last pin state of each key
+    pin_states: [[bool; ROW]; COL],
+    /// Time of the last pin change in each row
+    row_changed_at: [u16; ROW],
+}
+
+impl<const ROW: usize, const COL: usize> Default for SymDeferPrDebouncer<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROW: usize, const COL: usize> SymDeferPrDebouncer<ROW, COL> {
+    /// Create a debouncer with the default debounce time
+    pub fn new() -> Self {
+        Self {
+            thresholds: KeyThresholds::default(),
+            pin_states: [[false; ROW]; COL],
+            row_changed_at: [0; ROW],
+        }
+    }
+
+    fn detect(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+        now: u16,
+    ) -> DebounceState {
+        if self.pin_states[col_idx][row_idx] != pin_state {
+            self.pin_states[col_idx][row_idx] = pin_state;
+            self.row_changed_at[row_idx] = now;
+        }
+
+        if key_state.pressed == pin_state {
+            return DebounceState::Ignored;
+        }
+
+        // The key differs from the pin state only after a pin change, so the row timer is always valid here
+        let threshold = self.thresholds.get(row_idx, col_idx).get(pin_state);
+        if elapsed(self.row_changed_at[row_idx], now) >= threshold {
+            DebounceState::Debounced
+        } else {
+            DebounceState::InProgress
+        }
+    }
+}
+
+impl_threshold_builder!(SymDeferPrDebouncer);
+
+impl<const ROW: usize, const COL: usize> DebouncerTrait<ROW, COL> for SymDeferPrDebouncer<ROW, COL> {
+    fn detect_change_with_debounce(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        self.detect(row_idx, col_idx, pin_state, key_state, now_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::{DebounceCase, simulate};
+
+    #[test]
+    fn test_sym_defer_pr() {
+        let cases: &[DebounceCase] = &[
+            // Clean press and release
+            (
+                5,
+                5,
+                &[(0, true), (3, true), (5, true), (10, false), (15, false)],
+                &[5, 15],
+            ),
+            // Bounces restart the timer
+            (5, 5, &[(0, true), (1, false), (2, true), (6, true), (7, true)], &[7]),
+            // A short glitch is filtered out
+            (5, 5, &[(0, true), (2, false), (10, false)], &[]),
+            // Asymmetric thresholds
+            (
+                2,
+                10,
+                &[(0, true), (2, true), (10, false), (15, false), (20, false)],
+                &[2, 20],
+            ),
+        ];
+        for (press, release, steps, expected) in cases {
+            let mut debouncer = SymDeferPrDebouncer::<1, 1>::new().with_threshold(*press, *release);
+            let changes = simulate(steps, |pin, key, now| debouncer.detect(0, 0, pin, key, now));
+            assert_eq!(changes.as_slice(), *expected, "steps: {:?}", steps);
+        }
+    }
+
+    #[test]
+    fn test_sym_defer_pr_row() {
+        // A change of another key in the same row restarts the timer
+        let mut debouncer = SymDeferPrDebouncer::<2, 2>::new().with_threshold(5, 5);
+        let key = KeyState::new();
+        assert!(matches!(
+            debouncer.detect(0, 0, true, &key, 0),
+            DebounceState::InProgress
+        ));
+        assert!(matches!(
+            debouncer.detect(0, 1, true, &key, 3),
+            DebounceState::InProgress
+        ));
+        assert!(matches!(
+            debouncer.detect(0, 0, true, &key, 5),
+            DebounceState::InProgress
+        ));
+        // Keys in other rows are not affected
+        assert!(matches!(
+            debouncer.detect(1, 0, true, &key, 5),
+            DebounceState::InProgress
+        ));
+        assert!(matches!(
+            debouncer.detect(1, 0, true, &key, 10),
+            DebounceState::Debounced
+        ));
+        assert!(matches!(
+            debouncer.detect(0, 0, true, &key, 8),
+            DebounceState::Debounced
+        ));
+        assert!(matches!(
+            debouncer.detect(0, 1, true, &key, 8),
+            DebounceState::Debounced
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+use super::{DebounceState, DebouncerTrait, KeyThresholds, elapsed, impl_threshold_builder, now_ms};
+use crate::matrix::KeyState;
+
+/// Symmetric eager per-key debouncer, same as QMK's `sym_eager_pk`.
+///
+/// A key change is reported immediately, then the key ignores any change for the debounce time.
+pub struct SymEagerPkDebouncer<const ROW: usize, const COL: usize> {
+    thresholds: KeyThresholds,
+    /// Time of the last reported change, the key is locked until the debounce time elapsed
+    locked_at: [[Option<u16>; ROW]; COL],
+}
+
+impl<const ROW: usize, const COL: usize> Default for SymEagerPkDebouncer<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROW: usize, const COL: usize> SymEagerPkDebouncer<ROW, COL> {
+    /// Create a debouncer with the default debounce time
+    pub fn new() -> Self {
+        Self {
+            thresholds: KeyThresholds::default(),
+            locked_at: [[None; ROW]; COL],
+        }
+    }
+
+    fn detect(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+        now: u16,
+    ) -> DebounceState {
+        let locked_at = &mut self.locked_at[col_idx][row_idx];
+        if let Some(start) = *locked_at {
+            // The lock time is decided by the last reported change, which is the current key state
+            let threshold = self.thresholds.get(row_idx, col_idx).get(key_state.pressed);
+            if elapsed(start, now) < threshold {
+                return DebounceState::InProgress;
+            }
+            *locked_at = None;
+        }
+
+        if key_state.pressed != pin_state {
+            *locked_at = Some(now);
+            DebounceState::Debounced
+        } else {
+            DebounceState::Ignored
+        }
+    }
+}
+
+impl_threshold_builder!(SymEagerPkDebouncer);
+
+impl<const ROW: usize, const COL: usize> DebouncerTrait<ROW, COL> for SymEagerPkDebouncer<ROW, COL> {
+    fn detect_change_with_debounce(
+        &mut self,
+        row_idx: usize,
+        col_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        self.detect(row_idx, col_idx, pin_state, key_state, now_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::{DebounceCase, simulate};
+
+    #[test]
+    fn test_sym_eager_pk() {
+        let cases: &[DebounceCase] = &[
+            // Clean press and release
+            (5, 5, &[(0, true), (3, true), (10, false), (12, false)], &[0, 10]),
+            // Bounces after the press are ignored
+            (
+                5,
+                5,
+                &[(0, true), (1, false), (2, true), (3, false), (4, true), (6, true)],
+                &[0],
+            ),
+            // A release right after the lock time
+            (5, 5, &[(0, true), (1, false), (5, false)], &[0, 5]),
+            // A glitch is reported, it's the tradeoff of eager debouncing
+            (5, 5, &[(0, true), (5, false), (6, false)], &[0, 5]),
+            // Asymmetric thresholds
+            (
+                2,
+                10,
+                &[(0, true), (2, false), (3, true), (8, true), (12, true), (13, false)],
+                &[0, 2, 12],
+            ),
+        ];
+        for (press, release, steps, expected) in cases {
+            let mut debouncer = SymEagerPkDebouncer::<1, 1>::new().with_threshold(*press, *release);
+            let changes = simulate(steps, |pin, key, now| debouncer.detect(0, 0, pin, key, now));
+            assert_eq!(changes.as_slice(), *expected, "steps: {:?}", steps);
+        }
+    }
+}
@@ -124,3 +124,102 @@ pub struct CompositeReport {
     pub(crate) media_usage_id: u16,
     pub(crate) system_usage_id: u8,
 }
+
+/// Gamepad report with 32 buttons, a hat switch and 6 axes.
+///
+/// The descriptor is written by hand, because the hat switch requires a 4-bit field with null state,
+/// which is not supported by `#[gen_hid_descriptor]`.
+#[cfg(feature = "gamepad")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GamepadReport {
+    /// Bitmask of the buttons
+    pub buttons: u32,
+    /// Hat switch, 0 ~ 7 from up clockwise, [`GamepadReport::HAT_CENTERED`] if released
+    pub hat: u8,
+    pub x: i8,
+    pub y: i8,
+    pub z: i8,
+    pub rx: i8,
+    pub ry: i8,
+    pub rz: i8,
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadReport {
+    /// Hat switch value when no direction is pressed
+    pub const HAT_CENTERED: u8 = 0x08;
+    /// Size of the serialized report
+    pub const SIZE: usize = 11;
+
+    pub(crate) const DESCRIPTOR: [u8; 76] = [
+        0x05, 0x01, // Usage Page (Generic Desktop)
+        0x09, 0x05, // Usage (Game Pad)
+        0xA1, 0x01, // Collection (Application)
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x01, //   Usage Minimum (Button 1)
+        0x29, 0x20, //   Usage Maximum (Button 32)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x20, //   Report Count (32)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+        0x05, 0x01, //   Usage Page (Generic Desktop)
+        0x09, 0x39, //   Usage (Hat Switch)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x07, //   Logical Maximum (7)
+        0x35, 0x00, //   Physical Minimum (0)
+        0x46, 0x3B, 0x01, //   Physical Maximum (315)
+        0x65, 0x14, //   Unit (Degrees)
+        0x75, 0x04, //   Report Size (4)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x42, //   Input (Data, Variable, Absolute, Null State)
+        0x65, 0x00, //   Unit (None)
+        0x45, 0x00, //   Physical Maximum (0)
+        0x75, 0x04, //   Report Size (4)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x03, //   Input (Constant, Variable, Absolute), padding
+        0x09, 0x30, //   Usage (X)
+        0x09, 0x31, //   Usage (Y)
+        0x09, 0x32, //   Usage (Z)
+        0x09, 0x33, //   Usage (Rx)
+        0x09, 0x34, //   Usage (Ry)
+        0x09, 0x35, //   Usage (Rz)
+        0x15, 0x81, //   Logical Minimum (-127)
+        0x25, 0x7F, //   Logical Maximum (127)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x06, //   Report Count (6)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+        0xC0, // End Collection
+    ];
+
+    pub(crate) const fn new() -> Self {
+        Self {
+            buttons: 0,
+            hat: Self::HAT_CENTERED,
+            x: 0,
+            y: 0,
+            z: 0,
+            rx: 0,
+            ry: 0,
+            rz: 0,
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl Default for GamepadReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl SerializedDescriptor for GamepadReport {
+    fn desc() -> &'static [u8] {
+        &Self::DESCRIPTOR
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl AsInputReport for GamepadReport {}
@@ -96,7 +96,10 @@ impl<
                             let key_state = self.key_states[row_idx][col_idx];
 
                             self.scan_pos = (row_idx, col_idx);
-                            return Event::Key(KeyboardEvent::key(row_idx as u8, col_idx as u8, key_state.pressed));
+                            return Event::Key(
+                                KeyboardEvent::key(row_idx as u8, col_idx as u8, key_state.pressed)
+                                    .with_timestamp(Instant::now()),
+                            );
                         }
 
                         // If there's key still pressed, always refresh the self.scan_start
@@ -109,6 +112,8 @@ impl<
             }
 
             self.scan_pos = (0, 0);
+            #[cfg(feature = "instrumentation")]
+            crate::instrumentation::record_scan();
 
             Timer::after_micros(100).await;
         }
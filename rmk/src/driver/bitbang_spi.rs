@@ -5,6 +5,7 @@
 
 use embedded_hal::digital::OutputPin;
 use embedded_hal::spi::ErrorType;
+pub use embedded_hal::spi::Phase;
 use embedded_hal_async::spi::SpiBus;
 
 use crate::driver::flex_pin::FlexPin;
@@ -38,6 +39,8 @@ where
 {
     sck: SCK,
     sdio: SDIO,
+    /// Clock edge on which the data line is sampled, the clock idles high
+    phase: Phase,
 }
 
 impl<SCK, SDIO> BitBangSpiBus<SCK, SDIO>
@@ -48,7 +51,21 @@ where
     /// Create a new bit-banging SPI bus
     pub fn new(mut sck: SCK, sdio: SDIO) -> Self {
         let _ = sck.set_high();
-        Self { sck, sdio }
+        Self {
+            sck,
+            sdio,
+            phase: Phase::CaptureOnSecondTransition,
+        }
+    }
+
+    /// Set the clock edge on which the data line is sampled.
+    ///
+    /// The default is sampling on the rising edge (SPI mode 3).
+    /// `Phase::CaptureOnFirstTransition` samples on the falling edge (SPI mode 2),
+    /// which is needed by devices that shift out the next bit on the rising edge, e.g. 74HC165.
+    pub fn with_phase(mut self, phase: Phase) -> Self {
+        self.phase = phase;
+        self
     }
 
     #[inline(always)]
@@ -86,10 +103,14 @@ where
             let _ = self.sck.set_low();
             Self::spi_delay();
 
+            if self.phase == Phase::CaptureOnFirstTransition && self.sdio.is_high().unwrap_or(false) {
+                byte |= 1 << i;
+            }
+
             let _ = self.sck.set_high();
             Self::spi_delay();
 
-            if self.sdio.is_high().unwrap_or(false) {
+            if self.phase == Phase::CaptureOnSecondTransition && self.sdio.is_high().unwrap_or(false) {
                 byte |= 1 << i;
             }
         }
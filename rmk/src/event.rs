@@ -1,3 +1,4 @@
+use embassy_time::Instant;
 use postcard::experimental::max_size::MaxSize;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "controller")]
@@ -44,6 +45,10 @@ pub enum Event {
 pub struct KeyboardEvent {
     pub(crate) pressed: bool,
     pub(crate) pos: KeyboardEventPos,
+    /// Time when the event happened, in microseconds since the boot of the device which captured it.
+    ///
+    /// Events from split peripherals are converted to the central's clock when they are received.
+    pub(crate) timestamp: Option<u64>,
 }
 
 impl KeyboardEvent {
@@ -51,6 +56,7 @@ impl KeyboardEvent {
         Self {
             pressed,
             pos: KeyboardEventPos::Key(KeyPos { row, col }),
+            timestamp: None,
         }
     }
 
@@ -58,6 +64,30 @@ impl KeyboardEvent {
         Self {
             pressed,
             pos: KeyboardEventPos::RotaryEncoder(RotaryEncoderPos { id, direction }),
+            timestamp: None,
+        }
+    }
+
+    /// Attach the time when the event happened.
+    ///
+    /// The keyboard uses it instead of the time when the event is processed,
+    /// so the time spent in channels and split links doesn't affect tap-hold, combo and morse decisions.
+    pub fn with_timestamp(mut self, time: Instant) -> Self {
+        self.timestamp = Some(time.as_micros());
+        self
+    }
+
+    /// Time when the event happened, if the input device captured it
+    pub fn timestamp(&self) -> Option<Instant> {
+        self.timestamp.map(Instant::from_micros)
+    }
+
+    /// Time when the event happened, falls back to the current time if the event doesn't carry a timestamp
+    pub(crate) fn time(&self) -> Instant {
+        match self.timestamp() {
+            // An event never happens in the future
+            Some(time) => time.min(Instant::now()),
+            None => Instant::now(),
         }
     }
 }
@@ -169,6 +199,8 @@ pub enum ControllerEvent {
     KeyboardIndicator(LedIndicator),
     /// Sleep state changed
     Sleep(bool),
+    /// Key chatter detected, (row, col, number of detected chatter bursts of the key)
+    KeyChatter(u8, u8, u16),
     /// Ble state changed
     #[cfg(feature = "_ble")]
     BleState(u8, crate::ble::BleState),
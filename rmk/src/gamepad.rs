@@ -0,0 +1,79 @@
+//! Gamepad report state
+//!
+//! The gamepad report is shared by the keyboard, which sets buttons and the hat switch,
+//! and joystick processors, which set the axes. Each of them updates its part and sends the whole report.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use rmk_types::action::HatDirection;
+
+use crate::RawMutex;
+use crate::descriptor::GamepadReport;
+
+/// Current gamepad report
+pub(crate) static GAMEPAD_REPORT: Mutex<RawMutex, Cell<GamepadReport>> = Mutex::new(Cell::new(GamepadReport::new()));
+
+/// Update the current gamepad report, returns the updated report
+pub(crate) fn update_gamepad_report(f: impl FnOnce(&mut GamepadReport)) -> GamepadReport {
+    GAMEPAD_REPORT.lock(|r| {
+        let mut report = r.get();
+        f(&mut report);
+        r.set(report);
+        report
+    })
+}
+
+/// Bit of the hat direction in the pressed hat directions
+pub(crate) fn hat_direction_bit(direction: HatDirection) -> u8 {
+    match direction {
+        HatDirection::Up => 0b0001,
+        HatDirection::Down => 0b0010,
+        HatDirection::Left => 0b0100,
+        HatDirection::Right => 0b1000,
+    }
+}
+
+/// Convert pressed hat directions to the hat switch value, opposite directions cancel each other
+pub(crate) fn hat_switch_value(pressed: u8) -> u8 {
+    let up = pressed & hat_direction_bit(HatDirection::Up) != 0;
+    let down = pressed & hat_direction_bit(HatDirection::Down) != 0;
+    let left = pressed & hat_direction_bit(HatDirection::Left) != 0;
+    let right = pressed & hat_direction_bit(HatDirection::Right) != 0;
+    let vertical = up as i8 - down as i8;
+    let horizontal = right as i8 - left as i8;
+    match (vertical, horizontal) {
+        (1, 0) => 0,
+        (1, 1) => 1,
+        (0, 1) => 2,
+        (-1, 1) => 3,
+        (-1, 0) => 4,
+        (-1, -1) => 5,
+        (0, -1) => 6,
+        (1, -1) => 7,
+        _ => GamepadReport::HAT_CENTERED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hat_switch_value() {
+        let up = hat_direction_bit(HatDirection::Up);
+        let down = hat_direction_bit(HatDirection::Down);
+        let left = hat_direction_bit(HatDirection::Left);
+        let right = hat_direction_bit(HatDirection::Right);
+
+        assert_eq!(hat_switch_value(0), GamepadReport::HAT_CENTERED);
+        assert_eq!(hat_switch_value(up), 0);
+        assert_eq!(hat_switch_value(up | right), 1);
+        assert_eq!(hat_switch_value(down), 4);
+        assert_eq!(hat_switch_value(down | left), 5);
+        assert_eq!(hat_switch_value(up | left), 7);
+        // Opposite directions cancel each other
+        assert_eq!(hat_switch_value(up | down), GamepadReport::HAT_CENTERED);
+        assert_eq!(hat_switch_value(up | down | left), 6);
+    }
+}
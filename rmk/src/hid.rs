@@ -8,6 +8,8 @@ use usbd_hid::descriptor::{AsInputReport, MediaKeyboardReport, MouseReport, Syst
 
 use crate::CONNECTION_STATE;
 use crate::channel::KEYBOARD_REPORT_CHANNEL;
+#[cfg(feature = "gamepad")]
+use crate::descriptor::GamepadReport;
 use crate::descriptor::KeyboardReport;
 use crate::state::ConnectionState;
 #[cfg(not(feature = "_no_usb"))]
@@ -23,6 +25,9 @@ pub enum Report {
     MediaKeyboardReport(MediaKeyboardReport),
     /// System control report
     SystemControlReport(SystemControlReport),
+    /// Gamepad report
+    #[cfg(feature = "gamepad")]
+    GamepadReport(GamepadReport),
 }
 
 impl AsInputReport for Report {}
@@ -62,22 +67,30 @@ pub trait RunnableHidWriter: HidWriterTrait {
                 let report = self.get_report().await;
                 // Only send the report after the connection is established.
                 if CONNECTION_STATE.load(Ordering::Acquire)
-                    == <ConnectionState as Into<bool>>::into(ConnectionState::Connected)
-                    && let Err(e) = self.write_report(report.clone()).await
+                    != <ConnectionState as Into<bool>>::into(ConnectionState::Connected)
                 {
-                    error!("Failed to send report: {:?}", e);
-                    #[cfg(not(feature = "_no_usb"))]
-                    // If the USB endpoint is disabled, try wakeup
-                    if let HidError::UsbEndpointError(EndpointError::Disabled) = e {
-                        USB_REMOTE_WAKEUP.signal(());
-                        // Wait 200ms for the wakeup, then send the report again
-                        // Ignore the error for the second send
-                        embassy_time::Timer::after_millis(200).await;
-                        if let Err(e) = self.write_report(report).await {
-                            error!("Failed to send report after wakeup: {:?}", e);
+                    continue;
+                }
+                match self.write_report(report.clone()).await {
+                    Ok(_) => {
+                        #[cfg(feature = "instrumentation")]
+                        crate::instrumentation::record_report_sent();
+                    }
+                    Err(e) => {
+                        error!("Failed to send report: {:?}", e);
+                        #[cfg(not(feature = "_no_usb"))]
+                        // If the USB endpoint is disabled, try wakeup
+                        if let HidError::UsbEndpointError(EndpointError::Disabled) = e {
+                            USB_REMOTE_WAKEUP.signal(());
+                            // Wait 200ms for the wakeup, then send the report again
+                            // Ignore the error for the second send
+                            embassy_time::Timer::after_millis(200).await;
+                            if let Err(e) = self.write_report(report).await {
+                                error!("Failed to send report after wakeup: {:?}", e);
+                            }
                         }
                     }
-                };
+                }
             }
         }
     }
@@ -0,0 +1,873 @@
+//! Backup and restore of the whole configuration.
+//!
+//! A backup contains the keymap, encoder map, macros, combos, forks, morses and behavior settings in a single blob,
+//! so that the configuration can be saved by the host and restored later, or on another board with the same layout.
+//! The blob consists of:
+//!
+//! - a postcard encoded header with the magic bytes, the format version and the layout of the keyboard,
+//! - the records, each of them is a little endian `u16` length followed by a storage item, which is encoded in the
+//!   same way as in the storage,
+//! - a zero `u16` length, which terminates the records,
+//! - the little endian CRC-32 of all bytes above.
+//!
+//! The blob is transferred in chunks over the host protocol, see [`ViaCustomValue::Backup`]. The records of an
+//! imported backup are staged in the storage, and they're applied only after the whole backup, including the CRC, is
+//! verified, so a corrupted or incompatible backup never changes the configuration.
+//!
+//! [`ViaCustomValue::Backup`]: rmk_types::protocol::vial::ViaCustomValue::Backup
+
+use core::cell::RefCell;
+
+use byteorder::{ByteOrder, LittleEndian};
+use postcard::experimental::max_size::MaxSize;
+use sequential_storage::map::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::channel::FLASH_CHANNEL;
+use crate::combo::ComboConfig;
+use crate::host::storage::{EncoderKeymap, KeymapData, KeymapKey, apply_keymap_data};
+use crate::keymap::KeyMap;
+use crate::storage::backend::{StorageBackend, StorageError};
+use crate::storage::migration::StorageLayout;
+use crate::storage::{
+    BehaviorConfig, FlashOperationMessage, Storage, StorageData, StorageKeys, get_backup_staging_key, get_buffer_size,
+    get_keymap_data_key,
+};
+use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, MORSE_MAX_NUM};
+
+#[cfg(feature = "std")]
+pub mod toml;
+
+/// Magic bytes at the start of a backup
+const BACKUP_MAGIC: [u8; 4] = *b"RMKB";
+
+/// Version of the backup format, bump it when the format changes
+const BACKUP_VERSION: u16 = 1;
+
+/// Max size of an encoded record
+const RECORD_MAX_SIZE: usize = get_buffer_size();
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, MaxSize)]
+struct BackupHeader {
+    magic: [u8; 4],
+    version: u16,
+    layout: StorageLayout,
+}
+
+/// Error of reading a backup
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BackupError {
+    /// It's not a backup, or the version of the backup format isn't supported
+    InvalidHeader = 1,
+    /// The backup is made on a keyboard with a different layout
+    LayoutMismatch = 2,
+    /// A record can't be decoded, or it's out of the layout
+    InvalidRecord = 3,
+    /// The CRC doesn't match, the backup is corrupted
+    CrcMismatch = 4,
+    /// The backup ends before the CRC
+    Incomplete = 5,
+    /// The chunk isn't expected: it's not in order, or an import isn't begun or verified
+    UnexpectedChunk = 6,
+}
+
+/// Continue the CRC-32 (IEEE) of the data. The CRC starts with `!0`, and the result is inverted
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// State of encoding a backup, the encoded bytes are passed to the sink with their offset in the backup
+struct BackupEncoder {
+    len: usize,
+    crc: u32,
+}
+
+impl BackupEncoder {
+    fn new() -> Self {
+        Self { len: 0, crc: !0 }
+    }
+
+    fn header(&mut self, layout: StorageLayout, sink: &mut impl FnMut(usize, &[u8])) {
+        let header = BackupHeader {
+            magic: BACKUP_MAGIC,
+            version: BACKUP_VERSION,
+            layout,
+        };
+        let mut buffer = [0; BackupHeader::POSTCARD_MAX_SIZE];
+        if let Ok(bytes) = postcard::to_slice(&header, &mut buffer) {
+            self.write(bytes, sink);
+        }
+    }
+
+    fn record(&mut self, data: &StorageData, sink: &mut impl FnMut(usize, &[u8])) {
+        let mut buffer = [0; RECORD_MAX_SIZE];
+        match data.serialize_into(&mut buffer) {
+            Ok(len) => {
+                self.write(&(len as u16).to_le_bytes(), sink);
+                self.write(&buffer[..len], sink);
+            }
+            Err(e) => error!("Failed to encode backup record: {:?}", e),
+        }
+    }
+
+    /// Terminate the records and append the CRC, returns the size of the backup
+    fn finish(&mut self, sink: &mut impl FnMut(usize, &[u8])) -> usize {
+        self.write(&0u16.to_le_bytes(), sink);
+        let crc = !self.crc;
+        sink(self.len, &crc.to_le_bytes());
+        self.len + 4
+    }
+
+    fn write(&mut self, bytes: &[u8], sink: &mut impl FnMut(usize, &[u8])) {
+        self.crc = crc32(self.crc, bytes);
+        sink(self.len, bytes);
+        self.len += bytes.len();
+    }
+}
+
+/// Encoder of a backup, the encoded bytes are passed to the sink with their offset in the backup
+pub(crate) struct BackupWriter<W: FnMut(usize, &[u8])> {
+    sink: W,
+    encoder: BackupEncoder,
+}
+
+impl<W: FnMut(usize, &[u8])> BackupWriter<W> {
+    pub(crate) fn new(layout: StorageLayout, mut sink: W) -> Self {
+        let mut encoder = BackupEncoder::new();
+        encoder.header(layout, &mut sink);
+        Self { sink, encoder }
+    }
+
+    pub(crate) fn record(&mut self, data: &StorageData) {
+        self.encoder.record(data, &mut self.sink);
+    }
+
+    /// Terminate the records and append the CRC, returns the size of the backup
+    pub(crate) fn finish(mut self) -> usize {
+        self.encoder.finish(&mut self.sink)
+    }
+}
+
+/// Item decoded from a backup
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum BackupItem {
+    /// Layout of the keyboard which the backup is made on
+    Header(StorageLayout),
+    Record(StorageData),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ReaderState {
+    Header,
+    Records,
+    Crc,
+    Done,
+}
+
+/// Decoder of a backup which is received in chunks
+pub(crate) struct BackupReader {
+    state: ReaderState,
+    /// Received bytes of the current header, record or CRC
+    pending: heapless::Vec<u8, { RECORD_MAX_SIZE + 2 }>,
+    received: usize,
+    crc: u32,
+}
+
+impl BackupReader {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: ReaderState::Header,
+            pending: heapless::Vec::new(),
+            received: 0,
+            crc: !0,
+        }
+    }
+
+    /// Number of the bytes read
+    pub(crate) fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Read the bytes until an item is decoded, `bytes` is advanced past the read bytes.
+    ///
+    /// Returns `None` when all bytes are read before the next item is complete.
+    pub(crate) fn read(&mut self, bytes: &mut &[u8]) -> Result<Option<BackupItem>, BackupError> {
+        while let Some((&byte, rest)) = bytes.split_first() {
+            *bytes = rest;
+            self.received += 1;
+            match self.state {
+                // Nothing is expected after the CRC
+                ReaderState::Done => return Err(BackupError::InvalidRecord),
+                ReaderState::Crc => (),
+                _ => self.crc = crc32(self.crc, &[byte]),
+            }
+            if self.pending.push(byte).is_err() {
+                return Err(match self.state {
+                    ReaderState::Header => BackupError::InvalidHeader,
+                    _ => BackupError::InvalidRecord,
+                });
+            }
+
+            match self.state {
+                ReaderState::Header => {
+                    let len = self.pending.len();
+                    if len <= BACKUP_MAGIC.len() && byte != BACKUP_MAGIC[len - 1] {
+                        return Err(BackupError::InvalidHeader);
+                    }
+                    match postcard::take_from_bytes::<BackupHeader>(&self.pending) {
+                        Ok((header, _)) => {
+                            if header.version != BACKUP_VERSION {
+                                return Err(BackupError::InvalidHeader);
+                            }
+                            self.pending.clear();
+                            self.state = ReaderState::Records;
+                            return Ok(Some(BackupItem::Header(header.layout)));
+                        }
+                        Err(postcard::Error::DeserializeUnexpectedEnd) => (),
+                        Err(_) => return Err(BackupError::InvalidHeader),
+                    }
+                }
+                ReaderState::Records if self.pending.len() >= 2 => {
+                    let len = LittleEndian::read_u16(&self.pending) as usize;
+                    if len == 0 {
+                        self.pending.clear();
+                        self.state = ReaderState::Crc;
+                    } else if len > RECORD_MAX_SIZE {
+                        return Err(BackupError::InvalidRecord);
+                    } else if self.pending.len() == len + 2 {
+                        let record = match StorageData::deserialize_from(&self.pending[2..]) {
+                            Ok((record, size)) if size == len => record,
+                            _ => return Err(BackupError::InvalidRecord),
+                        };
+                        self.pending.clear();
+                        return Ok(Some(BackupItem::Record(record)));
+                    }
+                }
+                ReaderState::Crc if self.pending.len() == 4 => {
+                    if LittleEndian::read_u32(&self.pending) != !self.crc {
+                        return Err(BackupError::CrcMismatch);
+                    }
+                    self.state = ReaderState::Done;
+                }
+                _ => (),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check that the whole backup is read
+    pub(crate) fn finish(&self) -> Result<(), BackupError> {
+        match self.state {
+            ReaderState::Done => Ok(()),
+            _ => Err(BackupError::Incomplete),
+        }
+    }
+}
+
+/// Get the record of the current configuration at the index, `None` if it's after the last record.
+///
+/// The records are the keymap keys, the encoder actions, the macros, the combos, forks and morses, and the behavior
+/// settings, in this order.
+fn backup_record<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    keymap: &KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    mut idx: usize,
+) -> Option<StorageData> {
+    if idx < NUM_LAYER * ROW * COL {
+        let (layer, row, col) = (idx / (ROW * COL), idx / COL % ROW, idx % COL);
+        return Some(StorageData::VialData(KeymapData::KeymapKey(KeymapKey {
+            row: row as u8,
+            col: col as u8,
+            layer: layer as u8,
+            action: keymap.layers[layer][row][col],
+        })));
+    }
+    idx -= NUM_LAYER * ROW * COL;
+    if let Some(encoders) = &keymap.encoders {
+        if idx < NUM_LAYER * NUM_ENCODER {
+            let (layer, encoder) = (idx / NUM_ENCODER, idx % NUM_ENCODER);
+            return Some(StorageData::VialData(KeymapData::Encoder(EncoderKeymap {
+                idx: encoder as u8,
+                layer: layer as u8,
+                action: encoders[layer][encoder],
+            })));
+        }
+        idx -= NUM_LAYER * NUM_ENCODER;
+    }
+    let behavior = &*keymap.behavior;
+    if idx == 0 {
+        return Some(StorageData::VialData(KeymapData::Macro(
+            behavior.keyboard_macros.macro_sequences,
+        )));
+    }
+    idx -= 1;
+    if idx < COMBO_MAX_NUM {
+        let config = behavior.combo.combos[idx].map_or(ComboConfig::empty(), |c| c.config);
+        return Some(StorageData::VialData(KeymapData::Combo(idx as u8, config)));
+    }
+    idx -= COMBO_MAX_NUM;
+    if idx < FORK_MAX_NUM {
+        let fork = behavior.fork.forks.get(idx).copied().unwrap_or_default();
+        return Some(StorageData::VialData(KeymapData::Fork(idx as u8, fork)));
+    }
+    idx -= FORK_MAX_NUM;
+    if idx < MORSE_MAX_NUM {
+        let morse = behavior.morse.morses.get(idx).cloned().unwrap_or_default();
+        return Some(StorageData::VialData(KeymapData::Morse(idx as u8, morse)));
+    }
+    idx -= MORSE_MAX_NUM;
+    (idx == 0).then(|| StorageData::BehaviorConfig(BehaviorConfig::from(behavior)))
+}
+
+/// Write the backup of the current configuration, returns the size of the backup
+pub(crate) fn write_backup<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    keymap: &KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    sink: impl FnMut(usize, &[u8]),
+) -> usize {
+    let mut writer = BackupWriter::new(StorageLayout::new::<ROW, COL, NUM_LAYER, NUM_ENCODER>(), sink);
+    let mut idx = 0;
+    while let Some(record) = backup_record(keymap, idx) {
+        writer.record(&record);
+        idx += 1;
+    }
+    writer.finish()
+}
+
+/// Export of the backup over the host protocol.
+///
+/// The host reads the backup in chunks, usually in order. The export keeps a cursor, so that each record is encoded
+/// only once when the chunks are read in order. It's restarted when the first chunk, or a chunk before the cursor, is
+/// read. The keymap shouldn't be changed during an export, otherwise the CRC of the backup won't match.
+pub(crate) struct BackupExport {
+    encoder: BackupEncoder,
+    /// Size of the backup, it's computed when the export is started
+    size: usize,
+    /// Index of the next record to encode
+    next_record: usize,
+    /// Encoded bytes which aren't fully read yet, they're the header, a record, or the end of the backup
+    pending: heapless::Vec<u8, { RECORD_MAX_SIZE + 2 }>,
+    /// Offset of the pending bytes in the backup
+    pos: usize,
+    finished: bool,
+}
+
+impl BackupExport {
+    pub(crate) fn new() -> Self {
+        Self {
+            encoder: BackupEncoder::new(),
+            size: 0,
+            next_record: 0,
+            pending: heapless::Vec::new(),
+            pos: 0,
+            finished: true,
+        }
+    }
+
+    /// Read the chunk of the backup at the offset, returns the size of the backup
+    pub(crate) fn read<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        &mut self,
+        keymap: &KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+        offset: usize,
+        chunk: &mut [u8],
+    ) -> usize {
+        if offset == 0 || offset < self.pos {
+            self.restart(keymap);
+        }
+        chunk.fill(0);
+        let end = offset + chunk.len();
+        loop {
+            if self.pending.is_empty() && !self.encode_next(keymap) {
+                break;
+            }
+            // Copy the part of the pending bytes which is in the chunk
+            let pending_end = self.pos + self.pending.len();
+            let (start, copy_end) = (offset.max(self.pos), end.min(pending_end));
+            if start < copy_end {
+                chunk[start - offset..copy_end - offset]
+                    .copy_from_slice(&self.pending[start - self.pos..copy_end - self.pos]);
+            }
+            // The rest of the pending bytes is read by the next chunk
+            if pending_end > end {
+                break;
+            }
+            self.pos = pending_end;
+            self.pending.clear();
+        }
+        self.size
+    }
+
+    fn restart<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        &mut self,
+        keymap: &KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    ) {
+        self.size = write_backup(keymap, |_, _| {});
+        self.encoder = BackupEncoder::new();
+        self.next_record = 0;
+        self.pending.clear();
+        self.pos = 0;
+        self.finished = false;
+        let pending = &mut self.pending;
+        self.encoder.header(
+            StorageLayout::new::<ROW, COL, NUM_LAYER, NUM_ENCODER>(),
+            &mut |_, bytes| {
+                pending.extend_from_slice(bytes).ok();
+            },
+        );
+    }
+
+    /// Encode the next record, or the end of the backup, to the pending bytes. Returns false if all are encoded
+    fn encode_next<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        &mut self,
+        keymap: &KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    ) -> bool {
+        if self.finished {
+            return false;
+        }
+        let pending = &mut self.pending;
+        let mut sink = |_: usize, bytes: &[u8]| {
+            pending.extend_from_slice(bytes).ok();
+        };
+        match backup_record(keymap, self.next_record) {
+            Some(record) => {
+                self.encoder.record(&record, &mut sink);
+                self.next_record += 1;
+            }
+            None => {
+                self.encoder.finish(&mut sink);
+                self.finished = true;
+            }
+        }
+        true
+    }
+}
+
+/// Import of a backup over the host protocol.
+///
+/// The backup is sent twice: the first pass only verifies it, and the second pass applies it, which is only allowed
+/// after a successful verification. The records of the second pass are staged by the storage task, and they're applied
+/// after the second pass is verified too, so a different or corrupted backup in the second pass changes nothing.
+pub(crate) struct BackupImport {
+    /// Reader of the current pass, `None` if no import is in progress
+    reader: Option<BackupReader>,
+    apply: bool,
+    verified: bool,
+}
+
+impl BackupImport {
+    pub(crate) fn new() -> Self {
+        Self {
+            reader: None,
+            apply: false,
+            verified: false,
+        }
+    }
+
+    /// Begin to verify, or apply the verified backup
+    pub(crate) async fn begin(&mut self, apply: bool) -> Result<(), BackupError> {
+        self.abort().await;
+        if apply && !self.verified {
+            return Err(BackupError::UnexpectedChunk);
+        }
+        if apply {
+            FLASH_CHANNEL.send(FlashOperationMessage::BeginBackupImport).await;
+        }
+        self.reader = Some(BackupReader::new());
+        self.apply = apply;
+        self.verified &= apply;
+        Ok(())
+    }
+
+    /// Read the chunk of the backup at the offset, the records are staged if it's the second pass
+    pub(crate) async fn write<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        &mut self,
+        offset: usize,
+        chunk: &[u8],
+    ) -> Result<(), BackupError> {
+        let result = self.read_chunk::<ROW, COL, NUM_LAYER, NUM_ENCODER>(offset, chunk).await;
+        if result.is_err() {
+            self.abort().await;
+            self.verified = false;
+        }
+        result
+    }
+
+    /// End the current pass, check that the whole backup is read.
+    ///
+    /// The staged records are applied if the second pass is verified, or discarded otherwise.
+    pub(crate) async fn end(&mut self) -> Result<(), BackupError> {
+        let result = self.reader.take().ok_or(BackupError::UnexpectedChunk)?.finish();
+        if self.apply {
+            FLASH_CHANNEL
+                .send(FlashOperationMessage::EndBackupImport(result.is_ok()))
+                .await;
+        }
+        // The backup is applied only once
+        self.verified = result.is_ok() && !self.apply;
+        result
+    }
+
+    /// Stop the current pass, the staged records are discarded
+    async fn abort(&mut self) {
+        if self.reader.take().is_some() && self.apply {
+            FLASH_CHANNEL.send(FlashOperationMessage::EndBackupImport(false)).await;
+        }
+    }
+
+    async fn read_chunk<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        &mut self,
+        offset: usize,
+        mut chunk: &[u8],
+    ) -> Result<(), BackupError> {
+        let apply = self.apply;
+        let reader = self.reader.as_mut().ok_or(BackupError::UnexpectedChunk)?;
+        if offset != reader.received() {
+            return Err(BackupError::UnexpectedChunk);
+        }
+        while let Some(item) = reader.read(&mut chunk)? {
+            match item {
+                BackupItem::Header(layout) => {
+                    if layout != StorageLayout::new::<ROW, COL, NUM_LAYER, NUM_ENCODER>() {
+                        return Err(BackupError::LayoutMismatch);
+                    }
+                }
+                BackupItem::Record(data) => {
+                    check_record::<ROW, COL, NUM_LAYER, NUM_ENCODER>(&data)?;
+                    if apply {
+                        FLASH_CHANNEL.send(FlashOperationMessage::BackupRecord(data)).await;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that the record is a part of the configuration, and it's in the layout
+fn check_record<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    data: &StorageData,
+) -> Result<(), BackupError> {
+    let valid = match data {
+        StorageData::VialData(KeymapData::KeymapKey(k)) => {
+            (k.layer as usize) < NUM_LAYER && (k.row as usize) < ROW && (k.col as usize) < COL
+        }
+        StorageData::VialData(KeymapData::Encoder(e)) => {
+            (e.layer as usize) < NUM_LAYER && (e.idx as usize) < NUM_ENCODER
+        }
+        StorageData::VialData(KeymapData::Combo(idx, _)) => (*idx as usize) < COMBO_MAX_NUM,
+        StorageData::VialData(KeymapData::Fork(idx, _)) => (*idx as usize) < FORK_MAX_NUM,
+        StorageData::VialData(KeymapData::Morse(idx, _)) => (*idx as usize) < MORSE_MAX_NUM,
+        StorageData::VialData(KeymapData::Macro(_)) | StorageData::BehaviorConfig(_) => true,
+        _ => false,
+    };
+    if valid { Ok(()) } else { Err(BackupError::InvalidRecord) }
+}
+
+impl<B: StorageBackend, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Save the record of an imported backup under a staging key.
+    ///
+    /// If the record can't be saved, all staged records are discarded, and the import can't be applied.
+    pub(crate) async fn stage_backup_record(&mut self, data: &StorageData) -> Result<(), StorageError> {
+        let Some(count) = self.staged_backup else {
+            return Ok(());
+        };
+        let result = self.store_item(get_backup_staging_key(count), data).await;
+        self.staged_backup = Some(count + 1);
+        if result.is_err() {
+            self.discard_staged_backup().await.ok();
+        }
+        result
+    }
+
+    /// Remove the staged records of an imported backup
+    pub(crate) async fn discard_staged_backup(&mut self) -> Result<(), StorageError> {
+        let Some(count) = self.staged_backup.take() else {
+            return Ok(());
+        };
+        for idx in 0..count {
+            self.remove_item(get_backup_staging_key(idx)).await?;
+        }
+        Ok(())
+    }
+
+    /// Save the staged records of an imported backup to the active keymap profile, and apply them to the keymap
+    pub(crate) async fn commit_staged_backup(
+        &mut self,
+        keymap: Option<&RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>>,
+    ) -> Result<(), StorageError> {
+        let Some(count) = self.staged_backup.take() else {
+            warn!("No backup is staged, it's not applied");
+            return Ok(());
+        };
+        // The pending writes are older than the backup, they mustn't overwrite it later
+        self.flush_pending_writes().await?;
+        info!("Applying the imported backup");
+        for idx in 0..count {
+            let staging_key = get_backup_staging_key(idx);
+            match self.fetch_item(staging_key).await? {
+                Some(StorageData::VialData(data)) => {
+                    let key = get_keymap_data_key(self.keymap_profiles.config.active, &data);
+                    self.store_item(key, &StorageData::VialData(data.clone())).await?;
+                    if let Some(keymap) = keymap {
+                        apply_keymap_data(&mut keymap.borrow_mut(), data);
+                    }
+                }
+                Some(StorageData::BehaviorConfig(config)) => {
+                    if let Some(keymap) = keymap {
+                        config.apply_to(keymap.borrow_mut().behavior);
+                    }
+                    self.store_item(StorageKeys::BehaviorConfig as u32, &StorageData::BehaviorConfig(config))
+                        .await?;
+                }
+                _ => (),
+            }
+            self.remove_item(staging_key).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_futures::select::{Either, select};
+    use rmk_types::action::KeyAction;
+    use rmk_types::modifier::ModifierCombination;
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+    use crate::config::{BehaviorConfig, PositionalConfig, StorageConfig};
+    use crate::storage::backend::MemoryBackend;
+    use crate::storage::flush;
+    use crate::{k, mt};
+
+    fn keymap_layers() -> [[[KeyAction; 3]; 2]; 2] {
+        [
+            [
+                [k!(A), k!(B), k!(C)],
+                [k!(D), mt!(E, ModifierCombination::LSHIFT), k!(F)],
+            ],
+            [[k!(G), k!(H), k!(I)], [k!(J), k!(K), k!(L)]],
+        ]
+    }
+
+    fn backup_of<const ROW: usize, const COL: usize, const NUM_LAYER: usize>(
+        keymap: &KeyMap<'_, ROW, COL, NUM_LAYER, 0>,
+    ) -> Vec<u8> {
+        let mut backup = Vec::new();
+        let size = write_backup(keymap, |_, bytes| backup.extend_from_slice(bytes));
+        assert_eq!(size, backup.len());
+        backup
+    }
+
+    fn read_all(backup: &[u8]) -> Result<Vec<BackupItem>, BackupError> {
+        let mut reader = BackupReader::new();
+        let mut items = Vec::new();
+        // Feed the backup in small chunks, like the host protocol
+        for mut chunk in backup.chunks(7) {
+            while let Some(item) = reader.read(&mut chunk)? {
+                items.push(item);
+            }
+        }
+        reader.finish()?;
+        Ok(items)
+    }
+
+    async fn import_pass(import: &mut BackupImport, backup: &[u8], apply: bool) -> Result<(), BackupError> {
+        import.begin(apply).await?;
+        for (idx, chunk) in backup.chunks(25).enumerate() {
+            import.write::<2, 3, 2, 0>(idx * 25, chunk).await?;
+        }
+        import.end().await
+    }
+
+    #[test]
+    fn test_backup_roundtrip() {
+        let mut layers = keymap_layers();
+        let mut behavior = BehaviorConfig::default();
+        behavior.one_shot.timeout = embassy_time::Duration::from_millis(700);
+        let mut positional_config = PositionalConfig::default();
+        let keymap = block_on(KeyMap::new(&mut layers, None, &mut behavior, &mut positional_config));
+        let backup = backup_of(&keymap);
+
+        // Reading in chunks returns the same bytes
+        let mut export = BackupExport::new();
+        let mut chunks = Vec::new();
+        for offset in (0..backup.len()).step_by(25) {
+            let mut chunk = [0; 25];
+            assert_eq!(export.read(&keymap, offset, &mut chunk), backup.len());
+            chunks.extend_from_slice(&chunk[..25.min(backup.len() - offset)]);
+        }
+        assert_eq!(chunks, backup);
+
+        // A chunk before the cursor restarts the export, the bytes after the backup are zeros
+        let mut chunk = [0xFF; 25];
+        export.read(&keymap, 30, &mut chunk);
+        assert_eq!(chunk, backup[30..55]);
+        export.read(&keymap, backup.len() - 5, &mut chunk);
+        assert_eq!(chunk[..5], backup[backup.len() - 5..]);
+        assert!(chunk[5..].iter().all(|b| *b == 0));
+
+        let items = read_all(&backup).unwrap();
+        assert!(matches!(items[0], BackupItem::Header(layout) if layout == StorageLayout::new::<2, 3, 2, 0>()));
+        assert_eq!(
+            items.len(),
+            1 + 12 + 1 + COMBO_MAX_NUM + FORK_MAX_NUM + MORSE_MAX_NUM + 1
+        );
+        assert!(items.iter().any(|item| matches!(
+            item,
+            BackupItem::Record(StorageData::VialData(KeymapData::KeymapKey(k)))
+                if k.layer == 0 && k.row == 1 && k.col == 1 && k.action == mt!(E, ModifierCombination::LSHIFT)
+        )));
+        assert!(matches!(
+            items.last(),
+            Some(BackupItem::Record(StorageData::BehaviorConfig(config))) if config.one_shot_timeout == 700
+        ));
+    }
+
+    #[test]
+    fn test_backup_corrupted() {
+        let mut layers = keymap_layers();
+        let mut behavior = BehaviorConfig::default();
+        let mut positional_config = PositionalConfig::default();
+        let keymap = block_on(KeyMap::new(&mut layers, None, &mut behavior, &mut positional_config));
+        let backup = backup_of(&keymap);
+
+        let mut invalid = backup.clone();
+        invalid[0] = b'X';
+        assert_eq!(read_all(&invalid).err(), Some(BackupError::InvalidHeader));
+
+        let mut corrupted = backup.clone();
+        let len = corrupted.len();
+        corrupted[len - 4] ^= 0xFF;
+        assert_eq!(read_all(&corrupted).err(), Some(BackupError::CrcMismatch));
+
+        assert_eq!(read_all(&backup[..len - 2]).err(), Some(BackupError::Incomplete));
+    }
+
+    #[test]
+    fn test_backup_import() {
+        let mut layers = keymap_layers();
+        let mut behavior = BehaviorConfig::default();
+        let mut positional_config = PositionalConfig::default();
+        let keymap = block_on(KeyMap::new(&mut layers, None, &mut behavior, &mut positional_config));
+        let backup = backup_of(&keymap);
+        let mut import = BackupImport::new();
+
+        // A backup must be verified before it's applied
+        assert_eq!(block_on(import.begin(true)), Err(BackupError::UnexpectedChunk));
+        block_on(import.begin(false)).unwrap();
+        assert_eq!(
+            block_on(import.write::<2, 3, 2, 0>(1, &backup[..10])),
+            Err(BackupError::UnexpectedChunk)
+        );
+        assert_eq!(block_on(import.end()), Err(BackupError::UnexpectedChunk));
+
+        block_on(import.begin(false)).unwrap();
+        for (idx, chunk) in backup.chunks(25).enumerate() {
+            block_on(import.write::<2, 3, 2, 0>(idx * 25, chunk)).unwrap();
+        }
+        block_on(import.end()).unwrap();
+        assert!(import.verified);
+
+        // A backup of another layout is rejected
+        let mut other_layers = [[[k!(A); 3]; 2]; 3];
+        let mut behavior = BehaviorConfig::default();
+        let mut positional_config = PositionalConfig::default();
+        let other = block_on(KeyMap::new(
+            &mut other_layers,
+            None,
+            &mut behavior,
+            &mut positional_config,
+        ));
+        let other_backup = backup_of(&other);
+        block_on(import.begin(false)).unwrap();
+        assert_eq!(
+            block_on(import.write::<2, 3, 2, 0>(0, &other_backup)),
+            Err(BackupError::LayoutMismatch)
+        );
+        assert!(!import.verified);
+        assert_eq!(block_on(import.begin(true)), Err(BackupError::UnexpectedChunk));
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_backup_import_corrupted_second_pass() {
+            let mut imported_layers = [[[k!(X); 3]; 2]; 2];
+            let mut behavior = BehaviorConfig::default();
+            let mut positional_config = PositionalConfig::default();
+            let imported = block_on(KeyMap::new(
+                &mut imported_layers,
+                None,
+                &mut behavior,
+                &mut positional_config,
+            ));
+            let backup = backup_of(&imported);
+            let mut corrupted = backup.clone();
+            let len = corrupted.len();
+            corrupted[len - 1] ^= 0xFF;
+
+            let default_layers = keymap_layers();
+            let mut storage: Storage<MemoryBackend<16384>, 2, 3, 2, 0> = block_on(Storage::new_with_backend(
+                MemoryBackend::new(),
+                &default_layers,
+                &None,
+                &StorageConfig::default(),
+                &BehaviorConfig::default(),
+            ));
+            let mut layers = default_layers;
+            let mut behavior = BehaviorConfig::default();
+            let mut positional_config = PositionalConfig::default();
+            let keymap = RefCell::new(block_on(KeyMap::new(
+                &mut layers,
+                None,
+                &mut behavior,
+                &mut positional_config,
+            )));
+            let mut import = BackupImport::new();
+
+            // The second pass is corrupted after all records are staged, nothing is changed
+            let host = async {
+                import_pass(&mut import, &backup, false).await.unwrap();
+                assert_eq!(
+                    import_pass(&mut import, &corrupted, true).await,
+                    Err(BackupError::CrcMismatch)
+                );
+                flush().await.unwrap();
+            };
+            let result = block_on(select(storage.run(Some(&keymap)), host));
+            assert!(matches!(result, Either::Second(())));
+            assert_eq!(*keymap.borrow().layers, keymap_layers());
+            let mut saved = keymap_layers();
+            block_on(storage.read_keymap(&mut saved, &mut None)).unwrap();
+            assert_eq!(saved, keymap_layers());
+            assert!(matches!(block_on(storage.fetch_item(get_backup_staging_key(0))), Ok(None)));
+
+            // A verified second pass is applied
+            let host = async {
+                import_pass(&mut import, &backup, false).await.unwrap();
+                import_pass(&mut import, &backup, true).await.unwrap();
+                flush().await.unwrap();
+            };
+            let result = block_on(select(storage.run(Some(&keymap)), host));
+            assert!(matches!(result, Either::Second(())));
+            assert_eq!(*keymap.borrow().layers, [[[k!(X); 3]; 2]; 2]);
+            let mut saved = keymap_layers();
+            block_on(storage.read_keymap(&mut saved, &mut None)).unwrap();
+            assert_eq!(saved, [[[k!(X); 3]; 2]; 2]);
+            assert!(matches!(block_on(storage.fetch_item(get_backup_staging_key(0))), Ok(None)));
+        }
+    }
+}
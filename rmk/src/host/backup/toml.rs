@@ -0,0 +1,1130 @@
+//! Conversion between the backup and a `keyboard.toml` fragment.
+//!
+//! The fragment contains the `[layout]` section with the keymap and the encoder map, and the `[behavior]` section
+//! with the one-shot, combo, macro, fork and morse settings, using the same syntax as `keyboard.toml`. So a backup
+//! can be reviewed and edited as text, or be pasted into the config of the firmware. Tap interval and tap capslock
+//! interval aren't available in `keyboard.toml`, they're dropped in the fragment and reset to the defaults when the
+//! fragment is converted back.
+//!
+//! The conversion uses the limits of this build, e.g. `COMBO_MAX_NUM` and `MACRO_SPACE_SIZE`, so only the backups of
+//! firmwares with the same limits are accepted.
+
+extern crate std;
+
+use std::collections::HashMap;
+use std::format;
+use std::string::{String, ToString};
+use std::sync::OnceLock;
+use std::vec::Vec;
+
+use rmk_config::{
+    BehaviorConfig as TomlBehaviorConfig, DurationMillis, KEYCODE_ALIAS, LayoutTomlConfig,
+    MacroOperation as TomlMacroOperation, MorseConfig as TomlMorseConfig,
+};
+use rmk_types::action::{Action, EncoderAction, HatDirection, KeyAction, MorseMode, MorseProfile};
+use rmk_types::keycode::{KeyCode, to_ascii};
+use rmk_types::led_indicator::LedIndicator;
+use rmk_types::modifier::ModifierCombination;
+use rmk_types::mouse_button::MouseButtons;
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use super::{BackupError, BackupItem, BackupReader, BackupWriter};
+use crate::combo::ComboConfig;
+use crate::config;
+use crate::fork::{Fork, StateBits};
+use crate::host::storage::{EncoderKeymap, KeymapData, KeymapKey};
+use crate::keyboard_macros::{MacroOperation, define_macro_sequences, to_macro_sequence};
+use crate::morse::{DOUBLE_TAP, HOLD, HOLD_AFTER_TAP, Morse, MorsePattern, TAP};
+use crate::storage::migration::StorageLayout;
+use crate::storage::{BehaviorConfig, StorageData};
+use crate::{COMBO_MAX_LENGTH, COMBO_MAX_NUM, FORK_MAX_NUM, MACRO_SPACE_SIZE, MORSE_MAX_NUM};
+
+/// Error of the conversion between the backup and `keyboard.toml`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackupTomlError {
+    /// The backup can't be read
+    Backup(BackupError),
+    /// The TOML can't be parsed
+    Toml(String),
+    /// The configuration can't be converted, with the reason
+    Unsupported(String),
+}
+
+impl From<BackupError> for BackupTomlError {
+    fn from(e: BackupError) -> Self {
+        Self::Backup(e)
+    }
+}
+
+impl core::fmt::Display for BackupTomlError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Backup(e) => write!(f, "invalid backup: {:?}", e),
+            Self::Toml(e) => write!(f, "invalid TOML: {}", e),
+            Self::Unsupported(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackupTomlError {}
+
+/// Convert the backup to a `keyboard.toml` fragment
+pub fn backup_to_toml(backup: &[u8]) -> Result<String, BackupTomlError> {
+    Configuration::decode(backup)?.to_toml()
+}
+
+/// Convert a `keyboard.toml` fragment, or a whole `keyboard.toml`, to a backup.
+///
+/// The keymap must be defined by `keymap` in the `[layout]` section, `matrix_map` isn't supported.
+pub fn backup_from_toml(toml: &str) -> Result<Vec<u8>, BackupTomlError> {
+    let fragment: Fragment = toml::from_str(toml).map_err(|e| BackupTomlError::Toml(e.to_string()))?;
+    Ok(Configuration::from_toml(&fragment)?.encode())
+}
+
+#[derive(Deserialize)]
+struct Fragment {
+    layout: LayoutTomlConfig,
+    #[serde(default)]
+    behavior: TomlBehaviorConfig,
+}
+
+/// Configuration in a backup
+struct Configuration {
+    layout: StorageLayout,
+    keymap: Vec<Vec<Vec<KeyAction>>>,
+    encoders: Vec<Vec<EncoderAction>>,
+    macros: [u8; MACRO_SPACE_SIZE],
+    combos: Vec<ComboConfig>,
+    forks: Vec<Fork>,
+    morses: Vec<Morse>,
+    behavior: BehaviorConfig,
+}
+
+impl Configuration {
+    fn new(layout: StorageLayout) -> Self {
+        let (rows, cols, layers, encoders) = (
+            layout.rows() as usize,
+            layout.cols() as usize,
+            layout.layers() as usize,
+            layout.encoders() as usize,
+        );
+        Self {
+            layout,
+            keymap: std::vec![std::vec![std::vec![KeyAction::No; cols]; rows]; layers],
+            encoders: std::vec![std::vec![EncoderAction::default(); encoders]; layers],
+            macros: [0; MACRO_SPACE_SIZE],
+            combos: std::vec![ComboConfig::empty(); COMBO_MAX_NUM],
+            forks: std::vec![Fork::empty(); FORK_MAX_NUM],
+            morses: std::vec![Morse::default(); MORSE_MAX_NUM],
+            behavior: BehaviorConfig::from(&config::BehaviorConfig::default()),
+        }
+    }
+
+    fn decode(backup: &[u8]) -> Result<Self, BackupTomlError> {
+        let mut reader = BackupReader::new();
+        let mut bytes = backup;
+        let mut configuration = None;
+        while let Some(item) = reader.read(&mut bytes)? {
+            match item {
+                BackupItem::Header(layout) => {
+                    // Only the size of the keymap may differ from this build
+                    if layout
+                        != StorageLayout::with_size(layout.rows(), layout.cols(), layout.layers(), layout.encoders())
+                    {
+                        return Err(BackupError::LayoutMismatch.into());
+                    }
+                    configuration = Some(Self::new(layout));
+                }
+                BackupItem::Record(data) => {
+                    // The header is always read before the records
+                    if let Some(configuration) = configuration.as_mut() {
+                        configuration.apply(data)?;
+                    }
+                }
+            }
+        }
+        reader.finish()?;
+        configuration.ok_or(BackupError::Incomplete.into())
+    }
+
+    fn apply(&mut self, data: StorageData) -> Result<(), BackupError> {
+        match data {
+            StorageData::VialData(KeymapData::KeymapKey(k)) => {
+                *self
+                    .keymap
+                    .get_mut(k.layer as usize)
+                    .and_then(|l| l.get_mut(k.row as usize))
+                    .and_then(|r| r.get_mut(k.col as usize))
+                    .ok_or(BackupError::InvalidRecord)? = k.action
+            }
+            StorageData::VialData(KeymapData::Encoder(e)) => {
+                *self
+                    .encoders
+                    .get_mut(e.layer as usize)
+                    .and_then(|l| l.get_mut(e.idx as usize))
+                    .ok_or(BackupError::InvalidRecord)? = e.action
+            }
+            StorageData::VialData(KeymapData::Macro(m)) => self.macros = m,
+            StorageData::VialData(KeymapData::Combo(idx, combo)) => {
+                *self.combos.get_mut(idx as usize).ok_or(BackupError::InvalidRecord)? = combo
+            }
+            StorageData::VialData(KeymapData::Fork(idx, fork)) => {
+                *self.forks.get_mut(idx as usize).ok_or(BackupError::InvalidRecord)? = fork
+            }
+            StorageData::VialData(KeymapData::Morse(idx, morse)) => {
+                *self.morses.get_mut(idx as usize).ok_or(BackupError::InvalidRecord)? = morse
+            }
+            StorageData::BehaviorConfig(behavior) => self.behavior = behavior,
+            _ => return Err(BackupError::InvalidRecord),
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut backup = Vec::new();
+        let mut writer = BackupWriter::new(self.layout, |_, bytes| backup.extend_from_slice(bytes));
+        for (layer, layer_data) in self.keymap.iter().enumerate() {
+            for (row, row_data) in layer_data.iter().enumerate() {
+                for (col, action) in row_data.iter().enumerate() {
+                    writer.record(&StorageData::VialData(KeymapData::KeymapKey(KeymapKey {
+                        row: row as u8,
+                        col: col as u8,
+                        layer: layer as u8,
+                        action: *action,
+                    })));
+                }
+            }
+        }
+        for (layer, layer_data) in self.encoders.iter().enumerate() {
+            for (idx, action) in layer_data.iter().enumerate() {
+                writer.record(&StorageData::VialData(KeymapData::Encoder(EncoderKeymap {
+                    idx: idx as u8,
+                    layer: layer as u8,
+                    action: *action,
+                })));
+            }
+        }
+        writer.record(&StorageData::VialData(KeymapData::Macro(self.macros)));
+        for (idx, combo) in self.combos.iter().enumerate() {
+            writer.record(&StorageData::VialData(KeymapData::Combo(idx as u8, *combo)));
+        }
+        for (idx, fork) in self.forks.iter().enumerate() {
+            writer.record(&StorageData::VialData(KeymapData::Fork(idx as u8, *fork)));
+        }
+        for (idx, morse) in self.morses.iter().enumerate() {
+            writer.record(&StorageData::VialData(KeymapData::Morse(idx as u8, morse.clone())));
+        }
+        writer.record(&StorageData::BehaviorConfig(self.behavior));
+        writer.finish();
+        backup
+    }
+
+    fn to_toml(&self) -> Result<String, BackupTomlError> {
+        let mut profiles = Profiles::default();
+
+        // The layout is formatted by hand, so that each row of the keymap is in a line like `keyboard.toml`
+        let mut layout = format!(
+            "[layout]\nrows = {}\ncols = {}\nlayers = {}\nkeymap = [\n",
+            self.layout.rows(),
+            self.layout.cols(),
+            self.layout.layers()
+        );
+        for layer in &self.keymap {
+            layout.push_str("    [\n");
+            for row in layer {
+                let keys = row
+                    .iter()
+                    .map(|action| key_to_string(action, &mut profiles).map(|k| Value::String(k).to_string()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                layout.push_str(&format!("        [{}],\n", keys.join(", ")));
+            }
+            layout.push_str("    ],\n");
+        }
+        layout.push_str("]\n");
+        if self.layout.encoders() > 0 {
+            layout.push_str("encoder_map = [\n");
+            for layer in &self.encoders {
+                let mut encoders = Vec::new();
+                for encoder in layer {
+                    encoders.push(format!(
+                        "[{}, {}]",
+                        Value::String(key_to_string(&encoder.clockwise(), &mut profiles)?),
+                        Value::String(key_to_string(&encoder.counter_clockwise(), &mut profiles)?),
+                    ));
+                }
+                layout.push_str(&format!("    [{}],\n", encoders.join(", ")));
+            }
+            layout.push_str("]\n");
+        }
+
+        let mut behavior = Table::new();
+        let mut one_shot = Table::new();
+        one_shot.insert("timeout".into(), duration(self.behavior.one_shot_timeout));
+        behavior.insert("one_shot".into(), Value::Table(one_shot));
+
+        let mut combos = Vec::new();
+        for combo in self.combos.iter().filter(|c| c.output != KeyAction::No) {
+            let mut table = Table::new();
+            let actions = combo
+                .actions
+                .iter()
+                .filter(|a| **a != KeyAction::No)
+                .map(|a| key_to_string(a, &mut profiles).map(Value::String))
+                .collect::<Result<_, _>>()?;
+            table.insert("actions".into(), Value::Array(actions));
+            table.insert(
+                "output".into(),
+                Value::String(key_to_string(&combo.output, &mut profiles)?),
+            );
+            if let Some(layer) = combo.layer {
+                table.insert("layer".into(), Value::Integer(layer as i64));
+            }
+            combos.push(Value::Table(table));
+        }
+        let mut combo = Table::new();
+        combo.insert("timeout".into(), duration(self.behavior.combo_timeout));
+        combo.insert("combos".into(), Value::Array(combos));
+        behavior.insert("combo".into(), Value::Table(combo));
+
+        let macros = macros_to_toml(&self.macros);
+        if !macros.is_empty() {
+            let mut table = Table::new();
+            table.insert("macros".into(), Value::Array(macros));
+            behavior.insert("macros".into(), Value::Table(table));
+        }
+
+        let mut forks = Vec::new();
+        for fork in self.forks.iter().filter(|f| f.trigger != KeyAction::No) {
+            let mut table = Table::new();
+            table.insert(
+                "trigger".into(),
+                Value::String(key_to_string(&fork.trigger, &mut profiles)?),
+            );
+            table.insert(
+                "negative_output".into(),
+                Value::String(key_to_string(&fork.negative_output, &mut profiles)?),
+            );
+            table.insert(
+                "positive_output".into(),
+                Value::String(key_to_string(&fork.positive_output, &mut profiles)?),
+            );
+            let kept = StateBits {
+                modifiers: fork.kept_modifiers,
+                ..Default::default()
+            };
+            for (key, states) in [
+                ("match_any", fork.match_any),
+                ("match_none", fork.match_none),
+                ("kept_modifiers", kept),
+            ] {
+                if let Some(states) = states_to_string(&states) {
+                    table.insert(key.into(), Value::String(states));
+                }
+            }
+            table.insert("bindable".into(), Value::Boolean(fork.bindable));
+            forks.push(Value::Table(table));
+        }
+        if !forks.is_empty() {
+            let mut table = Table::new();
+            table.insert("forks".into(), Value::Array(forks));
+            behavior.insert("fork".into(), Value::Table(table));
+        }
+
+        // Morses are referenced by the index, so only the empty morses at the end are omitted
+        let count = self
+            .morses
+            .iter()
+            .rposition(|m| m.profile != MorseProfile::const_default() || !m.actions.is_empty())
+            .map_or(0, |i| i + 1);
+        let mut morses = Vec::new();
+        for morse in &self.morses[..count] {
+            let mut table = Table::new();
+            if morse.profile != MorseProfile::const_default() {
+                table.insert("profile".into(), Value::String(profiles.name(morse.profile)));
+            }
+            if !morse.actions.is_empty() {
+                let mut actions = Vec::new();
+                for (pattern, action) in morse.actions.iter() {
+                    let mut pair = Table::new();
+                    pair.insert("pattern".into(), Value::String(pattern_to_string(*pattern)));
+                    pair.insert(
+                        "action".into(),
+                        Value::String(key_to_string(&KeyAction::Single(*action), &mut profiles)?),
+                    );
+                    actions.push(Value::Table(pair));
+                }
+                table.insert("morse_actions".into(), Value::Array(actions));
+            }
+            morses.push(Value::Table(table));
+        }
+        let mut morse = profile_to_toml(self.behavior.morse_default_profile);
+        morse.insert("prior_idle_time".into(), duration(self.behavior.prior_idle_time));
+        if !profiles.0.is_empty() {
+            let mut table = Table::new();
+            for (idx, profile) in profiles.0.iter().enumerate() {
+                table.insert(format!("profile_{}", idx), Value::Table(profile_to_toml(*profile)));
+            }
+            morse.insert("profiles".into(), Value::Table(table));
+        }
+        if !morses.is_empty() {
+            morse.insert("morses".into(), Value::Array(morses));
+        }
+        behavior.insert("morse".into(), Value::Table(morse));
+
+        let mut fragment = Table::new();
+        fragment.insert("behavior".into(), Value::Table(behavior));
+        let behavior = toml::to_string_pretty(&fragment).map_err(|e| BackupTomlError::Toml(e.to_string()))?;
+        Ok(format!("{}\n{}", layout, behavior))
+    }
+
+    fn from_toml(fragment: &Fragment) -> Result<Self, BackupTomlError> {
+        let layout = &fragment.layout;
+        let Some(keymap) = &layout.keymap else {
+            return Err(unsupported(
+                "The keymap must be defined by `keymap` in the `[layout]` section",
+            ));
+        };
+        let encoder_map = layout.encoder_map.as_deref().unwrap_or_default();
+        let encoders = encoder_map.first().map_or(0, |l| l.len());
+        let mut configuration = Self::new(StorageLayout::with_size(
+            layout.rows,
+            layout.cols,
+            layout.layers,
+            u8::try_from(encoders).map_err(|_| unsupported("Too many encoders"))?,
+        ));
+
+        let behavior = &fragment.behavior;
+        let morse = behavior.morse.as_ref();
+        let mut profiles = HashMap::new();
+        for (name, p) in morse.and_then(|m| m.profiles.as_ref()).into_iter().flatten() {
+            let profile = parse_profile(
+                p.unilateral_tap,
+                p.permissive_hold,
+                p.hold_on_other_press,
+                p.normal_mode,
+                &p.hold_timeout,
+                &p.gap_timeout,
+            )?;
+            profiles.insert(name.clone(), profile);
+        }
+
+        if keymap.len() != configuration.keymap.len()
+            || keymap.iter().flatten().any(|row| row.len() != layout.cols as usize)
+            || keymap.iter().any(|layer| layer.len() != layout.rows as usize)
+        {
+            return Err(unsupported(
+                "The size of the keymap doesn't match `rows`, `cols` and `layers`",
+            ));
+        }
+        for (layer, layer_data) in keymap.iter().enumerate() {
+            for (row, row_data) in layer_data.iter().enumerate() {
+                for (col, key) in row_data.iter().enumerate() {
+                    configuration.keymap[layer][row][col] = parse_key(key, &profiles)?;
+                }
+            }
+        }
+        if encoder_map.len() > configuration.encoders.len() || encoder_map.iter().any(|l| l.len() != encoders) {
+            return Err(unsupported("The size of the encoder map doesn't match `layers`"));
+        }
+        for (layer, layer_data) in encoder_map.iter().enumerate() {
+            for (idx, [clockwise, counter_clockwise]) in layer_data.iter().enumerate() {
+                configuration.encoders[layer][idx] = EncoderAction::new(
+                    parse_key(clockwise, &profiles)?,
+                    parse_key(counter_clockwise, &profiles)?,
+                );
+            }
+        }
+
+        let combos = behavior.combo.as_ref().map_or(&[][..], |c| &c.combos);
+        if combos.len() > COMBO_MAX_NUM {
+            return Err(unsupported("Too many combos"));
+        }
+        for (idx, combo) in combos.iter().enumerate() {
+            if combo.actions.len() > COMBO_MAX_LENGTH {
+                return Err(unsupported("Too many actions in a combo"));
+            }
+            let actions = combo
+                .actions
+                .iter()
+                .map(|a| parse_key(a, &profiles))
+                .collect::<Result<Vec<_>, _>>()?;
+            configuration.combos[idx] = ComboConfig::new(actions, parse_key(&combo.output, &profiles)?, combo.layer);
+        }
+
+        if let Some(macros) = &behavior.macros {
+            configuration.macros = parse_macros(&macros.macros)?;
+        }
+
+        let forks = behavior.fork.as_ref().map_or(&[][..], |f| &f.forks);
+        if forks.len() > FORK_MAX_NUM {
+            return Err(unsupported("Too many forks"));
+        }
+        for (idx, fork) in forks.iter().enumerate() {
+            let states = |s: &Option<String>| s.as_deref().map_or(Ok(StateBits::default()), parse_states);
+            configuration.forks[idx] = Fork::new_ex(
+                parse_key(&fork.trigger, &profiles)?,
+                parse_key(&fork.negative_output, &profiles)?,
+                parse_key(&fork.positive_output, &profiles)?,
+                states(&fork.match_any)?,
+                states(&fork.match_none)?,
+                states(&fork.kept_modifiers)?,
+                fork.bindable.unwrap_or(false),
+            );
+        }
+
+        let morses = morse.and_then(|m| m.morses.as_deref()).unwrap_or_default();
+        if morses.len() > MORSE_MAX_NUM {
+            return Err(unsupported("Too many morses"));
+        }
+        for (idx, morse) in morses.iter().enumerate() {
+            configuration.morses[idx] = parse_morse(morse, &profiles)?;
+        }
+
+        let defaults = &configuration.behavior;
+        let timeout = |t: Option<&DurationMillis>, default: u16| t.map_or(Ok(default), millis);
+        configuration.behavior = BehaviorConfig {
+            one_shot_timeout: timeout(
+                behavior.one_shot.as_ref().and_then(|o| o.timeout.as_ref()),
+                defaults.one_shot_timeout,
+            )?,
+            combo_timeout: timeout(
+                behavior.combo.as_ref().and_then(|c| c.timeout.as_ref()),
+                defaults.combo_timeout,
+            )?,
+            prior_idle_time: timeout(morse.and_then(|m| m.prior_idle_time.as_ref()), defaults.prior_idle_time)?,
+            morse_default_profile: match morse {
+                Some(m) => parse_profile(
+                    m.unilateral_tap,
+                    m.permissive_hold,
+                    m.hold_on_other_press,
+                    m.normal_mode,
+                    &m.hold_timeout,
+                    &m.gap_timeout,
+                )?,
+                None => defaults.morse_default_profile,
+            },
+            ..*defaults
+        };
+
+        Ok(configuration)
+    }
+}
+
+/// Morse profiles other than the default, which are referenced by their names in `keyboard.toml`
+#[derive(Default)]
+struct Profiles(Vec<MorseProfile>);
+
+impl Profiles {
+    fn name(&mut self, profile: MorseProfile) -> String {
+        let idx = match self.0.iter().position(|p| *p == profile) {
+            Some(idx) => idx,
+            None => {
+                self.0.push(profile);
+                self.0.len() - 1
+            }
+        };
+        format!("profile_{}", idx)
+    }
+}
+
+fn unsupported(reason: &str) -> BackupTomlError {
+    BackupTomlError::Unsupported(reason.to_string())
+}
+
+fn duration(ms: u16) -> Value {
+    Value::String(format!("{}ms", ms))
+}
+
+fn millis(duration: &DurationMillis) -> Result<u16, BackupTomlError> {
+    u16::try_from(duration.0).map_err(|_| unsupported("The duration is too long"))
+}
+
+fn key_to_string(action: &KeyAction, profiles: &mut Profiles) -> Result<String, BackupTomlError> {
+    let key = match *action {
+        KeyAction::No => "No".to_string(),
+        KeyAction::Transparent => "_".to_string(),
+        KeyAction::Single(action) => action_to_string(action)?,
+        KeyAction::Morse(idx) => format!("TD({})", idx),
+        KeyAction::TapHold(tap, hold, profile) => {
+            let profile = if profile == MorseProfile::const_default() {
+                String::new()
+            } else {
+                format!(", {}", profiles.name(profile))
+            };
+            match (tap, hold) {
+                (Action::Key(key), Action::LayerOn(layer)) => format!("LT({}, {:?}{})", layer, key, profile),
+                (Action::Key(key), Action::Modifier(modifiers)) => {
+                    format!("MT({:?}, {}{})", key, modifiers_to_string(modifiers)?, profile)
+                }
+                (Action::Key(tap), Action::Key(hold)) => format!("TH({:?}, {:?}{})", tap, hold, profile),
+                (Action::LayerToggle(tap), Action::LayerOn(hold)) if tap == hold && profile.is_empty() => {
+                    format!("TT({})", tap)
+                }
+                _ => return Err(BackupTomlError::Unsupported(format!("Unsupported key: {:?}", action))),
+            }
+        }
+        KeyAction::Tap(_) => return Err(BackupTomlError::Unsupported(format!("Unsupported key: {:?}", action))),
+    };
+    Ok(key)
+}
+
+fn action_to_string(action: Action) -> Result<String, BackupTomlError> {
+    let key = match action {
+        Action::No => "No".to_string(),
+        Action::Transparent => "_".to_string(),
+        Action::Key(key) => format!("{:?}", key),
+        Action::KeyWithModifier(key, modifiers) => format!("WM({:?}, {})", key, modifiers_to_string(modifiers)?),
+        Action::LayerOn(layer) => format!("MO({})", layer),
+        Action::LayerOnWithModifier(layer, modifiers) => format!("LM({}, {})", layer, modifiers_to_string(modifiers)?),
+        Action::LayerToggle(layer) => format!("TG({})", layer),
+        Action::DefaultLayer(layer) => format!("DF({})", layer),
+        Action::LayerToggleOnly(layer) => format!("TO({})", layer),
+        Action::TriggerMacro(idx) => format!("MACRO({})", idx),
+        Action::OneShotLayer(layer) => format!("OSL({})", layer),
+        Action::OneShotModifier(modifiers) => format!("OSM({})", modifiers_to_string(modifiers)?),
+        Action::GamepadButton(button) => format!("GP({})", button),
+        Action::GamepadHat(direction) => format!("HAT({:?})", direction),
+        Action::SwitchKeymapProfile(profile) => format!("KMP({})", profile),
+        Action::Modifier(_) | Action::LayerOff(_) | Action::OneShotKey(_) => {
+            return Err(BackupTomlError::Unsupported(format!(
+                "Unsupported action: {:?}",
+                action
+            )));
+        }
+    };
+    Ok(key)
+}
+
+/// Modifiers in `keyboard.toml` are either all left or all right
+fn modifiers_to_string(modifiers: ModifierCombination) -> Result<String, BackupTomlError> {
+    let left = [
+        ("LCtrl", modifiers.left_ctrl()),
+        ("LShift", modifiers.left_shift()),
+        ("LAlt", modifiers.left_alt()),
+        ("LGui", modifiers.left_gui()),
+    ];
+    let right = [
+        ("RCtrl", modifiers.right_ctrl()),
+        ("RShift", modifiers.right_shift()),
+        ("RAlt", modifiers.right_alt()),
+        ("RGui", modifiers.right_gui()),
+    ];
+    let names = match (left.iter().any(|m| m.1), right.iter().any(|m| m.1)) {
+        (true, false) => left,
+        (false, true) => right,
+        _ => {
+            return Err(BackupTomlError::Unsupported(format!(
+                "Unsupported modifiers: {:?}",
+                modifiers
+            )));
+        }
+    };
+    Ok(names.iter().filter(|m| m.1).map(|m| m.0).collect::<Vec<_>>().join("|"))
+}
+
+fn states_to_string(states: &StateBits) -> Option<String> {
+    let (m, l, b) = (states.modifiers, states.leds, states.mouse);
+    let names: Vec<_> = [
+        ("LCtrl", m.left_ctrl()),
+        ("LShift", m.left_shift()),
+        ("LAlt", m.left_alt()),
+        ("LGui", m.left_gui()),
+        ("RCtrl", m.right_ctrl()),
+        ("RShift", m.right_shift()),
+        ("RAlt", m.right_alt()),
+        ("RGui", m.right_gui()),
+        ("NumLock", l.num_lock()),
+        ("CapsLock", l.caps_lock()),
+        ("ScrollLock", l.scroll_lock()),
+        ("Compose", l.compose()),
+        ("Kana", l.kana()),
+        ("MouseBtn1", b.button1()),
+        ("MouseBtn2", b.button2()),
+        ("MouseBtn3", b.button3()),
+        ("MouseBtn4", b.button4()),
+        ("MouseBtn5", b.button5()),
+        ("MouseBtn6", b.button6()),
+        ("MouseBtn7", b.button7()),
+        ("MouseBtn8", b.button8()),
+    ]
+    .into_iter()
+    .filter(|s| s.1)
+    .map(|s| s.0)
+    .collect();
+    (!names.is_empty()).then(|| names.join("|"))
+}
+
+/// Morse pattern in `keyboard.toml`, `.` is a tap and `-` is a hold
+fn pattern_to_string(pattern: MorsePattern) -> String {
+    let bits = pattern.to_u16();
+    (0..pattern.pattern_length())
+        .rev()
+        .map(|i| if (bits >> i) & 1 == 1 { '-' } else { '.' })
+        .collect()
+}
+
+fn profile_to_toml(profile: MorseProfile) -> Table {
+    let mut table = Table::new();
+    if let Some(unilateral_tap) = profile.unilateral_tap() {
+        table.insert("unilateral_tap".into(), Value::Boolean(unilateral_tap));
+    }
+    let mode = match profile.mode() {
+        Some(MorseMode::PermissiveHold) => Some("permissive_hold"),
+        Some(MorseMode::HoldOnOtherPress) => Some("hold_on_other_press"),
+        Some(MorseMode::Normal) => Some("normal_mode"),
+        None => None,
+    };
+    if let Some(mode) = mode {
+        table.insert(mode.into(), Value::Boolean(true));
+    }
+    if let Some(timeout) = profile.hold_timeout_ms() {
+        table.insert("hold_timeout".into(), duration(timeout));
+    }
+    if let Some(timeout) = profile.gap_timeout_ms() {
+        table.insert("gap_timeout".into(), duration(timeout));
+    }
+    table
+}
+
+/// Convert the macros to the tables of operations, the empty macros at the end are omitted
+fn macros_to_toml(sequences: &[u8]) -> Vec<Value> {
+    fn operation(name: &str, key: &str, value: String) -> Value {
+        let mut table = Table::new();
+        table.insert("operation".into(), Value::String(name.into()));
+        table.insert(key.into(), Value::String(value));
+        Value::Table(table)
+    }
+
+    let mut macros = Vec::new();
+    let mut start = 0;
+    while start < sequences.len() {
+        let mut operations = Vec::new();
+        let mut text = String::new();
+        let mut offset = 0;
+        loop {
+            let (op, next) = MacroOperation::get_next_macro_operation(sequences, start, offset);
+            offset = next;
+            if let MacroOperation::Text(key, shifted) = op {
+                text.push(to_ascii(key, shifted) as char);
+                continue;
+            }
+            if !text.is_empty() {
+                operations.push(operation("text", "text", core::mem::take(&mut text)));
+            }
+            match op {
+                MacroOperation::Tap(key) => operations.push(operation("tap", "keycode", format!("{:?}", key))),
+                MacroOperation::Press(key) => operations.push(operation("down", "keycode", format!("{:?}", key))),
+                MacroOperation::Release(key) => operations.push(operation("up", "keycode", format!("{:?}", key))),
+                MacroOperation::Delay(ms) => {
+                    operations.push(operation("delay", "duration", format!("{}ms", ms)));
+                }
+                _ => break,
+            }
+        }
+        let mut table = Table::new();
+        table.insert("operations".into(), Value::Array(operations));
+        macros.push(Value::Table(table));
+        start += offset + 1;
+    }
+    while let Some(Value::Table(table)) = macros.last()
+        && table
+            .get("operations")
+            .and_then(Value::as_array)
+            .is_some_and(Vec::is_empty)
+    {
+        macros.pop();
+    }
+    macros
+}
+
+fn parse_macros(macros: &[rmk_config::MacroConfig]) -> Result<[u8; MACRO_SPACE_SIZE], BackupTomlError> {
+    let mut sequences = Vec::new();
+    // Each macro is terminated by a zero
+    let mut size = macros.len();
+    for m in macros {
+        let mut sequence = heapless::Vec::<MacroOperation, MACRO_SPACE_SIZE>::new();
+        for op in &m.operations {
+            let ops = match op {
+                TomlMacroOperation::Tap { keycode } => MacroOperation::Tap(parse_keycode(keycode)?).into_iter(),
+                TomlMacroOperation::Down { keycode } => MacroOperation::Press(parse_keycode(keycode)?).into_iter(),
+                TomlMacroOperation::Up { keycode } => MacroOperation::Release(parse_keycode(keycode)?).into_iter(),
+                TomlMacroOperation::Delay { duration } => {
+                    MacroOperation::Delay(vial_macro_delay(millis(duration)?)).into_iter()
+                }
+                TomlMacroOperation::Text { text } => {
+                    if !text.is_ascii() || text.len() > MACRO_SPACE_SIZE {
+                        return Err(unsupported("Only ASCII text is supported in macros"));
+                    }
+                    to_macro_sequence(text).into_iter()
+                }
+            };
+            for op in ops {
+                size += match op {
+                    MacroOperation::Text(_, _) => 1,
+                    MacroOperation::Delay(_) => 4,
+                    _ => 3,
+                };
+                if size > MACRO_SPACE_SIZE || sequence.push(op).is_err() {
+                    return Err(unsupported("Macros are too long"));
+                }
+            }
+        }
+        sequences.push(sequence);
+    }
+    if size > MACRO_SPACE_SIZE {
+        return Err(unsupported("Macros are too long"));
+    }
+    Ok(define_macro_sequences(&sequences))
+}
+
+/// Convert the delay, so that it's saved in the encoding of Vial.
+///
+/// `define_macro_sequences` saves the delay as big endian bytes, but the macro space is read with the encoding of
+/// Vial, where the delay is `(low - 1) + (high - 1) * 255`.
+fn vial_macro_delay(ms: u16) -> u16 {
+    let ms = ms.min(255 * 255 - 1);
+    u16::from_be_bytes([(ms % 255 + 1) as u8, (ms / 255 + 1) as u8])
+}
+
+fn parse_morse(morse: &TomlMorseConfig, profiles: &HashMap<String, MorseProfile>) -> Result<Morse, BackupTomlError> {
+    let profile = match &morse.profile {
+        Some(name) => parse_profile_name(name, profiles)?,
+        None => MorseProfile::const_default(),
+    };
+    let action = |key: &String| parse_key(key, profiles).map(KeyAction::to_action);
+    let mut actions = Vec::new();
+    for pair in morse.morse_actions.iter().flatten() {
+        let mut pattern = 0b1u16;
+        for c in pair.pattern.chars() {
+            match c {
+                '1' | '-' | '_' => pattern = (pattern << 1) | 1,
+                '0' | '.' => pattern <<= 1,
+                _ => (),
+            }
+        }
+        actions.push((MorsePattern::from_u16(pattern), action(&pair.action)?));
+    }
+    for (taps, key) in morse.tap_actions.iter().flatten().enumerate() {
+        actions.push((MorsePattern::from_u16(0b10 << taps), action(key)?));
+    }
+    for (taps, key) in morse.hold_actions.iter().flatten().enumerate() {
+        actions.push((MorsePattern::from_u16((0b10 << taps) | 1), action(key)?));
+    }
+    for (pattern, key) in [
+        (TAP, &morse.tap),
+        (HOLD, &morse.hold),
+        (HOLD_AFTER_TAP, &morse.hold_after_tap),
+        (DOUBLE_TAP, &morse.double_tap),
+    ] {
+        if let Some(key) = key {
+            actions.push((pattern, action(key)?));
+        }
+    }
+
+    let mut result = Morse {
+        profile,
+        ..Default::default()
+    };
+    for (pattern, action) in actions {
+        if action != Action::No && result.actions.insert(pattern, action).is_err() {
+            return Err(unsupported("Too many actions in a morse"));
+        }
+    }
+    Ok(result)
+}
+
+fn parse_profile(
+    unilateral_tap: Option<bool>,
+    permissive_hold: Option<bool>,
+    hold_on_other_press: Option<bool>,
+    normal_mode: Option<bool>,
+    hold_timeout: &Option<DurationMillis>,
+    gap_timeout: &Option<DurationMillis>,
+) -> Result<MorseProfile, BackupTomlError> {
+    let mode = if permissive_hold == Some(true) {
+        Some(MorseMode::PermissiveHold)
+    } else if hold_on_other_press == Some(true) {
+        Some(MorseMode::HoldOnOtherPress)
+    } else if normal_mode == Some(true) {
+        Some(MorseMode::Normal)
+    } else {
+        None
+    };
+    let hold_timeout = hold_timeout.as_ref().map(millis).transpose()?;
+    let gap_timeout = gap_timeout.as_ref().map(millis).transpose()?;
+    Ok(MorseProfile::new(unilateral_tap, mode, hold_timeout, gap_timeout))
+}
+
+fn parse_profile_name(name: &str, profiles: &HashMap<String, MorseProfile>) -> Result<MorseProfile, BackupTomlError> {
+    profiles
+        .get(name.trim())
+        .copied()
+        .ok_or_else(|| BackupTomlError::Unsupported(format!("Unknown morse profile: {}", name)))
+}
+
+/// Parse the key string in the same way as the keymap in `keyboard.toml`
+fn parse_key(key: &str, profiles: &HashMap<String, MorseProfile>) -> Result<KeyAction, BackupTomlError> {
+    let key = key.trim();
+    if !key.is_empty() && (key.trim_start_matches('_').is_empty() || key.eq_ignore_ascii_case("trns")) {
+        return Ok(KeyAction::Transparent);
+    } else if key == "No" {
+        return Ok(KeyAction::No);
+    }
+
+    let Some((name, args)) = key.strip_suffix(')').and_then(|k| k.split_once('(')) else {
+        return Ok(KeyAction::Single(Action::Key(parse_keycode(key)?)));
+    };
+    let args: Vec<&str> = args.split(',').map(str::trim).filter(|a| !a.is_empty()).collect();
+    let profile = |args: &[&str]| match args {
+        [] => Ok(MorseProfile::const_default()),
+        [name] => parse_profile_name(name, profiles),
+        _ => Err(BackupTomlError::Unsupported(format!("Invalid key: {}", key))),
+    };
+    let action = match (name.trim().to_lowercase().as_str(), args.as_slice()) {
+        ("wm", [k, m]) => KeyAction::Single(Action::KeyWithModifier(parse_keycode(k)?, parse_modifiers(m)?)),
+        ("shifted", [k]) => KeyAction::Single(Action::KeyWithModifier(
+            parse_keycode(k)?,
+            ModifierCombination::new_from(false, false, false, true, false),
+        )),
+        ("mo", [n]) => KeyAction::Single(Action::LayerOn(parse_number(n)?)),
+        ("lm", [n, m]) => KeyAction::Single(Action::LayerOnWithModifier(parse_number(n)?, parse_modifiers(m)?)),
+        ("tg", [n]) => KeyAction::Single(Action::LayerToggle(parse_number(n)?)),
+        ("to", [n]) => KeyAction::Single(Action::LayerToggleOnly(parse_number(n)?)),
+        ("df", [n]) => KeyAction::Single(Action::DefaultLayer(parse_number(n)?)),
+        ("osl", [n]) => KeyAction::Single(Action::OneShotLayer(parse_number(n)?)),
+        ("osm", [m]) => KeyAction::Single(Action::OneShotModifier(parse_modifiers(m)?)),
+        ("macro", [n]) => KeyAction::Single(Action::TriggerMacro(parse_number(n)?)),
+        ("gp", [n]) => match parse_number(n)? {
+            button if button < 32 => KeyAction::Single(Action::GamepadButton(button)),
+            _ => return Err(unsupported("The gamepad button should be in 0..=31")),
+        },
+        ("hat", [d]) => KeyAction::Single(Action::GamepadHat(match d.to_lowercase().as_str() {
+            "up" => HatDirection::Up,
+            "down" => HatDirection::Down,
+            "left" => HatDirection::Left,
+            "right" => HatDirection::Right,
+            _ => return Err(BackupTomlError::Unsupported(format!("Invalid key: {}", key))),
+        })),
+        ("kmp", [n]) => KeyAction::Single(Action::SwitchKeymapProfile(parse_number(n)?)),
+        ("td" | "morse", [n]) => KeyAction::Morse(parse_number(n)?),
+        ("tt", [n]) => {
+            let layer = parse_number(n)?;
+            KeyAction::TapHold(
+                Action::LayerToggle(layer),
+                Action::LayerOn(layer),
+                MorseProfile::const_default(),
+            )
+        }
+        ("lt", [n, k, rest @ ..]) => KeyAction::TapHold(
+            Action::Key(parse_keycode(k)?),
+            Action::LayerOn(parse_number(n)?),
+            profile(rest)?,
+        ),
+        ("mt", [k, m, rest @ ..]) => KeyAction::TapHold(
+            Action::Key(parse_keycode(k)?),
+            Action::Modifier(parse_modifiers(m)?),
+            profile(rest)?,
+        ),
+        ("th", [t, h, rest @ ..]) => KeyAction::TapHold(
+            Action::Key(parse_keycode(t)?),
+            Action::Key(parse_keycode(h)?),
+            profile(rest)?,
+        ),
+        _ => return Err(BackupTomlError::Unsupported(format!("Invalid key: {}", key))),
+    };
+    Ok(action)
+}
+
+fn parse_number(number: &str) -> Result<u8, BackupTomlError> {
+    number
+        .parse()
+        .map_err(|_| BackupTomlError::Unsupported(format!("Invalid number: {}", number)))
+}
+
+fn parse_keycode(name: &str) -> Result<KeyCode, BackupTomlError> {
+    static KEYCODES: OnceLock<HashMap<String, KeyCode>> = OnceLock::new();
+    let keycodes = KEYCODES.get_or_init(|| {
+        (0..=u16::MAX)
+            .filter_map(KeyCode::from_repr)
+            .map(|k| (format!("{:?}", k), k))
+            .collect()
+    });
+    let name = name.trim();
+    let name = KEYCODE_ALIAS.get(name.to_lowercase().as_str()).copied().unwrap_or(name);
+    keycodes
+        .get(name)
+        .copied()
+        .ok_or_else(|| BackupTomlError::Unsupported(format!("Unknown keycode: {}", name)))
+}
+
+fn parse_modifiers(modifiers: &str) -> Result<ModifierCombination, BackupTomlError> {
+    let (mut right, mut gui, mut alt, mut shift, mut ctrl) = (false, false, false, false, false);
+    for name in modifiers.split('|').map(str::trim) {
+        let name = KEYCODE_ALIAS.get(name.to_lowercase().as_str()).copied().unwrap_or(name);
+        match name {
+            "LShift" | "RShift" => shift = true,
+            "LCtrl" | "RCtrl" => ctrl = true,
+            "LAlt" | "RAlt" => alt = true,
+            "LGui" | "RGui" => gui = true,
+            _ => {
+                return Err(BackupTomlError::Unsupported(format!(
+                    "Invalid modifiers: {}",
+                    modifiers
+                )));
+            }
+        }
+        right |= name.starts_with('R');
+    }
+    Ok(ModifierCombination::new_from(right, gui, alt, shift, ctrl))
+}
+
+fn parse_states(states: &str) -> Result<StateBits, BackupTomlError> {
+    let mut flags = [false; 21];
+    for name in states.split('|').map(str::trim) {
+        let idx = [
+            "LCtrl",
+            "LShift",
+            "LAlt",
+            "LGui",
+            "RCtrl",
+            "RShift",
+            "RAlt",
+            "RGui",
+            "NumLock",
+            "CapsLock",
+            "ScrollLock",
+            "Compose",
+            "Kana",
+            "MouseBtn1",
+            "MouseBtn2",
+            "MouseBtn3",
+            "MouseBtn4",
+            "MouseBtn5",
+            "MouseBtn6",
+            "MouseBtn7",
+            "MouseBtn8",
+        ]
+        .iter()
+        .position(|n| *n == name)
+        .ok_or_else(|| BackupTomlError::Unsupported(format!("Invalid states: {}", states)))?;
+        flags[idx] = true;
+    }
+    let f = flags;
+    Ok(StateBits::new_from(
+        ModifierCombination::new_from_vals(f[0], f[1], f[2], f[3], f[4], f[5], f[6], f[7]),
+        LedIndicator::new_from(f[8], f[9], f[10], f[11], f[12]),
+        MouseButtons::new_from(f[13], f[14], f[15], f[16], f[17], f[18], f[19], f[20]),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAGMENT: &str = r#"
+[layout]
+rows = 2
+cols = 3
+layers = 2
+keymap = [
+    [["A", "LT(1, B)", "MT(C, LShift|LCtrl, fast)"], ["WM(D, RAlt)", "TD(0)", "MACRO(0)"]],
+    [["_", "No", "TG(1)"], ["OSM(LGui)", "HAT(Up)", "TT(1)"]],
+]
+encoder_map = [[["AudioVolUp", "AudioVolDown"]], [["_", "_"]]]
+
+[behavior.one_shot]
+timeout = "800ms"
+
+[behavior.combo]
+timeout = "60ms"
+combos = [{ actions = ["A", "LT(1, B)"], output = "Escape", layer = 0 }]
+
+[[behavior.macros.macros]]
+operations = [
+    { operation = "text", text = "Hi!" },
+    { operation = "delay", duration = "20ms" },
+    { operation = "tap", keycode = "Enter" },
+]
+
+[[behavior.fork.forks]]
+trigger = "Dot"
+negative_output = "Dot"
+positive_output = "WM(Semicolon, LShift)"
+match_any = "LShift|RShift"
+kept_modifiers = "LShift"
+
+[behavior.morse]
+hold_timeout = "200ms"
+prior_idle_time = "150ms"
+
+[behavior.morse.profiles]
+fast = { permissive_hold = true, hold_timeout = "120ms" }
+
+[[behavior.morse.morses]]
+profile = "fast"
+tap_actions = ["A", "B"]
+hold = "LCtrl"
+morse_actions = [{ pattern = ".-", action = "MO(1)" }]
+"#;
+
+    #[test]
+    fn test_toml_roundtrip() {
+        let backup = backup_from_toml(FRAGMENT).unwrap();
+        let toml = backup_to_toml(&backup).unwrap();
+        assert_eq!(backup_from_toml(&toml).unwrap(), backup);
+        assert_eq!(backup_to_toml(&backup_from_toml(&toml).unwrap()).unwrap(), toml);
+
+        let configuration = Configuration::decode(&backup).unwrap();
+        assert_eq!(configuration.layout, StorageLayout::with_size(2, 3, 2, 1));
+        let fast = MorseProfile::new(None, Some(MorseMode::PermissiveHold), Some(120), None);
+        assert_eq!(
+            configuration.keymap[0][0][2],
+            KeyAction::TapHold(
+                Action::Key(KeyCode::C),
+                Action::Modifier(ModifierCombination::LSHIFT | ModifierCombination::LCTRL),
+                fast
+            )
+        );
+        assert_eq!(
+            configuration.combos[0].output,
+            KeyAction::Single(Action::Key(KeyCode::Escape))
+        );
+        assert_eq!(configuration.combos[0].layer, Some(0));
+        assert_eq!(configuration.forks[0].kept_modifiers, ModifierCombination::LSHIFT);
+        assert_eq!(configuration.morses[0].profile, fast);
+        assert_eq!(configuration.morses[0].actions.len(), 4);
+        assert_eq!(configuration.behavior.one_shot_timeout, 800);
+        assert_eq!(configuration.behavior.combo_timeout, 60);
+        assert_eq!(configuration.behavior.prior_idle_time, 150);
+        assert_eq!(
+            configuration.behavior.morse_default_profile.hold_timeout_ms(),
+            Some(200)
+        );
+        assert!(toml.contains(r#"text = "Hi!""#));
+        assert!(toml.contains(r#"pattern = ".-""#));
+    }
+
+    #[test]
+    fn test_toml_errors() {
+        let invalid_key = FRAGMENT.replace("\"TG(1)\"", "\"TG(x)\"");
+        assert!(matches!(
+            backup_from_toml(&invalid_key),
+            Err(BackupTomlError::Unsupported(_))
+        ));
+        let invalid_size = FRAGMENT.replace("rows = 2", "rows = 3");
+        assert!(matches!(
+            backup_from_toml(&invalid_size),
+            Err(BackupTomlError::Unsupported(_))
+        ));
+        assert!(matches!(backup_from_toml("[layout"), Err(BackupTomlError::Toml(_))));
+
+        let mut backup = backup_from_toml(FRAGMENT).unwrap();
+        let len = backup.len();
+        backup[len - 1] ^= 0xFF;
+        assert_eq!(
+            backup_to_toml(&backup),
+            Err(BackupTomlError::Backup(BackupError::CrcMismatch))
+        );
+    }
+}
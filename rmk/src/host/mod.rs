@@ -1,4 +1,6 @@
 #[cfg(feature = "storage")]
+pub mod backup;
+#[cfg(feature = "storage")]
 pub(crate) mod storage;
 pub mod via;
 
@@ -1,16 +1,16 @@
-use embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash;
 use postcard::experimental::max_size::MaxSize;
 use rmk_types::action::{EncoderAction, KeyAction};
-use sequential_storage::cache::NoCache;
-use sequential_storage::map::{SerializationError, Value, fetch_item};
+use sequential_storage::map::{SerializationError, Value};
 use serde::{Deserialize, Serialize};
 
 use crate::combo::{Combo, ComboConfig};
 use crate::fork::Fork;
+use crate::keymap::KeyMap;
 use crate::morse::Morse;
+use crate::storage::backend::StorageBackend;
 use crate::storage::{
-    Storage, StorageData, StorageKeys, get_combo_key, get_fork_key, get_morse_key,
-    postcard_error_to_serialization_error, print_storage_error,
+    Storage, StorageData, StorageKeys, get_combo_key, get_encoder_config_key, get_fork_key, get_keymap_key,
+    get_morse_key, postcard_error_to_serialization_error, print_storage_error,
 };
 use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, MACRO_SPACE_SIZE, MORSE_MAX_NUM, ser_storage_variant};
 
@@ -149,68 +149,47 @@ impl Value<'_> for KeymapData {
     }
 }
 
-impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
-    Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>
+impl<B: StorageBackend, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>
 {
     pub(crate) async fn read_keymap(
         &mut self,
         keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
         encoder_map: &mut Option<&mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
     ) -> Result<(), ()> {
-        use sequential_storage::map::fetch_all_items;
-
-        let mut storage_cache = NoCache::new();
-        // Use fetch_all_items to speed up the keymap reading
-        let mut key_iterator = fetch_all_items::<u32, _, _>(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut storage_cache,
-            &mut self.buffer,
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?;
-
-        // Read all keymap keys and encoder configs
-        while let Some((_key, item)) = key_iterator
-            .next::<StorageData>(&mut self.buffer)
-            .await
-            .map_err(|e| print_storage_error::<F>(e))?
-        {
-            match item {
-                StorageData::VialData(KeymapData::KeymapKey(key)) => {
-                    let layer = key.layer as usize;
-                    let row = key.row as usize;
-                    let col = key.col as usize;
-                    if layer < NUM_LAYER && row < ROW && col < COL {
-                        keymap[layer][row][col] = key.action;
-                    }
+        // Read all keymap keys and encoder configs of the active keymap profile in a single scan,
+        // items under keys of older firmwares are ignored
+        let profile = self.keymap_profiles.config.active;
+        self.for_each_item(|key, item| match item {
+            StorageData::VialData(KeymapData::KeymapKey(keymap_key)) if key == get_keymap_key(profile, &keymap_key) => {
+                let layer = keymap_key.layer as usize;
+                let row = keymap_key.row as usize;
+                let col = keymap_key.col as usize;
+                if layer < NUM_LAYER && row < ROW && col < COL {
+                    keymap[layer][row][col] = keymap_key.action;
                 }
-                StorageData::VialData(KeymapData::Encoder(encoder)) => {
-                    if let Some(map) = encoder_map {
-                        let idx = encoder.idx as usize;
-                        let layer = encoder.layer as usize;
-                        if layer < NUM_LAYER && idx < NUM_ENCODER {
-                            map[layer][idx] = encoder.action;
-                        }
+            }
+            StorageData::VialData(KeymapData::Encoder(encoder))
+                if key == get_encoder_config_key(profile, encoder.idx, encoder.layer) =>
+            {
+                if let Some(map) = encoder_map {
+                    let idx = encoder.idx as usize;
+                    let layer = encoder.layer as usize;
+                    if layer < NUM_LAYER && idx < NUM_ENCODER {
+                        map[layer][idx] = encoder.action;
                     }
                 }
-                _ => continue,
             }
-        }
-
-        Ok(())
+            _ => {}
+        })
+        .await
     }
 
     pub(crate) async fn read_macro_cache(&mut self, macro_cache: &mut [u8]) -> Result<(), ()> {
-        let read_data = fetch_item::<u32, StorageData, _>(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut NoCache::new(),
-            &mut self.buffer,
-            &(StorageKeys::MacroData as u32),
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        let read_data = self
+            .fetch_item(StorageKeys::MacroData as u32)
+            .await
+            .map_err(print_storage_error)?;
 
         if let Some(StorageData::VialData(KeymapData::Macro(data))) = read_data {
             macro_cache.copy_from_slice(&data);
@@ -220,69 +199,92 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
     }
 
     pub(crate) async fn read_combos(&mut self, combos: &mut [Option<Combo>; COMBO_MAX_NUM]) -> Result<(), ()> {
-        use crate::combo::Combo;
-
-        for (i, item) in combos.iter_mut().enumerate() {
-            let key = get_combo_key(i as u8);
-            let read_data = fetch_item::<u32, StorageData, _>(
-                &mut self.flash,
-                self.storage_range.clone(),
-                &mut NoCache::new(),
-                &mut self.buffer,
-                &key,
-            )
-            .await
-            .map_err(|e| print_storage_error::<F>(e))?;
-
-            if let Some(StorageData::VialData(KeymapData::Combo(_idx, config))) = read_data {
+        let profile = self.keymap_profiles.config.active;
+        self.for_each_item(|key, item| {
+            if let StorageData::VialData(KeymapData::Combo(idx, config)) = item
+                && key == get_combo_key(profile, idx)
+                && let Some(combo) = combos.get_mut(idx as usize)
+            {
                 debug!("Read combo config: {:?}", config);
-                *item = Some(Combo::new(config));
+                *combo = Some(Combo::new(config));
             }
-        }
-
-        Ok(())
+        })
+        .await
     }
 
     pub(crate) async fn read_forks(&mut self, forks: &mut heapless::Vec<Fork, FORK_MAX_NUM>) -> Result<(), ()> {
-        for (i, item) in forks.iter_mut().enumerate() {
-            let key = get_fork_key(i as u8);
-            let read_data = fetch_item::<u32, StorageData, _>(
-                &mut self.flash,
-                self.storage_range.clone(),
-                &mut NoCache::new(),
-                &mut self.buffer,
-                &key,
-            )
-            .await
-            .map_err(|e| print_storage_error::<F>(e))?;
-
-            if let Some(StorageData::VialData(KeymapData::Fork(_idx, fork))) = read_data {
+        self.for_each_item(|key, item| {
+            if let StorageData::VialData(KeymapData::Fork(idx, fork)) = item
+                && key == get_fork_key(idx)
+                && let Some(item) = forks.get_mut(idx as usize)
+            {
                 *item = fork;
             }
-        }
-
-        Ok(())
+        })
+        .await
     }
 
     pub(crate) async fn read_morses(&mut self, morses: &mut heapless::Vec<Morse, MORSE_MAX_NUM>) -> Result<(), ()> {
-        for (i, item) in morses.iter_mut().enumerate() {
-            let key = get_morse_key(i as u8);
-            let read_data = fetch_item::<u32, StorageData, _>(
-                &mut self.flash,
-                self.storage_range.clone(),
-                &mut NoCache::new(),
-                &mut self.buffer,
-                &key,
-            )
-            .await
-            .map_err(|e| print_storage_error::<F>(e))?;
+        let profile = self.keymap_profiles.config.active;
+        self.for_each_item(|key, item| {
+            if let StorageData::VialData(KeymapData::Morse(idx, morse)) = item
+                && key == get_morse_key(profile, idx)
+                && let Some(item) = morses.get_mut(idx as usize)
+            {
+                *item = morse;
+            }
+        })
+        .await
+    }
+}
 
-            if let Some(StorageData::VialData(KeymapData::Morse(_, morse))) = read_data {
+/// Apply the keymap data read from the storage, or from a backup, to the keymap
+pub(crate) fn apply_keymap_data<
+    const ROW: usize,
+    const COL: usize,
+    const NUM_LAYER: usize,
+    const NUM_ENCODER: usize,
+>(
+    keymap: &mut KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    data: KeymapData,
+) {
+    match data {
+        KeymapData::KeymapKey(k) => {
+            if let Some(action) = keymap
+                .layers
+                .get_mut(k.layer as usize)
+                .and_then(|l| l.get_mut(k.row as usize))
+                .and_then(|r| r.get_mut(k.col as usize))
+            {
+                *action = k.action;
+            }
+        }
+        KeymapData::Encoder(e) => {
+            if let Some(action) = keymap
+                .encoders
+                .as_mut()
+                .and_then(|m| m.get_mut(e.layer as usize))
+                .and_then(|l| l.get_mut(e.idx as usize))
+            {
+                *action = e.action;
+            }
+        }
+        KeymapData::Macro(m) => keymap.behavior.keyboard_macros.macro_sequences = m,
+        KeymapData::Combo(idx, config) => {
+            if let Some(combo) = keymap.behavior.combo.combos.get_mut(idx as usize) {
+                *combo = Some(Combo::new(config));
+            }
+        }
+        KeymapData::Fork(idx, fork) => {
+            if let Some(item) = keymap.behavior.fork.forks.get_mut(idx as usize) {
+                *item = fork;
+            }
+        }
+        KeymapData::Morse(idx, morse) => {
+            if let Some(item) = keymap.behavior.morse.morses.get_mut(idx as usize) {
                 *item = morse;
             }
         }
-
-        Ok(())
     }
 }
 
@@ -17,7 +17,7 @@ pub(crate) fn to_via_keycode(key_action: KeyAction) -> u16 {
                     k as u16 & 0xFF | 0x7700
                 } else if k.is_user() {
                     k as u16 & 0xF | 0x7E00
-                } else if k.is_combo() || k.is_boot() {
+                } else if k.is_combo() || k.is_boot() || k.is_cpi() {
                     // is_rmk() 's subset
                     k as u16 & 0xFF | 0x7C00
                 } else {
@@ -53,6 +53,8 @@ pub(crate) fn to_via_keycode(key_action: KeyAction) -> u16 {
                     0
                 }
             }
+            // QK_JOYSTICK_BUTTON_0 - QK_JOYSTICK_BUTTON_31
+            Action::GamepadButton(b) if b < 32 => 0x7400 | b as u16,
             _ => 0x0000,
         },
         KeyAction::Tap(_) => {
@@ -161,6 +163,11 @@ pub(crate) fn from_via_keycode(via_keycode: u16) -> KeyAction {
             warn!("QMK functions {:#X} not supported", via_keycode);
             KeyAction::No
         }
+        0x7400..=0x741F => {
+            // Joystick buttons
+            let button = (via_keycode & 0x1F) as u8;
+            KeyAction::Single(Action::GamepadButton(button))
+        }
         0x7700..=0x771F => {
             // Macro
             let keycode = via_keycode & 0xFF | 0x500;
@@ -171,8 +178,8 @@ pub(crate) fn from_via_keycode(via_keycode: u16) -> KeyAction {
             warn!("Backlight and RGB configuration key not supported");
             KeyAction::No
         }
-        // boot related | combo related
-        0x7C00..=0x7C01 | 0x7C50..=0x7C52 => {
+        // boot related | combo related | motion sensor cpi related
+        0x7C00..=0x7C01 | 0x7C50..=0x7C52 | 0x7C90..=0x7C92 => {
             // is_rmk() 's related
             let keycode = via_keycode & 0xFF | 0x700;
             KeyAction::Single(Action::Key(keycode.into()))
@@ -514,6 +521,11 @@ mod test {
         let a = KeyAction::Single(Action::Key(KeyCode::RepeatKey));
         assert_eq!(0x7C79, to_via_keycode(a));
 
+        // CpiCycle
+        let a = KeyAction::Single(Action::Key(KeyCode::CpiCycle));
+        assert_eq!(0x7C92, to_via_keycode(a));
+        assert_eq!(a, from_via_keycode(0x7C92));
+
         // Morse
         let a = KeyAction::Morse(0);
         assert_eq!(0x5700, to_via_keycode(a));
@@ -523,6 +535,11 @@ mod test {
 
         let a = KeyAction::Morse(255);
         assert_eq!(0x57FF, to_via_keycode(a));
+
+        // Gamepad button
+        let a = KeyAction::Single(Action::GamepadButton(3));
+        assert_eq!(0x7403, to_via_keycode(a));
+        assert_eq!(a, from_via_keycode(0x7403));
     }
 
     #[test]
@@ -4,19 +4,27 @@ use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use embassy_time::{Instant, Timer};
 use embassy_usb::class::hid::HidReaderWriter;
 use embassy_usb::driver::Driver;
-use rmk_types::protocol::vial::{VIA_FIRMWARE_VERSION, VIA_PROTOCOL_VERSION, ViaCommand, ViaKeyboardInfo};
+use rmk_types::protocol::vial::{
+    VIA_CUSTOM_CHANNEL_KEYBOARD, VIA_FIRMWARE_VERSION, VIA_PROTOCOL_VERSION, ViaCommand, ViaCustomValue,
+    ViaKeyboardInfo,
+};
 use ssmarshal::serialize;
 use vial::process_vial;
 
 use crate::config::VialConfig;
+use crate::debounce::chatter::{chatter_counters, clear_chatter_counters};
 use crate::descriptor::ViaReport;
 use crate::event::KeyboardEventPos;
 use crate::hid::{HidError, HidReaderTrait, HidWriterTrait};
 #[cfg(feature = "storage")]
+use crate::host::backup::{BackupError, BackupExport, BackupImport};
+#[cfg(feature = "storage")]
 use crate::host::storage::{KeymapData, KeymapKey};
 use crate::host::via::keycode_convert::{from_via_keycode, to_via_keycode};
 use crate::keymap::KeyMap;
 use crate::state::ConnectionState;
+#[cfg(feature = "storage")]
+use crate::storage::keymap_profile::request_keymap_profile_operation;
 use crate::{CONNECTION_STATE, MACRO_SPACE_SIZE, boot};
 #[cfg(feature = "storage")]
 use crate::{channel::FLASH_CHANNEL, storage::FlashOperationMessage};
@@ -44,6 +52,14 @@ pub(crate) struct VialService<
     #[cfg(feature = "vial_lock")]
     locker: vial_lock::VialLock<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>,
 
+    // Export of a configuration backup
+    #[cfg(feature = "storage")]
+    backup_export: BackupExport,
+
+    // Import of a configuration backup
+    #[cfg(feature = "storage")]
+    backup_import: BackupImport,
+
     // Usb vial hid reader writer
     pub(crate) reader_writer: RW,
 }
@@ -69,6 +85,10 @@ impl<
             vial_config,
             #[cfg(feature = "vial_lock")]
             locker: vial_lock::VialLock::<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>::new(vial_config.unlock_keys, keymap),
+            #[cfg(feature = "storage")]
+            backup_export: BackupExport::new(),
+            #[cfg(feature = "storage")]
+            backup_import: BackupImport::new(),
             reader_writer,
         }
     }
@@ -207,6 +227,153 @@ impl<
             ViaCommand::DynamicKeymapReset => {
                 warn!("Dynamic keymap reset -- not supported")
             }
+            ViaCommand::CustomSetValue if report.output_data[1] == VIA_CUSTOM_CHANNEL_KEYBOARD => {
+                match report.output_data[2].try_into() {
+                    Ok(ViaCustomValue::ChatterCounters) => clear_chatter_counters(),
+                    #[cfg(feature = "instrumentation")]
+                    Ok(ViaCustomValue::Instrumentation) => crate::instrumentation::reset_instrumentation(),
+                    #[cfg(not(feature = "instrumentation"))]
+                    Ok(ViaCustomValue::Instrumentation) => warn!("Instrumentation is not enabled"),
+                    #[cfg(feature = "storage")]
+                    Ok(ViaCustomValue::KeymapProfile) => {
+                        // Request: [cmd, channel, value id, operation, arg0, arg1]
+                        // Operations: 0 = switch to arg0, 1 = copy arg0 to arg1, 2 = clear arg0,
+                        // 3 = bind profile arg1 to target arg0 (0: USB, 1 + n: BLE profile n, arg1 = 0xFF to unbind)
+                        // Response: [cmd, channel, value id, status], status is 0 for success or 1 for failure
+                        let (arg0, arg1) = (report.output_data[4], report.output_data[5]);
+                        let succeeded = match report.output_data[3] {
+                            op @ 0..=3 => {
+                                request_keymap_profile_operation(|seq| match op {
+                                    0 => FlashOperationMessage::SwitchKeymapProfile(arg0, seq),
+                                    1 => FlashOperationMessage::CopyKeymapProfile(arg0, arg1, seq),
+                                    2 => FlashOperationMessage::ClearKeymapProfile(arg0, seq),
+                                    _ => FlashOperationMessage::BindKeymapProfile(arg0, arg1, seq),
+                                })
+                                .await
+                            }
+                            op => {
+                                error!("Invalid keymap profile operation: {}", op);
+                                false
+                            }
+                        };
+                        report.input_data[3] = if succeeded { 0 } else { 1 };
+                    }
+                    #[cfg(not(feature = "storage"))]
+                    Ok(ViaCustomValue::KeymapProfile) => warn!("Keymap profiles require the `storage` feature"),
+                    #[cfg(feature = "storage")]
+                    Ok(ViaCustomValue::Backup) => {
+                        // Request: [cmd, channel, value id, operation, args...]
+                        // Operations: 0 = begin, arg is 0 to verify or 1 to apply the verified backup,
+                        // 1 = write a chunk: [offset (2), size, data...], 2 = end
+                        // Response: [cmd, channel, value id, status], status is 0 for success or the error code
+                        let result = match report.output_data[3] {
+                            0 => self.backup_import.begin(report.output_data[4] == 1).await,
+                            1 => {
+                                let offset = BigEndian::read_u16(&report.output_data[4..6]) as usize;
+                                let size = (report.output_data[6] as usize).min(report.output_data.len() - 7);
+                                self.backup_import
+                                    .write::<ROW, COL, NUM_LAYER, NUM_ENCODER>(offset, &report.output_data[7..7 + size])
+                                    .await
+                            }
+                            2 => self.backup_import.end().await,
+                            op => {
+                                error!("Invalid backup operation: {}", op);
+                                Err(BackupError::UnexpectedChunk)
+                            }
+                        };
+                        report.input_data[3] = match result {
+                            Ok(()) => 0,
+                            Err(e) => {
+                                warn!("Failed to import backup: {:?}", e);
+                                e as u8
+                            }
+                        };
+                    }
+                    #[cfg(not(feature = "storage"))]
+                    Ok(ViaCustomValue::Backup) => warn!("Backup requires the `storage` feature"),
+                    #[cfg(feature = "storage")]
+                    Ok(ViaCustomValue::StorageStats) => FLASH_CHANNEL.send(FlashOperationMessage::Flush(None)).await,
+                    #[cfg(not(feature = "storage"))]
+                    Ok(ViaCustomValue::StorageStats) => warn!("Storage stats require the `storage` feature"),
+                    Err(e) => error!("Invalid value id: {} of CustomSetValue", e),
+                }
+            }
+            ViaCommand::CustomGetValue if report.output_data[1] == VIA_CUSTOM_CHANNEL_KEYBOARD => {
+                match report.output_data[2].try_into() {
+                    Ok(ViaCustomValue::ChatterCounters) => {
+                        // Request: [cmd, channel, value id, start index]
+                        // Response: [cmd, channel, value id, start index, total number, (row, col, count_h, count_l)...]
+                        let start = report.output_data[3] as usize;
+                        let counters = chatter_counters();
+                        report.input_data[4] = counters.len() as u8;
+                        report.input_data[5..].fill(0);
+                        for (i, counter) in counters.iter().skip(start).take(6).enumerate() {
+                            let offset = 5 + i * 4;
+                            report.input_data[offset] = counter.row;
+                            report.input_data[offset + 1] = counter.col;
+                            BigEndian::write_u16(&mut report.input_data[offset + 2..offset + 4], counter.count);
+                        }
+                    }
+                    #[cfg(feature = "instrumentation")]
+                    Ok(ViaCustomValue::Instrumentation) => {
+                        // Response: [cmd, channel, value id, scan rate (4), key event queue, key event queue peak,
+                        // event queue, event queue peak, processing avg (4), processing max (4), latency avg (4), latency max (4)]
+                        // Durations are in microseconds, all values are big endian
+                        let stats = crate::instrumentation::instrumentation();
+                        report.input_data[3..].fill(0);
+                        BigEndian::write_u32(&mut report.input_data[3..7], stats.scan_rate);
+                        report.input_data[7] = stats.key_event_queue;
+                        report.input_data[8] = stats.key_event_queue_peak;
+                        report.input_data[9] = stats.event_queue;
+                        report.input_data[10] = stats.event_queue_peak;
+                        BigEndian::write_u32(&mut report.input_data[11..15], stats.processing_time.avg());
+                        BigEndian::write_u32(&mut report.input_data[15..19], stats.processing_time.max);
+                        BigEndian::write_u32(&mut report.input_data[19..23], stats.report_latency.avg());
+                        BigEndian::write_u32(&mut report.input_data[23..27], stats.report_latency.max);
+                    }
+                    #[cfg(not(feature = "instrumentation"))]
+                    Ok(ViaCustomValue::Instrumentation) => warn!("Instrumentation is not enabled"),
+                    #[cfg(feature = "storage")]
+                    Ok(ViaCustomValue::KeymapProfile) => {
+                        // Response: [cmd, channel, value id, number of profiles, active profile, USB binding,
+                        // BLE profile bindings...], 0xFF means not bound
+                        let profiles = crate::storage::keymap_profile::KEYMAP_PROFILES.lock(|p| p.get());
+                        report.input_data[3..].fill(0);
+                        report.input_data[3] = profiles.num;
+                        report.input_data[4] = profiles.config.active;
+                        report.input_data[5] = profiles.config.usb;
+                        report.input_data[6..6 + profiles.config.ble.len()].copy_from_slice(&profiles.config.ble);
+                    }
+                    #[cfg(not(feature = "storage"))]
+                    Ok(ViaCustomValue::KeymapProfile) => warn!("Keymap profiles require the `storage` feature"),
+                    #[cfg(feature = "storage")]
+                    Ok(ViaCustomValue::Backup) => {
+                        // Request: [cmd, channel, value id, offset (2)]
+                        // Response: [cmd, channel, value id, offset (2), total size (2), data...]
+                        let offset = BigEndian::read_u16(&report.output_data[3..5]) as usize;
+                        let size = self
+                            .backup_export
+                            .read(&self.keymap.borrow(), offset, &mut report.input_data[7..]);
+                        BigEndian::write_u16(&mut report.input_data[5..7], size as u16);
+                    }
+                    #[cfg(not(feature = "storage"))]
+                    Ok(ViaCustomValue::Backup) => warn!("Backup requires the `storage` feature"),
+                    #[cfg(feature = "storage")]
+                    Ok(ViaCustomValue::StorageStats) => {
+                        // Response: [cmd, channel, value id, erase cycles (4), free space (4), capacity (4),
+                        // pending writes], all values are big endian
+                        let stats = crate::storage::stats::read().await;
+                        report.input_data[3..].fill(0);
+                        BigEndian::write_u32(&mut report.input_data[3..7], stats.erase_cycles);
+                        BigEndian::write_u32(&mut report.input_data[7..11], stats.free_space);
+                        BigEndian::write_u32(&mut report.input_data[11..15], stats.capacity);
+                        report.input_data[15] = stats.pending_writes;
+                    }
+                    #[cfg(not(feature = "storage"))]
+                    Ok(ViaCustomValue::StorageStats) => warn!("Storage stats require the `storage` feature"),
+                    Err(e) => error!("Invalid value id: {} of CustomGetValue", e),
+                }
+            }
             ViaCommand::CustomSetValue => {
                 // backlight/rgblight/rgb matrix/led matrix/audio settings here
                 warn!("Custom set value -- not supported")
@@ -216,8 +383,9 @@ impl<
                 warn!("Custom get value -- not supported")
             }
             ViaCommand::CustomSave => {
-                // backlight/rgblight/rgb matrix/led matrix/audio settings here
-                warn!("Custom get value -- not supported")
+                // Values of the keyboard are saved when they're set, save the pending keymap writes as well
+                #[cfg(feature = "storage")]
+                FLASH_CHANNEL.send(FlashOperationMessage::Flush(None)).await;
             }
             ViaCommand::EepromReset => {
                 warn!("Reseting storage..");
@@ -227,6 +395,8 @@ impl<
             }
             ViaCommand::BootloaderJump => {
                 warn!("Bootloader jumping");
+                #[cfg(feature = "storage")]
+                crate::storage::flush_before_reboot().await;
                 boot::jump_to_bootloader();
             }
             ViaCommand::DynamicKeymapMacroGetCount => {
@@ -8,7 +8,7 @@ use rmk_types::protocol::vial::{
 };
 
 use crate::combo::{Combo, ComboConfig};
-use crate::config::VialConfig;
+use crate::config::{MouseKeyConfig, VialConfig};
 use crate::descriptor::ViaReport;
 use crate::host::via::keycode_convert::{from_via_keycode, to_via_keycode};
 use crate::keymap::KeyMap;
@@ -111,17 +111,11 @@ pub(crate) async fn process_vial<
         }
         VialCommand::BehaviorSettingQuery => {
             report.input_data.fill(0xFF);
-            let value = u16::from_le_bytes([report.output_data[2], report.output_data[3]]);
-            if value <= 8 {
-                LittleEndian::write_u16(&mut report.input_data[0..2], 0x02);
-                LittleEndian::write_u16(&mut report.input_data[2..4], 0x06);
-                LittleEndian::write_u16(&mut report.input_data[4..6], 0x07);
-                LittleEndian::write_u16(&mut report.input_data[6..8], 0x12);
-                LittleEndian::write_u16(&mut report.input_data[8..10], 0x13);
-                LittleEndian::write_u16(&mut report.input_data[10..12], 0x16);
-                LittleEndian::write_u16(&mut report.input_data[12..14], 0x17);
-                LittleEndian::write_u16(&mut report.input_data[14..16], 0x1A);
-                LittleEndian::write_u16(&mut report.input_data[16..18], 0x1B);
+            // Return the settings after the given one, in ascending order
+            let after = u16::from_le_bytes([report.output_data[2], report.output_data[3]]);
+            let settings = SettingKey::ALL.iter().map(|&key| key as u16).filter(|&key| key > after);
+            for (data, key) in report.input_data.as_chunks_mut::<2>().0.iter_mut().zip(settings) {
+                *data = key.to_le_bytes();
             }
         }
         VialCommand::GetBehaviorSetting => {
@@ -153,8 +147,8 @@ pub(crate) async fn process_vial<
                     LittleEndian::write_u16(&mut report.input_data[1..3], tap_interval);
                 }
                 SettingKey::TapCapslockInterval => {
-                    let tap_interval = keymap.borrow().behavior.tap.tap_interval;
-                    LittleEndian::write_u16(&mut report.input_data[1..3], tap_interval);
+                    let tap_capslock_interval = keymap.borrow().behavior.tap.tap_capslock_interval;
+                    LittleEndian::write_u16(&mut report.input_data[1..3], tap_capslock_interval);
                 }
                 SettingKey::PermissiveHold => {
                     if let Some(m) = keymap.borrow().behavior.morse.default_profile.mode()
@@ -192,6 +186,33 @@ pub(crate) async fn process_vial<
                     let prior_idle_time = keymap.borrow().behavior.morse.prior_idle_time.as_millis() as u16;
                     LittleEndian::write_u16(&mut report.input_data[1..3], prior_idle_time);
                 }
+                SettingKey::FlowTap => {
+                    report.input_data[1] = keymap.borrow().behavior.morse.enable_flow_tap as u8;
+                }
+                SettingKey::TriLayer => {
+                    // 0xFF means that the tri-layer is disabled
+                    let tri_layer = keymap.borrow().behavior.tri_layer.unwrap_or([0xFF; 3]);
+                    report.input_data[1..4].copy_from_slice(&tri_layer);
+                }
+                SettingKey::CapsWordTimeout => {
+                    let caps_word_timeout = keymap.borrow().behavior.caps_word.timeout.as_millis() as u16;
+                    LittleEndian::write_u16(&mut report.input_data[1..3], caps_word_timeout);
+                }
+                key @ (SettingKey::MouseKeyDelay
+                | SettingKey::MouseKeyInterval
+                | SettingKey::MouseKeyMoveDelta
+                | SettingKey::MouseKeyMaxSpeed
+                | SettingKey::MouseKeyTimeToMax
+                | SettingKey::MouseKeyWheelDelay
+                | SettingKey::MouseKeyWheelInterval
+                | SettingKey::MouseKeyWheelMaxSpeed
+                | SettingKey::MouseKeyWheelTimeToMax
+                | SettingKey::MouseKeyWheelDelta
+                | SettingKey::MouseKeyMoveMax
+                | SettingKey::MouseKeyWheelMax) => {
+                    let value = get_mouse_key_setting(&keymap.borrow().behavior.mouse_key, key);
+                    LittleEndian::write_u16(&mut report.input_data[1..3], value);
+                }
             }
         }
         VialCommand::SetBehaviorSetting => {
@@ -305,6 +326,48 @@ pub(crate) async fn process_vial<
                         .send(FlashOperationMessage::PriorIdleTime(prior_idle_time))
                         .await;
                 }
+                SettingKey::FlowTap => {
+                    keymap.borrow_mut().behavior.morse.enable_flow_tap = report.output_data[4] == 1;
+                    #[cfg(feature = "storage")]
+                    save_behavior_config(keymap).await;
+                }
+                SettingKey::TriLayer => {
+                    let tri_layer: [u8; 3] = [report.output_data[4], report.output_data[5], report.output_data[6]];
+                    match parse_tri_layer::<NUM_LAYER>(tri_layer) {
+                        Some(tri_layer) => {
+                            keymap.borrow_mut().behavior.tri_layer = tri_layer;
+                            #[cfg(feature = "storage")]
+                            save_behavior_config(keymap).await;
+                        }
+                        None => {
+                            warn!("Invalid tri-layer {:?}, the setting is unchanged", tri_layer);
+                            report.input_data[0] = 0xFF;
+                        }
+                    }
+                }
+                SettingKey::CapsWordTimeout => {
+                    let timeout = u16::from_le_bytes([report.output_data[4], report.output_data[5]]);
+                    keymap.borrow_mut().behavior.caps_word.timeout = Duration::from_millis(timeout as u64);
+                    #[cfg(feature = "storage")]
+                    save_behavior_config(keymap).await;
+                }
+                key @ (SettingKey::MouseKeyDelay
+                | SettingKey::MouseKeyInterval
+                | SettingKey::MouseKeyMoveDelta
+                | SettingKey::MouseKeyMaxSpeed
+                | SettingKey::MouseKeyTimeToMax
+                | SettingKey::MouseKeyWheelDelay
+                | SettingKey::MouseKeyWheelInterval
+                | SettingKey::MouseKeyWheelMaxSpeed
+                | SettingKey::MouseKeyWheelTimeToMax
+                | SettingKey::MouseKeyWheelDelta
+                | SettingKey::MouseKeyMoveMax
+                | SettingKey::MouseKeyWheelMax) => {
+                    let value = u16::from_le_bytes([report.output_data[4], report.output_data[5]]);
+                    set_mouse_key_setting(&mut keymap.borrow_mut().behavior.mouse_key, key, value);
+                    #[cfg(feature = "storage")]
+                    save_behavior_config(keymap).await;
+                }
             }
         }
         VialCommand::DynamicEntryOp => {
@@ -553,6 +616,67 @@ pub(crate) async fn process_vial<
     }
 }
 
+/// Get the value of a mouse key setting
+fn get_mouse_key_setting(config: &MouseKeyConfig, key: SettingKey) -> u16 {
+    match key {
+        SettingKey::MouseKeyDelay => config.initial_delay_ms,
+        SettingKey::MouseKeyInterval => config.repeat_interval_ms,
+        SettingKey::MouseKeyMoveDelta => config.move_delta as u16,
+        SettingKey::MouseKeyMaxSpeed => config.max_speed as u16,
+        SettingKey::MouseKeyTimeToMax => config.time_to_max as u16,
+        SettingKey::MouseKeyWheelDelay => config.wheel_initial_delay_ms,
+        SettingKey::MouseKeyWheelInterval => config.wheel_repeat_interval_ms,
+        SettingKey::MouseKeyWheelMaxSpeed => config.wheel_max_speed_multiplier as u16,
+        SettingKey::MouseKeyWheelTimeToMax => config.wheel_time_to_max as u16,
+        SettingKey::MouseKeyWheelDelta => config.wheel_delta as u16,
+        SettingKey::MouseKeyMoveMax => config.move_max as u16,
+        SettingKey::MouseKeyWheelMax => config.wheel_max as u16,
+        _ => 0,
+    }
+}
+
+/// Set a mouse key setting, the value is saturated if the setting is a byte
+fn set_mouse_key_setting(config: &mut MouseKeyConfig, key: SettingKey, value: u16) {
+    let byte = value.min(u8::MAX as u16) as u8;
+    match key {
+        SettingKey::MouseKeyDelay => config.initial_delay_ms = value,
+        SettingKey::MouseKeyInterval => config.repeat_interval_ms = value,
+        SettingKey::MouseKeyMoveDelta => config.move_delta = byte,
+        SettingKey::MouseKeyMaxSpeed => config.max_speed = byte,
+        SettingKey::MouseKeyTimeToMax => config.time_to_max = byte,
+        SettingKey::MouseKeyWheelDelay => config.wheel_initial_delay_ms = value,
+        SettingKey::MouseKeyWheelInterval => config.wheel_repeat_interval_ms = value,
+        SettingKey::MouseKeyWheelMaxSpeed => config.wheel_max_speed_multiplier = byte,
+        SettingKey::MouseKeyWheelTimeToMax => config.wheel_time_to_max = byte,
+        SettingKey::MouseKeyWheelDelta => config.wheel_delta = byte,
+        SettingKey::MouseKeyMoveMax => config.move_max = byte,
+        SettingKey::MouseKeyWheelMax => config.wheel_max = byte,
+        _ => (),
+    }
+}
+
+/// Parse the tri-layer setting written by the host, `[0xFF; 3]` disables it.
+///
+/// Returns `None` if any of the layers is out of the keymap.
+fn parse_tri_layer<const NUM_LAYER: usize>(tri_layer: [u8; 3]) -> Option<Option<[u8; 3]>> {
+    if tri_layer == [0xFF; 3] {
+        Some(None)
+    } else if tri_layer.iter().all(|&l| (l as usize) < NUM_LAYER) {
+        Some(Some(tri_layer))
+    } else {
+        None
+    }
+}
+
+/// Save all behavior settings, for the settings which don't have their own flash operation
+#[cfg(feature = "storage")]
+async fn save_behavior_config<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+) {
+    let config = crate::storage::BehaviorConfig::from(&*keymap.borrow().behavior);
+    FLASH_CHANNEL.send(FlashOperationMessage::BehaviorConfig(config)).await;
+}
+
 #[cfg(test)]
 #[cfg(feature = "storage")]
 mod tests {
@@ -562,6 +686,27 @@ mod tests {
 
     use super::*;
     use crate::storage::StorageData;
+
+    #[test]
+    fn test_setting_keys() {
+        // Settings are queried in pages, which requires the ascending order
+        for keys in SettingKey::ALL.windows(2) {
+            assert!((keys[0] as u16) < (keys[1] as u16));
+        }
+        for key in SettingKey::ALL {
+            assert_eq!(SettingKey::from(key as u16), key);
+        }
+    }
+
+    #[test]
+    fn test_parse_tri_layer() {
+        assert_eq!(parse_tri_layer::<4>([1, 2, 3]), Some(Some([1, 2, 3])));
+        assert_eq!(parse_tri_layer::<4>([0xFF; 3]), Some(None));
+        // Invalid layers are rejected instead of disabling the tri-layer
+        assert_eq!(parse_tri_layer::<4>([1, 2, 4]), None);
+        assert_eq!(parse_tri_layer::<4>([1, 0xFF, 3]), None);
+    }
+
     #[test]
     fn test_combo_serialization_deserialization() {
         let mut actions = [KeyAction::No; COMBO_MAX_LENGTH];
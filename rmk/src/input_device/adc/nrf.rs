@@ -4,6 +4,7 @@ use embassy_time::{Duration, Instant};
 use super::{AdcState, AnalogEventType};
 use crate::event::{Axis, AxisEvent, AxisValType, Event};
 use crate::input_device::InputDevice;
+use crate::input_device::analog_matrix::AnalogChannels;
 
 pub struct NrfAdc<'a, const PIN_NUM: usize, const EVENT_NUM: usize> {
     saadc: Saadc<'a, PIN_NUM>,
@@ -128,3 +129,14 @@ impl<'a, const PIN_NUM: usize, const EVENT_NUM: usize> InputDevice for NrfAdc<'a
         ret_e
     }
 }
+
+impl<const PIN_NUM: usize> AnalogChannels for Saadc<'_, PIN_NUM> {
+    async fn sample(&mut self, values: &mut [u16]) {
+        let mut buf = [0i16; PIN_NUM];
+        Saadc::sample(self, &mut buf).await;
+        for (value, sample) in values.iter_mut().zip(buf.iter()) {
+            // Negative values are noise around the ground
+            *value = (*sample).max(0) as u16;
+        }
+    }
+}
@@ -0,0 +1,504 @@
+//! Analog key matrix, for hall-effect or other analog switches.
+//!
+//! The raw value of every key is read by an [`AnalogKeyReader`], e.g. ADC channels behind multiplexers,
+//! see [`MuxAnalogKeyReader`]. The raw value is converted to the travel distance using the per-key calibration,
+//! then the key is pressed or released according to the actuation point, the release point and rapid trigger.
+//! The changes are reported as ordinary [`KeyboardEvent`]s, so the rest of the stack works unchanged.
+//!
+//! Calibration:
+//! - The rest position of every key is sampled when the matrix starts, so no key should be pressed at that time.
+//!   If a key was calibrated before, and the sampled value is far from its saved rest position, the saved one is used.
+//! - The bottom-out position is extended automatically when a key is pressed further than ever.
+//!   Keys without calibration use [`AnalogMatrixConfig::default_range`].
+//! - Changed calibration is saved to the storage once no key changes for a while, and only if it drifts noticeably,
+//!   to reduce flash wear. The saved calibration can be read by [`crate::storage::Storage::read_analog_calibration`]
+//!   and applied by [`AnalogMatrix::with_calibration`].
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::OutputPin;
+
+use crate::event::{Event, KeyboardEvent};
+use crate::input_device::InputDevice;
+use crate::matrix::MatrixTrait;
+
+/// Number of samples averaged when calibrating the rest position
+const REST_SAMPLES: u32 = 8;
+/// Calibration is saved after no key changes for this time
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// Source of the raw values of the analog keys
+pub trait AnalogKeyReader<const ROW: usize, const COL: usize> {
+    /// Read the raw values of all keys
+    async fn read(&mut self, values: &mut [[u16; COL]; ROW]);
+}
+
+/// ADC with multiple channels which are sampled together
+pub trait AnalogChannels {
+    /// Sample all channels, `values[n]` is the value of channel `n`
+    async fn sample(&mut self, values: &mut [u16]);
+}
+
+/// Keys read by ADC channels behind analog multiplexers, such as 74HC4067.
+///
+/// Every ADC channel is connected to the output of a multiplexer, and all multiplexers share the select pins.
+/// The key at (row, col) is the input `col` of the multiplexer on ADC channel `row`.
+pub struct MuxAnalogKeyReader<A: AnalogChannels, SEL: OutputPin, const SEL_NUM: usize, const CHANNEL_NUM: usize> {
+    adc: A,
+    /// Select pins of the multiplexers, the first one is the least significant bit
+    select: [SEL; SEL_NUM],
+    /// Time for the multiplexer outputs to settle after switching
+    settle_time: Duration,
+}
+
+impl<A: AnalogChannels, SEL: OutputPin, const SEL_NUM: usize, const CHANNEL_NUM: usize>
+    MuxAnalogKeyReader<A, SEL, SEL_NUM, CHANNEL_NUM>
+{
+    pub fn new(adc: A, select: [SEL; SEL_NUM], settle_time: Duration) -> Self {
+        Self {
+            adc,
+            select,
+            settle_time,
+        }
+    }
+}
+
+impl<
+    A: AnalogChannels,
+    SEL: OutputPin,
+    const SEL_NUM: usize,
+    const CHANNEL_NUM: usize,
+    const ROW: usize,
+    const COL: usize,
+> AnalogKeyReader<ROW, COL> for MuxAnalogKeyReader<A, SEL, SEL_NUM, CHANNEL_NUM>
+{
+    async fn read(&mut self, values: &mut [[u16; COL]; ROW]) {
+        assert!(
+            ROW <= CHANNEL_NUM && COL <= 1 << SEL_NUM,
+            "Analog matrix is larger than the multiplexers"
+        );
+        let mut samples = [0u16; CHANNEL_NUM];
+        for col in 0..COL {
+            for (bit, pin) in self.select.iter_mut().enumerate() {
+                if (col >> bit) & 1 == 1 {
+                    pin.set_high().ok();
+                } else {
+                    pin.set_low().ok();
+                }
+            }
+            Timer::after(self.settle_time).await;
+            self.adc.sample(&mut samples).await;
+            for (row, value) in values.iter_mut().enumerate() {
+                value[col] = samples[row];
+            }
+        }
+    }
+}
+
+/// Raw values of a key at rest and bottom-out positions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogKeyCalibration {
+    pub rest: u16,
+    pub bottom: u16,
+}
+
+impl AnalogKeyCalibration {
+    fn range(&self) -> i32 {
+        self.bottom as i32 - self.rest as i32
+    }
+
+    /// Convert the raw value to the travel distance, from 0 to `total_travel`
+    fn travel(&self, raw: u16, total_travel: u16) -> u16 {
+        let range = self.range();
+        if range == 0 {
+            return 0;
+        }
+        ((raw as i32 - self.rest as i32) * total_travel as i32 / range).clamp(0, total_travel as i32) as u16
+    }
+
+    /// Extend the bottom-out position if the key is pressed further, returns true if it's extended
+    fn extend(&mut self, raw: u16) -> bool {
+        let offset = raw as i32 - self.rest as i32;
+        let range = self.range();
+        if offset.signum() == range.signum() && offset.abs() > range.abs() {
+            self.bottom = raw;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the calibration differs from `other` noticeably
+    fn drifts_from(&self, other: &AnalogKeyCalibration) -> bool {
+        let tolerance = (other.range().abs() / 32).max(1);
+        (self.rest as i32 - other.rest as i32).abs() > tolerance
+            || (self.bottom as i32 - other.bottom as i32).abs() > tolerance
+    }
+}
+
+/// Rapid trigger, a pressed key is released once it moves up by `release_sensitivity`,
+/// and it's pressed again once it moves down by `press_sensitivity`, no matter where the key is.
+///
+/// Rapid trigger works until the key goes above the release point, then the actuation point applies again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RapidTrigger {
+    /// Travel distance moving down to press the key again, in 0.01mm
+    pub press_sensitivity: u16,
+    /// Travel distance moving up to release the key, in 0.01mm
+    pub release_sensitivity: u16,
+}
+
+/// Config of the analog matrix, all distances are in 0.01mm
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogMatrixConfig {
+    /// Total travel distance of the switches
+    pub total_travel: u16,
+    /// A key is pressed when its travel reaches the actuation point
+    pub actuation_point: u16,
+    /// A key is released when its travel goes above the release point, should be less than `actuation_point`
+    pub release_point: u16,
+    /// Rapid trigger, disabled if `None`
+    pub rapid_trigger: Option<RapidTrigger>,
+    /// Difference of the raw values between the bottom-out and rest positions, used by keys which are not calibrated yet.
+    /// It's negative if the raw value decreases when the key is pressed.
+    pub default_range: i16,
+    /// Interval between two scans
+    pub scan_interval: Duration,
+}
+
+impl Default for AnalogMatrixConfig {
+    fn default() -> Self {
+        Self {
+            total_travel: 400,
+            actuation_point: 150,
+            release_point: 120,
+            rapid_trigger: None,
+            default_range: 1000,
+            scan_interval: Duration::from_micros(500),
+        }
+    }
+}
+
+/// Pressed state of an analog key
+#[derive(Clone, Copy, Debug, Default)]
+struct AnalogKeyState {
+    pressed: bool,
+    /// Deepest travel since pressed, or highest travel since released
+    extreme: u16,
+    /// Released by rapid trigger, so the next press is triggered by rapid trigger too
+    rapid: bool,
+}
+
+impl AnalogKeyState {
+    /// Update the state by the travel distance, returns the new pressed state if it's changed
+    fn update(&mut self, travel: u16, config: &AnalogMatrixConfig) -> Option<bool> {
+        if self.pressed {
+            self.extreme = self.extreme.max(travel);
+            let rapid_release = config
+                .rapid_trigger
+                .is_some_and(|rt| travel + rt.release_sensitivity <= self.extreme);
+            if travel < config.release_point || rapid_release {
+                self.pressed = false;
+                self.rapid = travel >= config.release_point;
+                self.extreme = travel;
+                return Some(false);
+            }
+        } else {
+            self.extreme = self.extreme.min(travel);
+            if travel < config.release_point {
+                self.rapid = false;
+            }
+            let press = match config.rapid_trigger {
+                Some(rt) if self.rapid => travel >= self.extreme + rt.press_sensitivity,
+                _ => travel >= config.actuation_point,
+            };
+            if press {
+                self.pressed = true;
+                self.extreme = travel;
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+/// Calibration and state of an analog key
+#[derive(Clone, Copy, Debug, Default)]
+struct AnalogKey {
+    calibration: AnalogKeyCalibration,
+    /// Calibration in the storage
+    saved: Option<AnalogKeyCalibration>,
+    state: AnalogKeyState,
+}
+
+/// Analog key matrix, which reports the key changes as [`KeyboardEvent`]s
+pub struct AnalogMatrix<R: AnalogKeyReader<ROW, COL>, const ROW: usize, const COL: usize> {
+    reader: R,
+    config: AnalogMatrixConfig,
+    keys: [[AnalogKey; COL]; ROW],
+    /// Raw values of the latest scan
+    values: [[u16; COL]; ROW],
+    /// Next key to be checked in the latest scan: (row_idx, col_idx)
+    scan_pos: (usize, usize),
+    /// The rest positions are calibrated
+    calibrated: bool,
+    /// Time of the last key change, if the changed calibration is not saved yet
+    unsaved_since: Option<Instant>,
+}
+
+impl<R: AnalogKeyReader<ROW, COL>, const ROW: usize, const COL: usize> AnalogMatrix<R, ROW, COL> {
+    pub fn new(reader: R, config: AnalogMatrixConfig) -> Self {
+        Self {
+            reader,
+            config,
+            keys: [[AnalogKey::default(); COL]; ROW],
+            values: [[0; COL]; ROW],
+            scan_pos: (ROW, 0),
+            calibrated: false,
+            unsaved_since: None,
+        }
+    }
+
+    /// Use the calibration saved in the storage
+    pub fn with_calibration(mut self, calibration: [[Option<AnalogKeyCalibration>; COL]; ROW]) -> Self {
+        for (keys, saved) in self.keys.iter_mut().zip(calibration.iter()) {
+            for (key, saved) in keys.iter_mut().zip(saved.iter()) {
+                key.saved = *saved;
+            }
+        }
+        self
+    }
+
+    /// Sample the rest positions of all keys
+    async fn calibrate_rest(&mut self) {
+        let mut sums = [[0u32; COL]; ROW];
+        for _ in 0..REST_SAMPLES {
+            self.reader.read(&mut self.values).await;
+            for (sums, values) in sums.iter_mut().zip(self.values.iter()) {
+                for (sum, value) in sums.iter_mut().zip(values.iter()) {
+                    *sum += *value as u32;
+                }
+            }
+            Timer::after(self.config.scan_interval).await;
+        }
+
+        for (keys, sums) in self.keys.iter_mut().zip(sums.iter()) {
+            for (key, sum) in keys.iter_mut().zip(sums.iter()) {
+                let rest = (sum / REST_SAMPLES) as u16;
+                key.calibration = match key.saved {
+                    Some(saved) => {
+                        // A key held at boot is far from its saved rest position
+                        let offset = rest as i32 - saved.rest as i32;
+                        if offset.signum() == saved.range().signum() && offset.abs() > saved.range().abs() / 2 {
+                            saved
+                        } else {
+                            AnalogKeyCalibration {
+                                rest,
+                                bottom: (rest as i32 + saved.range()).clamp(0, u16::MAX as i32) as u16,
+                            }
+                        }
+                    }
+                    None => AnalogKeyCalibration {
+                        rest,
+                        bottom: (rest as i32 + self.config.default_range as i32).clamp(0, u16::MAX as i32) as u16,
+                    },
+                };
+            }
+        }
+        self.unsaved_since = Some(Instant::now());
+        self.calibrated = true;
+    }
+
+    /// Check the next changed key in the latest scan
+    fn next_change(&mut self) -> Option<Event> {
+        let (row_start, col_start) = self.scan_pos;
+        for row_idx in row_start..ROW {
+            let col_start = if row_idx == row_start { col_start } else { 0 };
+            for col_idx in col_start..COL {
+                let raw = self.values[row_idx][col_idx];
+                let key = &mut self.keys[row_idx][col_idx];
+                key.calibration.extend(raw);
+                let travel = key.calibration.travel(raw, self.config.total_travel);
+                if let Some(pressed) = key.state.update(travel, &self.config) {
+                    self.scan_pos = (row_idx, col_idx + 1);
+                    return Some(Event::Key(
+                        KeyboardEvent::key(row_idx as u8, col_idx as u8, pressed).with_timestamp(Instant::now()),
+                    ));
+                }
+            }
+        }
+        None
+    }
+
+    /// Save the drifted calibration after the keys are idle for a while
+    async fn save_calibration(&mut self) {
+        match self.unsaved_since {
+            Some(since) if since.elapsed() >= SAVE_DELAY => self.unsaved_since = None,
+            _ => return,
+        }
+        for (row_idx, keys) in self.keys.iter_mut().enumerate() {
+            for (col_idx, key) in keys.iter_mut().enumerate() {
+                if key.saved.is_none_or(|saved| key.calibration.drifts_from(&saved)) {
+                    info!(
+                        "Analog key ({}, {}): calibrated rest {}, bottom {}",
+                        row_idx, col_idx, key.calibration.rest, key.calibration.bottom
+                    );
+                    key.saved = Some(key.calibration);
+                    #[cfg(feature = "storage")]
+                    {
+                        use crate::channel::FLASH_CHANNEL;
+                        use crate::storage::FlashOperationMessage;
+
+                        FLASH_CHANNEL
+                            .send(FlashOperationMessage::AnalogCalibration(
+                                row_idx as u8,
+                                col_idx as u8,
+                                [key.calibration.rest, key.calibration.bottom],
+                            ))
+                            .await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: AnalogKeyReader<ROW, COL>, const ROW: usize, const COL: usize> InputDevice for AnalogMatrix<R, ROW, COL> {
+    async fn read_event(&mut self) -> Event {
+        if !self.calibrated {
+            self.calibrate_rest().await;
+        }
+        loop {
+            if let Some(event) = self.next_change() {
+                self.unsaved_since = Some(Instant::now());
+                return event;
+            }
+            self.save_calibration().await;
+            Timer::after(self.config.scan_interval).await;
+            self.reader.read(&mut self.values).await;
+            self.scan_pos = (0, 0);
+            #[cfg(feature = "instrumentation")]
+            crate::instrumentation::record_scan();
+        }
+    }
+}
+
+impl<R: AnalogKeyReader<ROW, COL>, const ROW: usize, const COL: usize> MatrixTrait<ROW, COL>
+    for AnalogMatrix<R, ROW, COL>
+{
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_key(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::debounce::next_key;
+
+    fn run(config: &AnalogMatrixConfig, travels: &[u16]) -> heapless::Vec<(u16, bool), 16> {
+        let mut state = AnalogKeyState::default();
+        let mut changes = heapless::Vec::new();
+        for travel in travels {
+            if let Some(pressed) = state.update(*travel, config) {
+                changes.push((*travel, pressed)).unwrap();
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn test_actuation_and_release_points() {
+        let config = AnalogMatrixConfig::default();
+        let changes = run(&config, &[0, 100, 149, 150, 300, 400, 130, 121, 119, 140, 0]);
+        assert_eq!(changes.as_slice(), &[(150, true), (119, false)]);
+    }
+
+    #[test]
+    fn test_rapid_trigger() {
+        let config = AnalogMatrixConfig {
+            rapid_trigger: Some(RapidTrigger {
+                press_sensitivity: 20,
+                release_sensitivity: 30,
+            }),
+            ..Default::default()
+        };
+        let changes = run(
+            &config,
+            &[
+                0, 150, // Actuated at the actuation point
+                300, 280, 270, // Released after moving up by 30
+                260, 280, // Pressed again after moving down by 20
+                400, 380, 375, 370, // Released at 370, since the deepest travel is 400
+                100, 130, 149, // Above the release point, the actuation point applies again
+                150,
+            ],
+        );
+        assert_eq!(
+            changes.as_slice(),
+            &[(150, true), (270, false), (280, true), (370, false), (150, true)]
+        );
+    }
+
+    #[test]
+    fn test_calibration() {
+        // Raw value decreases when pressed
+        let mut calibration = AnalogKeyCalibration {
+            rest: 2000,
+            bottom: 1000,
+        };
+        assert_eq!(calibration.travel(2100, 400), 0);
+        assert_eq!(calibration.travel(1500, 400), 200);
+        assert_eq!(calibration.travel(900, 400), 400);
+        assert!(!calibration.extend(1200));
+        assert!(calibration.extend(900));
+        assert_eq!(calibration.bottom, 900);
+        assert!(!calibration.drifts_from(&AnalogKeyCalibration {
+            rest: 2010,
+            bottom: 910
+        }));
+        assert!(calibration.drifts_from(&AnalogKeyCalibration {
+            rest: 2000,
+            bottom: 1000
+        }));
+    }
+
+    /// Analog values of a 2x2 matrix, the raw value increases when pressed
+    struct MockReader<'a>(&'a RefCell<[[u16; 2]; 2]>);
+
+    impl AnalogKeyReader<2, 2> for MockReader<'_> {
+        async fn read(&mut self, values: &mut [[u16; 2]; 2]) {
+            *values = *self.0.borrow();
+        }
+    }
+
+    #[test]
+    fn test_analog_matrix() {
+        let values = RefCell::new([[500, 520], [480, 2000]]);
+        let mut calibration = [[None; 2]; 2];
+        // Saved calibration of a key, which is held at boot
+        calibration[1][1] = Some(AnalogKeyCalibration {
+            rest: 500,
+            bottom: 2100,
+        });
+        let mut matrix =
+            AnalogMatrix::new(MockReader(&values), AnalogMatrixConfig::default()).with_calibration(calibration);
+
+        // The held key uses its saved rest position, so it's pressed
+        assert_eq!(next_key(&mut matrix), Some((1, 1, true)));
+        assert_eq!(next_key(&mut matrix), None);
+
+        // Default range is 1000, the actuation point is 1.5mm of 4mm
+        values.borrow_mut()[0][1] = 520 + 370;
+        assert_eq!(next_key(&mut matrix), None);
+        values.borrow_mut()[0][1] = 520 + 380;
+        assert_eq!(next_key(&mut matrix), Some((0, 1, true)));
+        values.borrow_mut()[0][1] = 520;
+        values.borrow_mut()[1][1] = 500;
+        assert_eq!(next_key(&mut matrix), Some((0, 1, false)));
+        assert_eq!(next_key(&mut matrix), Some((1, 1, false)));
+    }
+}
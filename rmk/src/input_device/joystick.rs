@@ -1,13 +1,138 @@
-use core::cell::RefCell;
+//! Joystick processor
+//!
+//! The raw axis values from the [`crate::input_device::adc`] device are processed in the following order:
+//!
+//! 1. Centering, using the boot-time calibrated center if available, or the configured `bias`
+//! 2. Radial deadzone and axial deadzone
+//! 3. Response curve
+//! 4. Mouse mode: `transform` and `resolution`, then a mouse report is sent.
+//!    Digital mode: stick directions are converted to key events at the configured keymap positions.
+//!    Gamepad mode: the values are scaled by `range` and sent as gamepad axes.
 
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
 use usbd_hid::descriptor::MouseReport;
 
-use crate::channel::KEYBOARD_REPORT_CHANNEL;
-use crate::event::Event;
+use crate::RawMutex;
+use crate::channel::{KEY_EVENT_CHANNEL, KEYBOARD_REPORT_CHANNEL};
+use crate::event::{Event, KeyPos, KeyboardEvent};
 use crate::hid::Report;
 use crate::input_device::{InputProcessor, ProcessResult};
 use crate::keymap::KeyMap;
 
+/// Maximum number of joysticks which can persist their calibrated center
+pub const MAX_JOYSTICKS: usize = 4;
+
+/// Calibrated centers loaded from the storage, indexed by the joystick id
+pub(crate) static SAVED_JOYSTICK_CENTER: Mutex<RawMutex, Cell<[Option<[i16; 3]>; MAX_JOYSTICKS]>> =
+    Mutex::new(Cell::new([None; MAX_JOYSTICKS]));
+
+/// Response curve applied to the centered axis values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResponseCurve {
+    /// Output is proportional to the input
+    #[default]
+    Linear,
+    /// Output grows with the square of the input, which gives finer control near the center
+    Quadratic,
+    /// Output grows with the cube of the input
+    Cubic,
+}
+
+/// Keymap positions triggered by the stick directions in digital mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DigitalKeys {
+    pub up: Option<KeyPos>,
+    pub down: Option<KeyPos>,
+    pub left: Option<KeyPos>,
+    pub right: Option<KeyPos>,
+    /// The key is pressed when the axis value exceeds this threshold
+    pub threshold: u16,
+    /// The key is released when the axis value drops below this threshold, should be less than `threshold`
+    pub release_threshold: u16,
+}
+
+/// Output mode of the joystick
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JoystickMode {
+    /// Move the mouse cursor
+    #[default]
+    Mouse,
+    /// Trigger keys in the keymap, for example arrow keys or WASD on a layer
+    Digital(DigitalKeys),
+    /// Report as the analog stick of the gamepad
+    #[cfg(feature = "gamepad")]
+    Gamepad(GamepadStick),
+}
+
+/// Gamepad axes used by the joystick
+#[cfg(feature = "gamepad")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GamepadStick {
+    /// X, Y and Z axes
+    #[default]
+    Left,
+    /// Rx, Ry and Rz axes
+    Right,
+}
+
+/// State of the boot-time center calibration
+struct Calibration<const N: usize> {
+    /// Number of samples to collect
+    samples: u16,
+    /// Maximum spread of the samples, larger spread means the stick is not at rest
+    tolerance: u16,
+    count: u16,
+    sum: [i32; N],
+    min: [i16; N],
+    max: [i16; N],
+}
+
+impl<const N: usize> Calibration<N> {
+    fn new(samples: u16, tolerance: u16) -> Self {
+        Self {
+            samples: samples.max(1),
+            tolerance,
+            count: 0,
+            sum: [0; N],
+            min: [i16::MAX; N],
+            max: [i16::MIN; N],
+        }
+    }
+
+    /// Add a sample, returns `true` when all samples are collected
+    fn add(&mut self, record: &[i16; N]) -> bool {
+        for (i, value) in record.iter().enumerate() {
+            self.sum[i] += *value as i32;
+            self.min[i] = self.min[i].min(*value);
+            self.max[i] = self.max[i].max(*value);
+        }
+        self.count += 1;
+        self.count >= self.samples
+    }
+
+    /// Get the center, returns `None` if the stick moved during the calibration
+    fn center(&self) -> Option<[i16; N]> {
+        if self.count == 0 {
+            return None;
+        }
+        let mut center = [0; N];
+        for (i, c) in center.iter_mut().enumerate() {
+            if (self.max[i] as i32 - self.min[i] as i32) > self.tolerance as i32 {
+                return None;
+            }
+            *c = (self.sum[i] / self.count as i32) as i16;
+        }
+        Some(center)
+    }
+}
+
 pub struct JoystickProcessor<
     'a,
     const ROW: usize,
@@ -21,6 +146,17 @@ pub struct JoystickProcessor<
     keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     record: [i16; N],
     resolution: u16,
+    /// Id of the joystick, used to save the calibrated center
+    id: u8,
+    calibration: Option<Calibration<N>>,
+    radial_deadzone: u16,
+    axial_deadzone: u16,
+    curve: ResponseCurve,
+    /// Maximum deflection of the centered axis value
+    range: u16,
+    mode: JoystickMode,
+    /// Pressed state of the digital keys: up, down, left, right
+    pressed: [bool; 4],
 }
 
 impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize, const N: usize>
@@ -38,8 +174,130 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             resolution,
             keymap,
             record: [0; N],
+            id: 0,
+            calibration: None,
+            radial_deadzone: 0,
+            axial_deadzone: 0,
+            curve: ResponseCurve::Linear,
+            range: i16::MAX as u16,
+            mode: JoystickMode::Mouse,
+            pressed: [false; 4],
+        }
+    }
+
+    /// Set the joystick id, which is used to save the calibrated center to storage.
+    pub fn with_id(mut self, id: u8) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Calibrate the center using the first `samples` readings after boot.
+    ///
+    /// If the readings spread more than `tolerance`, the stick is considered not at rest,
+    /// then the center saved in the storage is used, or the `bias` if there's no saved center.
+    pub fn with_calibration(mut self, samples: u16, tolerance: u16) -> Self {
+        self.calibration = Some(Calibration::new(samples, tolerance));
+        self
+    }
+
+    /// Set the radial deadzone, applied to the length of the stick vector, and the axial deadzone, applied to each axis.
+    pub fn with_deadzone(mut self, radial: u16, axial: u16) -> Self {
+        self.radial_deadzone = radial;
+        self.axial_deadzone = axial;
+        self
+    }
+
+    /// Set the response curve. `range` is the maximum deflection of the centered axis value.
+    pub fn with_curve(mut self, curve: ResponseCurve, range: u16) -> Self {
+        self.curve = curve;
+        self.range = range.clamp(1, i16::MAX as u16);
+        self
+    }
+
+    /// Set the output mode of the joystick.
+    pub fn with_mode(mut self, mode: JoystickMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    fn saved_center(&self) -> Option<[i16; N]> {
+        let saved = SAVED_JOYSTICK_CENTER.lock(|c| c.get());
+        let center = saved.get(self.id as usize).copied().flatten()?;
+        if N > center.len() {
+            return None;
+        }
+        let mut result = [0; N];
+        result.copy_from_slice(&center[..N]);
+        Some(result)
+    }
+
+    fn apply_center(&mut self, center: &[i16; N]) {
+        for (b, c) in self.bias.iter_mut().zip(center.iter()) {
+            *b = c.saturating_neg();
+        }
+    }
+
+    /// Feed a sample to the calibration, returns `true` if the calibration is still in progress
+    async fn calibrate(&mut self) -> bool {
+        let Some(calibration) = self.calibration.as_mut() else {
+            return false;
+        };
+        if !calibration.add(&self.record) {
+            return true;
+        }
+
+        let tolerance = calibration.tolerance as i32;
+        let center = calibration.center();
+        self.calibration = None;
+        match center {
+            Some(center) => {
+                info!("Joystick {}: calibrated center {:?}", self.id, center);
+                let saved = self.saved_center();
+                self.apply_center(&center);
+                // Only write the flash when the center drifts, to reduce flash wear
+                let changed = match saved {
+                    Some(saved) => saved
+                        .iter()
+                        .zip(center.iter())
+                        .any(|(s, c)| (*s as i32 - *c as i32).abs() > tolerance),
+                    None => true,
+                };
+                if changed {
+                    self.save_center(center).await;
+                }
+            }
+            None => {
+                warn!("Joystick {}: stick is not at rest, calibration skipped", self.id);
+                if let Some(saved) = self.saved_center() {
+                    self.apply_center(&saved);
+                }
+            }
+        }
+        false
+    }
+
+    async fn save_center(&self, center: [i16; N]) {
+        if self.id as usize >= MAX_JOYSTICKS || N > 3 {
+            return;
+        }
+        let mut value = [0; 3];
+        value[..N].copy_from_slice(&center);
+        SAVED_JOYSTICK_CENTER.lock(|c| {
+            let mut saved = c.get();
+            saved[self.id as usize] = Some(value);
+            c.set(saved);
+        });
+        #[cfg(feature = "storage")]
+        {
+            use crate::channel::FLASH_CHANNEL;
+            use crate::storage::FlashOperationMessage;
+
+            FLASH_CHANNEL
+                .send(FlashOperationMessage::JoystickCenter(self.id, value))
+                .await;
         }
     }
+
     async fn generate_report(&mut self) {
         let mut report = [0i16; N];
 
@@ -47,6 +305,24 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         for (rec, b) in self.record.iter_mut().zip(self.bias.iter()) {
             *rec = rec.saturating_add(*b);
         }
+        shape(
+            &mut self.record,
+            self.radial_deadzone,
+            self.axial_deadzone,
+            self.curve,
+            self.range,
+        );
+
+        if let JoystickMode::Digital(keys) = self.mode {
+            self.process_digital(&keys).await;
+            return;
+        }
+
+        #[cfg(feature = "gamepad")]
+        if let JoystickMode::Gamepad(stick) = self.mode {
+            self.process_gamepad(stick).await;
+            return;
+        }
 
         for (rep, transform) in report.iter_mut().zip(self.transform.iter()) {
             for (w, v) in transform.iter().zip(self.record) {
@@ -70,6 +346,87 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         };
         self.send_report(Report::MouseReport(mouse_report)).await;
     }
+
+    /// Scale the axis values to the gamepad axes, `range` is mapped to the full range of the axis
+    #[cfg(feature = "gamepad")]
+    async fn process_gamepad(&mut self, stick: GamepadStick) {
+        let mut axes = [0i8; 3];
+        for (axis, v) in axes.iter_mut().zip(self.record.iter()) {
+            *axis = (*v as i32 * i8::MAX as i32 / self.range as i32).clamp(-(i8::MAX as i32), i8::MAX as i32) as i8;
+        }
+        let report = crate::gamepad::update_gamepad_report(|r| match stick {
+            GamepadStick::Left => [r.x, r.y, r.z] = axes,
+            GamepadStick::Right => [r.rx, r.ry, r.rz] = axes,
+        });
+        self.send_report(Report::GamepadReport(report)).await;
+    }
+
+    /// Convert the stick directions to key events
+    async fn process_digital(&mut self, keys: &DigitalKeys) {
+        let x = self.record.first().copied().unwrap_or(0);
+        let y = self.record.get(1).copied().unwrap_or(0);
+        // Positive y is down, same as the mouse
+        let directions = [(keys.up, -y), (keys.down, y), (keys.left, -x), (keys.right, x)];
+        for (i, (key, value)) in directions.into_iter().enumerate() {
+            let pressed = digital_state(self.pressed[i], value, keys.threshold, keys.release_threshold);
+            if pressed == self.pressed[i] {
+                continue;
+            }
+            self.pressed[i] = pressed;
+            if let Some(pos) = key {
+                KEY_EVENT_CHANNEL
+                    .send(KeyboardEvent::key(pos.row, pos.col, pressed).with_timestamp(Instant::now()))
+                    .await;
+            }
+        }
+    }
+}
+
+/// Get the pressed state of a digital direction, with hysteresis between `threshold` and `release_threshold`
+fn digital_state(pressed: bool, value: i16, threshold: u16, release_threshold: u16) -> bool {
+    let value = value as i32;
+    if pressed {
+        value > release_threshold.min(threshold) as i32
+    } else {
+        value > threshold as i32
+    }
+}
+
+/// Apply the deadzones and the response curve to the centered axis values
+fn shape<const N: usize>(values: &mut [i16; N], radial: u16, axial: u16, curve: ResponseCurve, range: u16) {
+    let range = range.max(1) as i64;
+
+    if radial > 0 {
+        let radial = radial as i64;
+        let magnitude = values.iter().map(|v| (*v as i64).pow(2)).sum::<i64>().isqrt();
+        if magnitude <= radial {
+            *values = [0; N];
+        } else if radial < range {
+            // Rescale so that the output starts from 0 at the edge of the deadzone
+            let scaled = ((magnitude - radial) * range / (range - radial)).min(range);
+            for v in values.iter_mut() {
+                *v = (*v as i64 * scaled / magnitude) as i16;
+            }
+        }
+    }
+
+    for v in values.iter_mut() {
+        let value = (*v as i64).clamp(-range, range);
+        let abs = value.abs();
+        let abs = if axial == 0 {
+            abs
+        } else if abs <= axial as i64 || axial as i64 >= range {
+            0
+        } else {
+            (abs - axial as i64) * range / (range - axial as i64)
+        };
+        let abs = match curve {
+            ResponseCurve::Linear => abs,
+            ResponseCurve::Quadratic => abs * abs / range,
+            ResponseCurve::Cubic => abs * abs * abs / (range * range),
+        };
+        *v = (abs * value.signum()) as i16;
+    }
 }
 
 impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize, const N: usize>
@@ -84,6 +441,9 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                     *rec = e.value;
                 }
                 debug!("Joystick info: {:#?}", self.record);
+                if self.calibrate().await {
+                    return ProcessResult::Stop;
+                }
                 self.generate_report().await;
                 ProcessResult::Stop
             }
@@ -101,3 +461,71 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         self.keymap
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shape_linear_without_deadzone() {
+        let mut values = [100, -200];
+        shape(&mut values, 0, 0, ResponseCurve::Linear, i16::MAX as u16);
+        assert_eq!(values, [100, -200]);
+    }
+
+    #[test]
+    fn test_shape_deadzone() {
+        // Radial deadzone: the vector (30, 40) has length 50
+        let mut values = [30, 40];
+        shape(&mut values, 50, 0, ResponseCurve::Linear, 1000);
+        assert_eq!(values, [0, 0]);
+
+        // Out of the radial deadzone, rescaled from the edge of the deadzone
+        let mut values = [600, 800];
+        shape(&mut values, 500, 0, ResponseCurve::Linear, 1000);
+        assert_eq!(values, [600, 800]);
+        let mut values = [0, 750];
+        shape(&mut values, 500, 0, ResponseCurve::Linear, 1000);
+        assert_eq!(values, [0, 500]);
+
+        // Axial deadzone only affects the axis in the deadzone
+        let mut values = [50, 600];
+        shape(&mut values, 0, 100, ResponseCurve::Linear, 1000);
+        assert_eq!(values, [0, 555]);
+    }
+
+    #[test]
+    fn test_shape_curve() {
+        let mut values = [500, -1000, 2000];
+        shape(&mut values, 0, 0, ResponseCurve::Quadratic, 1000);
+        assert_eq!(values, [250, -1000, 1000]);
+
+        let mut values = [500, -500];
+        shape(&mut values, 0, 0, ResponseCurve::Cubic, 1000);
+        assert_eq!(values, [125, -125]);
+    }
+
+    #[test]
+    fn test_digital_state_hysteresis() {
+        assert!(!digital_state(false, 400, 500, 300));
+        assert!(digital_state(false, 600, 500, 300));
+        // Keep pressed between the two thresholds
+        assert!(digital_state(true, 400, 500, 300));
+        assert!(!digital_state(true, 200, 500, 300));
+    }
+
+    #[test]
+    fn test_calibration() {
+        let mut calibration = Calibration::<2>::new(3, 10);
+        assert!(!calibration.add(&[100, -100]));
+        assert!(!calibration.add(&[104, -96]));
+        assert!(calibration.add(&[102, -98]));
+        assert_eq!(calibration.center(), Some([102, -98]));
+
+        // The stick is moved during the calibration
+        let mut calibration = Calibration::<2>::new(2, 10);
+        calibration.add(&[100, 0]);
+        calibration.add(&[200, 0]);
+        assert_eq!(calibration.center(), None);
+    }
+}
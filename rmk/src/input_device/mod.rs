@@ -10,8 +10,13 @@ use crate::hid::Report;
 use crate::keymap::KeyMap;
 
 pub mod adc;
+pub mod analog_matrix;
 pub mod battery;
 pub mod joystick;
+pub mod motion_sensor;
+pub mod paw3395;
+pub mod pinnacle;
+pub mod pmw33xx;
 pub mod pmw3610;
 pub mod rotary_encoder;
 
@@ -0,0 +1,429 @@
+//! Generic optical motion sensor support
+//!
+//! [`MotionSensor`] abstracts the common operations of optical mouse sensors (PMW3610, PMW3360, PMW3389, PAW3395, etc.),
+//! so that the polling device, the processor and the runtime CPI control can be shared between sensor drivers.
+
+use core::cell::RefCell;
+#[cfg(feature = "storage")]
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use embassy_sync::pubsub::{PubSubChannel, Subscriber};
+use embassy_time::{Duration, Timer};
+use usbd_hid::descriptor::MouseReport;
+
+use crate::RawMutex;
+use crate::channel::KEYBOARD_REPORT_CHANNEL;
+use crate::event::{Axis, AxisEvent, AxisValType, Event};
+use crate::hid::Report;
+use crate::input_device::{InputDevice, InputProcessor, ProcessResult};
+use crate::keymap::KeyMap;
+
+/// Maximum number of motion sensors which can receive CPI actions and persist their CPI
+pub const MAX_MOTION_SENSORS: usize = 4;
+
+/// Default CPI steps used by CPI cycling keycodes
+pub const DEFAULT_CPI_STEPS: &[u16] = &[400, 800, 1200, 1600, 2400, 3200];
+
+/// Channel which broadcasts CPI actions from the keyboard to all motion sensor devices
+pub(crate) static MOTION_SENSOR_CPI_CHANNEL: PubSubChannel<RawMutex, CpiAction, 4, MAX_MOTION_SENSORS, 0> =
+    PubSubChannel::new();
+
+/// CPI values loaded from the storage, indexed by the sensor id. 0 means there's no saved value.
+#[cfg(feature = "storage")]
+pub(crate) static SAVED_CPI: [AtomicU16; MAX_MOTION_SENSORS] = [const { AtomicU16::new(0) }; MAX_MOTION_SENSORS];
+
+/// Motion sensor error types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotionSensorError {
+    /// SPI communication error
+    Spi,
+    /// Invalid product ID detected
+    InvalidProductId(u8),
+    /// Initialization failed
+    InitFailed,
+    /// Invalid CPI value
+    InvalidCpi,
+    /// SROM upload failed, or the SROM id read back doesn't match
+    SromFailed,
+    /// The operation is not supported by the sensor
+    Unsupported,
+}
+
+/// Motion data from the sensor
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MotionData {
+    pub dx: i16,
+    pub dy: i16,
+}
+
+/// Power saving behavior of the sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RestMode {
+    /// The sensor downshifts to rest modes automatically when there's no motion
+    #[default]
+    Normal,
+    /// Keep the sensor in run mode, disable all rest modes
+    ForceAwake,
+}
+
+/// Runtime CPI actions, triggered by `CpiUp`/`CpiDown`/`CpiCycle` keycodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CpiAction {
+    /// Switch to the next CPI step, stop at the last one
+    Up,
+    /// Switch to the previous CPI step, stop at the first one
+    Down,
+    /// Switch to the next CPI step, wrap around to the first one
+    Cycle,
+}
+
+/// The trait for optical motion sensors.
+pub trait MotionSensor {
+    /// Supported CPI range, (min, max, step)
+    const CPI_RANGE: (u16, u16, u16);
+
+    /// Reset and initialize the sensor
+    async fn init(&mut self) -> Result<(), MotionSensorError>;
+
+    /// Set the sensor resolution in CPI
+    async fn set_cpi(&mut self, cpi: u16) -> Result<(), MotionSensorError>;
+
+    /// Read the current sensor resolution in CPI
+    async fn get_cpi(&mut self) -> Result<u16, MotionSensorError>;
+
+    /// Read the accumulated motion since last read
+    async fn read_motion(&mut self) -> Result<MotionData, MotionSensorError>;
+
+    /// Check whether motion is pending, sensors without motion pin are always pending
+    fn motion_pending(&mut self) -> bool {
+        true
+    }
+
+    /// Set the power saving behavior of the sensor
+    async fn set_rest_mode(&mut self, _mode: RestMode) -> Result<(), MotionSensorError> {
+        Err(MotionSensorError::Unsupported)
+    }
+
+    /// Upload the SROM firmware to the sensor.
+    ///
+    /// Sensors which don't need firmware upload do nothing.
+    async fn upload_srom(&mut self, _srom: &[u8]) -> Result<(), MotionSensorError> {
+        Ok(())
+    }
+
+    /// Check whether the given CPI is supported by the sensor
+    fn is_valid_cpi(cpi: u16) -> bool {
+        let (min, max, step) = Self::CPI_RANGE;
+        (min..=max).contains(&cpi) && cpi.is_multiple_of(step)
+    }
+}
+
+/// Get the CPI after applying the `action` to `current`.
+///
+/// CPI steps should be sorted in ascending order. If `current` is not one of the steps,
+/// the nearest step in the direction of the action is used.
+pub(crate) fn next_cpi(steps: &[u16], current: u16, action: CpiAction) -> u16 {
+    let (Some(&first), Some(&last)) = (steps.first(), steps.last()) else {
+        return current;
+    };
+    match action {
+        CpiAction::Up => steps.iter().copied().find(|&s| s > current).unwrap_or(last),
+        CpiAction::Down => steps.iter().rev().copied().find(|&s| s < current).unwrap_or(first),
+        CpiAction::Cycle => steps.iter().copied().find(|&s| s > current).unwrap_or(first),
+    }
+}
+
+/// Initialization state for the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InitState {
+    Pending,
+    Initializing(u8),
+    Ready,
+    Failed,
+}
+
+/// Motion sensor as an InputDevice for RMK
+///
+/// This device returns `Event::Joystick` events with relative X/Y movement.
+/// It also handles CPI actions triggered by CPI keycodes, and saves the CPI to storage if the `storage` feature is enabled.
+pub struct MotionSensorDevice<S: MotionSensor> {
+    sensor: S,
+    init_state: InitState,
+    poll_interval: Duration,
+    /// Id of the sensor, used as the key of saved CPI
+    id: u8,
+    /// Available CPI steps for CPI actions
+    cpi_steps: &'static [u16],
+    /// Current CPI of the sensor
+    cpi: u16,
+    cpi_sub: Option<Subscriber<'static, RawMutex, CpiAction, 4, MAX_MOTION_SENSORS, 0>>,
+}
+
+impl<S: MotionSensor> MotionSensorDevice<S> {
+    const MAX_INIT_RETRIES: u8 = 3;
+
+    /// Create a new motion sensor device for RMK
+    pub fn new(sensor: S) -> Self {
+        Self {
+            sensor,
+            init_state: InitState::Pending,
+            poll_interval: Duration::from_micros(500),
+            id: 0,
+            cpi_steps: DEFAULT_CPI_STEPS,
+            cpi: 0,
+            cpi_sub: MOTION_SENSOR_CPI_CHANNEL.subscriber().ok(),
+        }
+    }
+
+    /// Set custom poll interval
+    pub fn with_poll_interval(mut self, poll_interval_us: u64) -> Self {
+        self.poll_interval = Duration::from_micros(poll_interval_us);
+        self
+    }
+
+    /// Set the sensor id, which is used to save the CPI to storage.
+    /// Each sensor on the same keyboard should have an unique id, less than [`MAX_MOTION_SENSORS`].
+    pub fn with_id(mut self, id: u8) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Set the CPI steps used by CPI cycling keycodes, should be sorted in ascending order
+    pub fn with_cpi_steps(mut self, cpi_steps: &'static [u16]) -> Self {
+        self.cpi_steps = cpi_steps;
+        self
+    }
+
+    /// Get the underlying sensor
+    pub fn sensor(&mut self) -> &mut S {
+        &mut self.sensor
+    }
+
+    async fn try_init(&mut self) -> bool {
+        match self.init_state {
+            InitState::Ready => return true,
+            InitState::Failed => return false,
+            InitState::Pending => {
+                self.init_state = InitState::Initializing(0);
+            }
+            InitState::Initializing(_) => {}
+        }
+
+        if let InitState::Initializing(retry_count) = self.init_state {
+            info!(
+                "MotionSensor {}: Initializing sensor (attempt {})",
+                self.id,
+                retry_count + 1
+            );
+
+            match self.sensor.init().await {
+                Ok(()) => {
+                    info!("MotionSensor {}: Sensor initialized successfully", self.id);
+                    self.init_state = InitState::Ready;
+                    self.restore_cpi().await;
+                    return true;
+                }
+                Err(_e) => {
+                    error!("MotionSensor {}: Init failed: {:?}", self.id, _e);
+                    if retry_count + 1 >= Self::MAX_INIT_RETRIES {
+                        error!("MotionSensor {}: Max retries reached, giving up", self.id);
+                        self.init_state = InitState::Failed;
+                        return false;
+                    }
+                    self.init_state = InitState::Initializing(retry_count + 1);
+                    Timer::after(Duration::from_millis(100)).await;
+                    return false;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Restore the saved CPI after initialization
+    async fn restore_cpi(&mut self) {
+        #[cfg(feature = "storage")]
+        if let Some(saved) = SAVED_CPI.get(self.id as usize) {
+            let saved = saved.load(Ordering::Relaxed);
+            if saved != 0 && S::is_valid_cpi(saved) && self.sensor.set_cpi(saved).await.is_err() {
+                warn!("MotionSensor {}: Failed to restore CPI {}", self.id, saved);
+            }
+        }
+
+        self.cpi = self.sensor.get_cpi().await.unwrap_or(0);
+        debug!("MotionSensor {}: Current CPI {}", self.id, self.cpi);
+    }
+
+    async fn process_cpi_action(&mut self, action: CpiAction) {
+        let cpi = next_cpi(self.cpi_steps, self.cpi, action);
+        if cpi == self.cpi || !S::is_valid_cpi(cpi) {
+            return;
+        }
+
+        match self.sensor.set_cpi(cpi).await {
+            Ok(()) => {
+                info!("MotionSensor {}: CPI changed to {}", self.id, cpi);
+                self.cpi = cpi;
+                #[cfg(feature = "storage")]
+                {
+                    use crate::channel::FLASH_CHANNEL;
+                    use crate::storage::FlashOperationMessage;
+
+                    if let Some(saved) = SAVED_CPI.get(self.id as usize) {
+                        saved.store(cpi, Ordering::Relaxed);
+                        FLASH_CHANNEL
+                            .send(FlashOperationMessage::MotionSensorCpi(self.id, cpi))
+                            .await;
+                    }
+                }
+            }
+            Err(_e) => error!("MotionSensor {}: Failed to set CPI: {:?}", self.id, _e),
+        }
+    }
+}
+
+impl<S: MotionSensor> InputDevice for MotionSensorDevice<S> {
+    async fn read_event(&mut self) -> Event {
+        loop {
+            Timer::after(self.poll_interval).await;
+
+            if self.init_state != InitState::Ready && !self.try_init().await {
+                continue;
+            }
+
+            if let Some(action) = self.cpi_sub.as_mut().and_then(|sub| sub.try_next_message_pure()) {
+                self.process_cpi_action(action).await;
+            }
+
+            if !self.sensor.motion_pending() {
+                continue;
+            }
+
+            match self.sensor.read_motion().await {
+                Ok(motion) => {
+                    if motion.dx != 0 || motion.dy != 0 {
+                        return Event::Joystick([
+                            AxisEvent {
+                                typ: AxisValType::Rel,
+                                axis: Axis::X,
+                                value: motion.dx,
+                            },
+                            AxisEvent {
+                                typ: AxisValType::Rel,
+                                axis: Axis::Y,
+                                value: motion.dy,
+                            },
+                            AxisEvent {
+                                typ: AxisValType::Rel,
+                                axis: Axis::Z,
+                                value: 0,
+                            },
+                        ]);
+                    }
+                }
+                Err(_e) => {
+                    warn!("MotionSensor {}: read error", self.id);
+                }
+            }
+        }
+    }
+}
+
+/// Motion sensor processor that converts motion events to mouse reports
+pub struct MotionSensorProcessor<
+    'a,
+    const ROW: usize,
+    const COL: usize,
+    const NUM_LAYER: usize,
+    const NUM_ENCODER: usize,
+> {
+    /// Reference to the keymap
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    MotionSensorProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Create a new motion sensor processor with default settings
+    pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+        Self { keymap }
+    }
+
+    async fn generate_report(&self, x: i16, y: i16) {
+        let mouse_report = MouseReport {
+            buttons: 0,
+            x: x.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
+            y: y.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
+            wheel: 0,
+            pan: 0,
+        };
+        self.send_report(Report::MouseReport(mouse_report)).await;
+    }
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    InputProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+    for MotionSensorProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    async fn process(&mut self, event: Event) -> ProcessResult {
+        match event {
+            Event::Joystick(axis_events) => {
+                let mut x = 0i16;
+                let mut y = 0i16;
+
+                for axis_event in axis_events.iter() {
+                    match axis_event.axis {
+                        Axis::X => x = axis_event.value,
+                        Axis::Y => y = axis_event.value,
+                        _ => {}
+                    }
+                }
+
+                self.generate_report(x, y).await;
+                ProcessResult::Stop
+            }
+            _ => ProcessResult::Continue(event),
+        }
+    }
+
+    async fn send_report(&self, report: Report) {
+        KEYBOARD_REPORT_CHANNEL.send(report).await;
+    }
+
+    fn get_keymap(&self) -> &RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>> {
+        self.keymap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: &[u16] = &[400, 800, 1600];
+
+    #[test]
+    fn test_next_cpi_up_down() {
+        assert_eq!(next_cpi(STEPS, 400, CpiAction::Up), 800);
+        assert_eq!(next_cpi(STEPS, 1600, CpiAction::Up), 1600);
+        assert_eq!(next_cpi(STEPS, 800, CpiAction::Down), 400);
+        assert_eq!(next_cpi(STEPS, 400, CpiAction::Down), 400);
+    }
+
+    #[test]
+    fn test_next_cpi_cycle() {
+        assert_eq!(next_cpi(STEPS, 800, CpiAction::Cycle), 1600);
+        assert_eq!(next_cpi(STEPS, 1600, CpiAction::Cycle), 400);
+    }
+
+    #[test]
+    fn test_next_cpi_off_step() {
+        // Current CPI is not one of the steps, e.g. set by the sensor default
+        assert_eq!(next_cpi(STEPS, 1000, CpiAction::Up), 1600);
+        assert_eq!(next_cpi(STEPS, 1000, CpiAction::Down), 800);
+        assert_eq!(next_cpi(STEPS, 3200, CpiAction::Cycle), 400);
+        assert_eq!(next_cpi(&[], 1000, CpiAction::Up), 1000);
+    }
+}
@@ -0,0 +1,260 @@
+//! PAW3395 Optical Mouse Sensor Driver
+//!
+//! PAW3395 doesn't need an SROM, but the vendor's power-up register settings should be written after reset.
+//! The register settings are distributed with the datasheet and not included in RMK,
+//! pass them via [`Paw3395Config::init_sequence`].
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::spi::SpiBus;
+
+pub use crate::input_device::motion_sensor::MotionData;
+use crate::input_device::motion_sensor::{MotionSensor, MotionSensorDevice, MotionSensorError};
+
+// ============================================================================
+// Registers
+// ============================================================================
+const REG_PRODUCT_ID: u8 = 0x00;
+const REG_MOTION: u8 = 0x02;
+const REG_DELTA_Y_H: u8 = 0x06;
+const REG_MOTION_BURST: u8 = 0x16;
+const REG_POWER_UP_RESET: u8 = 0x3a;
+const REG_SET_RESOLUTION: u8 = 0x47;
+const REG_RESOLUTION_X_L: u8 = 0x48;
+const REG_RESOLUTION_X_H: u8 = 0x49;
+const REG_RESOLUTION_Y_L: u8 = 0x4a;
+const REG_RESOLUTION_Y_H: u8 = 0x4b;
+
+// ============================================================================
+// Burst register offsets
+// ============================================================================
+const BURST_MOTION: usize = 0;
+const BURST_DELTA_X_L: usize = 2;
+const BURST_DELTA_X_H: usize = 3;
+const BURST_DELTA_Y_L: usize = 4;
+const BURST_DELTA_Y_H: usize = 5;
+const BURST_DATA_LEN: usize = 12;
+
+// ============================================================================
+// Constants
+// ============================================================================
+const PRODUCT_ID_PAW3395: u8 = 0x51;
+const SPI_WRITE: u8 = 0x80;
+const MOTION_STATUS_MOTION: u8 = 0x80;
+const POWER_UP_RESET_VAL: u8 = 0x5a;
+const SET_RESOLUTION_APPLY: u8 = 0x01;
+
+// Timing constants
+const RESET_DELAY_MS: u64 = 5;
+
+// SPI timing constants (from PAW3395 datasheet)
+const T_NCS_SCLK_US: u64 = 1;
+const T_SRAD_US: u64 = 2;
+const T_SRX_US: u64 = 2;
+const T_SCLK_NCS_WR_US: u64 = 1;
+const T_SWX_US: u64 = 5;
+const T_BEXIT_US: u64 = 1;
+
+// Resolution constants
+const RES_STEP: u16 = 50;
+const RES_MIN: u16 = 50;
+const RES_MAX: u16 = 26000;
+
+/// PAW3395 configuration
+#[derive(Clone, Default)]
+pub struct Paw3395Config {
+    /// CPI resolution, 0 to use default
+    pub cpi: u16,
+    /// Invert X axis
+    pub invert_x: bool,
+    /// Invert Y axis
+    pub invert_y: bool,
+    /// Swap X and Y axes
+    pub swap_xy: bool,
+    /// Power-up register settings from the datasheet, written as (register, value) pairs after reset
+    pub init_sequence: &'static [(u8, u8)],
+}
+
+/// PAW3395 driver using embedded-hal SPI traits
+pub struct Paw3395<SPI, CS, MOTION>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+    MOTION: InputPin,
+{
+    spi: SPI,
+    cs: CS,
+    motion_gpio: Option<MOTION>,
+    config: Paw3395Config,
+}
+
+/// PAW3395 as an InputDevice for RMK
+pub type Paw3395Device<SPI, CS, MOTION> = MotionSensorDevice<Paw3395<SPI, CS, MOTION>>;
+
+impl<SPI, CS, MOTION> Paw3395<SPI, CS, MOTION>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+    MOTION: InputPin,
+{
+    /// Create a new PAW3395 driver instance
+    pub fn new(spi: SPI, cs: CS, motion_gpio: Option<MOTION>, config: Paw3395Config) -> Self {
+        Self {
+            spi,
+            cs,
+            motion_gpio,
+            config,
+        }
+    }
+
+    async fn read_reg(&mut self, addr: u8) -> Result<u8, MotionSensorError> {
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(T_NCS_SCLK_US)).await;
+
+        self.spi
+            .write(&[addr & 0x7f])
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+
+        Timer::after(Duration::from_micros(T_SRAD_US)).await;
+
+        let mut value = [0u8];
+        self.spi.read(&mut value).await.map_err(|_| MotionSensorError::Spi)?;
+        self.spi.flush().await.map_err(|_| MotionSensorError::Spi)?;
+
+        let _ = self.cs.set_high();
+
+        Timer::after(Duration::from_micros(T_SRX_US)).await;
+
+        Ok(value[0])
+    }
+
+    async fn write_reg(&mut self, addr: u8, value: u8) -> Result<(), MotionSensorError> {
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(T_NCS_SCLK_US)).await;
+
+        self.spi
+            .write(&[addr | SPI_WRITE, value])
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+        self.spi.flush().await.map_err(|_| MotionSensorError::Spi)?;
+
+        Timer::after(Duration::from_micros(T_SCLK_NCS_WR_US)).await;
+        let _ = self.cs.set_high();
+
+        Timer::after(Duration::from_micros(T_SWX_US)).await;
+
+        Ok(())
+    }
+
+    fn transform(&self, dx: i16, dy: i16) -> MotionData {
+        let (mut dx, mut dy) = if self.config.swap_xy { (dy, dx) } else { (dx, dy) };
+        if self.config.invert_x {
+            dx = dx.saturating_neg();
+        }
+        if self.config.invert_y {
+            dy = dy.saturating_neg();
+        }
+        MotionData { dx, dy }
+    }
+}
+
+impl<SPI, CS, MOTION> MotionSensor for Paw3395<SPI, CS, MOTION>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+    MOTION: InputPin,
+{
+    const CPI_RANGE: (u16, u16, u16) = (RES_MIN, RES_MAX, RES_STEP);
+
+    async fn init(&mut self) -> Result<(), MotionSensorError> {
+        let _ = self.cs.set_high();
+        Timer::after(Duration::from_millis(1)).await;
+
+        self.write_reg(REG_POWER_UP_RESET, POWER_UP_RESET_VAL).await?;
+        Timer::after(Duration::from_millis(RESET_DELAY_MS)).await;
+
+        let product_id = self.read_reg(REG_PRODUCT_ID).await?;
+        if product_id != PRODUCT_ID_PAW3395 {
+            error!("PAW3395: Invalid product id: {:#02x}", product_id);
+            return Err(MotionSensorError::InvalidProductId(product_id));
+        }
+        info!("PAW3395 detected, product ID: {:#02x}", product_id);
+
+        if self.config.init_sequence.is_empty() {
+            warn!("PAW3395: No power-up register settings provided, the sensor may not track properly");
+        }
+        for &(reg, value) in self.config.init_sequence {
+            self.write_reg(reg, value).await?;
+        }
+
+        // Read motion registers once, regardless of the motion state
+        for reg in REG_MOTION..=REG_DELTA_Y_H {
+            self.read_reg(reg).await?;
+        }
+
+        if self.config.cpi > 0 {
+            self.set_cpi(self.config.cpi).await?;
+        }
+
+        info!("PAW3395 initialized successfully");
+        Ok(())
+    }
+
+    async fn set_cpi(&mut self, cpi: u16) -> Result<(), MotionSensorError> {
+        if !Self::is_valid_cpi(cpi) {
+            return Err(MotionSensorError::InvalidCpi);
+        }
+
+        let [low, high] = (cpi / RES_STEP).to_le_bytes();
+        self.write_reg(REG_RESOLUTION_X_L, low).await?;
+        self.write_reg(REG_RESOLUTION_X_H, high).await?;
+        self.write_reg(REG_RESOLUTION_Y_L, low).await?;
+        self.write_reg(REG_RESOLUTION_Y_H, high).await?;
+        self.write_reg(REG_SET_RESOLUTION, SET_RESOLUTION_APPLY).await?;
+
+        debug!("PAW3395: Resolution set to {} CPI", cpi);
+        Ok(())
+    }
+
+    async fn get_cpi(&mut self) -> Result<u16, MotionSensorError> {
+        let low = self.read_reg(REG_RESOLUTION_X_L).await?;
+        let high = self.read_reg(REG_RESOLUTION_X_H).await?;
+        Ok(u16::from_le_bytes([low, high]) * RES_STEP)
+    }
+
+    async fn read_motion(&mut self) -> Result<MotionData, MotionSensorError> {
+        let mut burst_data = [0u8; BURST_DATA_LEN];
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(T_NCS_SCLK_US)).await;
+        self.spi
+            .write(&[REG_MOTION_BURST])
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+        Timer::after(Duration::from_micros(T_SRAD_US)).await;
+        self.spi
+            .read(&mut burst_data)
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+        self.spi.flush().await.map_err(|_| MotionSensorError::Spi)?;
+        let _ = self.cs.set_high();
+        Timer::after(Duration::from_micros(T_BEXIT_US)).await;
+
+        if (burst_data[BURST_MOTION] & MOTION_STATUS_MOTION) == 0x00 {
+            return Ok(MotionData::default());
+        }
+
+        let dx = i16::from_le_bytes([burst_data[BURST_DELTA_X_L], burst_data[BURST_DELTA_X_H]]);
+        let dy = i16::from_le_bytes([burst_data[BURST_DELTA_Y_L], burst_data[BURST_DELTA_Y_H]]);
+
+        Ok(self.transform(dx, dy))
+    }
+
+    /// Check if motion is pending (motion GPIO is active low)
+    fn motion_pending(&mut self) -> bool {
+        match &mut self.motion_gpio {
+            Some(gpio) => gpio.is_low().unwrap_or(true),
+            None => true,
+        }
+    }
+}
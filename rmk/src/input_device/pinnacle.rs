@@ -0,0 +1,936 @@
+//! Cirque Pinnacle (1CA027) circular trackpad driver
+//!
+//! Register layout and init sequence follow Cirque's GT-AN-090620 application note and the QMK `cirque_pinnacle` driver:
+//! https://github.com/qmk/qmk_firmware/blob/master/drivers/sensors/cirque_pinnacle.c
+//!
+//! The driver talks to the trackpad through the Register Access Protocol (RAP), over either I2C or SPI.
+
+use core::cell::RefCell;
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::InputPin;
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+use usbd_hid::descriptor::MouseReport;
+
+use crate::channel::KEYBOARD_REPORT_CHANNEL;
+use crate::event::{Axis, AxisEvent, AxisValType, Event, TouchpadEvent};
+use crate::hid::Report;
+use crate::input_device::{InputDevice, InputProcessor, ProcessResult};
+use crate::keymap::KeyMap;
+
+// ============================================================================
+// Host registers
+// ============================================================================
+const REG_FIRMWARE_ID: u8 = 0x00;
+const REG_STATUS1: u8 = 0x02;
+const REG_SYS_CONFIG1: u8 = 0x03;
+const REG_FEED_CONFIG1: u8 = 0x04;
+const REG_FEED_CONFIG2: u8 = 0x05;
+const REG_CAL_CONFIG1: u8 = 0x07;
+const REG_Z_IDLE: u8 = 0x0a;
+const REG_PACKET_BYTE0: u8 = 0x12;
+const REG_ERA_VALUE: u8 = 0x1b;
+const REG_ERA_HIGH_BYTE: u8 = 0x1c;
+const REG_ERA_LOW_BYTE: u8 = 0x1d;
+const REG_ERA_CONTROL: u8 = 0x1e;
+
+// ============================================================================
+// Extended registers
+// ============================================================================
+const EXT_REG_X_AXIS_WIDE_Z_MIN: u16 = 0x0149;
+const EXT_REG_Y_AXIS_WIDE_Z_MIN: u16 = 0x0168;
+const EXT_REG_TRACK_ADC_CONFIG: u16 = 0x0187;
+
+// ============================================================================
+// Register values
+// ============================================================================
+const RAP_READ: u8 = 0xa0;
+const RAP_WRITE: u8 = 0x80;
+const RAP_SPI_FILLER: u8 = 0xfc;
+
+const FIRMWARE_ID_PINNACLE: u8 = 0x07;
+
+const STATUS1_SW_DR: u8 = 1 << 2;
+const STATUS1_SW_CC: u8 = 1 << 3;
+
+const SYS_CONFIG1_RESET: u8 = 1 << 0;
+const SYS_CONFIG1_DEFAULT: u8 = 0x00;
+
+const FEED_CONFIG1_FEED_ENABLE: u8 = 1 << 0;
+const FEED_CONFIG1_DATA_MODE_ABS: u8 = 1 << 1;
+
+const FEED_CONFIG2_INTELLIMOUSE_ENABLE: u8 = 1 << 0;
+const FEED_CONFIG2_ALL_TAPS_DISABLE: u8 = 1 << 1;
+const FEED_CONFIG2_SECONDARY_TAP_DISABLE: u8 = 1 << 2;
+const FEED_CONFIG2_SCROLL_DISABLE: u8 = 1 << 3;
+const FEED_CONFIG2_GLIDE_EXTEND_DISABLE: u8 = 1 << 4;
+
+const CAL_CONFIG1_CALIBRATE: u8 = 1 << 0;
+const CAL_CONFIG1_BACKGROUND_COMP_ENABLE: u8 = 1 << 1;
+const CAL_CONFIG1_NERD_COMP_ENABLE: u8 = 1 << 2;
+const CAL_CONFIG1_TRACK_ERROR_COMP_ENABLE: u8 = 1 << 3;
+const CAL_CONFIG1_TAP_COMP_ENABLE: u8 = 1 << 4;
+
+const ERA_CONTROL_READ: u8 = 0x01;
+const ERA_CONTROL_WRITE: u8 = 0x02;
+
+const TRACK_ADC_CONFIG_ATTENUATION_MASK: u8 = 0xc0;
+
+// Magic numbers from Cirque's sample code, they lower the minimum "wide z" threshold near the edges of the sensor
+const X_AXIS_WIDE_Z_MIN_CURVED: u8 = 0x04;
+const Y_AXIS_WIDE_Z_MIN_CURVED: u8 = 0x03;
+
+// Number of z-idle packets sent after lifting the finger in absolute mode
+const Z_IDLE_COUNT: u8 = 5;
+
+// Usable range of absolute coordinates
+const X_LOWER: u16 = 127;
+const X_UPPER: u16 = 1919;
+const Y_LOWER: u16 = 63;
+const Y_UPPER: u16 = 1471;
+const Z_MASK: u8 = 0x3f;
+
+// Timing constants
+const RESET_DELAY_MS: u64 = 30;
+const ERA_TIMEOUT_MS: u64 = 10;
+const CALIBRATE_TIMEOUT_MS: u64 = 200;
+
+/// Report mode of the trackpad
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PinnacleMode {
+    /// Report absolute finger position, scaled by the pad diameter and `cpi`
+    #[default]
+    Absolute,
+    /// Report relative motion computed by the trackpad
+    Relative,
+}
+
+/// ADC attenuation, higher attenuation reduces sensitivity.
+///
+/// Thicker overlays need less attenuation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum PinnacleAttenuation {
+    X1 = 0x00,
+    X2 = 0x40,
+    X3 = 0x80,
+    #[default]
+    X4 = 0xc0,
+}
+
+/// Cirque Pinnacle configuration
+#[derive(Clone)]
+pub struct PinnacleConfig {
+    /// Report mode
+    pub mode: PinnacleMode,
+    /// ADC attenuation
+    pub attenuation: PinnacleAttenuation,
+    /// Tune edge sensitivity for curved overlays
+    pub curved_overlay: bool,
+    /// Diameter of the trackpad in millimeters, used to scale absolute coordinates
+    pub diameter_mm: u8,
+    /// Resolution of absolute mode output, in counts per inch
+    pub cpi: u16,
+    /// Enable the background, NERD, track error and tap compensations during calibration
+    pub compensation: bool,
+    /// Invert X axis
+    pub invert_x: bool,
+    /// Invert Y axis
+    pub invert_y: bool,
+    /// Swap X and Y axes
+    pub swap_xy: bool,
+}
+
+impl Default for PinnacleConfig {
+    fn default() -> Self {
+        Self {
+            mode: PinnacleMode::Absolute,
+            attenuation: PinnacleAttenuation::X4,
+            curved_overlay: false,
+            diameter_mm: 35,
+            cpi: 800,
+            compensation: true,
+            invert_x: false,
+            invert_y: false,
+            swap_xy: false,
+        }
+    }
+}
+
+/// Cirque Pinnacle error types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PinnacleError {
+    /// Bus communication error
+    Bus,
+    /// Invalid firmware ID detected
+    InvalidFirmwareId(u8),
+    /// Extended register access or calibration timed out
+    Timeout,
+}
+
+/// Register access of the trackpad, implemented for I2C and SPI.
+pub trait PinnacleInterface {
+    /// Read `buf.len()` consecutive registers starting at `reg`
+    async fn read_regs(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), PinnacleError>;
+
+    /// Write a single register
+    async fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), PinnacleError>;
+}
+
+/// I2C interface of the trackpad
+pub struct PinnacleI2c<I2C: I2c> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> PinnacleI2c<I2C> {
+    /// The default 7-bit address of the trackpad
+    pub const DEFAULT_ADDRESS: u8 = 0x2a;
+
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I2C: I2c> PinnacleInterface for PinnacleI2c<I2C> {
+    async fn read_regs(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), PinnacleError> {
+        self.i2c
+            .write_read(self.address, &[RAP_READ | reg], buf)
+            .await
+            .map_err(|_| PinnacleError::Bus)
+    }
+
+    async fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), PinnacleError> {
+        self.i2c
+            .write(self.address, &[RAP_WRITE | reg, value])
+            .await
+            .map_err(|_| PinnacleError::Bus)
+    }
+}
+
+/// SPI interface of the trackpad, the SPI device should be configured in mode 1
+pub struct PinnacleSpi<SPI: SpiDevice> {
+    spi: SPI,
+}
+
+impl<SPI: SpiDevice> PinnacleSpi<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self { spi }
+    }
+}
+
+impl<SPI: SpiDevice> PinnacleInterface for PinnacleSpi<SPI> {
+    async fn read_regs(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), PinnacleError> {
+        // The trackpad clocks out valid data after the command byte and two filler bytes
+        buf.fill(RAP_SPI_FILLER);
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[RAP_READ | reg, RAP_SPI_FILLER, RAP_SPI_FILLER]),
+                Operation::TransferInPlace(buf),
+            ])
+            .await
+            .map_err(|_| PinnacleError::Bus)
+    }
+
+    async fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), PinnacleError> {
+        self.spi
+            .write(&[RAP_WRITE | reg, value])
+            .await
+            .map_err(|_| PinnacleError::Bus)
+    }
+}
+
+/// Touch data read from the trackpad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchData {
+    /// Absolute position, `z` is zero when the finger is lifted
+    Absolute { x: i16, y: i16, z: u8 },
+    /// Relative motion
+    Relative { dx: i16, dy: i16, wheel: i8 },
+}
+
+/// Cirque Pinnacle driver
+pub struct Pinnacle<IF: PinnacleInterface> {
+    interface: IF,
+    config: PinnacleConfig,
+}
+
+impl<I2C: I2c> Pinnacle<PinnacleI2c<I2C>> {
+    /// Create a new driver on an I2C bus
+    pub fn new_i2c(i2c: I2C, address: u8, config: PinnacleConfig) -> Self {
+        Self::new(PinnacleI2c::new(i2c, address), config)
+    }
+}
+
+impl<SPI: SpiDevice> Pinnacle<PinnacleSpi<SPI>> {
+    /// Create a new driver on an SPI device
+    pub fn new_spi(spi: SPI, config: PinnacleConfig) -> Self {
+        Self::new(PinnacleSpi::new(spi), config)
+    }
+}
+
+impl<IF: PinnacleInterface> Pinnacle<IF> {
+    /// Create a new driver instance
+    pub fn new(interface: IF, config: PinnacleConfig) -> Self {
+        Self { interface, config }
+    }
+
+    /// Consume the driver and return the underlying interface
+    pub fn release(self) -> IF {
+        self.interface
+    }
+
+    async fn read_reg(&mut self, reg: u8) -> Result<u8, PinnacleError> {
+        let mut buf = [0u8];
+        self.interface.read_regs(reg, &mut buf).await?;
+        Ok(buf[0])
+    }
+
+    async fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), PinnacleError> {
+        self.interface.write_reg(reg, value).await
+    }
+
+    /// Clear SW_CC and SW_DR flags
+    async fn clear_flags(&mut self) -> Result<(), PinnacleError> {
+        self.write_reg(REG_STATUS1, 0x00).await
+    }
+
+    /// Whether new data is ready, according to the status register
+    pub async fn data_ready(&mut self) -> Result<bool, PinnacleError> {
+        Ok(self.read_reg(REG_STATUS1).await? & STATUS1_SW_DR != 0)
+    }
+
+    async fn wait_era_done(&mut self) -> Result<(), PinnacleError> {
+        let start = Instant::now();
+        while self.read_reg(REG_ERA_CONTROL).await? != 0 {
+            if start.elapsed() > Duration::from_millis(ERA_TIMEOUT_MS) {
+                return Err(PinnacleError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an extended register, the data feed MUST be disabled
+    async fn era_read(&mut self, addr: u16) -> Result<u8, PinnacleError> {
+        self.write_reg(REG_ERA_HIGH_BYTE, (addr >> 8) as u8).await?;
+        self.write_reg(REG_ERA_LOW_BYTE, addr as u8).await?;
+        self.write_reg(REG_ERA_CONTROL, ERA_CONTROL_READ).await?;
+        self.wait_era_done().await?;
+        let value = self.read_reg(REG_ERA_VALUE).await?;
+        self.clear_flags().await?;
+        Ok(value)
+    }
+
+    /// Write an extended register, the data feed MUST be disabled
+    async fn era_write(&mut self, addr: u16, value: u8) -> Result<(), PinnacleError> {
+        self.write_reg(REG_ERA_VALUE, value).await?;
+        self.write_reg(REG_ERA_HIGH_BYTE, (addr >> 8) as u8).await?;
+        self.write_reg(REG_ERA_LOW_BYTE, addr as u8).await?;
+        self.write_reg(REG_ERA_CONTROL, ERA_CONTROL_WRITE).await?;
+        self.wait_era_done().await?;
+        self.clear_flags().await
+    }
+
+    /// Set ADC attenuation, the data feed MUST be disabled
+    async fn set_attenuation(&mut self, attenuation: PinnacleAttenuation) -> Result<(), PinnacleError> {
+        let mut val = self.era_read(EXT_REG_TRACK_ADC_CONFIG).await?;
+        val &= !TRACK_ADC_CONFIG_ATTENUATION_MASK;
+        val |= attenuation as u8;
+        self.era_write(EXT_REG_TRACK_ADC_CONFIG, val).await
+    }
+
+    /// Lower the wide-z thresholds so that touches near the edge of a curved overlay are detected
+    async fn tune_edge_sensitivity(&mut self) -> Result<(), PinnacleError> {
+        self.era_write(EXT_REG_X_AXIS_WIDE_Z_MIN, X_AXIS_WIDE_Z_MIN_CURVED)
+            .await?;
+        self.era_write(EXT_REG_Y_AXIS_WIDE_Z_MIN, Y_AXIS_WIDE_Z_MIN_CURVED)
+            .await
+    }
+
+    /// Run the trackpad's calibration.
+    ///
+    /// No finger should touch the trackpad during the calibration.
+    pub async fn calibrate(&mut self) -> Result<(), PinnacleError> {
+        let mut val = if self.config.compensation {
+            CAL_CONFIG1_BACKGROUND_COMP_ENABLE
+                | CAL_CONFIG1_NERD_COMP_ENABLE
+                | CAL_CONFIG1_TRACK_ERROR_COMP_ENABLE
+                | CAL_CONFIG1_TAP_COMP_ENABLE
+        } else {
+            0
+        };
+        val |= CAL_CONFIG1_CALIBRATE;
+        self.write_reg(REG_CAL_CONFIG1, val).await?;
+
+        let start = Instant::now();
+        while self.read_reg(REG_CAL_CONFIG1).await? & CAL_CONFIG1_CALIBRATE != 0 {
+            if start.elapsed() > Duration::from_millis(CALIBRATE_TIMEOUT_MS) {
+                return Err(PinnacleError::Timeout);
+            }
+            Timer::after_millis(1).await;
+        }
+        self.clear_flags().await?;
+        debug!("Pinnacle: calibrated");
+        Ok(())
+    }
+
+    /// Initialize the trackpad
+    pub async fn init(&mut self) -> Result<(), PinnacleError> {
+        let firmware_id = self.read_reg(REG_FIRMWARE_ID).await?;
+        if firmware_id != FIRMWARE_ID_PINNACLE {
+            error!("Invalid Pinnacle firmware id: {:#02x}", firmware_id);
+            return Err(PinnacleError::InvalidFirmwareId(firmware_id));
+        }
+
+        // Reset, in case of a soft reset of the MCU without power cycle
+        self.write_reg(REG_SYS_CONFIG1, SYS_CONFIG1_RESET).await?;
+        Timer::after_millis(RESET_DELAY_MS).await;
+        self.write_reg(REG_SYS_CONFIG1, SYS_CONFIG1_DEFAULT).await?;
+        self.clear_flags().await?;
+
+        // Hardware taps are disabled, since touchpad events don't carry buttons
+        let feed_config2 = match self.config.mode {
+            PinnacleMode::Absolute => FEED_CONFIG2_ALL_TAPS_DISABLE,
+            PinnacleMode::Relative => {
+                FEED_CONFIG2_INTELLIMOUSE_ENABLE
+                    | FEED_CONFIG2_ALL_TAPS_DISABLE
+                    | FEED_CONFIG2_SECONDARY_TAP_DISABLE
+                    | FEED_CONFIG2_GLIDE_EXTEND_DISABLE
+            }
+        };
+        self.write_reg(REG_FEED_CONFIG2, feed_config2).await?;
+
+        // Keep the feed disabled while accessing extended registers
+        let feed_config1 = match self.config.mode {
+            PinnacleMode::Absolute => FEED_CONFIG1_DATA_MODE_ABS,
+            PinnacleMode::Relative => 0,
+        };
+        self.write_reg(REG_FEED_CONFIG1, feed_config1).await?;
+
+        if self.config.mode == PinnacleMode::Absolute {
+            self.write_reg(REG_Z_IDLE, Z_IDLE_COUNT).await?;
+        }
+
+        self.set_attenuation(self.config.attenuation).await?;
+        if self.config.curved_overlay {
+            self.tune_edge_sensitivity().await?;
+        }
+
+        self.calibrate().await?;
+
+        self.write_reg(REG_FEED_CONFIG1, feed_config1 | FEED_CONFIG1_FEED_ENABLE)
+            .await?;
+
+        info!("Pinnacle initialized successfully");
+        Ok(())
+    }
+
+    /// Read the touch data, and clear the data ready flag
+    pub async fn read_data(&mut self) -> Result<TouchData, PinnacleError> {
+        let mut packet = [0u8; 6];
+        self.interface.read_regs(REG_PACKET_BYTE0, &mut packet).await?;
+        self.clear_flags().await?;
+
+        let data = match self.config.mode {
+            PinnacleMode::Absolute => self.parse_absolute(&packet),
+            PinnacleMode::Relative => self.parse_relative(&packet),
+        };
+        Ok(data)
+    }
+
+    fn parse_absolute(&self, packet: &[u8; 6]) -> TouchData {
+        let z = packet[5] & Z_MASK;
+        if z == 0 {
+            return TouchData::Absolute { x: 0, y: 0, z: 0 };
+        }
+
+        let raw_x = packet[2] as u16 | ((packet[4] as u16 & 0x0f) << 8);
+        let raw_y = packet[3] as u16 | ((packet[4] as u16 & 0xf0) << 4);
+
+        // Clip to the usable area, then scale to the configured resolution
+        let x = raw_x.clamp(X_LOWER, X_UPPER) - X_LOWER;
+        let y = raw_y.clamp(Y_LOWER, Y_UPPER) - Y_LOWER;
+        let (x, y) = (
+            self.scale(x, X_UPPER - X_LOWER, self.config.invert_x),
+            self.scale(y, Y_UPPER - Y_LOWER, self.config.invert_y),
+        );
+        let (x, y) = if self.config.swap_xy { (y, x) } else { (x, y) };
+
+        TouchData::Absolute { x, y, z }
+    }
+
+    fn parse_relative(&self, packet: &[u8; 6]) -> TouchData {
+        // The 9th bit of each delta is the sign bit in the first byte
+        let dx = if packet[0] & 0x10 != 0 {
+            packet[1] as i16 - 256
+        } else {
+            packet[1] as i16
+        };
+        let dy = if packet[0] & 0x20 != 0 {
+            packet[2] as i16 - 256
+        } else {
+            packet[2] as i16
+        };
+        let wheel = packet[3] as i8;
+
+        let dx = if self.config.invert_x { -dx } else { dx };
+        let dy = if self.config.invert_y { -dy } else { dy };
+        let (dx, dy) = if self.config.swap_xy { (dy, dx) } else { (dx, dy) };
+
+        TouchData::Relative { dx, dy, wheel }
+    }
+
+    /// Scale a coordinate in `0..=range` to the range covered by the trackpad diameter at the configured CPI
+    fn scale(&self, value: u16, range: u16, invert: bool) -> i16 {
+        let value = if invert { range - value } else { value };
+        let output_range = self.config.diameter_mm as u32 * self.config.cpi as u32 * 10 / 254;
+        (value as u32 * output_range / range as u32).min(i16::MAX as u32) as i16
+    }
+}
+
+/// Initialization state for the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InitState {
+    Pending,
+    Initializing(u8),
+    Ready,
+    Failed,
+}
+
+/// Cirque Pinnacle as an InputDevice for RMK
+///
+/// This device returns `Event::Touchpad` events.
+/// In absolute mode, the axes are absolute X/Y positions and Z, a Z of zero means the finger is lifted.
+/// In relative mode, the axes are relative X/Y movement and the wheel movement in `Axis::V`.
+pub struct PinnacleDevice<IF: PinnacleInterface, DR: InputPin> {
+    sensor: Pinnacle<IF>,
+    /// Hardware data ready pin, active high
+    data_ready: Option<DR>,
+    init_state: InitState,
+    poll_interval: Duration,
+    touching: bool,
+}
+
+impl<IF: PinnacleInterface, DR: InputPin> PinnacleDevice<IF, DR> {
+    const MAX_INIT_RETRIES: u8 = 3;
+
+    /// Create a new Cirque Pinnacle device for RMK
+    pub fn new(sensor: Pinnacle<IF>, data_ready: Option<DR>) -> Self {
+        Self::with_poll_interval(sensor, data_ready, 5000)
+    }
+
+    /// Create a new Cirque Pinnacle device with custom poll interval
+    pub fn with_poll_interval(sensor: Pinnacle<IF>, data_ready: Option<DR>, poll_interval_us: u64) -> Self {
+        Self {
+            sensor,
+            data_ready,
+            init_state: InitState::Pending,
+            poll_interval: Duration::from_micros(poll_interval_us),
+            touching: false,
+        }
+    }
+
+    async fn try_init(&mut self) -> bool {
+        match self.init_state {
+            InitState::Ready => return true,
+            InitState::Failed => return false,
+            InitState::Pending => self.init_state = InitState::Initializing(0),
+            InitState::Initializing(_) => {}
+        }
+
+        if let InitState::Initializing(retry_count) = self.init_state {
+            info!("Pinnacle: Initializing trackpad (attempt {})", retry_count + 1);
+            match self.sensor.init().await {
+                Ok(()) => {
+                    self.init_state = InitState::Ready;
+                    return true;
+                }
+                Err(_e) => {
+                    error!("Pinnacle: Init failed: {:?}", _e);
+                    if retry_count + 1 >= Self::MAX_INIT_RETRIES {
+                        error!("Pinnacle: Max retries reached, giving up");
+                        self.init_state = InitState::Failed;
+                        return false;
+                    }
+                    self.init_state = InitState::Initializing(retry_count + 1);
+                    Timer::after_millis(100).await;
+                    return false;
+                }
+            }
+        }
+
+        false
+    }
+
+    async fn data_ready(&mut self) -> bool {
+        match &mut self.data_ready {
+            Some(pin) => pin.is_high().unwrap_or(true),
+            None => self.sensor.data_ready().await.unwrap_or(false),
+        }
+    }
+}
+
+impl<IF: PinnacleInterface, DR: InputPin> InputDevice for PinnacleDevice<IF, DR> {
+    async fn read_event(&mut self) -> Event {
+        loop {
+            Timer::after(self.poll_interval).await;
+
+            if self.init_state != InitState::Ready && !self.try_init().await {
+                continue;
+            }
+
+            if !self.data_ready().await {
+                continue;
+            }
+
+            match self.sensor.read_data().await {
+                Ok(TouchData::Absolute { x, y, z }) => {
+                    // Report the lift once, then stay silent until the next touch
+                    if z == 0 && !self.touching {
+                        continue;
+                    }
+                    self.touching = z != 0;
+                    return Event::Touchpad(TouchpadEvent {
+                        finger: 0,
+                        axis: [
+                            AxisEvent {
+                                typ: AxisValType::Abs,
+                                axis: Axis::X,
+                                value: x,
+                            },
+                            AxisEvent {
+                                typ: AxisValType::Abs,
+                                axis: Axis::Y,
+                                value: y,
+                            },
+                            AxisEvent {
+                                typ: AxisValType::Abs,
+                                axis: Axis::Z,
+                                value: z as i16,
+                            },
+                        ],
+                    });
+                }
+                Ok(TouchData::Relative { dx, dy, wheel }) => {
+                    if dx == 0 && dy == 0 && wheel == 0 {
+                        continue;
+                    }
+                    return Event::Touchpad(TouchpadEvent {
+                        finger: 0,
+                        axis: [
+                            AxisEvent {
+                                typ: AxisValType::Rel,
+                                axis: Axis::X,
+                                value: dx,
+                            },
+                            AxisEvent {
+                                typ: AxisValType::Rel,
+                                axis: Axis::Y,
+                                value: dy,
+                            },
+                            AxisEvent {
+                                typ: AxisValType::Rel,
+                                axis: Axis::V,
+                                value: wheel as i16,
+                            },
+                        ],
+                    });
+                }
+                Err(_e) => {
+                    warn!("Pinnacle read error: {:?}", _e);
+                }
+            }
+        }
+    }
+}
+
+/// Touchpad processor that converts touchpad events to mouse reports
+///
+/// Absolute positions are converted to the motion between two consecutive touch events.
+pub struct PinnacleProcessor<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize> {
+    /// Reference to the keymap
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    /// Last absolute position of the finger
+    last_position: Option<(i16, i16)>,
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    PinnacleProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Create a new touchpad processor
+    pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+        Self {
+            keymap,
+            last_position: None,
+        }
+    }
+
+    /// Convert the touchpad event to the relative motion (x, y, wheel)
+    fn motion(&mut self, event: &TouchpadEvent) -> Option<(i16, i16, i16)> {
+        let mut x = 0i16;
+        let mut y = 0i16;
+        let mut z = 0i16;
+        let mut wheel = 0i16;
+        let mut absolute = false;
+
+        for axis_event in event.axis.iter() {
+            if let AxisValType::Abs = axis_event.typ {
+                absolute = true;
+            }
+            match axis_event.axis {
+                Axis::X => x = axis_event.value,
+                Axis::Y => y = axis_event.value,
+                Axis::Z => z = axis_event.value,
+                Axis::V => wheel = axis_event.value,
+                _ => {}
+            }
+        }
+
+        if !absolute {
+            return Some((x, y, wheel));
+        }
+
+        if z == 0 {
+            // Finger lifted
+            self.last_position = None;
+            return None;
+        }
+
+        let motion = self
+            .last_position
+            .map(|(last_x, last_y)| (x.saturating_sub(last_x), y.saturating_sub(last_y), 0));
+        self.last_position = Some((x, y));
+        motion
+    }
+
+    async fn generate_report(&self, x: i16, y: i16, wheel: i16) {
+        let mouse_report = MouseReport {
+            buttons: 0,
+            x: x.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
+            y: y.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
+            wheel: wheel.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
+            pan: 0,
+        };
+        self.send_report(Report::MouseReport(mouse_report)).await;
+    }
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    InputProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER> for PinnacleProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    async fn process(&mut self, event: Event) -> ProcessResult {
+        match event {
+            Event::Touchpad(touchpad_event) => {
+                if let Some((x, y, wheel)) = self.motion(&touchpad_event)
+                    && (x != 0 || y != 0 || wheel != 0)
+                {
+                    self.generate_report(x, y, wheel).await;
+                }
+                ProcessResult::Stop
+            }
+            _ => ProcessResult::Continue(event),
+        }
+    }
+
+    async fn send_report(&self, report: Report) {
+        KEYBOARD_REPORT_CHANNEL.send(report).await;
+    }
+
+    fn get_keymap(&self) -> &RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>> {
+        self.keymap
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embedded_hal_async::i2c::{ErrorType, Operation as I2cOperation};
+
+    use super::*;
+
+    /// A mock Pinnacle on an I2C bus, backed by a register file
+    struct MockPinnacle {
+        regs: [u8; 0x20],
+        era: [u8; 0x200],
+        calibrated: bool,
+    }
+
+    impl MockPinnacle {
+        fn new() -> Self {
+            let mut regs = [0u8; 0x20];
+            regs[REG_FIRMWARE_ID as usize] = FIRMWARE_ID_PINNACLE;
+            Self {
+                regs,
+                era: [0u8; 0x200],
+                calibrated: false,
+            }
+        }
+
+        fn write(&mut self, reg: u8, value: u8) {
+            self.regs[reg as usize] = value;
+            match reg {
+                REG_CAL_CONFIG1 if value & CAL_CONFIG1_CALIBRATE != 0 => {
+                    self.calibrated = true;
+                    self.regs[reg as usize] &= !CAL_CONFIG1_CALIBRATE;
+                    self.regs[REG_STATUS1 as usize] |= STATUS1_SW_CC;
+                }
+                REG_ERA_CONTROL => {
+                    let addr = ((self.regs[REG_ERA_HIGH_BYTE as usize] as usize) << 8)
+                        | self.regs[REG_ERA_LOW_BYTE as usize] as usize;
+                    if value & ERA_CONTROL_READ != 0 {
+                        self.regs[REG_ERA_VALUE as usize] = self.era[addr];
+                    } else if value & ERA_CONTROL_WRITE != 0 {
+                        self.era[addr] = self.regs[REG_ERA_VALUE as usize];
+                    }
+                    self.regs[REG_ERA_CONTROL as usize] = 0;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    struct MockI2c<'a> {
+        pad: &'a RefCell<MockPinnacle>,
+    }
+
+    impl ErrorType for MockI2c<'_> {
+        type Error = Infallible;
+    }
+
+    impl I2c for MockI2c<'_> {
+        async fn transaction(&mut self, address: u8, operations: &mut [I2cOperation<'_>]) -> Result<(), Self::Error> {
+            assert_eq!(address, 0x2a);
+            let mut pad = self.pad.borrow_mut();
+            let mut read_reg = None;
+            for op in operations.iter_mut() {
+                match op {
+                    I2cOperation::Write(data) if data[0] & 0xe0 == RAP_READ => read_reg = Some(data[0] & 0x1f),
+                    I2cOperation::Write(data) => pad.write(data[0] & 0x1f, data[1]),
+                    I2cOperation::Read(buf) => {
+                        let reg = read_reg.expect("read without register address") as usize;
+                        buf.copy_from_slice(&pad.regs[reg..reg + buf.len()]);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_init_absolute() {
+        let pad = RefCell::new(MockPinnacle::new());
+        let config = PinnacleConfig {
+            attenuation: PinnacleAttenuation::X2,
+            ..Default::default()
+        };
+        let mut pinnacle = Pinnacle::new_i2c(MockI2c { pad: &pad }, 0x2a, config);
+        block_on(pinnacle.init()).unwrap();
+
+        let pad = pad.borrow();
+        assert!(pad.calibrated);
+        assert_eq!(
+            pad.regs[REG_FEED_CONFIG1 as usize],
+            FEED_CONFIG1_FEED_ENABLE | FEED_CONFIG1_DATA_MODE_ABS
+        );
+        assert_eq!(pad.regs[REG_Z_IDLE as usize], Z_IDLE_COUNT);
+        assert_eq!(
+            pad.era[EXT_REG_TRACK_ADC_CONFIG as usize] & TRACK_ADC_CONFIG_ATTENUATION_MASK,
+            PinnacleAttenuation::X2 as u8
+        );
+        // Edge sensitivity is untouched without curved overlay
+        assert_eq!(pad.era[EXT_REG_X_AXIS_WIDE_Z_MIN as usize], 0);
+    }
+
+    #[test]
+    fn test_init_relative_curved_overlay() {
+        let pad = RefCell::new(MockPinnacle::new());
+        let config = PinnacleConfig {
+            mode: PinnacleMode::Relative,
+            curved_overlay: true,
+            ..Default::default()
+        };
+        let mut pinnacle = Pinnacle::new_i2c(MockI2c { pad: &pad }, 0x2a, config);
+        block_on(pinnacle.init()).unwrap();
+
+        let pad = pad.borrow();
+        assert_eq!(pad.regs[REG_FEED_CONFIG1 as usize], FEED_CONFIG1_FEED_ENABLE);
+        assert_eq!(pad.era[EXT_REG_X_AXIS_WIDE_Z_MIN as usize], X_AXIS_WIDE_Z_MIN_CURVED);
+        assert_eq!(pad.era[EXT_REG_Y_AXIS_WIDE_Z_MIN as usize], Y_AXIS_WIDE_Z_MIN_CURVED);
+    }
+
+    #[test]
+    fn test_invalid_firmware_id() {
+        let pad = RefCell::new(MockPinnacle::new());
+        pad.borrow_mut().regs[REG_FIRMWARE_ID as usize] = 0x42;
+        let mut pinnacle = Pinnacle::new_i2c(MockI2c { pad: &pad }, 0x2a, PinnacleConfig::default());
+        assert_eq!(block_on(pinnacle.init()), Err(PinnacleError::InvalidFirmwareId(0x42)));
+    }
+
+    #[test]
+    fn test_read_absolute() {
+        let pad = RefCell::new(MockPinnacle::new());
+        let config = PinnacleConfig {
+            diameter_mm: 254,
+            cpi: 100,
+            ..Default::default()
+        };
+        let mut pinnacle = Pinnacle::new_i2c(MockI2c { pad: &pad }, 0x2a, config);
+        block_on(pinnacle.init()).unwrap();
+
+        // x = 0x41f = 1055, y = 0x2ef = 751, z = 0x20
+        pad.borrow_mut().regs[REG_PACKET_BYTE0 as usize..REG_PACKET_BYTE0 as usize + 6]
+            .copy_from_slice(&[0x00, 0x00, 0x1f, 0xef, 0x24, 0x20]);
+        pad.borrow_mut().regs[REG_STATUS1 as usize] = STATUS1_SW_DR;
+        assert!(block_on(pinnacle.data_ready()).unwrap());
+
+        // 254mm at 100 cpi covers 1000 counts
+        let data = block_on(pinnacle.read_data()).unwrap();
+        assert_eq!(
+            data,
+            TouchData::Absolute {
+                x: ((1055 - X_LOWER) as u32 * 1000 / (X_UPPER - X_LOWER) as u32) as i16,
+                y: ((751 - Y_LOWER) as u32 * 1000 / (Y_UPPER - Y_LOWER) as u32) as i16,
+                z: 0x20,
+            }
+        );
+        // Data ready flag is cleared after reading
+        assert!(!block_on(pinnacle.data_ready()).unwrap());
+
+        // Lifted finger
+        pad.borrow_mut().regs[REG_PACKET_BYTE0 as usize + 5] = 0;
+        assert_eq!(
+            block_on(pinnacle.read_data()).unwrap(),
+            TouchData::Absolute { x: 0, y: 0, z: 0 }
+        );
+    }
+
+    #[test]
+    fn test_read_relative() {
+        let pad = RefCell::new(MockPinnacle::new());
+        let config = PinnacleConfig {
+            mode: PinnacleMode::Relative,
+            invert_y: true,
+            ..Default::default()
+        };
+        let mut pinnacle = Pinnacle::new_i2c(MockI2c { pad: &pad }, 0x2a, config);
+        block_on(pinnacle.init()).unwrap();
+
+        // dx = -3 (sign bit set), dy = 5, wheel = -1
+        pad.borrow_mut().regs[REG_PACKET_BYTE0 as usize..REG_PACKET_BYTE0 as usize + 4]
+            .copy_from_slice(&[0x10, 0xfd, 0x05, 0xff]);
+        assert_eq!(
+            block_on(pinnacle.read_data()).unwrap(),
+            TouchData::Relative {
+                dx: -3,
+                dy: -5,
+                wheel: -1
+            }
+        );
+    }
+}
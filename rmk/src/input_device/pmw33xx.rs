@@ -0,0 +1,432 @@
+//! PMW3360/PMW3389 Optical Mouse Sensor Driver
+//!
+//! PMW3360 and PMW3389 share the same register map and SPI protocol, the differences are the product id and the resolution registers.
+//! Both sensors need an SROM firmware uploaded after power up, which is provided by the sensor vendor and not included in RMK.
+//!
+//! Ported from the QMK driver implementation:
+//! https://github.com/qmk/qmk_firmware/blob/master/drivers/sensors/pmw33xx_common.c
+
+use core::marker::PhantomData;
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::spi::SpiBus;
+
+pub use crate::input_device::motion_sensor::MotionData;
+use crate::input_device::motion_sensor::{MotionSensor, MotionSensorDevice, MotionSensorError, RestMode};
+
+// ============================================================================
+// Registers
+// ============================================================================
+const REG_PRODUCT_ID: u8 = 0x00;
+const REG_MOTION: u8 = 0x02;
+const REG_DELTA_Y_H: u8 = 0x06;
+// PMW3389 only, resolution = value * 50
+const REG_RESOLUTION_L: u8 = 0x0e;
+const REG_RESOLUTION_H: u8 = 0x0f;
+// PMW3360 only, resolution = (value + 1) * 100
+const REG_CONFIG1: u8 = 0x0f;
+const REG_CONFIG2: u8 = 0x10;
+const REG_SROM_ENABLE: u8 = 0x13;
+const REG_SROM_ID: u8 = 0x2a;
+const REG_POWER_UP_RESET: u8 = 0x3a;
+const REG_INVERSE_PRODUCT_ID: u8 = 0x3f;
+const REG_MOTION_BURST: u8 = 0x50;
+const REG_SROM_LOAD_BURST: u8 = 0x62;
+const REG_LIFT_CONFIG: u8 = 0x63;
+
+// ============================================================================
+// Burst register offsets
+// ============================================================================
+const BURST_MOTION: usize = 0;
+const BURST_DELTA_X_L: usize = 2;
+const BURST_DELTA_X_H: usize = 3;
+const BURST_DELTA_Y_L: usize = 4;
+const BURST_DELTA_Y_H: usize = 5;
+const BURST_DATA_LEN: usize = 12;
+
+// ============================================================================
+// Constants
+// ============================================================================
+const SPI_WRITE: u8 = 0x80;
+const MOTION_STATUS_MOTION: u8 = 0x80;
+const POWER_UP_RESET_VAL: u8 = 0x5a;
+const CONFIG2_REST_EN: u8 = 0x20;
+const SROM_ENABLE_INIT: u8 = 0x1d;
+const SROM_ENABLE_START: u8 = 0x18;
+const LIFT_CONFIG_2MM: u8 = 0x02;
+const LIFT_CONFIG_3MM: u8 = 0x03;
+
+// Timing constants
+const RESET_DELAY_MS: u64 = 50;
+const SROM_INIT_DELAY_MS: u64 = 10;
+const SROM_BYTE_DELAY_US: u64 = 15;
+const SROM_DONE_DELAY_US: u64 = 200;
+
+// SPI timing constants (from PMW3360 datasheet)
+const T_NCS_SCLK_US: u64 = 1;
+const T_SRAD_US: u64 = 160;
+const T_SRAD_MOTBR_US: u64 = 35;
+const T_SRX_US: u64 = 20;
+const T_SCLK_NCS_WR_US: u64 = 35;
+const T_SWX_US: u64 = 180;
+const T_BEXIT_US: u64 = 1;
+
+/// Differences between sensors of PMW33xx family
+pub trait Pmw33xxVariant: Sized {
+    /// Name of the sensor, used in logs
+    const NAME: &'static str;
+    /// Expected value of the product id register
+    const PRODUCT_ID: u8;
+    /// Supported CPI range, (min, max, step)
+    const CPI_RANGE: (u16, u16, u16);
+
+    /// Write resolution registers
+    async fn write_cpi<SPI: SpiBus, CS: OutputPin, MOTION: InputPin>(
+        sensor: &mut Pmw33xx<SPI, CS, MOTION, Self>,
+        cpi: u16,
+    ) -> Result<(), MotionSensorError>;
+
+    /// Read resolution registers
+    async fn read_cpi<SPI: SpiBus, CS: OutputPin, MOTION: InputPin>(
+        sensor: &mut Pmw33xx<SPI, CS, MOTION, Self>,
+    ) -> Result<u16, MotionSensorError>;
+}
+
+/// PMW3360 sensor, 100-12000 CPI
+pub struct Pmw3360Variant;
+
+impl Pmw33xxVariant for Pmw3360Variant {
+    const NAME: &'static str = "PMW3360";
+    const PRODUCT_ID: u8 = 0x42;
+    const CPI_RANGE: (u16, u16, u16) = (100, 12000, 100);
+
+    async fn write_cpi<SPI: SpiBus, CS: OutputPin, MOTION: InputPin>(
+        sensor: &mut Pmw33xx<SPI, CS, MOTION, Self>,
+        cpi: u16,
+    ) -> Result<(), MotionSensorError> {
+        sensor.write_reg(REG_CONFIG1, (cpi / 100 - 1) as u8).await
+    }
+
+    async fn read_cpi<SPI: SpiBus, CS: OutputPin, MOTION: InputPin>(
+        sensor: &mut Pmw33xx<SPI, CS, MOTION, Self>,
+    ) -> Result<u16, MotionSensorError> {
+        let val = sensor.read_reg(REG_CONFIG1).await?;
+        Ok((val as u16 + 1) * 100)
+    }
+}
+
+/// PMW3389 sensor, 50-16000 CPI
+pub struct Pmw3389Variant;
+
+impl Pmw33xxVariant for Pmw3389Variant {
+    const NAME: &'static str = "PMW3389";
+    const PRODUCT_ID: u8 = 0x47;
+    const CPI_RANGE: (u16, u16, u16) = (50, 16000, 50);
+
+    async fn write_cpi<SPI: SpiBus, CS: OutputPin, MOTION: InputPin>(
+        sensor: &mut Pmw33xx<SPI, CS, MOTION, Self>,
+        cpi: u16,
+    ) -> Result<(), MotionSensorError> {
+        let val = cpi / 50;
+        sensor.write_reg(REG_RESOLUTION_L, (val & 0xff) as u8).await?;
+        sensor.write_reg(REG_RESOLUTION_H, (val >> 8) as u8).await
+    }
+
+    async fn read_cpi<SPI: SpiBus, CS: OutputPin, MOTION: InputPin>(
+        sensor: &mut Pmw33xx<SPI, CS, MOTION, Self>,
+    ) -> Result<u16, MotionSensorError> {
+        let low = sensor.read_reg(REG_RESOLUTION_L).await?;
+        let high = sensor.read_reg(REG_RESOLUTION_H).await?;
+        Ok((((high as u16) << 8) | low as u16) * 50)
+    }
+}
+
+/// PMW33xx configuration
+#[derive(Clone)]
+pub struct Pmw33xxConfig {
+    /// CPI resolution, 0 to use default
+    pub cpi: u16,
+    /// Invert X axis
+    pub invert_x: bool,
+    /// Invert Y axis
+    pub invert_y: bool,
+    /// Swap X and Y axes
+    pub swap_xy: bool,
+    /// Power saving behavior
+    pub rest_mode: RestMode,
+    /// Use 3mm lift-off distance instead of 2mm
+    pub lift_off_3mm: bool,
+    /// SROM firmware, uploaded during initialization.
+    /// The sensor doesn't work properly without SROM, but it's not redistributable so RMK doesn't include it.
+    pub srom: Option<&'static [u8]>,
+}
+
+impl Default for Pmw33xxConfig {
+    fn default() -> Self {
+        Self {
+            cpi: 0,
+            invert_x: false,
+            invert_y: false,
+            swap_xy: false,
+            rest_mode: RestMode::Normal,
+            lift_off_3mm: false,
+            srom: None,
+        }
+    }
+}
+
+/// PMW33xx driver using embedded-hal SPI traits
+pub struct Pmw33xx<SPI, CS, MOTION, V>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+    MOTION: InputPin,
+    V: Pmw33xxVariant,
+{
+    spi: SPI,
+    cs: CS,
+    motion_gpio: Option<MOTION>,
+    config: Pmw33xxConfig,
+    /// Whether the sensor is in burst mode, any other register access exits the burst mode
+    in_burst: bool,
+    _variant: PhantomData<V>,
+}
+
+/// PMW3360 driver
+pub type Pmw3360<SPI, CS, MOTION> = Pmw33xx<SPI, CS, MOTION, Pmw3360Variant>;
+/// PMW3389 driver
+pub type Pmw3389<SPI, CS, MOTION> = Pmw33xx<SPI, CS, MOTION, Pmw3389Variant>;
+/// PMW3360 as an InputDevice for RMK
+pub type Pmw3360Device<SPI, CS, MOTION> = MotionSensorDevice<Pmw3360<SPI, CS, MOTION>>;
+/// PMW3389 as an InputDevice for RMK
+pub type Pmw3389Device<SPI, CS, MOTION> = MotionSensorDevice<Pmw3389<SPI, CS, MOTION>>;
+
+impl<SPI, CS, MOTION, V> Pmw33xx<SPI, CS, MOTION, V>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+    MOTION: InputPin,
+    V: Pmw33xxVariant,
+{
+    /// Create a new PMW33xx driver instance
+    pub fn new(spi: SPI, cs: CS, motion_gpio: Option<MOTION>, config: Pmw33xxConfig) -> Self {
+        Self {
+            spi,
+            cs,
+            motion_gpio,
+            config,
+            in_burst: false,
+            _variant: PhantomData,
+        }
+    }
+
+    async fn read_reg(&mut self, addr: u8) -> Result<u8, MotionSensorError> {
+        self.in_burst = false;
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(T_NCS_SCLK_US)).await;
+
+        self.spi
+            .write(&[addr & 0x7f])
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+
+        Timer::after(Duration::from_micros(T_SRAD_US)).await;
+
+        let mut value = [0u8];
+        self.spi.read(&mut value).await.map_err(|_| MotionSensorError::Spi)?;
+        self.spi.flush().await.map_err(|_| MotionSensorError::Spi)?;
+
+        let _ = self.cs.set_high();
+
+        Timer::after(Duration::from_micros(T_SRX_US)).await;
+
+        Ok(value[0])
+    }
+
+    async fn write_reg(&mut self, addr: u8, value: u8) -> Result<(), MotionSensorError> {
+        self.in_burst = false;
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(T_NCS_SCLK_US)).await;
+
+        self.spi
+            .write(&[addr | SPI_WRITE, value])
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+        self.spi.flush().await.map_err(|_| MotionSensorError::Spi)?;
+
+        Timer::after(Duration::from_micros(T_SCLK_NCS_WR_US)).await;
+        let _ = self.cs.set_high();
+
+        Timer::after(Duration::from_micros(T_SWX_US)).await;
+
+        Ok(())
+    }
+
+    /// Reset the SPI port of the sensor by toggling CS
+    async fn reset_spi(&mut self) {
+        let _ = self.cs.set_high();
+        Timer::after(Duration::from_micros(40)).await;
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(40)).await;
+        let _ = self.cs.set_high();
+    }
+
+    fn transform(&self, dx: i16, dy: i16) -> MotionData {
+        let (mut dx, mut dy) = if self.config.swap_xy { (dy, dx) } else { (dx, dy) };
+        if self.config.invert_x {
+            dx = dx.saturating_neg();
+        }
+        if self.config.invert_y {
+            dy = dy.saturating_neg();
+        }
+        MotionData { dx, dy }
+    }
+}
+
+impl<SPI, CS, MOTION, V> MotionSensor for Pmw33xx<SPI, CS, MOTION, V>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+    MOTION: InputPin,
+    V: Pmw33xxVariant,
+{
+    const CPI_RANGE: (u16, u16, u16) = V::CPI_RANGE;
+
+    async fn init(&mut self) -> Result<(), MotionSensorError> {
+        self.reset_spi().await;
+
+        self.write_reg(REG_POWER_UP_RESET, POWER_UP_RESET_VAL).await?;
+        Timer::after(Duration::from_millis(RESET_DELAY_MS)).await;
+
+        // Read motion registers once, regardless of the motion state
+        for reg in REG_MOTION..=REG_DELTA_Y_H {
+            self.read_reg(reg).await?;
+        }
+
+        let product_id = self.read_reg(REG_PRODUCT_ID).await?;
+        let inverse_product_id = self.read_reg(REG_INVERSE_PRODUCT_ID).await?;
+        if product_id != V::PRODUCT_ID || product_id != !inverse_product_id {
+            error!("{}: Invalid product id: {:#02x}", V::NAME, product_id);
+            return Err(MotionSensorError::InvalidProductId(product_id));
+        }
+        info!("{} detected, product ID: {:#02x}", V::NAME, product_id);
+
+        if let Some(srom) = self.config.srom {
+            self.upload_srom(srom).await?;
+        } else {
+            warn!("{}: No SROM provided, the sensor may not track properly", V::NAME);
+        }
+
+        self.set_rest_mode(self.config.rest_mode).await?;
+
+        let lift_config = if self.config.lift_off_3mm {
+            LIFT_CONFIG_3MM
+        } else {
+            LIFT_CONFIG_2MM
+        };
+        self.write_reg(REG_LIFT_CONFIG, lift_config).await?;
+
+        if self.config.cpi > 0 {
+            self.set_cpi(self.config.cpi).await?;
+        }
+
+        info!("{} initialized successfully", V::NAME);
+        Ok(())
+    }
+
+    async fn set_cpi(&mut self, cpi: u16) -> Result<(), MotionSensorError> {
+        if !Self::is_valid_cpi(cpi) {
+            return Err(MotionSensorError::InvalidCpi);
+        }
+        V::write_cpi(self, cpi).await?;
+        debug!("{}: Resolution set to {} CPI", V::NAME, cpi);
+        Ok(())
+    }
+
+    async fn get_cpi(&mut self) -> Result<u16, MotionSensorError> {
+        V::read_cpi(self).await
+    }
+
+    async fn read_motion(&mut self) -> Result<MotionData, MotionSensorError> {
+        if !self.in_burst {
+            // Write any value to Motion_Burst register to enter burst mode
+            self.write_reg(REG_MOTION_BURST, 0x00).await?;
+            self.in_burst = true;
+        }
+
+        let mut burst_data = [0u8; BURST_DATA_LEN];
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(T_NCS_SCLK_US)).await;
+        self.spi
+            .write(&[REG_MOTION_BURST])
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+        Timer::after(Duration::from_micros(T_SRAD_MOTBR_US)).await;
+        self.spi
+            .read(&mut burst_data)
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+        self.spi.flush().await.map_err(|_| MotionSensorError::Spi)?;
+        let _ = self.cs.set_high();
+        Timer::after(Duration::from_micros(T_BEXIT_US)).await;
+
+        if (burst_data[BURST_MOTION] & MOTION_STATUS_MOTION) == 0x00 {
+            return Ok(MotionData::default());
+        }
+
+        let dx = i16::from_le_bytes([burst_data[BURST_DELTA_X_L], burst_data[BURST_DELTA_X_H]]);
+        let dy = i16::from_le_bytes([burst_data[BURST_DELTA_Y_L], burst_data[BURST_DELTA_Y_H]]);
+
+        Ok(self.transform(dx, dy))
+    }
+
+    /// Check if motion is pending (motion GPIO is active low)
+    fn motion_pending(&mut self) -> bool {
+        match &mut self.motion_gpio {
+            Some(gpio) => gpio.is_low().unwrap_or(true),
+            None => true,
+        }
+    }
+
+    async fn set_rest_mode(&mut self, mode: RestMode) -> Result<(), MotionSensorError> {
+        let val = match mode {
+            RestMode::Normal => CONFIG2_REST_EN,
+            RestMode::ForceAwake => 0x00,
+        };
+        self.write_reg(REG_CONFIG2, val).await
+    }
+
+    async fn upload_srom(&mut self, srom: &[u8]) -> Result<(), MotionSensorError> {
+        // Rest mode should be disabled before the SROM download
+        self.write_reg(REG_CONFIG2, 0x00).await?;
+
+        self.write_reg(REG_SROM_ENABLE, SROM_ENABLE_INIT).await?;
+        Timer::after(Duration::from_millis(SROM_INIT_DELAY_MS)).await;
+        self.write_reg(REG_SROM_ENABLE, SROM_ENABLE_START).await?;
+
+        let _ = self.cs.set_low();
+        Timer::after(Duration::from_micros(T_NCS_SCLK_US)).await;
+        self.spi
+            .write(&[REG_SROM_LOAD_BURST | SPI_WRITE])
+            .await
+            .map_err(|_| MotionSensorError::Spi)?;
+        for byte in srom {
+            Timer::after(Duration::from_micros(SROM_BYTE_DELAY_US)).await;
+            self.spi.write(&[*byte]).await.map_err(|_| MotionSensorError::Spi)?;
+        }
+        self.spi.flush().await.map_err(|_| MotionSensorError::Spi)?;
+        Timer::after(Duration::from_micros(SROM_BYTE_DELAY_US)).await;
+        let _ = self.cs.set_high();
+        Timer::after(Duration::from_micros(SROM_DONE_DELAY_US)).await;
+
+        let srom_id = self.read_reg(REG_SROM_ID).await?;
+        if srom_id == 0x00 || srom_id == 0xff {
+            error!("{}: SROM upload failed, SROM id: {:#02x}", V::NAME, srom_id);
+            return Err(MotionSensorError::SromFailed);
+        }
+        info!("{}: SROM uploaded, SROM id: {:#02x}", V::NAME, srom_id);
+
+        Ok(())
+    }
+}
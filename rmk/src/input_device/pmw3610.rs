@@ -3,19 +3,15 @@
 //! Ported from the Zephyr driver implementation:
 //! https://github.com/zephyrproject-rtos/zephyr/blob/d31c6e95033fd6b3763389edba6a655245ae1328/drivers/input/input_pmw3610.c
 
-use core::cell::RefCell;
-
 use embassy_time::{Duration, Timer};
 use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal_async::spi::SpiBus;
-use usbd_hid::descriptor::MouseReport;
 
-use crate::channel::KEYBOARD_REPORT_CHANNEL;
 pub use crate::driver::bitbang_spi::{BitBangError, BitBangSpiBus};
-use crate::event::{Axis, AxisEvent, AxisValType, Event};
-use crate::hid::Report;
-use crate::input_device::{InputDevice, InputProcessor, ProcessResult};
-use crate::keymap::KeyMap;
+pub use crate::input_device::motion_sensor::MotionData;
+use crate::input_device::motion_sensor::{
+    MotionSensor, MotionSensorDevice, MotionSensorError, MotionSensorProcessor, RestMode,
+};
 
 // ============================================================================
 // Page 0 registers
@@ -136,25 +132,7 @@ impl Default for Pmw3610Config {
 }
 
 /// PMW3610 error types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub enum Pmw3610Error {
-    /// SPI communication error
-    Spi,
-    /// Invalid product ID detected
-    InvalidProductId(u8),
-    /// Initialization failed
-    InitFailed,
-    /// Invalid CPI value
-    InvalidCpi,
-}
-
-/// Motion data from the sensor
-#[derive(Debug, Clone, Copy, Default)]
-pub struct MotionData {
-    pub dx: i16,
-    pub dy: i16,
-}
+pub type Pmw3610Error = MotionSensorError;
 
 /// PMW3610 driver using embedded-hal SPI traits
 pub struct Pmw3610<SPI, CS, MOTION>
@@ -187,14 +165,6 @@ where
         }
     }
 
-    /// Check if motion is pending (motion GPIO is active low)
-    pub fn motion_pending(&mut self) -> bool {
-        match &mut self.motion_gpio {
-            Some(gpio) => gpio.is_low().unwrap_or(true),
-            None => true,
-        }
-    }
-
     #[inline(always)]
     fn short_delay() {
         for _ in 0..64 {
@@ -289,6 +259,17 @@ where
         Ok(())
     }
 
+    /// Get sensor resolution in CPI
+    pub async fn get_resolution(&mut self) -> Result<u16, Pmw3610Error> {
+        self.spi_clk_on().await?;
+        self.write_reg(PMW3610_SPI_PAGE0, SPI_PAGE0_1).await?;
+        let val = self.read_reg(PMW3610_RES_STEP).await?;
+        self.write_reg(PMW3610_SPI_PAGE1, SPI_PAGE1_0).await?;
+        self.spi_clk_off().await?;
+
+        Ok((val & RES_STEP_RES_MASK) as u16 * RES_STEP)
+    }
+
     /// Set force awake mode
     pub async fn force_awake(&mut self, enable: bool) -> Result<(), Pmw3610Error> {
         let mut val = self.read_reg(PMW3610_PERFORMANCE).await?;
@@ -374,16 +355,32 @@ where
         Ok(())
     }
 
-    /// Initialize the sensor (public API)
-    pub async fn init(&mut self) -> Result<(), Pmw3610Error> {
+    fn sign_extend(value: u16, bits: usize) -> i16 {
+        let sign_bit = 1 << bits;
+        if value & sign_bit != 0 {
+            (value | !((1 << (bits + 1)) - 1)) as i16
+        } else {
+            value as i16
+        }
+    }
+}
+
+impl<SPI, CS, MOTION> MotionSensor for Pmw3610<SPI, CS, MOTION>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+    MOTION: InputPin,
+{
+    const CPI_RANGE: (u16, u16, u16) = (RES_MIN, RES_MAX, RES_STEP);
+
+    async fn init(&mut self) -> Result<(), MotionSensorError> {
         let _ = self.cs.set_high();
         Timer::after(Duration::from_millis(1)).await;
 
         self.configure().await
     }
 
-    /// Read motion data from the sensor
-    pub async fn read_motion(&mut self) -> Result<MotionData, Pmw3610Error> {
+    async fn read_motion(&mut self) -> Result<MotionData, MotionSensorError> {
         let burst_data_len = if self.config.smart_mode {
             BURST_DATA_LEN_SMART
         } else {
@@ -423,211 +420,32 @@ where
         Ok(MotionData { dx, dy })
     }
 
-    fn sign_extend(value: u16, bits: usize) -> i16 {
-        let sign_bit = 1 << bits;
-        if value & sign_bit != 0 {
-            (value | !((1 << (bits + 1)) - 1)) as i16
-        } else {
-            value as i16
-        }
+    async fn set_cpi(&mut self, cpi: u16) -> Result<(), MotionSensorError> {
+        self.set_resolution(cpi).await
     }
-}
 
-/// Initialization state for the device
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum InitState {
-    Pending,
-    Initializing(u8),
-    Ready,
-    Failed,
-}
-
-/// PMW3610 as an InputDevice for RMK
-///
-/// This device returns `Event::Joystick` events with relative X/Y movement.
-pub struct Pmw3610Device<SPI, CS, MOTION>
-where
-    SPI: SpiBus,
-    CS: OutputPin,
-    MOTION: InputPin,
-{
-    sensor: Pmw3610<SPI, CS, MOTION>,
-    init_state: InitState,
-    poll_interval: Duration,
-}
-
-impl<SPI, CS, MOTION> Pmw3610Device<SPI, CS, MOTION>
-where
-    SPI: SpiBus,
-    CS: OutputPin,
-    MOTION: InputPin,
-{
-    const MAX_INIT_RETRIES: u8 = 3;
-
-    /// Create a new PMW3610 device for RMK
-    pub fn new(spi: SPI, cs: CS, motion_gpio: Option<MOTION>, config: Pmw3610Config) -> Self {
-        Self {
-            sensor: Pmw3610::new(spi, cs, motion_gpio, config),
-            init_state: InitState::Pending,
-            poll_interval: Duration::from_micros(500),
-        }
+    async fn get_cpi(&mut self) -> Result<u16, MotionSensorError> {
+        self.get_resolution().await
     }
 
-    /// Create a new PMW3610 device with custom poll interval
-    pub fn with_poll_interval(
-        spi: SPI,
-        cs: CS,
-        motion_gpio: Option<MOTION>,
-        config: Pmw3610Config,
-        poll_interval_us: u64,
-    ) -> Self {
-        Self {
-            sensor: Pmw3610::new(spi, cs, motion_gpio, config),
-            init_state: InitState::Pending,
-            poll_interval: Duration::from_micros(poll_interval_us),
+    /// Check if motion is pending (motion GPIO is active low)
+    fn motion_pending(&mut self) -> bool {
+        match &mut self.motion_gpio {
+            Some(gpio) => gpio.is_low().unwrap_or(true),
+            None => true,
         }
     }
 
-    async fn try_init(&mut self) -> bool {
-        match self.init_state {
-            InitState::Ready => return true,
-            InitState::Failed => return false,
-            InitState::Pending => {
-                self.init_state = InitState::Initializing(0);
-            }
-            InitState::Initializing(_) => {}
-        }
-
-        if let InitState::Initializing(retry_count) = self.init_state {
-            info!("PMW3610: Initializing sensor (attempt {})", retry_count + 1);
-
-            match self.sensor.init().await {
-                Ok(()) => {
-                    info!("PMW3610: Sensor initialized successfully");
-                    self.init_state = InitState::Ready;
-                    return true;
-                }
-                Err(_e) => {
-                    error!("PMW3610: Init failed: {:?}", _e);
-                    if retry_count + 1 >= Self::MAX_INIT_RETRIES {
-                        error!("PMW3610: Max retries reached, giving up");
-                        self.init_state = InitState::Failed;
-                        return false;
-                    }
-                    self.init_state = InitState::Initializing(retry_count + 1);
-                    Timer::after(Duration::from_millis(100)).await;
-                    return false;
-                }
-            }
-        }
-
-        false
+    async fn set_rest_mode(&mut self, mode: RestMode) -> Result<(), MotionSensorError> {
+        self.force_awake(mode == RestMode::ForceAwake).await
     }
 }
 
-impl<SPI, CS, MOTION> InputDevice for Pmw3610Device<SPI, CS, MOTION>
-where
-    SPI: SpiBus,
-    CS: OutputPin,
-    MOTION: InputPin,
-{
-    async fn read_event(&mut self) -> Event {
-        loop {
-            Timer::after(self.poll_interval).await;
-
-            if self.init_state != InitState::Ready && !self.try_init().await {
-                continue;
-            }
-
-            if !self.sensor.motion_pending() {
-                continue;
-            }
-
-            match self.sensor.read_motion().await {
-                Ok(motion) => {
-                    if motion.dx != 0 || motion.dy != 0 {
-                        return Event::Joystick([
-                            AxisEvent {
-                                typ: AxisValType::Rel,
-                                axis: Axis::X,
-                                value: motion.dx,
-                            },
-                            AxisEvent {
-                                typ: AxisValType::Rel,
-                                axis: Axis::Y,
-                                value: motion.dy,
-                            },
-                            AxisEvent {
-                                typ: AxisValType::Rel,
-                                axis: Axis::Z,
-                                value: 0,
-                            },
-                        ]);
-                    }
-                }
-                Err(_e) => {
-                    warn!("PMW3610 read error");
-                }
-            }
-        }
-    }
-}
+/// PMW3610 as an InputDevice for RMK
+///
+/// This device returns `Event::Joystick` events with relative X/Y movement.
+pub type Pmw3610Device<SPI, CS, MOTION> = MotionSensorDevice<Pmw3610<SPI, CS, MOTION>>;
 
 /// PMW3610 Processor that converts motion events to mouse reports
-pub struct Pmw3610Processor<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize> {
-    /// Reference to the keymap
-    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
-}
-
-impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
-    Pmw3610Processor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
-{
-    /// Create a new PMW3610 processor with default settings
-    pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
-        Self { keymap }
-    }
-
-    async fn generate_report(&self, x: i16, y: i16) {
-        let mouse_report = MouseReport {
-            buttons: 0,
-            x: x.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
-            y: y.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
-            wheel: 0,
-            pan: 0,
-        };
-        self.send_report(Report::MouseReport(mouse_report)).await;
-    }
-}
-
-impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
-    InputProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER> for Pmw3610Processor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
-{
-    async fn process(&mut self, event: Event) -> ProcessResult {
-        match event {
-            Event::Joystick(axis_events) => {
-                let mut x = 0i16;
-                let mut y = 0i16;
-
-                for axis_event in axis_events.iter() {
-                    match axis_event.axis {
-                        Axis::X => x = axis_event.value,
-                        Axis::Y => y = axis_event.value,
-                        _ => {}
-                    }
-                }
-
-                self.generate_report(x, y).await;
-                ProcessResult::Stop
-            }
-            _ => ProcessResult::Continue(event),
-        }
-    }
-
-    async fn send_report(&self, report: Report) {
-        KEYBOARD_REPORT_CHANNEL.send(report).await;
-    }
-
-    fn get_keymap(&self) -> &RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>> {
-        self.keymap
-    }
-}
+pub type Pmw3610Processor<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize> =
+    MotionSensorProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>;
@@ -1,6 +1,7 @@
 //! General rotary encoder
 //!
 //! The rotary encoder implementation is adapted from: <https://github.com/leshow/rotary-encoder-hal/blob/master/src/lib.rs>
+use embassy_time::{Duration, Instant};
 use embedded_hal::digital::InputPin;
 #[cfg(feature = "async_matrix")]
 use embedded_hal_async::digital::Wait;
@@ -20,9 +21,76 @@ pub struct RotaryEncoder<A, B, P> {
     phase: P,
     /// The index of the rotary encoder
     id: u8,
-    /// The last action of the rotary encoder.
+    /// The last action of the rotary encoder, with the id used in the encoder map.
     /// When it's not `None`, the rotary encoder needs to emit a release event.
-    last_action: Option<Direction>,
+    last_action: Option<(u8, Direction)>,
+    /// The action to be repeated and the remaining count, used by the acceleration
+    repeat: Option<(u8, Direction, u8)>,
+    /// Acceleration and reverse debouncing
+    filter: StepFilter,
+}
+
+/// Velocity-sensitive output of the rotary encoder.
+///
+/// A step which comes within `interval` after the previous step in the same direction is considered as fast spinning.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EncoderAcceleration {
+    /// Maximum time between two steps to be considered as fast spinning
+    pub interval: Duration,
+    /// Number of actions triggered by a fast step
+    pub multiplier: u8,
+    /// Use the `EncoderAction` of this encoder id for fast steps, instead of the encoder's own
+    pub alternate_id: Option<u8>,
+}
+
+/// Decides what a step of the encoder triggers, according to the acceleration and reverse debouncing settings
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct StepFilter {
+    acceleration: Option<EncoderAcceleration>,
+    /// A reverse step within this duration after the previous step is ignored, unless it's followed by another one
+    reverse_debounce: Option<Duration>,
+    /// Direction and time of the last accepted step
+    last_step: Option<(Direction, Instant)>,
+    /// Whether a reverse step is waiting for the confirmation
+    pending_reverse: bool,
+}
+
+impl StepFilter {
+    /// Process a step, returns the encoder id and number of actions to trigger, or `None` if the step is ignored
+    fn process(&mut self, id: u8, direction: Direction, now: Instant) -> Option<(u8, u8)> {
+        let last_step = self.last_step;
+        if let Some((last_direction, last_time)) = last_step
+            && last_direction != direction
+        {
+            if let Some(debounce) = self.reverse_debounce
+                && !self.pending_reverse
+                && now.saturating_duration_since(last_time) < debounce
+            {
+                // Might be a bounce, wait for the next step
+                debug!("Encoder {}: reverse step ignored", id);
+                self.pending_reverse = true;
+                self.last_step = Some((last_direction, now));
+                return None;
+            }
+            // Direction changed, acceleration starts over
+            self.pending_reverse = false;
+            self.last_step = Some((direction, now));
+            return Some((id, 1));
+        }
+
+        self.pending_reverse = false;
+        self.last_step = Some((direction, now));
+        match (self.acceleration, last_step) {
+            (Some(acceleration), Some((_, last_time)))
+                if now.saturating_duration_since(last_time) < acceleration.interval =>
+            {
+                Some((acceleration.alternate_id.unwrap_or(id), acceleration.multiplier.max(1)))
+            }
+            _ => Some((id, 1)),
+        }
+    }
 }
 
 /// The encoder direction is either `Clockwise`, `CounterClockwise`, or `None`
@@ -141,6 +209,8 @@ where
             phase: DefaultPhase,
             id,
             last_action: None,
+            repeat: None,
+            filter: StepFilter::default(),
         }
     }
 }
@@ -160,6 +230,8 @@ where
             phase: ResolutionPhase::new(resolution, reverse),
             id,
             last_action: None,
+            repeat: None,
+            filter: StepFilter::default(),
         }
     }
 }
@@ -174,9 +246,25 @@ impl<A: InputPin, B: InputPin, P: Phase> RotaryEncoder<A, B, P> {
             phase,
             id,
             last_action: None,
+            repeat: None,
+            filter: StepFilter::default(),
         }
     }
 
+    /// Enable velocity-sensitive output: fast spinning triggers the action multiple times, or triggers another encoder's action
+    pub fn with_acceleration(mut self, acceleration: EncoderAcceleration) -> Self {
+        self.filter.acceleration = Some(acceleration);
+        self
+    }
+
+    /// Ignore a single reverse step within `debounce` after the previous step, which is usually caused by a noisy encoder.
+    ///
+    /// The reverse step is only accepted if it's followed by another step in the same direction.
+    pub fn with_reverse_debounce(mut self, debounce: Duration) -> Self {
+        self.filter.reverse_debounce = Some(debounce);
+        self
+    }
+
     /// Call `update` to evaluate the next state of the encoder, propagates errors from `InputPin` read
     pub fn update(&mut self) -> Direction {
         // use mask to get previous state value
@@ -234,11 +322,19 @@ impl<
 {
     async fn read_event(&mut self) -> Event {
         // Read until a valid rotary encoder event is detected
-        if let Some(last_action) = self.last_action {
+        if let Some((id, direction)) = self.last_action {
             embassy_time::Timer::after_millis(5).await;
-            let e = Event::Key(KeyboardEvent::rotary_encoder(self.id, last_action, false));
             self.last_action = None;
-            return e;
+            return Event::Key(KeyboardEvent::rotary_encoder(id, direction, false).with_timestamp(Instant::now()));
+        }
+
+        if let Some((id, direction, count)) = self.repeat.take() {
+            embassy_time::Timer::after_millis(5).await;
+            self.last_action = Some((id, direction));
+            if count > 1 {
+                self.repeat = Some((id, direction, count - 1));
+            }
+            return Event::Key(KeyboardEvent::rotary_encoder(id, direction, true).with_timestamp(Instant::now()));
         }
 
         loop {
@@ -249,10 +345,16 @@ impl<
             }
 
             let direction = self.update();
+            let now = Instant::now();
 
-            if direction != Direction::None {
-                self.last_action = Some(direction);
-                return Event::Key(KeyboardEvent::rotary_encoder(self.id, direction, true));
+            if direction != Direction::None
+                && let Some((id, count)) = self.filter.process(self.id, direction, now)
+            {
+                self.last_action = Some((id, direction));
+                if count > 1 {
+                    self.repeat = Some((id, direction, count - 1));
+                }
+                return Event::Key(KeyboardEvent::rotary_encoder(id, direction, true).with_timestamp(now));
             }
 
             #[cfg(not(feature = "async_matrix"))]
@@ -307,4 +409,58 @@ mod test {
             assert_eq!(d, d2);
         }
     }
+
+    #[test]
+    fn test_reverse_debounce() {
+        let mut filter = StepFilter {
+            reverse_debounce: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let cw = Direction::Clockwise;
+        let ccw = Direction::CounterClockwise;
+        assert_eq!(filter.process(0, cw, Instant::from_millis(0)), Some((0, 1)));
+        // A single bounce is ignored
+        assert_eq!(filter.process(0, ccw, Instant::from_millis(10)), None);
+        assert_eq!(filter.process(0, cw, Instant::from_millis(20)), Some((0, 1)));
+        // A confirmed reverse step is accepted
+        assert_eq!(filter.process(0, ccw, Instant::from_millis(30)), None);
+        assert_eq!(filter.process(0, ccw, Instant::from_millis(40)), Some((0, 1)));
+        assert_eq!(filter.process(0, ccw, Instant::from_millis(50)), Some((0, 1)));
+        // A slow reverse step is accepted immediately
+        assert_eq!(filter.process(0, cw, Instant::from_millis(200)), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_acceleration() {
+        let mut filter = StepFilter {
+            acceleration: Some(EncoderAcceleration {
+                interval: Duration::from_millis(30),
+                multiplier: 3,
+                alternate_id: None,
+            }),
+            ..Default::default()
+        };
+        let cw = Direction::Clockwise;
+        let ccw = Direction::CounterClockwise;
+        assert_eq!(filter.process(1, cw, Instant::from_millis(0)), Some((1, 1)));
+        assert_eq!(filter.process(1, cw, Instant::from_millis(100)), Some((1, 1)));
+        assert_eq!(filter.process(1, cw, Instant::from_millis(120)), Some((1, 3)));
+        assert_eq!(filter.process(1, cw, Instant::from_millis(140)), Some((1, 3)));
+        // Changing the direction resets the acceleration
+        assert_eq!(filter.process(1, ccw, Instant::from_millis(150)), Some((1, 1)));
+        assert_eq!(filter.process(1, ccw, Instant::from_millis(160)), Some((1, 3)));
+
+        // Switch to the alternate encoder action
+        let mut filter = StepFilter {
+            acceleration: Some(EncoderAcceleration {
+                interval: Duration::from_millis(30),
+                multiplier: 1,
+                alternate_id: Some(2),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(filter.process(1, cw, Instant::from_millis(0)), Some((1, 1)));
+        assert_eq!(filter.process(1, cw, Instant::from_millis(10)), Some((2, 1)));
+        assert_eq!(filter.process(1, cw, Instant::from_millis(100)), Some((1, 1)));
+    }
 }
@@ -0,0 +1,234 @@
+//! Runtime instrumentation of the key processing pipeline.
+//!
+//! It measures the matrix scan rate, the depth of the event queues, the processing time of every key event
+//! in the keyboard, and the latency from the key event to the sent report.
+//! The numbers are logged periodically, and can be read over the host protocol,
+//! see [`ViaCustomValue::Instrumentation`](rmk_types::protocol::vial::ViaCustomValue::Instrumentation).
+//!
+//! Instrumentation is enabled by the `instrumentation` feature, everything is compiled out when it's off.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use crate::RawMutex;
+use crate::channel::{EVENT_CHANNEL, KEY_EVENT_CHANNEL};
+
+/// Window of counting the matrix scans
+const SCAN_RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Interval of logging the instrumentation
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Statistics of measured durations, in microseconds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DurationStat {
+    /// The latest measured duration
+    pub last: u32,
+    /// The longest measured duration
+    pub max: u32,
+    /// Sum of all measured durations
+    total: u64,
+    /// Number of measurements
+    count: u32,
+}
+
+impl DurationStat {
+    fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros().min(u32::MAX as u64) as u32;
+        self.last = us;
+        self.max = self.max.max(us);
+        self.total = self.total.saturating_add(us as u64);
+        self.count = self.count.saturating_add(1);
+    }
+
+    /// Average of all measured durations
+    pub fn avg(&self) -> u32 {
+        if self.count == 0 {
+            0
+        } else {
+            (self.total / self.count as u64) as u32
+        }
+    }
+}
+
+/// Snapshot of the instrumentation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Instrumentation {
+    /// Full matrix scans in the last second
+    pub scan_rate: u32,
+    /// Current number of events in `KEY_EVENT_CHANNEL`
+    pub key_event_queue: u8,
+    /// Max number of events in `KEY_EVENT_CHANNEL` when the keyboard receives an event
+    pub key_event_queue_peak: u8,
+    /// Current number of events in `EVENT_CHANNEL`
+    pub event_queue: u8,
+    /// Max number of events in `EVENT_CHANNEL` when the keyboard receives an event
+    pub event_queue_peak: u8,
+    /// Time of processing a key event in the keyboard
+    pub processing_time: DurationStat,
+    /// Time from a key event is detected to the report is sent
+    pub report_latency: DurationStat,
+}
+
+struct State {
+    stats: Instrumentation,
+    /// Start of the current scan counting window
+    scan_window_start: Instant,
+    /// Scans in the current window
+    scans: u32,
+    /// Timestamp of the latest key event which isn't reported yet
+    pending_event: Option<Instant>,
+    /// Time of the last log
+    last_log: Instant,
+}
+
+static STATE: Mutex<RawMutex, RefCell<State>> = Mutex::new(RefCell::new(State {
+    stats: Instrumentation {
+        scan_rate: 0,
+        key_event_queue: 0,
+        key_event_queue_peak: 0,
+        event_queue: 0,
+        event_queue_peak: 0,
+        processing_time: DurationStat {
+            last: 0,
+            max: 0,
+            total: 0,
+            count: 0,
+        },
+        report_latency: DurationStat {
+            last: 0,
+            max: 0,
+            total: 0,
+            count: 0,
+        },
+    },
+    scan_window_start: Instant::MIN,
+    scans: 0,
+    pending_event: None,
+    last_log: Instant::MIN,
+}));
+
+/// Get the current instrumentation
+pub fn instrumentation() -> Instrumentation {
+    let mut stats = STATE.lock(|state| state.borrow().stats);
+    stats.key_event_queue = KEY_EVENT_CHANNEL.len() as u8;
+    stats.event_queue = EVENT_CHANNEL.len() as u8;
+    stats
+}
+
+/// Reset the peaks and the duration statistics
+pub fn reset_instrumentation() {
+    STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        state.stats = Instrumentation {
+            scan_rate: state.stats.scan_rate,
+            ..Default::default()
+        };
+    });
+}
+
+/// Record a full scan of the matrix
+pub(crate) fn record_scan() {
+    let now = Instant::now();
+    let log = STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        state.scans += 1;
+        let elapsed = now.saturating_duration_since(state.scan_window_start);
+        if elapsed < SCAN_RATE_WINDOW {
+            return false;
+        }
+        state.stats.scan_rate = (state.scans as u64 * 1_000_000 / elapsed.as_micros().max(1)) as u32;
+        state.scans = 0;
+        state.scan_window_start = now;
+        if now.saturating_duration_since(state.last_log) >= LOG_INTERVAL {
+            state.last_log = now;
+            true
+        } else {
+            false
+        }
+    });
+    if log {
+        let stats = instrumentation();
+        info!(
+            "Scan rate: {}/s, queue: {}/{} (peak {}/{}), processing: {}us (max {}us), latency: {}us (max {}us)",
+            stats.scan_rate,
+            stats.key_event_queue,
+            stats.event_queue,
+            stats.key_event_queue_peak,
+            stats.event_queue_peak,
+            stats.processing_time.avg(),
+            stats.processing_time.max,
+            stats.report_latency.avg(),
+            stats.report_latency.max
+        );
+    }
+}
+
+/// Record the depth of the event queues, called when the keyboard receives a key event.
+///
+/// The received event is counted too.
+pub(crate) fn record_queue_depth() {
+    let key_events = KEY_EVENT_CHANNEL.len() as u8 + 1;
+    let events = EVENT_CHANNEL.len() as u8;
+    STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        state.stats.key_event_queue_peak = state.stats.key_event_queue_peak.max(key_events);
+        state.stats.event_queue_peak = state.stats.event_queue_peak.max(events);
+    });
+}
+
+/// Mark the start of processing a key event, returns the start time.
+///
+/// The event is unreported until the next report is sent. Events from the matrix have their detected time,
+/// otherwise the latency is measured from the start of the processing.
+pub(crate) fn start_processing(event_time: Option<Instant>) -> Instant {
+    let now = Instant::now();
+    STATE.lock(|state| state.borrow_mut().pending_event = Some(event_time.unwrap_or(now)));
+    now
+}
+
+/// Record the processing time of a key event
+pub(crate) fn record_processing(start: Instant) {
+    let now = Instant::now();
+    STATE.lock(|state| {
+        state
+            .borrow_mut()
+            .stats
+            .processing_time
+            .record(now.saturating_duration_since(start))
+    });
+}
+
+/// Record that a report is sent, the latency is measured from the latest unreported key event
+pub(crate) fn record_report_sent() {
+    let now = Instant::now();
+    STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        if let Some(event_time) = state.pending_event.take() {
+            state
+                .stats
+                .report_latency
+                .record(now.saturating_duration_since(event_time));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_stat() {
+        let mut stat = DurationStat::default();
+        assert_eq!(stat.avg(), 0);
+        stat.record(Duration::from_micros(100));
+        stat.record(Duration::from_micros(300));
+        stat.record(Duration::from_micros(200));
+        assert_eq!(stat.last, 200);
+        assert_eq!(stat.max, 300);
+        assert_eq!(stat.avg(), 200);
+    }
+}
@@ -27,6 +27,7 @@ use crate::event::{KeyPos, KeyboardEvent, KeyboardEventPos};
 use crate::fork::{ActiveFork, StateBits};
 use crate::hid::Report;
 use crate::input_device::Runnable;
+use crate::input_device::motion_sensor::{CpiAction, MOTION_SENSOR_CPI_CHANNEL};
 use crate::input_device::rotary_encoder::Direction;
 use crate::keyboard::held_buffer::{HeldBuffer, HeldKey, KeyState};
 use crate::keyboard_macros::MacroOperation;
@@ -34,6 +35,8 @@ use crate::keymap::KeyMap;
 use crate::morse::{MorsePattern, TAP};
 #[cfg(all(feature = "split", feature = "_ble"))]
 use crate::split::ble::central::update_activity_time;
+#[cfg(feature = "storage")]
+use crate::storage::runtime_state::RuntimeState;
 use crate::{FORK_MAX_NUM, boot};
 
 pub(crate) mod combo;
@@ -83,6 +86,8 @@ enum CapsWordState {
     Activated {
         /// Time since last key press
         timer: Instant,
+        /// Caps Word is deactivated when no key is pressed for this time
+        timeout: Duration,
         /// Whether the current key should be shifted
         shift_current: bool,
     },
@@ -92,13 +97,11 @@ enum CapsWordState {
 }
 
 impl CapsWordState {
-    /// Caps Word timeout duration
-    const TIMEOUT: Duration = Duration::from_secs(5);
-
     /// Activate Caps Word
-    fn activate(&mut self) {
+    fn activate(&mut self, timeout: Duration) {
         *self = CapsWordState::Activated {
             timer: Instant::now(),
+            timeout,
             shift_current: false,
         };
     }
@@ -109,17 +112,17 @@ impl CapsWordState {
     }
 
     /// Toggle Caps Word
-    fn toggle(&mut self) {
+    fn toggle(&mut self, timeout: Duration) {
         match self {
             CapsWordState::Activated { .. } => self.deactivate(),
-            CapsWordState::Deactivated => self.activate(),
+            CapsWordState::Deactivated => self.activate(timeout),
         }
     }
 
     /// Return whether Caps Word is active (and has not timed out)
     fn is_active(&self) -> bool {
-        if let CapsWordState::Activated { timer, .. } = self {
-            timer.elapsed() < Self::TIMEOUT
+        if let CapsWordState::Activated { timer, timeout, .. } = self {
+            timer.elapsed() < *timeout
         } else {
             false
         }
@@ -138,8 +141,13 @@ impl CapsWordState {
     ///
     /// Note that this function does not check the CapsWord key itself.
     fn check(&mut self, key: KeyCode) {
-        if let CapsWordState::Activated { timer, shift_current } = self {
-            if key.is_caps_word_continue_key() && timer.elapsed() < Self::TIMEOUT {
+        if let CapsWordState::Activated {
+            timer,
+            timeout,
+            shift_current,
+        } = self
+        {
+            if key.is_caps_word_continue_key() && timer.elapsed() < *timeout {
                 *timer = Instant::now();
                 *shift_current = key.is_caps_word_shifted_key();
             } else {
@@ -169,9 +177,17 @@ impl<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCOD
             } else {
                 // No buffered tap-hold event, wait for new key
                 let event = KEY_EVENT_CHANNEL.receive().await;
+                #[cfg(feature = "instrumentation")]
+                crate::instrumentation::record_queue_depth();
+                #[cfg(feature = "instrumentation")]
+                let start = crate::instrumentation::start_processing(event.timestamp());
                 // Process the key event
-                self.process_inner(event).await
+                self.process_inner(event).await;
+                #[cfg(feature = "instrumentation")]
+                crate::instrumentation::record_processing(start);
             };
+            #[cfg(feature = "storage")]
+            self.save_runtime_state().await;
         }
     }
 }
@@ -247,6 +263,14 @@ pub struct Keyboard<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usi
     /// Used for temporarily disabling combos
     combo_on: bool,
 
+    /// Pressed hat switch directions of the gamepad
+    #[cfg(feature = "gamepad")]
+    gamepad_hat: u8,
+
+    /// Last runtime state sent to the storage task
+    #[cfg(feature = "storage")]
+    saved_state: RuntimeState,
+
     /// Publisher for controller channel
     #[cfg(feature = "controller")]
     controller_pub: ControllerPub,
@@ -256,7 +280,8 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
     Keyboard<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
 {
     pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
-        Keyboard {
+        #[allow(unused_mut)]
+        let mut keyboard = Keyboard {
             keymap,
             timer: [[None; ROW]; COL],
             rotary_encoder_timer: [[None; 2]; NUM_ENCODER],
@@ -288,8 +313,45 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             mouse_repeat: 0,
             mouse_wheel_repeat: 0,
             combo_on: true,
+            #[cfg(feature = "gamepad")]
+            gamepad_hat: 0,
+            #[cfg(feature = "storage")]
+            saved_state: RuntimeState::default(),
             #[cfg(feature = "controller")]
             controller_pub: unwrap!(CONTROLLER_CHANNEL.publisher()),
+        };
+
+        // Pick up the runtime state restored from the storage
+        #[cfg(feature = "storage")]
+        {
+            let restored = keymap.borrow().restored_state;
+            keyboard.combo_on = restored.combo_on;
+            keyboard.mouse_accel = restored.mouse_accel;
+            keyboard.saved_state = restored;
+        }
+
+        keyboard
+    }
+
+    /// Send the persisted runtime state to the storage task if it has changed.
+    ///
+    /// The storage task saves it lazily, together with the other pending writes.
+    #[cfg(feature = "storage")]
+    async fn save_runtime_state(&mut self) {
+        let state = {
+            let keymap = self.keymap.borrow();
+            RuntimeState {
+                toggled_layers: keymap.toggled_layers(),
+                combo_on: self.combo_on,
+                mouse_accel: self.mouse_accel,
+            }
+            .masked(&keymap.persisted_state)
+        };
+        if state != self.saved_state {
+            self.saved_state = state;
+            crate::channel::FLASH_CHANNEL
+                .send(crate::storage::FlashOperationMessage::RuntimeState(state))
+                .await;
         }
     }
 
@@ -372,14 +434,45 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
 
+    /// Handle the buffered keys whose timeout is before the given time.
+    ///
+    /// A timestamped event might be delayed in the channel or split link,
+    /// the timeouts that happened before it should be handled first.
+    async fn process_expired_keys(&mut self, time: Instant) {
+        // Each round removes a key from the candidates, so the loop is bounded by the buffer size
+        for _ in 0..HOLD_BUFFER_SIZE {
+            let Some(key) = self.next_buffered_key() else {
+                break;
+            };
+            if key.timeout_time > time {
+                break;
+            }
+            match key.state {
+                KeyState::WaitingCombo => {
+                    debug!("[Combo] Timeout before a delayed key event, dispatch combo");
+                    self.dispatch_combos(&key.action, key.event).await;
+                }
+                KeyState::Pressed(_) | KeyState::Released(_) => {
+                    debug!("Buffered morse key timeout before a delayed key event");
+                    self.handle_morse_timeout(&key).await;
+                }
+                _ => break,
+            }
+        }
+    }
+
     /// Process key changes at (row, col)
     pub async fn process_inner(&mut self, event: KeyboardEvent) {
         #[cfg(feature = "vial_lock")]
         self.keymap.borrow_mut().matrix_state.update(&event);
 
+        if event.timestamp.is_some() {
+            self.process_expired_keys(event.time()).await;
+        }
+
         // Matrix should process key pressed event first, record the timestamp of key changes
         if event.pressed {
-            self.set_timer_value(event, Some(Instant::now()));
+            self.set_timer_value(event, Some(event.time()));
         }
         // Update activity time for BLE split central sleep management
         #[cfg(all(feature = "split", feature = "_ble"))]
@@ -419,7 +512,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             }
             KeyBehaviorDecision::Buffer => {
                 debug!("Current key is buffered");
-                let press_time = Instant::now();
+                let press_time = event.time();
                 let timeout_time = if key_action.is_morse() {
                     press_time + Self::morse_timeout(&self.keymap.borrow(), key_action, true)
                 } else {
@@ -448,7 +541,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                 let action = Self::action_from_pattern(self.keymap.borrow().behavior, key_action, TAP); //tap action
                 self.process_key_action_normal(action, event).await;
                 // Push back after triggered press
-                let now = Instant::now();
+                let now = event.time();
                 let time_out = now + Self::morse_timeout(&self.keymap.borrow(), key_action, true);
                 self.held_buffer.push(HeldKey::new(
                     event,
@@ -663,7 +756,8 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         if event.pressed
             && self.keymap.borrow().behavior.morse.enable_flow_tap
             && key_action.is_morse()
-            && self.last_press_time.elapsed() < self.keymap.borrow().behavior.morse.prior_idle_time
+            && event.time().saturating_duration_since(self.last_press_time)
+                < self.keymap.borrow().behavior.morse.prior_idle_time
         {
             // It's in key streak, trigger the first tap action
             debug!("Flow tap detected, trigger tap action for current morse key");
@@ -1018,7 +1112,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             && let Some(max_size) = max_size_of_updated_combo
             && max_size > 0
         {
-            let pressed_time = self.get_timer_value(event).unwrap_or(Instant::now());
+            let pressed_time = self.get_timer_value(event).unwrap_or(event.time());
             self.held_buffer.push(HeldKey::new(
                 event,
                 *key_action,
@@ -1157,7 +1251,13 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                 }
             }
             Action::DefaultLayer(layer_num) => {
-                // Set the default layer
+                // Set the default layer, and save it if it's changed
+                #[cfg(feature = "storage")]
+                if event.pressed && self.keymap.borrow().get_default_layer() != layer_num {
+                    crate::channel::FLASH_CHANNEL
+                        .send(crate::storage::FlashOperationMessage::DefaultLayer(layer_num))
+                        .await;
+                }
                 self.keymap.borrow_mut().set_default_layer(layer_num);
             }
             Action::Modifier(modifiers) => {
@@ -1207,6 +1307,57 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                 self.update_osl(event);
             }
             Action::OneShotKey(_k) => warn!("One-shot key is not supported: {:?}", action),
+            Action::GamepadButton(_) | Action::GamepadHat(_) => self.process_action_gamepad(action, event).await,
+            Action::SwitchKeymapProfile(profile) => {
+                if event.pressed {
+                    // The profile is loaded into the keymap by the storage task
+                    #[cfg(all(feature = "storage", feature = "host"))]
+                    crate::channel::FLASH_CHANNEL
+                        .send(crate::storage::FlashOperationMessage::SwitchKeymapProfile(
+                            profile, None,
+                        ))
+                        .await;
+                    #[cfg(not(all(feature = "storage", feature = "host")))]
+                    warn!("Switching keymap profile {} requires the `storage` feature", profile);
+                }
+            }
+        }
+    }
+
+    /// Process gamepad button and hat switch actions
+    async fn process_action_gamepad(&mut self, action: Action, event: KeyboardEvent) {
+        #[cfg(feature = "gamepad")]
+        {
+            use crate::gamepad::{hat_direction_bit, hat_switch_value, update_gamepad_report};
+
+            let report = match action {
+                Action::GamepadButton(button) if button < 32 => update_gamepad_report(|r| {
+                    if event.pressed {
+                        r.buttons |= 1 << button;
+                    } else {
+                        r.buttons &= !(1 << button);
+                    }
+                }),
+                Action::GamepadHat(direction) => {
+                    if event.pressed {
+                        self.gamepad_hat |= hat_direction_bit(direction);
+                    } else {
+                        self.gamepad_hat &= !hat_direction_bit(direction);
+                    }
+                    let hat = hat_switch_value(self.gamepad_hat);
+                    update_gamepad_report(|r| r.hat = hat)
+                }
+                _ => {
+                    warn!("Invalid gamepad action: {:?}", action);
+                    return;
+                }
+            };
+            self.send_report(Report::GamepadReport(report)).await;
+        }
+        #[cfg(not(feature = "gamepad"))]
+        {
+            let _ = event;
+            warn!("Gamepad action {:?} requires the `gamepad` feature", action);
         }
     }
 
@@ -1426,7 +1577,8 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             KeyCode::CapsWordToggle => {
                 // Handle Caps Word keycode
                 if event.pressed {
-                    self.caps_word.toggle();
+                    let timeout = self.keymap.borrow().behavior.caps_word.timeout;
+                    self.caps_word.toggle(timeout);
                 };
                 return;
             }
@@ -1441,7 +1593,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             // Record last press time
             if key.is_simple_key() {
                 // Records only the simple key
-                self.last_press_time = Instant::now();
+                self.last_press_time = event.time();
             }
             // Check repeat key
             if key != KeyCode::Again {
@@ -1471,8 +1623,10 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             self.process_action_macro(key, event).await;
         } else if key.is_combo() {
             self.process_action_combo(key, event).await;
+        } else if key.is_cpi() {
+            self.process_action_cpi(key, event);
         } else if key.is_boot() {
-            self.process_boot(key, event);
+            self.process_boot(key, event).await;
         } else {
             warn!("Unsupported key: {:?}", key);
         }
@@ -1502,6 +1656,20 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
 
+    /// Process motion sensor CPI action, the action is broadcasted to all motion sensors.
+    fn process_action_cpi(&mut self, key: KeyCode, event: KeyboardEvent) {
+        if event.pressed {
+            let action = match key {
+                KeyCode::CpiUp => CpiAction::Up,
+                KeyCode::CpiDown => CpiAction::Down,
+                _ => CpiAction::Cycle,
+            };
+            MOTION_SENSOR_CPI_CHANNEL
+                .immediate_publisher()
+                .publish_immediate(action);
+        }
+    }
+
     /// Process consumer control action. Consumer control keys are keys in hid consumer page, such as media keys.
     async fn process_action_consumer_control(&mut self, key: KeyCode, event: KeyboardEvent) {
         if key.is_consumer() {
@@ -1600,15 +1768,9 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                     KeyCode::MouseBtn6 => self.mouse_report.buttons |= 1 << 5,
                     KeyCode::MouseBtn7 => self.mouse_report.buttons |= 1 << 6,
                     KeyCode::MouseBtn8 => self.mouse_report.buttons |= 1 << 7,
-                    KeyCode::MouseAccel0 => {
-                        self.mouse_accel |= 1 << 0;
-                    }
-                    KeyCode::MouseAccel1 => {
-                        self.mouse_accel |= 1 << 1;
-                    }
-                    KeyCode::MouseAccel2 => {
-                        self.mouse_accel |= 1 << 2;
-                    }
+                    KeyCode::MouseAccel0 => self.press_mouse_accel(1 << 0),
+                    KeyCode::MouseAccel1 => self.press_mouse_accel(1 << 1),
+                    KeyCode::MouseAccel2 => self.press_mouse_accel(1 << 2),
                     _ => {}
                 }
             } else {
@@ -1661,15 +1823,9 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                     KeyCode::MouseBtn6 => self.mouse_report.buttons &= !(1 << 5),
                     KeyCode::MouseBtn7 => self.mouse_report.buttons &= !(1 << 6),
                     KeyCode::MouseBtn8 => self.mouse_report.buttons &= !(1 << 7),
-                    KeyCode::MouseAccel0 => {
-                        self.mouse_accel &= !(1 << 0);
-                    }
-                    KeyCode::MouseAccel1 => {
-                        self.mouse_accel &= !(1 << 1);
-                    }
-                    KeyCode::MouseAccel2 => {
-                        self.mouse_accel &= !(1 << 2);
-                    }
+                    KeyCode::MouseAccel0 => self.release_mouse_accel(1 << 0),
+                    KeyCode::MouseAccel1 => self.release_mouse_accel(1 << 1),
+                    KeyCode::MouseAccel2 => self.release_mouse_accel(1 << 2),
                     _ => {}
                 }
 
@@ -1813,9 +1969,11 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
 
-    fn process_boot(&mut self, key: KeyCode, event: KeyboardEvent) {
+    async fn process_boot(&mut self, key: KeyCode, event: KeyboardEvent) {
         // When releasing the key, process the boot action
         if !event.pressed {
+            #[cfg(feature = "storage")]
+            crate::storage::flush_before_reboot().await;
             match key {
                 KeyCode::Bootloader => {
                     boot::jump_to_bootloader();
@@ -2112,6 +2270,33 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         send_controller_event(&mut self.controller_pub, ControllerEvent::Modifier(self.held_modifiers));
     }
 
+    /// Select the speed preset of a mouse acceleration key, bit n of `mouse_accel` is `MouseAccel{n}`.
+    ///
+    /// The preset is used while the key is held. When the preset is persisted, it's kept after the key is released,
+    /// and pressing the key of the selected preset again goes back to the accelerating speed.
+    fn press_mouse_accel(&mut self, bit: u8) {
+        if self.mouse_accel_latched() {
+            self.mouse_accel = if self.mouse_accel == bit { 0 } else { bit };
+        } else {
+            self.mouse_accel |= bit;
+        }
+    }
+
+    fn release_mouse_accel(&mut self, bit: u8) {
+        if !self.mouse_accel_latched() {
+            self.mouse_accel &= !bit;
+        }
+    }
+
+    /// Whether the mouse speed preset is kept after the acceleration key is released
+    fn mouse_accel_latched(&self) -> bool {
+        #[cfg(feature = "storage")]
+        let latched = self.keymap.borrow().persisted_state.mouse_speed;
+        #[cfg(not(feature = "storage"))]
+        let latched = false;
+        latched
+    }
+
     /// Calculate mouse movement distance based on current repeat count and acceleration settings
     fn calculate_mouse_move_unit(&self) -> i8 {
         let config = &self.keymap.borrow().behavior.mouse_key;
@@ -2544,6 +2729,50 @@ mod test {
             block_on(main);
         }
 
+        #[test]
+        fn test_tap_hold_with_delayed_event() {
+            let main = async {
+                let mut keyboard = create_test_keyboard();
+                keyboard.keymap.borrow_mut().set_action_at(
+                    KeyboardEventPos::Key(KeyPos { row: 0, col: 0 }),
+                    0,
+                    KeyAction::TapHold(Action::Key(KeyCode::A), Action::Key(KeyCode::B), Default::default()),
+                );
+
+                // Both events happened long before they are processed, but the key is held for only 50ms
+                embassy_time::Timer::after_millis(500).await;
+                let pressed_time = Instant::now() - Duration::from_millis(400);
+                keyboard
+                    .process_inner(KeyboardEvent::key(0, 0, true).with_timestamp(pressed_time))
+                    .await;
+                keyboard
+                    .process_inner(
+                        KeyboardEvent::key(0, 0, false).with_timestamp(pressed_time + Duration::from_millis(50)),
+                    )
+                    .await;
+
+                // It's a tap
+                assert_eq!(keyboard.last_key_code, KeyCode::A);
+                assert_eq!(keyboard.held_keycodes[0], KeyCode::No);
+
+                // The key is held for 300ms, the timeout happened before the release is processed
+                let pressed_time = Instant::now() - Duration::from_millis(400);
+                keyboard
+                    .process_inner(KeyboardEvent::key(0, 0, true).with_timestamp(pressed_time))
+                    .await;
+                keyboard
+                    .process_inner(
+                        KeyboardEvent::key(0, 0, false).with_timestamp(pressed_time + Duration::from_millis(300)),
+                    )
+                    .await;
+
+                // It's a hold
+                assert_eq!(keyboard.last_key_code, KeyCode::B);
+                assert_eq!(keyboard.held_keycodes[0], KeyCode::No);
+            };
+            block_on(main);
+        }
+
         #[test]
         fn test_key_action_transparent() {
             let main = async {
@@ -1,4 +1,4 @@
-use embassy_time::{Duration, Instant};
+use embassy_time::Duration;
 use rmk_types::action::{Action, KeyAction, MorseMode};
 
 use crate::config::BehaviorConfig;
@@ -67,7 +67,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         // Process the morse key
         if event.pressed {
             // Pressed, check the held buffer, update the tap state
-            let pressed_time = self.get_timer_value(event).unwrap_or(Instant::now());
+            let pressed_time = self.get_timer_value(event).unwrap_or(event.time());
             let timeout_time = pressed_time + Self::morse_timeout(&self.keymap.borrow(), key_action, true);
             match self.held_buffer.find_pos_mut(event.pos) {
                 Some(k) => {
@@ -97,7 +97,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                 debug!("Releasing morse key: {:?}", k);
                 match k.state {
                     KeyState::Pressed(pattern) => {
-                        let released_time = Instant::now(); // TODO? It would be better if the event would carry the real timestamp of the release event!
+                        let released_time = event.time();
 
                         let hold = released_time >= k.timeout_time;
 
@@ -138,7 +138,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                     KeyState::Holding(pattern) => {
                         // The try_predict_final_action => None is already decided, when we entered in Holding mode
                         // So, just expect a possible longer morse pattern (or idle timeout), update the state
-                        let released_time = Instant::now(); // TODO? It would be better if the event would carry the real timestamp of the release event!                        
+                        let released_time = event.time();
                         k.state = KeyState::Released(pattern);
                         // Use current release time for `IdleAfterTap` state
                         k.press_time = released_time; // Use release time as the "press_time"
@@ -4,12 +4,14 @@ use {
     crate::channel::{CONTROLLER_CHANNEL, ControllerPub, send_controller_event},
     crate::event::ControllerEvent,
 };
-#[cfg(all(feature = "storage", feature = "host"))]
+#[cfg(feature = "storage")]
 use {
-    crate::{boot::reboot_keyboard, storage::Storage},
-    embedded_storage_async::nor_flash::NorFlash,
+    crate::config::PersistedStateConfig, crate::storage::Storage, crate::storage::backend::StorageBackend,
+    crate::storage::runtime_state::RuntimeState,
 };
 
+#[cfg(all(feature = "storage", feature = "host"))]
+use crate::boot::reboot_keyboard;
 use crate::config::{BehaviorConfig, PositionalConfig};
 use crate::event::{KeyboardEvent, KeyboardEventPos};
 use crate::input_device::rotary_encoder::Direction;
@@ -30,6 +32,8 @@ pub struct KeyMap<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize
     layer_state: [bool; NUM_LAYER],
     /// Default layer number, max: 32
     default_layer: u8,
+    /// Layers toggled by `TG`, bit n is layer n
+    toggled_layers: u32,
     /// Layer cache
     layer_cache: [[u8; COL]; ROW],
     /// Rotary encoder cache
@@ -43,6 +47,12 @@ pub struct KeyMap<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize
     /// Matrix state
     #[cfg(feature = "vial_lock")]
     pub(crate) matrix_state: MatrixState<ROW, COL>,
+    /// Runtime states which are saved across reboots
+    #[cfg(feature = "storage")]
+    pub(crate) persisted_state: PersistedStateConfig,
+    /// Runtime state restored from the storage, the keyboard picks its own states up from here
+    #[cfg(feature = "storage")]
+    pub(crate) restored_state: RuntimeState,
 }
 
 /// fills up the vector to its capacity
@@ -52,6 +62,11 @@ pub(crate) fn fill_vec<T: Default + Clone, const N: usize>(vector: &mut heapless
         .expect("impossible error, as we resize to the capacity of the vector!");
 }
 
+/// Bit of the layer in the toggled layers bitmap, layers above 31 aren't tracked
+fn layer_bit(layer_num: u8) -> u32 {
+    1u32.checked_shl(layer_num as u32).unwrap_or(0)
+}
+
 impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
     KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
 {
@@ -71,6 +86,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             encoders: encoder_map,
             layer_state: [false; NUM_LAYER],
             default_layer: 0,
+            toggled_layers: 0,
             layer_cache: [[0; COL]; ROW],
             encoder_layer_cache: [[0; 2]; NUM_ENCODER],
             behavior,
@@ -79,14 +95,18 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             controller_pub: unwrap!(CONTROLLER_CHANNEL.publisher()),
             #[cfg(feature = "vial_lock")]
             matrix_state: MatrixState::new(),
+            #[cfg(feature = "storage")]
+            persisted_state: PersistedStateConfig::default(),
+            #[cfg(feature = "storage")]
+            restored_state: RuntimeState::default(),
         }
     }
 
     #[cfg(all(feature = "storage", feature = "host"))]
-    pub async fn new_from_storage<F: NorFlash>(
+    pub async fn new_from_storage<B: StorageBackend>(
         action_map: &'a mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
         mut encoder_map: Option<&'a mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
-        storage: Option<&mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+        mut storage: Option<&mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
         behavior: &'a mut BehaviorConfig,
         positional_config: &'a mut PositionalConfig<ROW, COL>,
     ) -> Self {
@@ -94,7 +114,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         fill_vec(&mut behavior.fork.forks); // Is this needed? (has no Vial support)
         fill_vec(&mut behavior.morse.morses);
 
-        if let Some(storage) = storage
+        if let Some(storage) = storage.as_deref_mut()
             && {
                 Ok(())
                     // Read keymap to `action_map`
@@ -117,18 +137,18 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             .is_err()
         {
             error!("Failed to read from storage, clearing...");
-            sequential_storage::erase_all(&mut storage.flash, storage.storage_range.clone())
-                .await
-                .ok();
+            storage.backend.erase_all().await.ok();
+            storage.save_erase_cycles().await.ok();
 
             reboot_keyboard();
         }
 
-        KeyMap {
+        let mut keymap = KeyMap {
             layers: action_map,
             encoders: encoder_map,
             layer_state: [false; NUM_LAYER],
             default_layer: 0,
+            toggled_layers: 0,
             layer_cache: [[0; COL]; ROW],
             encoder_layer_cache: [[0; 2]; NUM_ENCODER],
             behavior,
@@ -137,7 +157,13 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             controller_pub: unwrap!(CONTROLLER_CHANNEL.publisher()),
             #[cfg(feature = "vial_lock")]
             matrix_state: MatrixState::new(),
+            persisted_state: PersistedStateConfig::default(),
+            restored_state: RuntimeState::default(),
+        };
+        if let Some(storage) = storage {
+            keymap.load_runtime_state(storage).await;
         }
+        keymap
     }
 
     pub(crate) fn get_keymap_config(&self) -> (usize, usize, usize) {
@@ -154,6 +180,35 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         self.default_layer = layer_num;
     }
 
+    /// Get the layers toggled by `TG`, bit n is layer n
+    pub(crate) fn toggled_layers(&self) -> u32 {
+        self.toggled_layers
+    }
+
+    /// Restore the persisted runtime state saved in the storage
+    #[cfg(feature = "storage")]
+    pub(crate) async fn load_runtime_state<B: StorageBackend>(
+        &mut self,
+        storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    ) {
+        // The default layer is always restored, it's saved in the layout config when it's changed
+        if let Some(default_layer) = storage.read_default_layer().await
+            && (default_layer as usize) < NUM_LAYER
+        {
+            self.default_layer = default_layer;
+        }
+        let state = storage.read_runtime_state().await;
+        for layer in 0..NUM_LAYER.min(32) {
+            if state.toggled_layers & layer_bit(layer as u8) != 0 {
+                self.layer_state[layer] = true;
+                self.toggled_layers |= layer_bit(layer as u8);
+            }
+        }
+        self.update_tri_layer();
+        self.persisted_state = storage.persisted_state;
+        self.restored_state = state;
+    }
+
     pub(crate) fn get_next_macro_operation(&self, macro_start_idx: usize, offset: usize) -> (MacroOperation, usize) {
         MacroOperation::get_next_macro_operation(
             &self.behavior.keyboard_macros.macro_sequences,
@@ -382,6 +437,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             return;
         }
         self.layer_state[layer_num as usize] = false;
+        self.toggled_layers &= !layer_bit(layer_num);
         self.update_tri_layer();
     }
 
@@ -396,6 +452,11 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
 
         self.layer_state[layer_num as usize] = !self.layer_state[layer_num as usize];
+        if self.layer_state[layer_num as usize] {
+            self.toggled_layers |= layer_bit(layer_num);
+        } else {
+            self.toggled_layers &= !layer_bit(layer_num);
+        }
 
         #[cfg(feature = "controller")]
         {
@@ -226,3 +226,29 @@ macro_rules! macros {
         $crate::types::action::KeyAction::Single($crate::types::action::Action::TriggerMacro($index))
     };
 }
+
+/// Create a gamepad button action. For example, `gp!(0)` represents the first gamepad button
+#[macro_export]
+macro_rules! gp {
+    ($x: literal) => {
+        $crate::types::action::KeyAction::Single($crate::types::action::Action::GamepadButton($x))
+    };
+}
+
+/// Create a gamepad hat switch action. For example, `hat!(Up)` represents the up direction of the hat switch
+#[macro_export]
+macro_rules! hat {
+    ($d: ident) => {
+        $crate::types::action::KeyAction::Single($crate::types::action::Action::GamepadHat(
+            $crate::types::action::HatDirection::$d,
+        ))
+    };
+}
+
+/// Create a switch keymap profile action, `n` is the profile number
+#[macro_export]
+macro_rules! kmp {
+    ($x: literal) => {
+        $crate::types::action::KeyAction::Single($crate::types::action::Action::SwitchKeymapProfile($x))
+    };
+}
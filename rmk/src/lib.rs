@@ -30,6 +30,8 @@ use bt_hci::{
 use config::RmkConfig;
 #[cfg(feature = "controller")]
 use controller::{PollingController, wpm::WpmController};
+#[cfg(all(feature = "gamepad", not(feature = "_no_usb"), not(feature = "_ble")))]
+use descriptor::GamepadReport;
 #[cfg(not(feature = "_ble"))]
 use descriptor::{CompositeReport, KeyboardReport};
 #[cfg(not(any(cortex_m)))]
@@ -56,7 +58,10 @@ use {
 };
 pub use {embassy_futures, futures, heapless, rmk_macro as macros, rmk_types as types};
 #[cfg(feature = "storage")]
-use {embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash, storage::Storage};
+use {
+    storage::Storage,
+    storage::backend::{IntoFlashBackend, StorageBackend},
+};
 
 use crate::config::PositionalConfig;
 #[cfg(feature = "vial")]
@@ -67,6 +72,7 @@ use crate::state::ConnectionState;
 #[cfg(feature = "_ble")]
 pub mod ble;
 mod boot;
+pub mod bootmagic;
 pub mod channel;
 pub mod combo;
 pub mod config;
@@ -78,11 +84,15 @@ pub mod direct_pin;
 pub mod driver;
 pub mod event;
 pub mod fork;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
 pub mod helper_macro;
 pub mod hid;
 #[cfg(feature = "host")]
 pub mod host;
 pub mod input_device;
+#[cfg(feature = "instrumentation")]
+pub mod instrumentation;
 pub mod keyboard;
 pub mod keyboard_macros;
 pub mod keymap;
@@ -132,7 +142,7 @@ pub async fn initialize_encoder_keymap<
 #[cfg(feature = "storage")]
 pub async fn initialize_encoder_keymap_and_storage<
     'a,
-    F: AsyncNorFlash,
+    F: IntoFlashBackend,
     const ROW: usize,
     const COL: usize,
     const NUM_LAYER: usize,
@@ -146,12 +156,12 @@ pub async fn initialize_encoder_keymap_and_storage<
     positional_config: &'a mut PositionalConfig<ROW, COL>,
 ) -> (
     RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
-    Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    Storage<F::Backend, ROW, COL, NUM_LAYER, NUM_ENCODER>,
 ) {
     #[cfg(feature = "host")]
     {
-        let mut storage = Storage::new(
-            flash,
+        let mut storage = Storage::new_with_backend(
+            flash.into_backend(storage_config),
             default_keymap,
             &Some(default_encoder_map),
             storage_config,
@@ -174,24 +184,24 @@ pub async fn initialize_encoder_keymap_and_storage<
 
     #[cfg(not(feature = "host"))]
     {
-        let storage = Storage::new(flash, storage_config, &behavior_config).await;
-        let keymap = RefCell::new(
-            KeyMap::new(
-                default_keymap,
-                Some(default_encoder_map),
-                behavior_config,
-                positional_config,
-            )
-            .await,
-        );
-        (keymap, storage)
+        let mut storage =
+            Storage::new_with_backend(flash.into_backend(storage_config), storage_config, &behavior_config).await;
+        let mut keymap = KeyMap::new(
+            default_keymap,
+            Some(default_encoder_map),
+            behavior_config,
+            positional_config,
+        )
+        .await;
+        keymap.load_runtime_state(&mut storage).await;
+        (RefCell::new(keymap), storage)
     }
 }
 
 #[cfg(feature = "storage")]
 pub async fn initialize_keymap_and_storage<
     'a,
-    F: AsyncNorFlash,
+    F: IntoFlashBackend,
     const ROW: usize,
     const COL: usize,
     const NUM_LAYER: usize,
@@ -203,11 +213,18 @@ pub async fn initialize_keymap_and_storage<
     positional_config: &'a mut PositionalConfig<ROW, COL>,
 ) -> (
     RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, 0>>,
-    Storage<F, ROW, COL, NUM_LAYER, 0>,
+    Storage<F::Backend, ROW, COL, NUM_LAYER, 0>,
 ) {
     #[cfg(feature = "host")]
     {
-        let mut storage = Storage::new(flash, default_keymap, &None, storage_config, behavior_config).await;
+        let mut storage = Storage::new_with_backend(
+            flash.into_backend(storage_config),
+            default_keymap,
+            &None,
+            storage_config,
+            behavior_config,
+        )
+        .await;
         let keymap = RefCell::new(
             KeyMap::new_from_storage(
                 default_keymap,
@@ -223,9 +240,11 @@ pub async fn initialize_keymap_and_storage<
 
     #[cfg(not(feature = "host"))]
     {
-        let storage = Storage::new(flash, storage_config, &behavior_config).await;
-        let keymap = RefCell::new(KeyMap::new(default_keymap, None, behavior_config, positional_config).await);
-        (keymap, storage)
+        let mut storage =
+            Storage::new_with_backend(flash.into_backend(storage_config), storage_config, &behavior_config).await;
+        let mut keymap = KeyMap::new(default_keymap, None, behavior_config, positional_config).await;
+        keymap.load_runtime_state(&mut storage).await;
+        (RefCell::new(keymap), storage)
     }
 }
 
@@ -234,7 +253,7 @@ pub async fn run_rmk<
     'a,
     #[cfg(feature = "_ble")] 'b,
     #[cfg(feature = "_ble")] C: Controller + ControllerCmdAsync<LeSetPhy> + ControllerCmdSync<LeReadLocalSupportedFeatures>,
-    #[cfg(feature = "storage")] F: AsyncNorFlash,
+    #[cfg(feature = "storage")] B: StorageBackend,
     #[cfg(not(feature = "_no_usb"))] D: Driver<'static>,
     #[cfg(any(feature = "storage", feature = "host"))] const ROW: usize,
     #[cfg(any(feature = "storage", feature = "host"))] const COL: usize,
@@ -244,7 +263,7 @@ pub async fn run_rmk<
     #[cfg(feature = "host")] keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     #[cfg(not(feature = "_no_usb"))] usb_driver: D,
     #[cfg(feature = "_ble")] stack: &'b Stack<'b, C, DefaultPacketPool>,
-    #[cfg(feature = "storage")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    #[cfg(feature = "storage")] storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
     rmk_config: RmkConfig<'static>,
 ) -> ! {
     // Dispatch the keyboard runner
@@ -268,6 +287,8 @@ pub async fn run_rmk<
         let mut usb_builder: embassy_usb::Builder<'_, D> = new_usb_builder(usb_driver, rmk_config.device_config);
         let keyboard_reader_writer = add_usb_reader_writer!(&mut usb_builder, KeyboardReport, 1, 8);
         let mut other_writer = add_usb_writer!(&mut usb_builder, CompositeReport, 9);
+        #[cfg(feature = "gamepad")]
+        let mut gamepad_writer = add_usb_writer!(&mut usb_builder, GamepadReport, { GamepadReport::SIZE });
         #[cfg(feature = "host")]
         let mut host_reader_writer = add_usb_reader_writer!(&mut usb_builder, ViaReport, 32, 32);
 
@@ -315,7 +336,12 @@ pub async fn run_rmk<
                     rmk_config.vial_config,
                     usb_task,
                     UsbLedReader::new(&mut keyboard_reader),
-                    UsbKeyboardWriter::new(&mut keyboard_writer, &mut other_writer),
+                    UsbKeyboardWriter::new(
+                        &mut keyboard_writer,
+                        &mut other_writer,
+                        #[cfg(feature = "gamepad")]
+                        &mut gamepad_writer,
+                    ),
                 )
                 .await;
             }
@@ -334,7 +360,7 @@ pub(crate) async fn run_keyboard<
     'a,
     R: HidReaderTrait<ReportType = LedIndicator>,
     W: RunnableHidWriter,
-    #[cfg(feature = "storage")] F: AsyncNorFlash,
+    #[cfg(feature = "storage")] B: StorageBackend,
     #[cfg(feature = "host")] Rw: HidReaderTrait<ReportType = ViaReport> + HidWriterTrait<ReportType = ViaReport>,
     #[cfg(any(feature = "storage", feature = "host"))] const ROW: usize,
     #[cfg(any(feature = "storage", feature = "host"))] const COL: usize,
@@ -342,7 +368,7 @@ pub(crate) async fn run_keyboard<
     #[cfg(any(feature = "storage", feature = "host"))] const NUM_ENCODER: usize,
 >(
     // #[cfg(feature = "storage")] storage_task: impl Future<Output = ()>,
-    #[cfg(feature = "storage")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    #[cfg(feature = "storage")] storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
     // #[cfg(feature = "host")] host_task: impl Future<Output = ()>,
     #[cfg(feature = "host")] keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     #[cfg(feature = "host")] reader_writer: Rw,
@@ -384,7 +410,10 @@ pub(crate) async fn run_keyboard<
         vial_config,
     );
     #[cfg(feature = "storage")]
-    let storage_fut = storage.run();
+    let storage_fut = storage.run(
+        #[cfg(feature = "host")]
+        Some(keymap),
+    );
 
     #[cfg(feature = "controller")]
     let mut wpm_controller = WpmController::new();
@@ -2,7 +2,7 @@
 use core::pin::pin;
 use core::sync::atomic::Ordering;
 
-use embassy_time::Timer;
+use embassy_time::{Instant, Timer};
 use embedded_hal::digital::{InputPin, OutputPin};
 #[cfg(feature = "async_matrix")]
 use {embassy_futures::select::select_slice, embedded_hal_async::digital::Wait, heapless::Vec};
@@ -11,9 +11,15 @@ use crate::CONNECTION_STATE;
 use crate::debounce::{DebounceState, DebouncerTrait};
 use crate::event::{Event, KeyPos, KeyboardEvent, KeyboardEventPos};
 use crate::input_device::InputDevice;
+use crate::matrix::scan_throttle::{ScanPause, ScanThrottle, ScanThrottleConfig};
 use crate::state::ConnectionState;
 
 pub mod bidirectional_matrix;
+pub mod charlieplex;
+pub mod io_expander;
+pub mod lines;
+pub mod scan_throttle;
+pub mod shift_register;
 
 /// Recording the matrix pressed state
 #[cfg(feature = "vial_lock")]
@@ -171,8 +177,14 @@ pub struct Matrix<
     debouncer: D,
     /// Key state matrix
     key_states: [[KeyState; ROW]; COL],
+    /// Pin states of the whole matrix before debouncing, updated by the ghost filter
+    raw_states: [[bool; ROW]; COL],
+    /// Block the presses which may be ghost keys, for matrices without diodes
+    ghost_filter: bool,
     /// Current scan pos: (out_idx, in_idx)
     scan_pos: (usize, usize),
+    /// Adaptive scan rate, the matrix is scanned continuously if `None`
+    throttle: Option<ScanThrottle>,
     /// Re-scan needed flag
     #[cfg(feature = "async_matrix")]
     rescan_needed: bool,
@@ -345,11 +357,87 @@ where
             col_pins,
             debouncer,
             key_states: [[KeyState::new(); ROW]; COL],
+            raw_states: [[false; ROW]; COL],
+            ghost_filter: false,
             scan_pos: (0, 0),
+            throttle: None,
             #[cfg(feature = "async_matrix")]
             rescan_needed: false,
         }
     }
+
+    /// Block the ambiguous presses of a matrix without diodes.
+    ///
+    /// Without diodes, any three pressed corners of a rectangle in the matrix make the fourth corner look pressed.
+    /// When enabled, a press isn't reported while the key is a corner of such a rectangle.
+    pub fn with_ghost_filter(mut self, enabled: bool) -> Self {
+        self.ghost_filter = enabled;
+        self
+    }
+
+    /// Reduce the scan rate when no key changes for a while, see [`ScanThrottleConfig`].
+    ///
+    /// It replaces the default behavior of `async_matrix`, which waits for a key press as soon as all keys are released.
+    pub fn with_scan_throttle(mut self, config: ScanThrottleConfig) -> Self {
+        self.throttle = Some(ScanThrottle::new(config));
+        self
+    }
+}
+
+impl<
+    #[cfg(not(feature = "async_matrix"))] In: InputPin,
+    #[cfg(feature = "async_matrix")] In: Wait + InputPin,
+    Out: OutputPin,
+    D: DebouncerTrait<ROW, COL>,
+    const ROW: usize,
+    const COL: usize,
+    const COL2ROW: bool,
+> Matrix<In, Out, D, ROW, COL, COL2ROW>
+where
+    Self: RowPins<COL2ROW>,
+    Self: ColPins<COL2ROW>,
+    Self: MatrixOutputPins<Out>,
+    Self: MatrixInputPins<In>,
+{
+    /// Update the pin states of the whole matrix, `current_out_idx` is the output pin being scanned
+    async fn scan_raw_states(&mut self, current_out_idx: usize) {
+        if let Some(out_pin) = self.get_output_pins_mut().get_mut(current_out_idx) {
+            out_pin.set_low().ok();
+        }
+        for out_idx in 0..Self::OUTPUT_PIN_NUM {
+            if let Some(out_pin) = self.get_output_pins_mut().get_mut(out_idx) {
+                out_pin.set_high().ok();
+            }
+            Timer::after_micros(1).await;
+            for in_idx in 0..Self::INPUT_PIN_NUM {
+                let in_pin_state = if let Some(in_pin) = self.get_input_pins_mut().get_mut(in_idx) {
+                    in_pin.is_high().ok().unwrap_or_default()
+                } else {
+                    false
+                };
+                let (row_idx, col_idx) = if COL2ROW { (in_idx, out_idx) } else { (out_idx, in_idx) };
+                self.raw_states[col_idx][row_idx] = in_pin_state;
+            }
+            if let Some(out_pin) = self.get_output_pins_mut().get_mut(out_idx) {
+                out_pin.set_low().ok();
+            }
+        }
+        if let Some(out_pin) = self.get_output_pins_mut().get_mut(current_out_idx) {
+            out_pin.set_high().ok();
+        }
+        Timer::after_micros(1).await;
+    }
+}
+
+/// Check whether the key at (row, col) is a corner of a rectangle whose corners all look pressed
+fn in_pressed_rectangle<const ROW: usize, const COL: usize>(
+    states: &[[bool; ROW]; COL],
+    row_idx: usize,
+    col_idx: usize,
+) -> bool {
+    (0..COL)
+        .filter(|&col| col != col_idx && states[col][row_idx])
+        .any(|col| (0..ROW).any(|row| row != row_idx && states[col_idx][row] && states[col][row]))
 }
 
 impl<
@@ -399,17 +487,30 @@ where
                     );
 
                     if let DebounceState::Debounced = debounce_state {
+                        if self.ghost_filter && !self.key_states[col_idx][row_idx].pressed && {
+                            self.scan_raw_states(out_idx).await;
+                            in_pressed_rectangle(&self.raw_states, row_idx, col_idx)
+                        } {
+                            // Ambiguous press, check it again in the next scan
+                            #[cfg(feature = "async_matrix")]
+                            {
+                                self.rescan_needed = true;
+                            }
+                            continue;
+                        }
                         self.key_states[col_idx][row_idx].toggle_pressed();
                         self.scan_pos = (out_idx, in_idx);
+                        if let Some(throttle) = self.throttle.as_mut() {
+                            throttle.on_activity();
+                        }
                         #[cfg(feature = "async_matrix")]
                         {
                             self.rescan_needed = true;
                         }
-                        return Event::Key(KeyboardEvent::key(
-                            row_idx as u8,
-                            col_idx as u8,
-                            self.key_states[col_idx][row_idx].pressed,
-                        ));
+                        return Event::Key(
+                            KeyboardEvent::key(row_idx as u8, col_idx as u8, self.key_states[col_idx][row_idx].pressed)
+                                .with_timestamp(Instant::now()),
+                        );
                     }
 
                     // If there's key still pressed, always refresh the self.scan_start
@@ -425,14 +526,33 @@ where
                 }
             }
 
+            let pause = self.throttle.as_mut().map(|throttle| {
+                let key_held = self.key_states.iter().flatten().any(KeyState::is_pressing);
+                throttle.after_scan(key_held)
+            });
+            match pause {
+                Some(ScanPause::None) => {}
+                Some(ScanPause::Delay(delay)) => Timer::after(delay).await,
+                // Keys can wake up the async matrix by interrupts, otherwise scan at the lowest rate
+                #[cfg(feature = "async_matrix")]
+                Some(ScanPause::Sleep) => self.wait_for_key().await,
+                #[cfg(not(feature = "async_matrix"))]
+                Some(ScanPause::Sleep) => {
+                    if let Some(throttle) = self.throttle.as_ref() {
+                        Timer::after(throttle.sleep_interval()).await;
+                    }
+                }
+                #[cfg(feature = "async_matrix")]
+                None if !self.rescan_needed => self.wait_for_key().await,
+                None => {}
+            }
             #[cfg(feature = "async_matrix")]
             {
-                if !self.rescan_needed {
-                    self.wait_for_key().await;
-                }
                 self.rescan_needed = false;
             }
             self.scan_pos = (0, 0);
+            #[cfg(feature = "instrumentation")]
+            crate::instrumentation::record_scan();
         }
     }
 }
@@ -482,14 +602,18 @@ impl<const ROW: usize, const COL: usize, M: MatrixTrait<ROW, COL>, const ROW_OFF
 {
     async fn read_event(&mut self) -> Event {
         match self.0.read_event().await {
-            Event::Key(KeyboardEvent {
-                pressed,
-                pos: KeyboardEventPos::Key(KeyPos { row, col }),
-            }) => Event::Key(KeyboardEvent::key(
-                row + ROW_OFFSET as u8,
-                col + COL_OFFSET as u8,
-                pressed,
-            )),
+            Event::Key(
+                mut event @ KeyboardEvent {
+                    pos: KeyboardEventPos::Key(KeyPos { row, col }),
+                    ..
+                },
+            ) => {
+                event.pos = KeyboardEventPos::Key(KeyPos {
+                    row: row + ROW_OFFSET as u8,
+                    col: col + COL_OFFSET as u8,
+                });
+                Event::Key(event)
+            }
             event => event,
         }
     }
@@ -527,3 +651,181 @@ impl<const ROW: usize, const COL: usize> InputDevice for TestMatrix<ROW, COL> {
         Event::Key(KeyboardEvent::key(0, 0, self.last))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::ErrorType;
+
+    use super::*;
+    use crate::debounce::{NoDebounce, next_key};
+
+    /// Build the pin states from the pressed keys, (row, col)
+    fn states<const ROW: usize, const COL: usize>(pressed: &[(usize, usize)]) -> [[bool; ROW]; COL] {
+        let mut states = [[false; ROW]; COL];
+        for (row, col) in pressed {
+            states[*col][*row] = true;
+        }
+        states
+    }
+
+    #[test]
+    fn test_pressed_rectangle() {
+        // Three corners pressed and the fourth one is a ghost
+        let ghost = states::<4, 4>(&[(0, 0), (0, 2), (3, 0), (3, 2)]);
+        for (row, col) in [(0, 0), (0, 2), (3, 0), (3, 2)] {
+            assert!(in_pressed_rectangle(&ghost, row, col));
+        }
+        // Keys in the same row or column are not ambiguous
+        let line = states::<4, 4>(&[(1, 0), (1, 1), (1, 3), (0, 3), (2, 3)]);
+        for (row, col) in [(1, 0), (1, 1), (1, 3), (0, 3), (2, 3)] {
+            assert!(!in_pressed_rectangle(&line, row, col));
+        }
+        // Only the keys forming the rectangle are ambiguous
+        let mixed = states::<4, 4>(&[(0, 0), (0, 1), (2, 0), (2, 1), (3, 3)]);
+        assert!(in_pressed_rectangle(&mixed, 2, 1));
+        assert!(!in_pressed_rectangle(&mixed, 3, 3));
+    }
+
+    /// A 3x3 col2row matrix without diodes
+    struct MockBoard {
+        /// Driven output pin
+        driven: Option<usize>,
+        /// Pressed keys, (row, col)
+        pressed: heapless::Vec<(usize, usize), 9>,
+    }
+
+    impl MockBoard {
+        /// Rows connected to the driven column through the pressed switches
+        fn connected_rows(&self) -> [bool; 3] {
+            let mut rows = [false; 3];
+            let mut cols = [false; 3];
+            if let Some(col) = self.driven {
+                cols[col] = true;
+            }
+            // Without diodes, the current flows through the switches in both directions
+            for _ in 0..3 {
+                for (row, col) in self.pressed.iter() {
+                    if cols[*col] || rows[*row] {
+                        cols[*col] = true;
+                        rows[*row] = true;
+                    }
+                }
+            }
+            rows
+        }
+    }
+
+    enum MockPin<'a> {
+        Row(&'a RefCell<MockBoard>, usize),
+        Col(&'a RefCell<MockBoard>, usize),
+    }
+
+    impl ErrorType for MockPin<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockPin<'_> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            match self {
+                MockPin::Row(board, row) => Ok(board.borrow().connected_rows()[*row]),
+                MockPin::Col(..) => unreachable!(),
+            }
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    impl OutputPin for MockPin<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            if let MockPin::Col(board, col) = self {
+                let mut board = board.borrow_mut();
+                if board.driven == Some(*col) {
+                    board.driven = None;
+                }
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            if let MockPin::Col(board, col) = self {
+                board.borrow_mut().driven = Some(*col);
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "async_matrix")]
+    impl Wait for MockPin<'_> {
+        async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ghost_filter() {
+        let board = RefCell::new(MockBoard {
+            driven: None,
+            pressed: heapless::Vec::new(),
+        });
+        let rows = core::array::from_fn(|row| MockPin::Row(&board, row));
+        let cols = core::array::from_fn(|col| MockPin::Col(&board, col));
+        let mut matrix = Matrix::<_, _, _, 3, 3, true>::new(rows, cols, NoDebounce).with_ghost_filter(true);
+
+        board.borrow_mut().pressed.push((0, 0)).unwrap();
+        assert_eq!(next_key(&mut matrix), Some((0, 0, true)));
+        board.borrow_mut().pressed.push((0, 1)).unwrap();
+        assert_eq!(next_key(&mut matrix), Some((0, 1, true)));
+
+        // The third corner makes (1, 1) a ghost, neither of them is reported
+        board.borrow_mut().pressed.push((1, 0)).unwrap();
+        assert_eq!(next_key(&mut matrix), None);
+
+        // The press is reported once it's not ambiguous anymore
+        board.borrow_mut().pressed.retain(|key| *key != (0, 1));
+        let mut events = [next_key(&mut matrix), next_key(&mut matrix)];
+        events.sort();
+        assert_eq!(events, [Some((0, 1, false)), Some((1, 0, true))]);
+        assert_eq!(next_key(&mut matrix), None);
+    }
+
+    #[test]
+    fn test_ghost_without_filter() {
+        let board = RefCell::new(MockBoard {
+            driven: None,
+            pressed: heapless::Vec::new(),
+        });
+        let rows = core::array::from_fn(|row| MockPin::Row(&board, row));
+        let cols = core::array::from_fn(|col| MockPin::Col(&board, col));
+        let mut matrix = Matrix::<_, _, _, 3, 3, true>::new(rows, cols, NoDebounce);
+
+        for key in [(0, 0), (0, 1), (1, 0)] {
+            board.borrow_mut().pressed.push(key).unwrap();
+        }
+        assert_eq!(next_key(&mut matrix), Some((0, 0, true)));
+        assert_eq!(next_key(&mut matrix), Some((1, 0, true)));
+        assert_eq!(next_key(&mut matrix), Some((0, 1, true)));
+        // The ghost key
+        assert_eq!(next_key(&mut matrix), Some((1, 1, true)));
+    }
+}
@@ -79,11 +79,14 @@ impl<Pin: FlexPin, D: DebouncerTrait<ROW, COL>, const PIN_NUM: usize, const ROW:
                         if let DebounceState::Debounced = debounce_state {
                             self.key_state[scan_y_idx][scan_x_idx].toggle_pressed();
                             self.scan_pos = (scan_y_idx, scan_x_idx);
-                            return Event::Key(KeyboardEvent::key(
-                                scan_y_idx as u8,
-                                scan_x_idx as u8,
-                                self.key_state[scan_y_idx][scan_x_idx].pressed,
-                            ));
+                            return Event::Key(
+                                KeyboardEvent::key(
+                                    scan_y_idx as u8,
+                                    scan_x_idx as u8,
+                                    self.key_state[scan_y_idx][scan_x_idx].pressed,
+                                )
+                                .with_timestamp(Instant::now()),
+                            );
                         }
 
                         // Pull output pin back to low
@@ -94,6 +97,8 @@ impl<Pin: FlexPin, D: DebouncerTrait<ROW, COL>, const PIN_NUM: usize, const ROW:
                 }
             }
             self.scan_pos = (0, 0);
+            #[cfg(feature = "instrumentation")]
+            crate::instrumentation::record_scan();
         }
     }
 }
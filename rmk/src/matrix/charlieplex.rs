@@ -0,0 +1,231 @@
+//! Charlieplexed matrix.
+//!
+//! `N` pins address `N * (N - 1)` keys, every key is connected from a driving pin to a sensing pin through a diode.
+//! Any pin can drive or sense, so the pins are switched between output and input by [`FlexPin`].
+//! One pin is driven high at a time, and all other pins are sensed, so a full scan only takes `N` steps.
+
+use embassy_time::{Instant, Timer};
+
+use crate::debounce::{DebounceState, DebouncerTrait};
+use crate::driver::flex_pin::FlexPin;
+use crate::event::{Event, KeyboardEvent};
+use crate::input_device::InputDevice;
+use crate::matrix::bidirectional_matrix::ScanLocation;
+use crate::matrix::{KeyState, MatrixTrait};
+
+/// Charlieplexed matrix, the key position is mapped to the pins by a scan map.
+///
+/// `ScanLocation::Pins(in_idx, out_idx)` at `scan_map[row][col]` means that the key at (row, col)
+/// is sensed by pin `in_idx` when pin `out_idx` is driven high.
+pub struct CharlieplexMatrix<
+    Pin: FlexPin,
+    D: DebouncerTrait<ROW, COL>,
+    const PIN_NUM: usize,
+    const ROW: usize,
+    const COL: usize,
+> {
+    /// Pins of the matrix, all pins are inputs except the driving one
+    pins: [Pin; PIN_NUM],
+    /// Debouncer
+    debouncer: D,
+    /// Key state matrix
+    key_state: [[KeyState; COL]; ROW],
+    /// Key position of every (out_idx, in_idx) pin pair
+    positions: [[Option<(u8, u8)>; PIN_NUM]; PIN_NUM],
+    /// Current scan pos: (out_idx, in_idx)
+    scan_pos: (usize, usize),
+}
+
+impl<Pin: FlexPin, D: DebouncerTrait<ROW, COL>, const PIN_NUM: usize, const ROW: usize, const COL: usize>
+    CharlieplexMatrix<Pin, D, PIN_NUM, ROW, COL>
+{
+    /// Create a charlieplexed matrix from the pins and the scan map
+    pub fn new(mut pins: [Pin; PIN_NUM], debouncer: D, scan_map: [[ScanLocation; COL]; ROW]) -> Self {
+        let mut positions = [[None; PIN_NUM]; PIN_NUM];
+        for (row_idx, row) in scan_map.iter().enumerate() {
+            for (col_idx, location) in row.iter().enumerate() {
+                if let ScanLocation::Pins(in_idx, out_idx) = *location {
+                    assert!(
+                        in_idx != out_idx && in_idx < PIN_NUM && out_idx < PIN_NUM,
+                        "Invalid charlieplex scan location"
+                    );
+                    positions[out_idx][in_idx] = Some((row_idx as u8, col_idx as u8));
+                }
+            }
+        }
+        for pin in pins.iter_mut() {
+            pin.set_as_input();
+        }
+        Self {
+            pins,
+            debouncer,
+            key_state: [[KeyState::new(); COL]; ROW],
+            positions,
+            scan_pos: (0, 0),
+        }
+    }
+}
+
+impl<Pin: FlexPin, D: DebouncerTrait<ROW, COL>, const PIN_NUM: usize, const ROW: usize, const COL: usize> InputDevice
+    for CharlieplexMatrix<Pin, D, PIN_NUM, ROW, COL>
+{
+    async fn read_event(&mut self) -> Event {
+        loop {
+            let (out_idx_start, in_idx_start) = self.scan_pos;
+
+            for out_idx in out_idx_start..PIN_NUM {
+                if self.positions[out_idx].iter().all(Option::is_none) {
+                    continue;
+                }
+                // Drive the pin high, other pins are pulled down inputs
+                self.pins[out_idx].set_as_output();
+                self.pins[out_idx].set_high().ok();
+                Timer::after_micros(1).await;
+
+                let in_start = if out_idx == out_idx_start { in_idx_start } else { 0 };
+                for in_idx in in_start..PIN_NUM {
+                    let Some((row, col)) = self.positions[out_idx][in_idx] else {
+                        continue;
+                    };
+                    let (row_idx, col_idx) = (row as usize, col as usize);
+                    let debounce_state = self.debouncer.detect_change_with_debounce(
+                        row_idx,
+                        col_idx,
+                        self.pins[in_idx].is_high().ok().unwrap_or_default(),
+                        &self.key_state[row_idx][col_idx],
+                    );
+                    if let DebounceState::Debounced = debounce_state {
+                        self.key_state[row_idx][col_idx].toggle_pressed();
+                        self.pins[out_idx].set_low().ok();
+                        self.pins[out_idx].set_as_input();
+                        self.scan_pos = (out_idx, in_idx);
+                        return Event::Key(
+                            KeyboardEvent::key(row, col, self.key_state[row_idx][col_idx].pressed)
+                                .with_timestamp(Instant::now()),
+                        );
+                    }
+                }
+
+                // Release the pin, so that it can be sensed by other pins
+                self.pins[out_idx].set_low().ok();
+                self.pins[out_idx].set_as_input();
+            }
+            self.scan_pos = (0, 0);
+            #[cfg(feature = "instrumentation")]
+            crate::instrumentation::record_scan();
+        }
+    }
+}
+
+impl<Pin: FlexPin, D: DebouncerTrait<ROW, COL>, const PIN_NUM: usize, const ROW: usize, const COL: usize>
+    MatrixTrait<ROW, COL> for CharlieplexMatrix<Pin, D, PIN_NUM, ROW, COL>
+{
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_key(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+
+    use super::*;
+    use crate::debounce::NoDebounce;
+
+    /// Charlieplexed board with 4 pins, the pins' output levels and the pressed (out_idx, in_idx) pairs
+    struct MockBoard {
+        driven: [Option<bool>; 4],
+        pressed: heapless::Vec<(usize, usize), 8>,
+    }
+
+    struct MockFlexPin<'a>(&'a RefCell<MockBoard>, usize);
+
+    impl ErrorType for MockFlexPin<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockFlexPin<'_> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            let board = self.0.borrow();
+            assert!(board.driven[self.1].is_none(), "Sensing an output pin");
+            // Pulled down, unless a pressed key connects it to a pin driven high
+            Ok(board
+                .pressed
+                .iter()
+                .any(|(out_idx, in_idx)| *in_idx == self.1 && board.driven[*out_idx] == Some(true)))
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    impl OutputPin for MockFlexPin<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().driven[self.1] = Some(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().driven[self.1] = Some(true);
+            Ok(())
+        }
+    }
+
+    impl FlexPin for MockFlexPin<'_> {
+        fn set_as_input(&mut self) {
+            self.0.borrow_mut().driven[self.1] = None;
+        }
+
+        fn set_as_output(&mut self) {
+            self.0.borrow_mut().driven[self.1] = Some(false);
+        }
+    }
+
+    /// Row is the driving pin, col is the index of the sensing pin among the other pins
+    fn scan_map() -> [[ScanLocation; 3]; 4] {
+        core::array::from_fn(|out_idx| {
+            core::array::from_fn(|col| {
+                let in_idx = if col < out_idx { col } else { col + 1 };
+                ScanLocation::Pins(in_idx, out_idx)
+            })
+        })
+    }
+
+    fn assert_key(event: Event, row: u8, col: u8, pressed: bool) {
+        match event {
+            Event::Key(event) => {
+                assert_eq!(event.pos, KeyboardEvent::key(row, col, pressed).pos);
+                assert_eq!(event.pressed, pressed);
+            }
+            _ => panic!("Unexpected event"),
+        }
+    }
+
+    #[test]
+    fn test_charlieplex_matrix() {
+        let board = RefCell::new(MockBoard {
+            driven: [Some(false); 4],
+            pressed: heapless::Vec::new(),
+        });
+        let pins = core::array::from_fn(|idx| MockFlexPin(&board, idx));
+        let mut matrix = CharlieplexMatrix::<_, _, 4, 4, 3>::new(pins, NoDebounce, scan_map());
+        assert_eq!(board.borrow().driven, [None; 4]);
+
+        // Both directions of a pin pair are different keys
+        board.borrow_mut().pressed.push((1, 0)).unwrap();
+        board.borrow_mut().pressed.push((0, 1)).unwrap();
+        assert_key(block_on(matrix.read_event()), 0, 0, true);
+        assert_key(block_on(matrix.read_event()), 1, 0, true);
+
+        board.borrow_mut().pressed.push((3, 2)).unwrap();
+        assert_key(block_on(matrix.read_event()), 3, 2, true);
+        board.borrow_mut().pressed.retain(|key| *key != (0, 1));
+        assert_key(block_on(matrix.read_event()), 0, 0, false);
+        // All pins are released after reporting
+        assert_eq!(board.borrow().driven, [None; 4]);
+    }
+}
@@ -0,0 +1,537 @@
+//! Matrix whose rows and columns live on an I2C GPIO expander, such as MCP23017 or PCA9555.
+//!
+//! Each line of the matrix is either a pin of the expander or a native pin, see [`ExpanderLine`].
+//! The lines are scanned by [`LineMatrix`](crate::matrix::lines::LineMatrix).
+//!
+//! Expanders only have pull-up resistors, so the lines are active low: the selected output line is driven low,
+//! and a low input line means pressed. Native pins that are mixed with expander pins should use the same polarity,
+//! i.e. input pins should be pulled up.
+//!
+//! With `async_matrix`, the interrupt output of the expander can be used to wake up the matrix,
+//! if all input lines are on the expander. Otherwise, the input lines are polled.
+
+use embassy_sync::mutex::Mutex;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::i2c::I2c;
+
+use crate::RawMutex;
+use crate::matrix::lines::{InputLines, OutputLines};
+
+// MCP23017 registers, IOCON.BANK = 0. The registers of port B follow port A, so both ports are accessed sequentially
+const MCP23017_IODIRA: u8 = 0x00;
+const MCP23017_GPINTENA: u8 = 0x04;
+const MCP23017_IOCON: u8 = 0x0a;
+const MCP23017_GPPUA: u8 = 0x0c;
+const MCP23017_GPIOA: u8 = 0x12;
+const MCP23017_OLATA: u8 = 0x14;
+/// INTA and INTB are internally connected
+const MCP23017_IOCON_MIRROR: u8 = 0x40;
+
+// PCA9555 registers
+const PCA9555_INPUT0: u8 = 0x00;
+const PCA9555_OUTPUT0: u8 = 0x02;
+const PCA9555_CONFIG0: u8 = 0x06;
+
+/// Error of the I/O expander
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IoExpanderError {
+    /// I2C bus error
+    Bus,
+}
+
+/// 16-bit GPIO expander, pin `n` is bit `n` of the port value
+pub trait IoExpander {
+    /// Set pins in `outputs` as outputs, other pins as inputs with pull-up
+    async fn configure(&mut self, outputs: u16) -> Result<(), IoExpanderError>;
+
+    /// Set the levels of output pins
+    async fn write(&mut self, levels: u16) -> Result<(), IoExpanderError>;
+
+    /// Read the levels of all pins
+    async fn read(&mut self) -> Result<u16, IoExpanderError>;
+
+    /// Enable the interrupt output when any pin in `inputs` changes, the interrupt is cleared by reading the pins
+    async fn enable_interrupt(&mut self, inputs: u16) -> Result<(), IoExpanderError>;
+}
+
+/// MCP23017 I/O expander
+pub struct Mcp23017<I2C: I2c> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> Mcp23017<I2C> {
+    /// Create an MCP23017 driver, the default address is 0x20
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    async fn write_port(&mut self, reg: u8, value: u16) -> Result<(), IoExpanderError> {
+        let [a, b] = value.to_le_bytes();
+        self.i2c
+            .write(self.address, &[reg, a, b])
+            .await
+            .map_err(|_| IoExpanderError::Bus)
+    }
+}
+
+impl<I2C: I2c> IoExpander for Mcp23017<I2C> {
+    async fn configure(&mut self, outputs: u16) -> Result<(), IoExpanderError> {
+        self.i2c
+            .write(self.address, &[MCP23017_IOCON, MCP23017_IOCON_MIRROR])
+            .await
+            .map_err(|_| IoExpanderError::Bus)?;
+        self.write_port(MCP23017_IODIRA, !outputs).await?;
+        self.write_port(MCP23017_GPPUA, !outputs).await
+    }
+
+    async fn write(&mut self, levels: u16) -> Result<(), IoExpanderError> {
+        self.write_port(MCP23017_OLATA, levels).await
+    }
+
+    async fn read(&mut self) -> Result<u16, IoExpanderError> {
+        let mut data = [0u8; 2];
+        self.i2c
+            .write_read(self.address, &[MCP23017_GPIOA], &mut data)
+            .await
+            .map_err(|_| IoExpanderError::Bus)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    async fn enable_interrupt(&mut self, inputs: u16) -> Result<(), IoExpanderError> {
+        // INTCON defaults to comparing against the previous value, i.e. interrupt on change
+        self.write_port(MCP23017_GPINTENA, inputs).await
+    }
+}
+
+/// PCA9555 I/O expander, which has fixed pull-ups and always enabled interrupt output
+pub struct Pca9555<I2C: I2c> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> Pca9555<I2C> {
+    /// Create a PCA9555 driver, the default address is 0x20
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    async fn write_port(&mut self, reg: u8, value: u16) -> Result<(), IoExpanderError> {
+        let [port0, port1] = value.to_le_bytes();
+        self.i2c
+            .write(self.address, &[reg, port0, port1])
+            .await
+            .map_err(|_| IoExpanderError::Bus)
+    }
+}
+
+impl<I2C: I2c> IoExpander for Pca9555<I2C> {
+    async fn configure(&mut self, outputs: u16) -> Result<(), IoExpanderError> {
+        self.write_port(PCA9555_CONFIG0, !outputs).await
+    }
+
+    async fn write(&mut self, levels: u16) -> Result<(), IoExpanderError> {
+        self.write_port(PCA9555_OUTPUT0, levels).await
+    }
+
+    async fn read(&mut self) -> Result<u16, IoExpanderError> {
+        let mut data = [0u8; 2];
+        self.i2c
+            .write_read(self.address, &[PCA9555_INPUT0], &mut data)
+            .await
+            .map_err(|_| IoExpanderError::Bus)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    async fn enable_interrupt(&mut self, _inputs: u16) -> Result<(), IoExpanderError> {
+        Ok(())
+    }
+}
+
+struct ExpanderState<E: IoExpander> {
+    expander: E,
+    /// Pins used as outputs
+    outputs: u16,
+    /// Pins whose direction is not configured yet
+    pending: u16,
+}
+
+/// I/O expander shared by the output lines and the input lines of a matrix
+pub struct SharedIoExpander<E: IoExpander> {
+    state: Mutex<RawMutex, ExpanderState<E>>,
+}
+
+impl<E: IoExpander> SharedIoExpander<E> {
+    pub fn new(expander: E) -> Self {
+        Self {
+            state: Mutex::new(ExpanderState {
+                expander,
+                outputs: 0,
+                pending: 0,
+            }),
+        }
+    }
+
+    /// Use the given pins as inputs (`input` is true) or outputs, the direction is configured before the next access
+    async fn claim(&self, pins: u16, input: bool) {
+        let mut state = self.state.lock().await;
+        if input {
+            state.outputs &= !pins;
+        } else {
+            state.outputs |= pins;
+        }
+        state.pending |= pins;
+    }
+
+    async fn configure_pending(state: &mut ExpanderState<E>) -> Result<(), IoExpanderError> {
+        if state.pending != 0 {
+            state.expander.configure(state.outputs).await?;
+            state.pending = 0;
+        }
+        Ok(())
+    }
+
+    async fn write(&self, levels: u16) -> Result<(), IoExpanderError> {
+        let mut state = self.state.lock().await;
+        Self::configure_pending(&mut state).await?;
+        state.expander.write(levels).await
+    }
+
+    async fn read(&self) -> Result<u16, IoExpanderError> {
+        let mut state = self.state.lock().await;
+        Self::configure_pending(&mut state).await?;
+        state.expander.read().await
+    }
+
+    async fn enable_interrupt(&self, inputs: u16) -> Result<(), IoExpanderError> {
+        let mut state = self.state.lock().await;
+        Self::configure_pending(&mut state).await?;
+        state.expander.enable_interrupt(inputs).await
+    }
+}
+
+/// A line of the expander matrix
+pub enum ExpanderLine<P> {
+    /// Pin `n` of the expander
+    Expander(u8),
+    /// Native pin
+    Pin(P),
+}
+
+/// Output lines on the expander or native pins, the selected line is driven low
+pub struct ExpanderOutputLines<'a, E: IoExpander, Out: OutputPin, const N: usize> {
+    expander: &'a SharedIoExpander<E>,
+    lines: [ExpanderLine<Out>; N],
+    /// Expander pins used by the lines
+    mask: u16,
+    claimed: bool,
+}
+
+impl<'a, E: IoExpander, Out: OutputPin, const N: usize> ExpanderOutputLines<'a, E, Out, N> {
+    pub fn new(expander: &'a SharedIoExpander<E>, lines: [ExpanderLine<Out>; N]) -> Self {
+        let mask = expander_mask(&lines);
+        Self {
+            expander,
+            lines,
+            mask,
+            claimed: false,
+        }
+    }
+}
+
+impl<E: IoExpander, Out: OutputPin, const N: usize> OutputLines for ExpanderOutputLines<'_, E, Out, N> {
+    async fn write(&mut self, lines: u64) {
+        if !self.claimed {
+            self.expander.claim(self.mask, false).await;
+            self.claimed = true;
+        }
+        let mut levels = u16::MAX;
+        for (idx, line) in self.lines.iter_mut().enumerate() {
+            let selected = (lines >> idx) & 1 == 1;
+            match line {
+                ExpanderLine::Expander(pin) if selected => levels &= !(1 << *pin),
+                ExpanderLine::Expander(_) => {}
+                ExpanderLine::Pin(pin) if selected => {
+                    pin.set_low().ok();
+                }
+                ExpanderLine::Pin(pin) => {
+                    pin.set_high().ok();
+                }
+            }
+        }
+        if self.mask != 0 && self.expander.write(levels).await.is_err() {
+            error!("Failed to write I/O expander");
+        }
+    }
+}
+
+/// Input lines on the expander or native pins, a low line means pressed
+pub struct ExpanderInputLines<'a, E: IoExpander, In: InputPin, INT: InputPin + Wait, const N: usize> {
+    expander: &'a SharedIoExpander<E>,
+    lines: [ExpanderLine<In>; N],
+    /// Interrupt output of the expander
+    interrupt: Option<INT>,
+    /// Expander pins used by the lines
+    mask: u16,
+    claimed: bool,
+}
+
+impl<'a, E: IoExpander, In: InputPin, INT: InputPin + Wait, const N: usize> ExpanderInputLines<'a, E, In, INT, N> {
+    /// Create input lines, `interrupt` is the pin connected to the interrupt output of the expander
+    pub fn new(expander: &'a SharedIoExpander<E>, lines: [ExpanderLine<In>; N], interrupt: Option<INT>) -> Self {
+        let mask = expander_mask(&lines);
+        Self {
+            expander,
+            lines,
+            interrupt,
+            mask,
+            claimed: false,
+        }
+    }
+}
+
+impl<E: IoExpander, In: InputPin, INT: InputPin + Wait, const N: usize> InputLines
+    for ExpanderInputLines<'_, E, In, INT, N>
+{
+    async fn read(&mut self) -> u64 {
+        if !self.claimed {
+            self.expander.claim(self.mask, true).await;
+            self.claimed = true;
+        }
+        let levels = if self.mask != 0 {
+            match self.expander.read().await {
+                Ok(levels) => levels,
+                Err(_) => {
+                    error!("Failed to read I/O expander");
+                    return 0;
+                }
+            }
+        } else {
+            u16::MAX
+        };
+        self.lines.iter_mut().enumerate().fold(0, |lines, (idx, line)| {
+            let pressed = match line {
+                ExpanderLine::Expander(pin) => (levels >> *pin) & 1 == 0,
+                ExpanderLine::Pin(pin) => pin.is_low().ok().unwrap_or_default(),
+            };
+            lines | ((pressed as u64) << idx)
+        })
+    }
+
+    async fn wait_for_any(&mut self) {
+        let all_on_expander = self.lines.iter().all(|line| matches!(line, ExpanderLine::Expander(_)));
+        if !all_on_expander || self.interrupt.is_none() {
+            while self.read().await == 0 {
+                embassy_time::Timer::after_millis(1).await;
+            }
+            return;
+        }
+
+        if self.expander.enable_interrupt(self.mask).await.is_err() {
+            error!("Failed to enable the interrupt of I/O expander");
+        }
+        // Reading the pins clears the pending interrupt
+        while self.read().await == 0 {
+            if let Some(interrupt) = self.interrupt.as_mut() {
+                interrupt.wait_for_low().await.ok();
+            }
+        }
+    }
+}
+
+fn expander_mask<P>(lines: &[ExpanderLine<P>]) -> u16 {
+    lines.iter().fold(0, |mask, line| match line {
+        ExpanderLine::Expander(pin) => {
+            assert!(*pin < 16, "Invalid I/O expander pin: {}", pin);
+            mask | (1 << *pin)
+        }
+        ExpanderLine::Pin(_) => mask,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embedded_hal::digital::ErrorType as PinErrorType;
+    use embedded_hal_async::i2c::{ErrorType, Operation};
+
+    use super::*;
+    use crate::debounce::NoDebounce;
+    use crate::event::Event;
+    use crate::input_device::InputDevice;
+    use crate::matrix::lines::LineMatrix;
+
+    /// An MCP23017 with a 2x4 col2row matrix: columns on GPA0..GPA3, row 0 on GPB0, row 1 on a native pin
+    struct MockBoard {
+        regs: [u8; 0x16],
+        pressed: heapless::Vec<(usize, usize), 8>,
+    }
+
+    impl MockBoard {
+        fn new() -> Self {
+            let mut regs = [0u8; 0x16];
+            // Pins are inputs after reset
+            regs[MCP23017_IODIRA as usize] = 0xff;
+            regs[MCP23017_IODIRA as usize + 1] = 0xff;
+            Self {
+                regs,
+                pressed: heapless::Vec::new(),
+            }
+        }
+
+        fn port(&self, reg: u8) -> u16 {
+            u16::from_le_bytes([self.regs[reg as usize], self.regs[reg as usize + 1]])
+        }
+
+        /// Rows pulled low by the pressed keys of driven-low columns
+        fn active_rows(&self) -> u8 {
+            let outputs = !self.port(MCP23017_IODIRA) & self.port(MCP23017_OLATA);
+            let driven_low = !self.port(MCP23017_IODIRA) & !outputs;
+            self.pressed
+                .iter()
+                .filter(|(_, col)| (driven_low >> col) & 1 == 1)
+                .fold(0, |rows, (row, _)| rows | (1 << row))
+        }
+    }
+
+    struct MockI2c<'a>(&'a RefCell<MockBoard>);
+
+    impl ErrorType for MockI2c<'_> {
+        type Error = Infallible;
+    }
+
+    impl I2c for MockI2c<'_> {
+        async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            assert_eq!(address, 0x20);
+            let mut board = self.0.borrow_mut();
+            let mut reg = 0usize;
+            for op in operations.iter_mut() {
+                match op {
+                    Operation::Write(data) => {
+                        reg = data[0] as usize;
+                        for (offset, value) in data[1..].iter().enumerate() {
+                            board.regs[reg + offset] = *value;
+                        }
+                    }
+                    Operation::Read(buf) => {
+                        assert_eq!(reg, MCP23017_GPIOA as usize);
+                        // Pulled up unless pressed
+                        let gpiob = !(board.active_rows() & 0b1);
+                        buf.copy_from_slice(&[0xff, gpiob]);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct MockPin<'a>(&'a RefCell<MockBoard>);
+
+    impl PinErrorType for MockPin<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockPin<'_> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.0.borrow().active_rows() & 0b10 == 0)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    impl Wait for MockPin<'_> {
+        async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// Output pin which is never used, for lines all on the expander
+    struct UnusedPin;
+
+    impl PinErrorType for UnusedPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for UnusedPin {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+    }
+
+    fn assert_key(event: Event, row: u8, col: u8, pressed: bool) {
+        match event {
+            Event::Key(event) => {
+                assert_eq!(event.pos, crate::event::KeyboardEvent::key(row, col, pressed).pos);
+                assert_eq!(event.pressed, pressed);
+            }
+            _ => panic!("Unexpected event"),
+        }
+    }
+
+    #[test]
+    fn test_mcp23017_matrix() {
+        let board = RefCell::new(MockBoard::new());
+        let expander = SharedIoExpander::new(Mcp23017::new(MockI2c(&board), 0x20));
+        let outputs = ExpanderOutputLines::<_, UnusedPin, 4>::new(&expander, [0, 1, 2, 3].map(ExpanderLine::Expander));
+        let inputs = ExpanderInputLines::new(
+            &expander,
+            [ExpanderLine::Expander(8), ExpanderLine::Pin(MockPin(&board))],
+            None::<MockPin>,
+        );
+        let mut matrix = LineMatrix::<_, _, _, 2, 4, true>::new(outputs, inputs, NoDebounce);
+
+        board.borrow_mut().pressed.push((0, 2)).unwrap();
+        assert_key(block_on(matrix.read_event()), 0, 2, true);
+        // Columns are outputs, other pins are inputs with pull-up
+        assert_eq!(board.borrow().port(MCP23017_IODIRA), 0xfff0);
+        assert_eq!(board.borrow().port(MCP23017_GPPUA), 0xfff0);
+        assert_eq!(board.borrow().regs[MCP23017_IOCON as usize], MCP23017_IOCON_MIRROR);
+
+        board.borrow_mut().pressed.push((1, 3)).unwrap();
+        assert_key(block_on(matrix.read_event()), 1, 3, true);
+        board.borrow_mut().pressed.clear();
+        assert_key(block_on(matrix.read_event()), 1, 3, false);
+        assert_key(block_on(matrix.read_event()), 0, 2, false);
+    }
+
+    #[test]
+    fn test_mcp23017_interrupt() {
+        let board = RefCell::new(MockBoard::new());
+        let expander = SharedIoExpander::new(Mcp23017::new(MockI2c(&board), 0x20));
+        let mut outputs = ExpanderOutputLines::<_, UnusedPin, 1>::new(&expander, [ExpanderLine::Expander(0)]);
+        let mut inputs =
+            ExpanderInputLines::<_, MockPin, _, 1>::new(&expander, [ExpanderLine::Expander(8)], Some(MockPin(&board)));
+        // Select column 0, the key is pressed already
+        block_on(outputs.write(1));
+        board.borrow_mut().pressed.push((0, 0)).unwrap();
+        block_on(inputs.wait_for_any());
+        assert_eq!(board.borrow().port(MCP23017_GPINTENA), 0x0100);
+    }
+}
@@ -0,0 +1,197 @@
+//! Matrix built from output lines and input lines.
+//!
+//! The lines can be native GPIO pins, or pins of external chips, such as shift registers or I/O expanders.
+
+use embassy_time::{Instant, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use crate::debounce::{DebounceState, DebouncerTrait};
+use crate::event::{Event, KeyboardEvent};
+use crate::input_device::InputDevice;
+use crate::matrix::{KeyState, MatrixTrait};
+
+/// Max number of the output or input lines of a [`LineMatrix`]
+pub const MAX_MATRIX_LINES: usize = 64;
+
+/// Output lines of a matrix
+pub trait OutputLines {
+    /// Set the output lines, bit `n` of `lines` is the level of line `n`
+    async fn write(&mut self, lines: u64);
+}
+
+/// Input lines of a matrix
+pub trait InputLines {
+    /// Read the input lines, bit `n` of the returned value is the level of line `n`
+    async fn read(&mut self) -> u64;
+
+    /// Wait until any input line is high, the lines are polled every 1ms by default
+    async fn wait_for_any(&mut self) {
+        while self.read().await == 0 {
+            Timer::after_millis(1).await;
+        }
+    }
+}
+
+/// Output lines driven by native GPIO pins
+pub struct PinOutputLines<Out: OutputPin, const N: usize>(pub [Out; N]);
+
+impl<Out: OutputPin, const N: usize> OutputLines for PinOutputLines<Out, N> {
+    async fn write(&mut self, lines: u64) {
+        for (idx, pin) in self.0.iter_mut().enumerate() {
+            if (lines >> idx) & 1 == 1 {
+                pin.set_high().ok();
+            } else {
+                pin.set_low().ok();
+            }
+        }
+    }
+}
+
+/// Input lines read by native GPIO pins
+pub struct PinInputLines<In: InputPin, const N: usize>(pub [In; N]);
+
+impl<In: InputPin, const N: usize> InputLines for PinInputLines<In, N> {
+    async fn read(&mut self) -> u64 {
+        self.0.iter_mut().enumerate().fold(0, |lines, (idx, pin)| {
+            lines | ((pin.is_high().ok().unwrap_or_default() as u64) << idx)
+        })
+    }
+}
+
+/// Matrix whose output lines and input lines can be native pins or pins of external chips.
+///
+/// Like [`Matrix`](crate::matrix::Matrix), the selected output line is driven high and a high input line means pressed.
+/// Lines with different electrical polarity should be converted by their [`OutputLines`] and [`InputLines`] implementations.
+pub struct LineMatrix<
+    O: OutputLines,
+    I: InputLines,
+    D: DebouncerTrait<ROW, COL>,
+    const ROW: usize,
+    const COL: usize,
+    const COL2ROW: bool,
+> {
+    /// Output lines, columns for col2row matrix
+    outputs: O,
+    /// Input lines, rows for col2row matrix
+    inputs: I,
+    /// Debouncer
+    debouncer: D,
+    /// Key state matrix
+    key_states: [[KeyState; ROW]; COL],
+    /// Current scan pos: (out_idx, in_idx)
+    scan_pos: (usize, usize),
+    /// Re-scan needed flag
+    #[cfg(feature = "async_matrix")]
+    rescan_needed: bool,
+}
+
+impl<
+    O: OutputLines,
+    I: InputLines,
+    D: DebouncerTrait<ROW, COL>,
+    const ROW: usize,
+    const COL: usize,
+    const COL2ROW: bool,
+> LineMatrix<O, I, D, ROW, COL, COL2ROW>
+{
+    const OUTPUT_LINE_NUM: usize = const { if COL2ROW { COL } else { ROW } };
+    const INPUT_LINE_NUM: usize = const { if COL2ROW { ROW } else { COL } };
+
+    /// Create a matrix from output and input lines
+    pub fn new(outputs: O, inputs: I, debouncer: D) -> Self {
+        assert!(
+            Self::OUTPUT_LINE_NUM <= MAX_MATRIX_LINES && Self::INPUT_LINE_NUM <= MAX_MATRIX_LINES,
+            "Too many lines of the matrix"
+        );
+        Self {
+            outputs,
+            inputs,
+            debouncer,
+            key_states: [[KeyState::new(); ROW]; COL],
+            scan_pos: (0, 0),
+            #[cfg(feature = "async_matrix")]
+            rescan_needed: false,
+        }
+    }
+}
+
+impl<
+    O: OutputLines,
+    I: InputLines,
+    D: DebouncerTrait<ROW, COL>,
+    const ROW: usize,
+    const COL: usize,
+    const COL2ROW: bool,
+> InputDevice for LineMatrix<O, I, D, ROW, COL, COL2ROW>
+{
+    async fn read_event(&mut self) -> Event {
+        loop {
+            let (out_idx_start, in_idx_start) = self.scan_pos;
+
+            for out_idx in out_idx_start..Self::OUTPUT_LINE_NUM {
+                self.outputs.write(1 << out_idx).await;
+                Timer::after_micros(1).await;
+                let input_lines = self.inputs.read().await;
+
+                let in_start = if out_idx == out_idx_start { in_idx_start } else { 0 };
+                for in_idx in in_start..Self::INPUT_LINE_NUM {
+                    let in_state = (input_lines >> in_idx) & 1 == 1;
+                    let (row_idx, col_idx) = if COL2ROW { (in_idx, out_idx) } else { (out_idx, in_idx) };
+                    let debounce_state = self.debouncer.detect_change_with_debounce(
+                        row_idx,
+                        col_idx,
+                        in_state,
+                        &self.key_states[col_idx][row_idx],
+                    );
+                    if let DebounceState::Debounced = debounce_state {
+                        self.key_states[col_idx][row_idx].toggle_pressed();
+                        self.scan_pos = (out_idx, in_idx);
+                        #[cfg(feature = "async_matrix")]
+                        {
+                            self.rescan_needed = true;
+                        }
+                        return Event::Key(
+                            KeyboardEvent::key(row_idx as u8, col_idx as u8, self.key_states[col_idx][row_idx].pressed)
+                                .with_timestamp(Instant::now()),
+                        );
+                    }
+
+                    #[cfg(feature = "async_matrix")]
+                    if self.key_states[col_idx][row_idx].pressed {
+                        self.rescan_needed = true;
+                    }
+                }
+            }
+            self.outputs.write(0).await;
+
+            #[cfg(feature = "async_matrix")]
+            {
+                if !self.rescan_needed {
+                    self.wait_for_key().await;
+                }
+                self.rescan_needed = false;
+            }
+            self.scan_pos = (0, 0);
+            #[cfg(feature = "instrumentation")]
+            crate::instrumentation::record_scan();
+        }
+    }
+}
+
+impl<
+    O: OutputLines,
+    I: InputLines,
+    D: DebouncerTrait<ROW, COL>,
+    const ROW: usize,
+    const COL: usize,
+    const COL2ROW: bool,
+> MatrixTrait<ROW, COL> for LineMatrix<O, I, D, ROW, COL, COL2ROW>
+{
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_key(&mut self) {
+        // Select all output lines, then wait for any input line
+        self.outputs.write(u64::MAX >> (64 - Self::OUTPUT_LINE_NUM)).await;
+        self.inputs.wait_for_any().await;
+        self.outputs.write(0).await;
+    }
+}
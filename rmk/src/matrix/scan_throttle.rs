@@ -0,0 +1,167 @@
+//! Adaptive scan rate of the matrix.
+//!
+//! The matrix is scanned at full rate while typing. After no key changes for a while, it's scanned at a reduced rate,
+//! and after a longer idle, it sleeps: with `async_matrix` it waits for a key press interrupt,
+//! otherwise it's scanned at the lowest rate. Entering and leaving the sleep publish [`ControllerEvent::Sleep`].
+//!
+//! [`ControllerEvent::Sleep`]: crate::event::ControllerEvent::Sleep
+
+use embassy_time::{Duration, Instant};
+
+#[cfg(feature = "controller")]
+use crate::channel::send_controller_event_new;
+#[cfg(feature = "controller")]
+use crate::event::ControllerEvent;
+
+/// Config of the adaptive scan rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScanThrottleConfig {
+    /// After no key changes for this time, the matrix is scanned every `idle_interval`
+    pub idle_timeout: Duration,
+    /// Interval between two scans when idle
+    pub idle_interval: Duration,
+    /// After no key changes for this time, the matrix sleeps, should be longer than `idle_timeout`
+    pub sleep_timeout: Duration,
+    /// Interval between two scans when sleeping, used when the matrix can't be woken up by interrupts
+    pub sleep_interval: Duration,
+}
+
+impl Default for ScanThrottleConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(5),
+            idle_interval: Duration::from_millis(10),
+            sleep_timeout: Duration::from_secs(60),
+            sleep_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+/// What the matrix does after a full scan
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ScanPause {
+    /// Scan again immediately
+    None,
+    /// Wait for the given time before the next scan
+    Delay(Duration),
+    /// Sleep until a key is pressed
+    Sleep,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScanState {
+    Active,
+    Idle,
+    Sleep,
+}
+
+/// Scheduler of the matrix scans
+pub(crate) struct ScanThrottle {
+    config: ScanThrottleConfig,
+    state: ScanState,
+    /// Time of the last key change, or the last scan with keys held
+    last_active: Instant,
+}
+
+impl ScanThrottle {
+    pub(crate) fn new(config: ScanThrottleConfig) -> Self {
+        Self {
+            config,
+            state: ScanState::Active,
+            last_active: Instant::now(),
+        }
+    }
+
+    /// Record a key change or a held key, the matrix is scanned at full rate again
+    pub(crate) fn on_activity(&mut self) {
+        self.on_activity_at(Instant::now());
+    }
+
+    fn on_activity_at(&mut self, now: Instant) {
+        self.last_active = now;
+        if self.state == ScanState::Sleep {
+            debug!("Matrix wakes up");
+            #[cfg(feature = "controller")]
+            send_controller_event_new(ControllerEvent::Sleep(false));
+        }
+        self.state = ScanState::Active;
+    }
+
+    /// Decide the pause after a full scan, `key_held` is whether any key is pressed
+    pub(crate) fn after_scan(&mut self, key_held: bool) -> ScanPause {
+        self.after_scan_at(key_held, Instant::now())
+    }
+
+    fn after_scan_at(&mut self, key_held: bool, now: Instant) -> ScanPause {
+        if key_held {
+            self.on_activity_at(now);
+            return ScanPause::None;
+        }
+        let idle = now.saturating_duration_since(self.last_active);
+        if idle >= self.config.sleep_timeout {
+            if self.state != ScanState::Sleep {
+                debug!("Matrix sleeps after idle for {}ms", idle.as_millis());
+                self.state = ScanState::Sleep;
+                #[cfg(feature = "storage")]
+                crate::storage::request_flush();
+                #[cfg(feature = "controller")]
+                send_controller_event_new(ControllerEvent::Sleep(true));
+            }
+            ScanPause::Sleep
+        } else if idle >= self.config.idle_timeout {
+            self.state = ScanState::Idle;
+            ScanPause::Delay(self.config.idle_interval)
+        } else {
+            ScanPause::None
+        }
+    }
+
+    /// Interval between two scans when sleeping
+    pub(crate) fn sleep_interval(&self) -> Duration {
+        self.config.sleep_interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_throttle() {
+        let start = Instant::from_secs(100);
+        let mut throttle = ScanThrottle::new(ScanThrottleConfig::default());
+        throttle.on_activity_at(start);
+
+        assert_eq!(
+            throttle.after_scan_at(false, start + Duration::from_secs(1)),
+            ScanPause::None
+        );
+        assert_eq!(
+            throttle.after_scan_at(false, start + Duration::from_secs(5)),
+            ScanPause::Delay(Duration::from_millis(10))
+        );
+        assert_eq!(
+            throttle.after_scan_at(false, start + Duration::from_secs(60)),
+            ScanPause::Sleep
+        );
+        assert_eq!(throttle.state, ScanState::Sleep);
+
+        // A held key keeps the matrix active
+        let wake = start + Duration::from_secs(100);
+        throttle.on_activity_at(wake);
+        assert_eq!(throttle.state, ScanState::Active);
+        assert_eq!(
+            throttle.after_scan_at(true, wake + Duration::from_secs(10)),
+            ScanPause::None
+        );
+        assert_eq!(
+            throttle.after_scan_at(false, wake + Duration::from_secs(11)),
+            ScanPause::None
+        );
+        assert_eq!(
+            throttle.after_scan_at(false, wake + Duration::from_secs(16)),
+            ScanPause::Delay(Duration::from_millis(10))
+        );
+    }
+}
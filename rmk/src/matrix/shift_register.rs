@@ -0,0 +1,310 @@
+//! Matrix scanned through shift registers.
+//!
+//! The output lines of the matrix can be driven by chained 74HC595s, and the input lines can be read by chained 74HC165s,
+//! so that a large matrix only needs a few pins. They are scanned by [`LineMatrix`](crate::matrix::lines::LineMatrix),
+//! and either side can also use native GPIO pins.
+//!
+//! The shift registers are accessed by an `SpiBus`, which can be a hardware SPI, or a bit-banged
+//! [`BitBangSpiBus`](crate::driver::bitbang_spi::BitBangSpiBus). Note that 74HC165 shifts out the next bit on the rising edge,
+//! so the bus should sample on the falling edge, i.e. SPI mode 2 or `BitBangSpiBus::with_phase(Phase::CaptureOnFirstTransition)`.
+//!
+//! Shift registers can't wake the MCU, so with `async_matrix` the input lines are polled when no key is pressed.
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::spi::SpiBus;
+
+use crate::matrix::lines::{InputLines, MAX_MATRIX_LINES, OutputLines};
+
+/// Output lines driven by chained 74HC595s.
+///
+/// Line `n` is the output `Q(n % 8)` of the `n / 8`th register, the first register is the one connected to the MCU.
+pub struct ShiftRegisterOutputs<SPI: SpiBus, LATCH: OutputPin> {
+    spi: SPI,
+    /// Storage register clock (RCLK) pin, the shifted data is latched on its rising edge
+    latch: LATCH,
+    /// Number of the chained registers
+    registers: usize,
+}
+
+impl<SPI: SpiBus, LATCH: OutputPin> ShiftRegisterOutputs<SPI, LATCH> {
+    /// Create the output lines, `lines` is the number of used output lines
+    pub fn new(spi: SPI, mut latch: LATCH, lines: usize) -> Self {
+        assert!(lines <= MAX_MATRIX_LINES, "Too many lines of the shift registers");
+        latch.set_low().ok();
+        Self {
+            spi,
+            latch,
+            registers: lines.div_ceil(8),
+        }
+    }
+}
+
+impl<SPI: SpiBus, LATCH: OutputPin> OutputLines for ShiftRegisterOutputs<SPI, LATCH> {
+    async fn write(&mut self, lines: u64) {
+        // The data of the last register is shifted out first
+        let mut data = [0u8; MAX_MATRIX_LINES / 8];
+        for (reg, byte) in data[..self.registers].iter_mut().rev().enumerate() {
+            *byte = (lines >> (reg * 8)) as u8;
+        }
+        if self.spi.write(&data[..self.registers]).await.is_err() || self.spi.flush().await.is_err() {
+            error!("Failed to write shift registers");
+        }
+        self.latch.set_high().ok();
+        self.latch.set_low().ok();
+    }
+}
+
+/// Input lines read by chained 74HC165s.
+///
+/// Line `n` is the input `D(n % 8)` of the `n / 8`th register, the first register is the one connected to the MCU.
+pub struct ShiftRegisterInputs<SPI: SpiBus, LOAD: OutputPin> {
+    spi: SPI,
+    /// Parallel load (SH/LD) pin, the inputs are loaded when it's low
+    load: LOAD,
+    /// Number of the chained registers
+    registers: usize,
+}
+
+impl<SPI: SpiBus, LOAD: OutputPin> ShiftRegisterInputs<SPI, LOAD> {
+    /// Create the input lines, `lines` is the number of used input lines
+    pub fn new(spi: SPI, mut load: LOAD, lines: usize) -> Self {
+        assert!(lines <= MAX_MATRIX_LINES, "Too many lines of the shift registers");
+        load.set_high().ok();
+        Self {
+            spi,
+            load,
+            registers: lines.div_ceil(8),
+        }
+    }
+}
+
+impl<SPI: SpiBus, LOAD: OutputPin> InputLines for ShiftRegisterInputs<SPI, LOAD> {
+    async fn read(&mut self) -> u64 {
+        self.load.set_low().ok();
+        self.load.set_high().ok();
+        // The data of the first register is shifted out first
+        let mut data = [0u8; MAX_MATRIX_LINES / 8];
+        if self.spi.read(&mut data[..self.registers]).await.is_err() {
+            error!("Failed to read shift registers");
+            return 0;
+        }
+        data[..self.registers]
+            .iter()
+            .enumerate()
+            .fold(0, |lines, (reg, byte)| lines | ((*byte as u64) << (reg * 8)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embedded_hal::digital::{ErrorType as PinErrorType, InputPin};
+    use embedded_hal::spi::ErrorType as SpiErrorType;
+
+    use super::*;
+    use crate::debounce::NoDebounce;
+    use crate::event::{Event, KeyboardEvent};
+    use crate::input_device::InputDevice;
+    use crate::matrix::lines::{LineMatrix, PinInputLines};
+
+    /// A 3x10 col2row matrix, columns are driven by two 74HC595s and rows are read by a 74HC165
+    struct MockBoard {
+        /// Pressed keys, (row, col)
+        pressed: heapless::Vec<(usize, usize), 8>,
+        /// Content of the 74HC595 shift registers
+        shifted: u64,
+        /// Latched column outputs
+        cols: u64,
+        /// Bits to be shifted out of the 74HC165s, in order
+        stream: heapless::Deque<bool, 64>,
+    }
+
+    impl MockBoard {
+        fn new() -> Self {
+            Self {
+                pressed: heapless::Vec::new(),
+                shifted: 0,
+                cols: 0,
+                stream: heapless::Deque::new(),
+            }
+        }
+
+        fn rows(&self) -> u64 {
+            self.pressed
+                .iter()
+                .filter(|(_, col)| (self.cols >> col) & 1 == 1)
+                .fold(0, |rows, (row, _)| rows | (1 << row))
+        }
+    }
+
+    struct Mock595<'a>(&'a RefCell<MockBoard>);
+    struct MockLatch<'a>(&'a RefCell<MockBoard>);
+    struct Mock165<'a>(&'a RefCell<MockBoard>);
+    struct MockLoad<'a>(&'a RefCell<MockBoard>);
+    struct MockPin<'a>(&'a RefCell<MockBoard>, usize);
+
+    impl SpiErrorType for Mock595<'_> {
+        type Error = Infallible;
+    }
+
+    impl SpiBus for Mock595<'_> {
+        async fn read(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+            let mut board = self.0.borrow_mut();
+            for word in words {
+                for bit in (0..8).rev() {
+                    // Two chained registers
+                    board.shifted = ((board.shifted << 1) | ((*word as u64 >> bit) & 1)) & 0xFFFF;
+                }
+            }
+            Ok(())
+        }
+
+        async fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        async fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl SpiErrorType for Mock165<'_> {
+        type Error = Infallible;
+    }
+
+    impl SpiBus for Mock165<'_> {
+        async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+            let mut board = self.0.borrow_mut();
+            for word in words.iter_mut() {
+                *word = 0;
+                for bit in (0..8).rev() {
+                    // The serial input of the last register is grounded
+                    if board.stream.pop_front().unwrap_or(false) {
+                        *word |= 1 << bit;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        async fn write(&mut self, _words: &[u8]) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        async fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        async fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl PinErrorType for MockLatch<'_> {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockLatch<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            let mut board = self.0.borrow_mut();
+            board.cols = board.shifted;
+            Ok(())
+        }
+    }
+
+    impl PinErrorType for MockLoad<'_> {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockLoad<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            let mut board = self.0.borrow_mut();
+            let rows = board.rows();
+            // One register, D7 is shifted out first
+            board.stream.clear();
+            for bit in (0..8).rev() {
+                board.stream.push_back((rows >> bit) & 1 == 1).unwrap();
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl PinErrorType for MockPin<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockPin<'_> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok((self.0.borrow().rows() >> self.1) & 1 == 1)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    fn assert_key(event: Event, row: u8, col: u8, pressed: bool) {
+        match event {
+            Event::Key(event) => {
+                assert_eq!(event.pos, KeyboardEvent::key(row, col, pressed).pos);
+                assert_eq!(event.pressed, pressed);
+            }
+            _ => panic!("Unexpected event"),
+        }
+    }
+
+    #[test]
+    fn test_shift_register_matrix() {
+        let board = RefCell::new(MockBoard::new());
+        let outputs = ShiftRegisterOutputs::new(Mock595(&board), MockLatch(&board), 10);
+        let inputs = ShiftRegisterInputs::new(Mock165(&board), MockLoad(&board), 3);
+        let mut matrix = LineMatrix::<_, _, _, 3, 10, true>::new(outputs, inputs, NoDebounce);
+
+        for (row, col) in [(2, 9), (0, 3), (1, 8)] {
+            board.borrow_mut().pressed.push((row, col)).unwrap();
+            assert_key(block_on(matrix.read_event()), row as u8, col as u8, true);
+        }
+        board.borrow_mut().pressed.retain(|key| *key != (0, 3));
+        assert_key(block_on(matrix.read_event()), 0, 3, false);
+    }
+
+    #[test]
+    fn test_shift_register_outputs_with_native_inputs() {
+        let board = RefCell::new(MockBoard::new());
+        let outputs = ShiftRegisterOutputs::new(Mock595(&board), MockLatch(&board), 10);
+        let inputs = PinInputLines([MockPin(&board, 0), MockPin(&board, 1), MockPin(&board, 2)]);
+        let mut matrix = LineMatrix::<_, _, _, 3, 10, true>::new(outputs, inputs, NoDebounce);
+
+        board.borrow_mut().pressed.push((1, 0)).unwrap();
+        board.borrow_mut().pressed.push((2, 7)).unwrap();
+        assert_key(block_on(matrix.read_event()), 1, 0, true);
+        assert_key(block_on(matrix.read_event()), 2, 7, true);
+        // Scanning continues from the last reported key
+        board.borrow_mut().pressed.clear();
+        assert_key(block_on(matrix.read_event()), 2, 7, false);
+        assert_key(block_on(matrix.read_event()), 1, 0, false);
+    }
+}
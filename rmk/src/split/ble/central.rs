@@ -7,7 +7,6 @@ use embassy_futures::select::{Either, Either3, select, select3};
 use embassy_sync::mutex::Mutex;
 use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Timer, with_timeout};
-use embedded_storage_async::nor_flash::NorFlash;
 use heapless::{Vec, VecView};
 use trouble_host::prelude::*;
 #[cfg(feature = "controller")]
@@ -22,6 +21,7 @@ use crate::channel::FLASH_CHANNEL;
 use crate::split::ble::PeerAddress;
 use crate::split::driver::{PeripheralManager, SplitDriverError, SplitReader, SplitWriter};
 use crate::split::{SPLIT_MESSAGE_MAX_SIZE, SplitMessage};
+use crate::storage::backend::StorageBackend;
 use crate::storage::{FlashOperationMessage, Storage};
 use crate::{CONNECTION_STATE, SPLIT_CENTRAL_SLEEP_TIMEOUT_SECONDS};
 
@@ -141,13 +141,13 @@ pub async fn scan_peripherals<
 /// * `storage` - The storage to read peripheral addresses from
 pub async fn read_peripheral_addresses<
     const PERI_NUM: usize,
-    F: NorFlash,
+    B: StorageBackend,
     const ROW: usize,
     const COL: usize,
     const NUM_LAYER: usize,
     const NUM_ENCODER: usize,
 >(
-    storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
 ) -> RefCell<Vec<Option<[u8; 6]>, PERI_NUM>> {
     let mut peripheral_addresses: heapless::Vec<Option<[u8; 6]>, PERI_NUM> = heapless::Vec::new();
     for id in 0..PERI_NUM {
@@ -579,6 +579,7 @@ async fn sleep_manager_task<
             // Update connection parameters
             update_conn_params(stack, conn, &conn_params).await;
             SLEEPING_STATE.store(true, Ordering::Release);
+            crate::storage::request_flush();
             #[cfg(feature = "controller")]
             send_controller_event(&mut controller_pub, ControllerEvent::Sleep(true));
         } else {
@@ -5,7 +5,7 @@ use embassy_futures::select::select;
 use embassy_time::{Duration, Timer, with_timeout};
 use trouble_host::prelude::*;
 #[cfg(feature = "storage")]
-use {super::PeerAddress, crate::storage::Storage, embedded_storage_async::nor_flash::NorFlash};
+use {super::PeerAddress, crate::storage::Storage, crate::storage::backend::StorageBackend};
 
 use crate::CONNECTION_STATE;
 use crate::channel::KEY_EVENT_CHANNEL;
@@ -135,7 +135,7 @@ impl<'stack, 'server, 'c, P: PacketPool> SplitWriter for BleSplitPeripheralDrive
 pub async fn initialize_nrf_ble_split_peripheral_and_run<
     'stack,
     C: Controller + ControllerCmdAsync<LeSetPhy>,
-    F: NorFlash,
+    B: StorageBackend,
     const ROW: usize,
     const COL: usize,
     const NUM_LAYER: usize,
@@ -143,7 +143,7 @@ pub async fn initialize_nrf_ble_split_peripheral_and_run<
 >(
     id: usize,
     stack: &'stack Stack<'stack, C, DefaultPacketPool>,
-    storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
 ) {
     #[cfg(feature = "controller")]
     let mut controller_pub = unwrap!(CONTROLLER_CHANNEL.publisher());
@@ -196,7 +196,14 @@ pub async fn initialize_nrf_ble_split_peripheral_and_run<
                         }
                     }
                     // Start run peripheral service
-                    select(storage.run(), peripheral.run()).await;
+                    select(
+                        storage.run(
+                            #[cfg(feature = "host")]
+                            None,
+                        ),
+                        peripheral.run(),
+                    )
+                    .await;
                     info!("Disconnected from the central");
                 }
                 Err(BleHostError::BleHost(Error::Timeout)) => {
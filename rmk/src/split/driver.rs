@@ -10,7 +10,7 @@ use {crate::channel::FLASH_CHANNEL, crate::split::ble::PeerAddress, crate::stora
 use super::SplitMessage;
 use crate::CONNECTION_STATE;
 use crate::channel::{CONTROLLER_CHANNEL, EVENT_CHANNEL, KEY_EVENT_CHANNEL};
-use crate::event::{ControllerEvent, Event, KeyboardEvent, KeyboardEventPos};
+use crate::event::{ControllerEvent, Event, KeyPos, KeyboardEventPos};
 use crate::input_device::InputDevice;
 
 #[derive(Debug, Clone, Copy)]
@@ -52,13 +52,56 @@ pub(crate) struct PeripheralManager<
     transceiver: T,
     /// Peripheral id
     id: usize,
+    /// Offset between the peripheral's clock and the central's clock
+    clock: ClockOffset,
+}
+
+/// Estimates the offset between a peripheral's clock and the central's clock.
+///
+/// Each timestamped event gives a sample of `central time - peripheral time`, which is the clock offset plus the link latency.
+/// The minimum sample of a sync period is the best estimate of the offset,
+/// the estimate is refreshed every sync period to follow the clock drift.
+#[derive(Default)]
+pub(crate) struct ClockOffset {
+    /// Current estimate, in microseconds
+    offset: Option<i64>,
+    /// Minimum sample in current sync period
+    period_min: Option<i64>,
+}
+
+impl ClockOffset {
+    /// Convert a peripheral timestamp to the central's clock, `now` is the central time when the event is received
+    pub(crate) fn adjust(&mut self, remote: u64, now: u64) -> u64 {
+        let sample = now as i64 - remote as i64;
+        self.period_min = Some(self.period_min.map_or(sample, |m| m.min(sample)));
+        let offset = match self.offset {
+            Some(offset) if offset <= sample => offset,
+            _ => {
+                // The event arrived faster than expected, the estimate was too large
+                self.offset = Some(sample);
+                sample
+            }
+        };
+        (remote as i64 + offset).max(0) as u64
+    }
+
+    /// Start a new sync period, the minimum sample of the last period becomes the estimate
+    pub(crate) fn sync(&mut self) {
+        if let Some(min) = self.period_min.take() {
+            self.offset = Some(min);
+        }
+    }
 }
 
 impl<const ROW: usize, const COL: usize, const ROW_OFFSET: usize, const COL_OFFSET: usize, T: SplitReader + SplitWriter>
     PeripheralManager<ROW, COL, ROW_OFFSET, COL_OFFSET, T>
 {
     pub(crate) fn new(transceiver: T, id: usize) -> Self {
-        Self { transceiver, id }
+        Self {
+            transceiver,
+            id,
+            clock: ClockOffset::default(),
+        }
     }
 
     /// Run the manager.
@@ -166,6 +209,7 @@ impl<const ROW: usize, const COL: usize, const ROW_OFFSET: usize, const COL_OFFS
                         }
                     }
                     last_sync_time = Instant::now();
+                    self.clock.sync();
                 }
             }
         }
@@ -178,7 +222,11 @@ impl<const ROW: usize, const COL: usize, const ROW_OFFSET: usize, const COL_OFFS
     async fn read_event(&mut self) -> Event {
         loop {
             match self.transceiver.read().await {
-                Ok(SplitMessage::Key(e)) => {
+                Ok(SplitMessage::Key(mut e)) => {
+                    // Convert the timestamp to the central's clock
+                    if let Some(timestamp) = e.timestamp {
+                        e.timestamp = Some(self.clock.adjust(timestamp, Instant::now().as_micros()));
+                    }
                     match e.pos {
                         KeyboardEventPos::Key(key_pos) => {
                             // Verify the row/col
@@ -189,12 +237,11 @@ impl<const ROW: usize, const COL: usize, const ROW_OFFSET: usize, const COL_OFFS
 
                             if CONNECTION_STATE.load(core::sync::atomic::Ordering::Acquire) {
                                 // Only when the connection is established, send the key event.
-                                let adjusted_key_event = KeyboardEvent::key(
-                                    key_pos.row + ROW_OFFSET as u8,
-                                    key_pos.col + COL_OFFSET as u8,
-                                    e.pressed,
-                                );
-                                return Event::Key(adjusted_key_event);
+                                e.pos = KeyboardEventPos::Key(KeyPos {
+                                    row: key_pos.row + ROW_OFFSET as u8,
+                                    col: key_pos.col + COL_OFFSET as u8,
+                                });
+                                return Event::Key(e);
                             } else {
                                 warn!(
                                     "Key event from peripheral is ignored because the connection is not established."
@@ -227,3 +274,25 @@ impl<const ROW: usize, const COL: usize, const ROW_OFFSET: usize, const COL_OFFS
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_offset() {
+        let mut clock = ClockOffset::default();
+        // The peripheral's clock is 1000us behind, latency is 300us
+        assert_eq!(clock.adjust(5_000, 6_300), 6_300);
+        // Faster message, the estimate is corrected
+        assert_eq!(clock.adjust(6_000, 7_100), 7_100);
+        // Slower message keeps the estimate
+        assert_eq!(clock.adjust(7_000, 8_500), 8_100);
+
+        // The peripheral's clock drifts, the estimate is updated after sync
+        clock.sync();
+        assert_eq!(clock.adjust(10_000, 11_200), 11_100);
+        clock.sync();
+        assert_eq!(clock.adjust(12_000, 13_200), 13_200);
+    }
+}
@@ -6,7 +6,7 @@ use embedded_io_async::{Read, Write};
 #[cfg(all(feature = "_ble", feature = "storage"))]
 use {super::ble::PeerAddress, crate::channel::FLASH_CHANNEL};
 #[cfg(feature = "_ble")]
-use {crate::storage::Storage, embedded_storage_async::nor_flash::NorFlash, trouble_host::prelude::*};
+use {crate::storage::Storage, crate::storage::backend::StorageBackend, trouble_host::prelude::*};
 
 use super::SplitMessage;
 use super::driver::{SplitReader, SplitWriter};
@@ -33,7 +33,7 @@ pub async fn run_rmk_split_peripheral<
     'a,
     #[cfg(feature = "_ble")] C: Controller + ControllerCmdAsync<LeSetPhy>,
     #[cfg(not(feature = "_ble"))] S: Write + Read,
-    #[cfg(feature = "_ble")] F: NorFlash,
+    #[cfg(feature = "_ble")] B: StorageBackend,
     #[cfg(feature = "_ble")] const ROW: usize,
     #[cfg(feature = "_ble")] const COL: usize,
     #[cfg(feature = "_ble")] const NUM_LAYER: usize,
@@ -41,7 +41,7 @@ pub async fn run_rmk_split_peripheral<
 >(
     #[cfg(feature = "_ble")] id: usize,
     #[cfg(feature = "_ble")] stack: &'a Stack<'a, C, DefaultPacketPool>,
-    #[cfg(feature = "_ble")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    #[cfg(feature = "_ble")] storage: &mut Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>,
     #[cfg(not(feature = "_ble"))] serial: S,
 ) {
     #[cfg(not(feature = "_ble"))]
@@ -0,0 +1,349 @@
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+use sequential_storage::Error as SSError;
+use sequential_storage::cache::{KeyCacheImpl, KeyPointerCache, NoCache};
+use sequential_storage::map::{SerializationError, Value, fetch_all_items, fetch_item, store_item};
+
+use super::{StorageBackend, StorageError};
+use crate::config::StorageConfig;
+use crate::storage::get_buffer_size;
+use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, MORSE_MAX_NUM};
+
+/// Default number of sectors whose states and item pointers are cached
+pub const DEFAULT_CACHE_PAGES: usize = 32;
+
+/// Default number of keys whose item locations are cached, it covers 32 keymap keys
+pub const DEFAULT_CACHE_KEYS: usize = cache_keys(4, 8);
+
+/// Number of keys whose item locations are cached for a keymap with the given size.
+///
+/// It covers the configs, combos, forks and morses, and the keys of a layer, which are edited together usually.
+/// The keymap is read with a single scan at boot, so the keys of all layers don't have to be cached.
+pub const fn cache_keys(row: usize, col: usize) -> usize {
+    16 + COMBO_MAX_NUM + FORK_MAX_NUM + MORSE_MAX_NUM + row * col
+}
+
+/// Run an operation of `sequential-storage` with the cache of the backend, or without a cache if the range has more
+/// sectors than the cache
+macro_rules! with_cache {
+    ($backend:ident, $cache:ident => $op:expr) => {
+        if $backend.cached {
+            let $cache = &mut $backend.cache;
+            $op.await
+        } else {
+            let $cache = &mut NoCache::new();
+            $op.await
+        }
+    };
+}
+
+/// Backend which saves the items to a range of a NOR flash by `sequential-storage`.
+///
+/// The writes are spread over all sectors of the range, and the erased sectors are counted.
+///
+/// The states of `CACHE_PAGES` sectors and the item locations of `CACHE_KEYS` keys are cached. `rmk-macro` sets them to
+/// the number of sectors and [`cache_keys`] of the keymap. If the range has more sectors than `CACHE_PAGES`, the cache
+/// isn't used.
+pub struct FlashBackend<
+    F: NorFlash,
+    const CACHE_PAGES: usize = DEFAULT_CACHE_PAGES,
+    const CACHE_KEYS: usize = DEFAULT_CACHE_KEYS,
+> {
+    pub(crate) flash: EraseCountingFlash<F>,
+    range: Range<u32>,
+    /// Cache of the page states and the item locations, it must be used by every operation on the flash, and reset
+    /// after erasing the flash
+    cache: KeyPointerCache<CACHE_PAGES, u32, CACHE_KEYS>,
+    /// Whether the cache covers all sectors of the range
+    pub(crate) cached: bool,
+    buffer: [u8; get_buffer_size()],
+}
+
+impl<F: NorFlash, const CACHE_PAGES: usize, const CACHE_KEYS: usize> FlashBackend<F, CACHE_PAGES, CACHE_KEYS> {
+    /// Create the backend on the internal flash of the microcontroller.
+    ///
+    /// If `start_addr` of the config is 0, the last `num_sectors` sectors of the flash are used,
+    /// or the sectors starting from 0x0006_0000 for nRF52.
+    pub fn new(flash: F, storage_config: &StorageConfig) -> Self {
+        // Check storage setting
+        assert!(
+            storage_config.num_sectors >= 2,
+            "Number of used sector for storage must larger than 1"
+        );
+
+        #[cfg(feature = "_nrf_ble")]
+        let start_addr = if storage_config.start_addr == 0 {
+            0x0006_0000
+        } else {
+            storage_config.start_addr
+        };
+
+        #[cfg(not(feature = "_nrf_ble"))]
+        let start_addr = storage_config.start_addr;
+
+        info!(
+            "Flash capacity {} KB, RMK use {} KB({} sectors) starting from 0x{:X} as storage",
+            flash.capacity() / 1024,
+            (F::ERASE_SIZE * storage_config.num_sectors as usize) / 1024,
+            storage_config.num_sectors,
+            storage_config.start_addr,
+        );
+
+        let range = if start_addr == 0 {
+            (flash.capacity() - storage_config.num_sectors as usize * F::ERASE_SIZE) as u32..flash.capacity() as u32
+        } else {
+            start_addr as u32..(start_addr + storage_config.num_sectors as usize * F::ERASE_SIZE) as u32
+        };
+        Self::with_range(flash, range)
+    }
+
+    /// Create the backend on the given range of the flash, e.g. a range of an external SPI NOR flash.
+    ///
+    /// The range must be aligned to sectors, and it must contain at least 2 sectors.
+    pub fn with_range(flash: F, range: Range<u32>) -> Self {
+        assert!(
+            range.start.is_multiple_of(F::ERASE_SIZE as u32) && range.end.is_multiple_of(F::ERASE_SIZE as u32),
+            "Storage's start addr MUST BE a multiplier of sector size"
+        );
+        let num_sectors = range.len() / F::ERASE_SIZE;
+        assert!(num_sectors >= 2, "Number of used sector for storage must larger than 1");
+        let cached = num_sectors <= CACHE_PAGES;
+        if !cached {
+            warn!(
+                "Storage uses {} sectors, but only {} sectors can be cached, the cache is disabled",
+                num_sectors, CACHE_PAGES
+            );
+        }
+
+        Self {
+            flash: EraseCountingFlash::new(flash),
+            range,
+            cache: KeyPointerCache::new(),
+            cached,
+            buffer: [0; get_buffer_size()],
+        }
+    }
+
+    /// Take the flash back, e.g. to open it again
+    pub fn into_inner(self) -> F {
+        self.flash.flash
+    }
+}
+
+/// Flash which the storage is created on by [`initialize_keymap_and_storage`].
+///
+/// A NOR flash is used with the default cache sizes, or a [`FlashBackend`] can be passed to choose the cache sizes,
+/// which is done by `rmk-macro`.
+///
+/// [`initialize_keymap_and_storage`]: crate::initialize_keymap_and_storage
+pub trait IntoFlashBackend {
+    type Backend: StorageBackend;
+
+    fn into_backend(self, storage_config: &StorageConfig) -> Self::Backend;
+}
+
+impl<F: NorFlash> IntoFlashBackend for F {
+    type Backend = FlashBackend<F>;
+
+    fn into_backend(self, storage_config: &StorageConfig) -> Self::Backend {
+        FlashBackend::new(self, storage_config)
+    }
+}
+
+impl<F: NorFlash, const CACHE_PAGES: usize, const CACHE_KEYS: usize> IntoFlashBackend
+    for FlashBackend<F, CACHE_PAGES, CACHE_KEYS>
+{
+    type Backend = Self;
+
+    fn into_backend(self, _storage_config: &StorageConfig) -> Self::Backend {
+        self
+    }
+}
+
+impl<F: NorFlash, const CACHE_PAGES: usize, const CACHE_KEYS: usize> StorageBackend
+    for FlashBackend<F, CACHE_PAGES, CACHE_KEYS>
+{
+    fn capacity(&self) -> u32 {
+        self.range.end - self.range.start
+    }
+
+    fn erase_cycles(&self) -> u32 {
+        self.flash.erase_cycles
+    }
+
+    /// Count the erased bytes at the end of each sector.
+    ///
+    /// Items are appended to the sectors, and a full sector is closed by a marker at its end, so the erased bytes
+    /// after the last written byte of a sector are free.
+    async fn free_space(&mut self) -> Result<u32, StorageError> {
+        let read_size = F::READ_SIZE.max(1);
+        let chunk_size = (self.buffer.len() / read_size * read_size).min(F::ERASE_SIZE) as u32;
+        let mut free_space = 0;
+        for sector in self.range.clone().step_by(F::ERASE_SIZE.max(1)) {
+            // Read the sector backwards until a written byte is found
+            let mut end = sector + F::ERASE_SIZE as u32;
+            while end > sector {
+                let start = end.saturating_sub(chunk_size).max(sector);
+                let chunk = &mut self.buffer[..(end - start) as usize];
+                self.flash
+                    .read(start, chunk)
+                    .await
+                    .map_err(|e| storage_error::<F>(SSError::Storage { value: e }))?;
+                if let Some(last) = chunk.iter().rposition(|b| *b != 0xFF) {
+                    free_space += end - start - last as u32 - 1;
+                    break;
+                }
+                free_space += end - start;
+                end = start;
+            }
+        }
+        Ok(free_space)
+    }
+
+    async fn fetch<V: for<'d> Value<'d>>(&mut self, key: u32) -> Result<Option<V>, StorageError> {
+        with_cache!(self, cache => fetch_item::<u32, Removable<V>, _>(
+            &mut self.flash,
+            self.range.clone(),
+            cache,
+            &mut self.buffer,
+            &key,
+        ))
+        .map(|item| item.and_then(|item| item.0))
+        .map_err(storage_error::<F>)
+    }
+
+    async fn store<V: for<'d> Value<'d>>(&mut self, key: u32, value: &V) -> Result<(), StorageError> {
+        with_cache!(self, cache => store_item::<u32, V, _>(
+            &mut self.flash,
+            self.range.clone(),
+            cache,
+            &mut self.buffer,
+            &key,
+            value,
+        ))
+        .map_err(storage_error::<F>)
+    }
+
+    /// `remove_item` of `sequential-storage` requires a `MultiwriteNorFlash`, so the item is replaced by an empty one,
+    /// which is read as `None`. The empty item is dropped when its sector is erased.
+    async fn remove(&mut self, key: u32) -> Result<(), StorageError> {
+        self.store(key, &Removable::<u8>(None)).await
+    }
+
+    async fn for_each<V: for<'d> Value<'d>>(&mut self, f: impl FnMut(u32, V)) -> Result<(), StorageError> {
+        with_cache!(self, cache => visit_items::<_, V>(&mut self.flash, self.range.clone(), cache, &mut self.buffer, f))
+    }
+
+    async fn erase_all(&mut self) -> Result<(), StorageError> {
+        self.cache = KeyPointerCache::new();
+        sequential_storage::erase_all(&mut self.flash, self.range.clone())
+            .await
+            .map_err(storage_error::<F>)
+    }
+}
+
+/// Visit all items in the range, the removed items and the items which can't be deserialized are skipped
+async fn visit_items<F: NorFlash, V: for<'d> Value<'d>>(
+    flash: &mut EraseCountingFlash<F>,
+    range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<u32>,
+    buffer: &mut [u8],
+    mut f: impl FnMut(u32, V),
+) -> Result<(), StorageError> {
+    let mut iterator = fetch_all_items::<u32, _, _>(flash, range, cache, buffer)
+        .await
+        .map_err(storage_error::<F>)?;
+    loop {
+        match iterator.next::<Removable<V>>(buffer).await {
+            Ok(Some((key, Removable(Some(item))))) => f(key, item),
+            Ok(Some((_, Removable(None)))) => continue,
+            Ok(None) => return Ok(()),
+            Err(SSError::SerializationError(_)) => continue,
+            Err(e) => return Err(storage_error::<F>(e)),
+        }
+    }
+}
+
+/// Item which is empty after it's removed
+struct Removable<V>(Option<V>);
+
+impl<'d, V: Value<'d>> Value<'d> for Removable<V> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        match &self.0 {
+            Some(value) => value.serialize_into(buffer),
+            None => Ok(0),
+        }
+    }
+
+    fn deserialize_from(buffer: &'d [u8]) -> Result<(Self, usize), SerializationError> {
+        if buffer.is_empty() {
+            return Ok((Self(None), 0));
+        }
+        V::deserialize_from(buffer).map(|(value, size)| (Self(Some(value)), size))
+    }
+}
+
+/// Convert the error of `sequential-storage`, the error of the flash is printed because it's flash specific
+fn storage_error<F: NorFlash>(e: SSError<F::Error>) -> StorageError {
+    match e {
+        #[cfg(feature = "defmt")]
+        SSError::Storage { value: e } => {
+            error!("Flash error: {:?}", defmt::Debug2Format(&e));
+            StorageError::Flash
+        }
+        #[cfg(not(feature = "defmt"))]
+        SSError::Storage { value: _e } => StorageError::Flash,
+        SSError::FullStorage => StorageError::FullStorage,
+        SSError::Corrupted {} => StorageError::Corrupted,
+        SSError::BufferTooBig | SSError::BufferTooSmall(_) | SSError::ItemTooBig => StorageError::ItemTooBig,
+        SSError::SerializationError(_) => StorageError::Serialization,
+        _ => StorageError::Flash,
+    }
+}
+
+/// Flash which counts the erased sectors
+pub(crate) struct EraseCountingFlash<F> {
+    pub(crate) flash: F,
+    /// Number of erased sectors since boot
+    pub(crate) erase_cycles: u32,
+}
+
+impl<F> EraseCountingFlash<F> {
+    fn new(flash: F) -> Self {
+        Self { flash, erase_cycles: 0 }
+    }
+}
+
+impl<F: ErrorType> ErrorType for EraseCountingFlash<F> {
+    type Error = F::Error;
+}
+
+impl<F: ReadNorFlash> ReadNorFlash for EraseCountingFlash<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash> NorFlash for EraseCountingFlash<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.flash.erase(from, to).await?;
+        if F::ERASE_SIZE > 0 {
+            self.erase_cycles = self.erase_cycles.saturating_add((to - from) / F::ERASE_SIZE as u32);
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.flash.write(offset, bytes).await
+    }
+}
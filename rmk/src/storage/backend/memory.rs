@@ -0,0 +1,162 @@
+use sequential_storage::map::{SerializationError, Value};
+
+use super::{StorageBackend, StorageError};
+
+/// Size of the header of an item: key (4) and size of the value (2)
+const HEADER_SIZE: usize = 6;
+
+/// Backend which keeps the items in RAM, all items are lost after power off.
+///
+/// Items are packed into a buffer of `SIZE` bytes, each item takes 6 bytes plus the serialized value.
+/// It can be used in tests, or by keyboards without a usable flash.
+pub struct MemoryBackend<const SIZE: usize> {
+    data: [u8; SIZE],
+    /// Used bytes of `data`
+    len: usize,
+}
+
+impl<const SIZE: usize> Default for MemoryBackend<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> MemoryBackend<SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            data: [0; SIZE],
+            len: 0,
+        }
+    }
+
+    /// Iterate the items: (key, position of the item, range of the value)
+    fn items(&self) -> impl Iterator<Item = (u32, usize, core::ops::Range<usize>)> + '_ {
+        let mut pos = 0;
+        core::iter::from_fn(move || {
+            if pos >= self.len {
+                return None;
+            }
+            let key = u32::from_le_bytes(self.data[pos..pos + 4].try_into().unwrap());
+            let size = u16::from_le_bytes(self.data[pos + 4..pos + HEADER_SIZE].try_into().unwrap()) as usize;
+            let item = (key, pos, pos + HEADER_SIZE..pos + HEADER_SIZE + size);
+            pos += HEADER_SIZE + size;
+            Some(item)
+        })
+    }
+}
+
+impl<const SIZE: usize> StorageBackend for MemoryBackend<SIZE> {
+    fn capacity(&self) -> u32 {
+        SIZE as u32
+    }
+
+    async fn free_space(&mut self) -> Result<u32, StorageError> {
+        Ok((SIZE - self.len) as u32)
+    }
+
+    async fn fetch<V: for<'d> Value<'d>>(&mut self, key: u32) -> Result<Option<V>, StorageError> {
+        match self.items().find(|(k, _, _)| *k == key) {
+            Some((_, _, value)) => V::deserialize_from(&self.data[value])
+                .map(|(value, _)| Some(value))
+                .map_err(|_| StorageError::Serialization),
+            None => Ok(None),
+        }
+    }
+
+    async fn store<V: for<'d> Value<'d>>(&mut self, key: u32, value: &V) -> Result<(), StorageError> {
+        // Serialize the new item after the used bytes first, so that the old item is kept if it fails
+        if SIZE - self.len < HEADER_SIZE {
+            return Err(StorageError::FullStorage);
+        }
+        let size = match value.serialize_into(&mut self.data[self.len + HEADER_SIZE..]) {
+            Ok(size) if size <= u16::MAX as usize => size,
+            Err(SerializationError::BufferTooSmall) => return Err(StorageError::FullStorage),
+            _ => return Err(StorageError::Serialization),
+        };
+        self.data[self.len..self.len + 4].copy_from_slice(&key.to_le_bytes());
+        self.data[self.len + 4..self.len + HEADER_SIZE].copy_from_slice(&(size as u16).to_le_bytes());
+        let new_len = self.len + HEADER_SIZE + size;
+
+        // Then remove the old item
+        let old = self.items().find(|(k, _, _)| *k == key);
+        if let Some((_, pos, value)) = old {
+            self.data.copy_within(value.end..new_len, pos);
+            self.len = new_len - (value.end - pos);
+        } else {
+            self.len = new_len;
+        }
+        Ok(())
+    }
+
+    async fn remove(&mut self, key: u32) -> Result<(), StorageError> {
+        let item = self.items().find(|(k, _, _)| *k == key);
+        if let Some((_, pos, value)) = item {
+            self.data.copy_within(value.end..self.len, pos);
+            self.len -= value.end - pos;
+        }
+        Ok(())
+    }
+
+    async fn for_each<V: for<'d> Value<'d>>(&mut self, mut f: impl FnMut(u32, V)) -> Result<(), StorageError> {
+        for (key, _, value) in self.items() {
+            // Skip the items which can't be deserialized
+            if let Ok((item, _)) = V::deserialize_from(&self.data[value]) {
+                f(key, item);
+            }
+        }
+        Ok(())
+    }
+
+    async fn erase_all(&mut self) -> Result<(), StorageError> {
+        self.len = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_memory_backend() {
+        let mut backend = MemoryBackend::<64>::new();
+        block_on(async {
+            backend.store(1, &10u32).await.unwrap();
+            backend.store(2, &20u32).await.unwrap();
+            backend.store(3, &30u32).await.unwrap();
+            assert_eq!(backend.fetch::<u32>(2).await, Ok(Some(20)));
+            assert_eq!(backend.fetch::<u32>(4).await, Ok(None));
+
+            // The old item is replaced
+            backend.store(1, &11u32).await.unwrap();
+            assert_eq!(backend.fetch::<u32>(1).await, Ok(Some(11)));
+            assert_eq!(backend.free_space().await, Ok(64 - 3 * 10));
+            let mut items = heapless::Vec::<(u32, u32), 4>::new();
+            backend
+                .for_each(|key, value| items.push((key, value)).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(items, [(2, 20), (3, 30), (1, 11)]);
+
+            // The removed item isn't read
+            backend.remove(2).await.unwrap();
+            assert_eq!(backend.fetch::<u32>(2).await, Ok(None));
+            assert_eq!(backend.free_space().await, Ok(64 - 2 * 10));
+            backend.store(2, &20u32).await.unwrap();
+
+            // The old item is kept when there's no space for the new one
+            for key in 4..7 {
+                backend.store(key, &0u32).await.unwrap();
+            }
+            assert_eq!(backend.store(7, &0u32).await, Err(StorageError::FullStorage));
+            assert_eq!(backend.store(1, &[0u8; 8]).await, Err(StorageError::FullStorage));
+            assert_eq!(backend.fetch::<u32>(1).await, Ok(Some(11)));
+
+            backend.erase_all().await.unwrap();
+            assert_eq!(backend.fetch::<u32>(1).await, Ok(None));
+            assert_eq!(backend.free_space().await, Ok(64));
+        });
+    }
+}
@@ -0,0 +1,69 @@
+//! Backends of the storage.
+//!
+//! [`Storage`](super::Storage) saves its items to a [`StorageBackend`] by `u32` keys, so everything above it,
+//! e.g. the keymap, BLE bonds and behavior configs, doesn't depend on where the items are saved. RMK provides:
+//!
+//! - [`FlashBackend`]: items are saved by `sequential-storage` to a range of a NOR flash, which is the internal
+//!   flash of the microcontroller by default
+//! - [`SpiNorBackend`]: [`FlashBackend`] on an external SPI NOR flash, driven by [`SpiNorFlash`]
+//! - [`MemoryBackend`]: items are kept in RAM, for tests and keyboards without a usable flash
+
+mod flash;
+mod memory;
+mod spi_nor;
+
+pub use flash::{DEFAULT_CACHE_KEYS, DEFAULT_CACHE_PAGES, FlashBackend, IntoFlashBackend, cache_keys};
+pub use memory::MemoryBackend;
+pub use sequential_storage::map::{SerializationError, Value};
+pub use spi_nor::{SpiNorBackend, SpiNorError, SpiNorFlash};
+
+/// Error of the storage backend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StorageError {
+    /// Error of the underlying flash
+    Flash,
+    /// There's no space for the item
+    FullStorage,
+    /// The saved items are corrupted, the storage should be erased
+    Corrupted,
+    /// The item is larger than the backend supports
+    ItemTooBig,
+    /// The item can't be serialized or deserialized
+    Serialization,
+}
+
+/// Backend of the storage, which saves items by `u32` keys.
+///
+/// Items are serialized by [`Value`], and only the newest item of a key is read.
+pub trait StorageBackend {
+    /// Size of the storage in bytes
+    fn capacity(&self) -> u32;
+
+    /// Number of sectors erased since the backend is created, backends which don't erase sectors return 0
+    fn erase_cycles(&self) -> u32 {
+        0
+    }
+
+    /// Bytes which can be written before a sector must be erased again
+    async fn free_space(&mut self) -> Result<u32, StorageError>;
+
+    /// Read the item of the key, `None` if it's not saved
+    async fn fetch<V: for<'d> Value<'d>>(&mut self, key: u32) -> Result<Option<V>, StorageError>;
+
+    /// Save the item of the key, replacing the saved one
+    async fn store<V: for<'d> Value<'d>>(&mut self, key: u32, value: &V) -> Result<(), StorageError>;
+
+    /// Remove the item of the key, it's read as `None` until it's saved again
+    async fn remove(&mut self, key: u32) -> Result<(), StorageError>;
+
+    /// Visit all saved items.
+    ///
+    /// An outdated item can be visited before the newest one with the same key, so the last visited one should be
+    /// used. Items which can't be deserialized as `V` are skipped. Removed items aren't visited, but the outdated
+    /// items of a removed key may be, so the keys which are read by visiting the items shouldn't be removed.
+    async fn for_each<V: for<'d> Value<'d>>(&mut self, f: impl FnMut(u32, V)) -> Result<(), StorageError>;
+
+    /// Erase all items
+    async fn erase_all(&mut self) -> Result<(), StorageError>;
+}
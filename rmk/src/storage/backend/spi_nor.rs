@@ -0,0 +1,264 @@
+//! Driver of the external SPI NOR flash, e.g. W25Q, GD25Q or MX25L series.
+//!
+//! Only the common commands with 3-byte addresses are used, so up to 16 MB flashes are supported.
+
+use embassy_time::Timer;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+use super::FlashBackend;
+
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_READ: u8 = 0x03;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_READ_JEDEC_ID: u8 = 0x9F;
+
+/// Write-in-progress bit of the status register
+const STATUS_BUSY: u8 = 0x01;
+
+/// Size of a page, a page program can't cross the page boundary
+const PAGE_SIZE: usize = 256;
+
+/// Size of a sector, which is the smallest erasable unit
+const SECTOR_SIZE: usize = 4096;
+
+/// Storage backend on an external SPI NOR flash
+pub type SpiNorBackend<SPI> = FlashBackend<SpiNorFlash<SPI>>;
+
+/// Error of the SPI NOR flash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpiNorError {
+    /// SPI transfer error
+    Spi,
+    /// The address is out of the flash
+    OutOfBounds,
+    /// The address isn't aligned to a sector
+    NotAligned,
+}
+
+impl NorFlashError for SpiNorError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::Spi => NorFlashErrorKind::Other,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+        }
+    }
+}
+
+/// SPI NOR flash with 4 KB sectors and 256-byte pages
+pub struct SpiNorFlash<SPI: SpiDevice> {
+    spi: SPI,
+    /// Size of the flash in bytes
+    capacity: usize,
+}
+
+impl<SPI: SpiDevice> SpiNorFlash<SPI> {
+    /// Create the flash with its size in bytes, e.g. `2 * 1024 * 1024` for W25Q16
+    pub fn new(spi: SPI, capacity: usize) -> Self {
+        Self { spi, capacity }
+    }
+
+    /// Read the JEDEC ID: manufacturer, memory type and capacity
+    pub async fn read_jedec_id(&mut self) -> Result<[u8; 3], SpiNorError> {
+        let mut id = [0; 3];
+        self.spi
+            .transaction(&mut [Operation::Write(&[CMD_READ_JEDEC_ID]), Operation::Read(&mut id)])
+            .await
+            .map_err(|_| SpiNorError::Spi)?;
+        Ok(id)
+    }
+
+    /// Check that the range is in the flash
+    fn check_range(&self, offset: u32, size: usize) -> Result<(), SpiNorError> {
+        if offset as usize + size > self.capacity {
+            return Err(SpiNorError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Set the write enable latch, which is required by every program or erase
+    async fn write_enable(&mut self) -> Result<(), SpiNorError> {
+        self.spi.write(&[CMD_WRITE_ENABLE]).await.map_err(|_| SpiNorError::Spi)
+    }
+
+    /// Wait for the program or erase to finish
+    async fn wait_ready(&mut self) -> Result<(), SpiNorError> {
+        loop {
+            let mut status = [0];
+            self.spi
+                .transaction(&mut [Operation::Write(&[CMD_READ_STATUS]), Operation::Read(&mut status)])
+                .await
+                .map_err(|_| SpiNorError::Spi)?;
+            if status[0] & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+            // A page program takes < 1ms, and a sector erase takes tens of ms
+            Timer::after_micros(100).await;
+        }
+    }
+}
+
+/// Command with a 3-byte address
+fn command(cmd: u8, address: u32) -> [u8; 4] {
+    let [_, a2, a1, a0] = address.to_be_bytes();
+    [cmd, a2, a1, a0]
+}
+
+impl<SPI: SpiDevice> ErrorType for SpiNorFlash<SPI> {
+    type Error = SpiNorError;
+}
+
+impl<SPI: SpiDevice> ReadNorFlash for SpiNorFlash<SPI> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_range(offset, bytes.len())?;
+        self.spi
+            .transaction(&mut [Operation::Write(&command(CMD_READ, offset)), Operation::Read(bytes)])
+            .await
+            .map_err(|_| SpiNorError::Spi)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<SPI: SpiDevice> NorFlash for SpiNorFlash<SPI> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(SpiNorError::OutOfBounds);
+        }
+        self.check_range(from, (to - from) as usize)?;
+        if !from.is_multiple_of(SECTOR_SIZE as u32) || !to.is_multiple_of(SECTOR_SIZE as u32) {
+            return Err(SpiNorError::NotAligned);
+        }
+        for sector in (from..to).step_by(SECTOR_SIZE) {
+            self.write_enable().await?;
+            self.spi
+                .write(&command(CMD_SECTOR_ERASE, sector))
+                .await
+                .map_err(|_| SpiNorError::Spi)?;
+            self.wait_ready().await?;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check_range(offset, bytes.len())?;
+        let mut offset = offset;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            // Split the data at page boundaries
+            let size = bytes.len().min(PAGE_SIZE - offset as usize % PAGE_SIZE);
+            self.write_enable().await?;
+            self.spi
+                .transaction(&mut [
+                    Operation::Write(&command(CMD_PAGE_PROGRAM, offset)),
+                    Operation::Write(&bytes[..size]),
+                ])
+                .await
+                .map_err(|_| SpiNorError::Spi)?;
+            self.wait_ready().await?;
+            offset += size as u32;
+            bytes = &bytes[size..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_hal::spi::{ErrorKind, ErrorType as SpiErrorType};
+
+    use super::*;
+
+    /// SPI NOR flash emulated by the SPI transactions
+    struct MockSpiNor {
+        data: [u8; 4 * SECTOR_SIZE],
+        write_enabled: bool,
+        /// Number of page programs, to check that the pages are split
+        programs: usize,
+    }
+
+    impl SpiErrorType for MockSpiNor {
+        type Error = ErrorKind;
+    }
+
+    impl SpiDevice for MockSpiNor {
+        async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+            let (cmd, address) = match &operations[0] {
+                Operation::Write(cmd) => (
+                    cmd[0],
+                    cmd.get(1..4)
+                        .map(|a| u32::from_be_bytes([0, a[0], a[1], a[2]]) as usize),
+                ),
+                _ => return Err(ErrorKind::Other),
+            };
+            match (cmd, &mut operations[1..]) {
+                (CMD_WRITE_ENABLE, []) => self.write_enabled = true,
+                (CMD_READ_STATUS, [Operation::Read(status)]) => status[0] = 0,
+                (CMD_READ_JEDEC_ID, [Operation::Read(id)]) => id.copy_from_slice(&[0xEF, 0x40, 0x15]),
+                (CMD_READ, [Operation::Read(bytes)]) => {
+                    let address = address.unwrap();
+                    bytes.copy_from_slice(&self.data[address..address + bytes.len()]);
+                }
+                (CMD_PAGE_PROGRAM, [Operation::Write(bytes)]) if self.write_enabled => {
+                    let address = address.unwrap();
+                    assert_eq!(address / PAGE_SIZE, (address + bytes.len() - 1) / PAGE_SIZE);
+                    for (data, byte) in self.data[address..].iter_mut().zip(bytes.iter()) {
+                        *data &= *byte;
+                    }
+                    self.write_enabled = false;
+                    self.programs += 1;
+                }
+                (CMD_SECTOR_ERASE, []) if self.write_enabled => {
+                    let address = address.unwrap();
+                    self.data[address..address + SECTOR_SIZE].fill(0xFF);
+                    self.write_enabled = false;
+                }
+                _ => return Err(ErrorKind::Other),
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_spi_nor_flash() {
+        let spi = MockSpiNor {
+            data: [0xFF; 4 * SECTOR_SIZE],
+            write_enabled: false,
+            programs: 0,
+        };
+        let mut flash = SpiNorFlash::new(spi, 4 * SECTOR_SIZE);
+        block_on(async {
+            assert_eq!(flash.read_jedec_id().await, Ok([0xEF, 0x40, 0x15]));
+
+            // The write is split at the page boundary
+            let data = [0x5A; 400];
+            flash.write(200, &data).await.unwrap();
+            assert_eq!(flash.spi.programs, 3);
+            let mut read = [0; 400];
+            flash.read(200, &mut read).await.unwrap();
+            assert_eq!(read, data);
+
+            flash.erase(0, SECTOR_SIZE as u32).await.unwrap();
+            flash.read(200, &mut read).await.unwrap();
+            assert_eq!(read, [0xFF; 400]);
+
+            assert_eq!(flash.erase(100, 200).await, Err(SpiNorError::NotAligned));
+            assert_eq!(
+                flash.read(4 * SECTOR_SIZE as u32, &mut read).await,
+                Err(SpiNorError::OutOfBounds)
+            );
+        });
+    }
+}
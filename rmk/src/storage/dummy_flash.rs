@@ -75,3 +75,93 @@ impl embedded_storage_async::nor_flash::ReadNorFlash for DummyFlash {
         0
     }
 }
+
+/// A `NorFlash` backed by RAM, which can be used in tests or when the flash isn't available.
+///
+/// It has the semantics of a NOR flash: erased bytes are `0xFF`, and writing only clears bits.
+/// The data is lost after power off.
+pub struct MemoryFlash<const SIZE: usize> {
+    data: [u8; SIZE],
+}
+
+impl<const SIZE: usize> Default for MemoryFlash<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> MemoryFlash<SIZE> {
+    /// Erase size of the flash, `SIZE` should be a multiple of it
+    pub const SECTOR_SIZE: usize = 4096;
+
+    pub fn new() -> Self {
+        Self { data: [0xFF; SIZE] }
+    }
+
+    fn check_range(from: u32, to: u32) -> Result<core::ops::Range<usize>, MemoryFlashError> {
+        if from > to || to as usize > SIZE {
+            return Err(MemoryFlashError::OutOfBounds);
+        }
+        Ok(from as usize..to as usize)
+    }
+}
+
+/// Error of [`MemoryFlash`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MemoryFlashError {
+    OutOfBounds,
+    NotAligned,
+}
+
+impl embedded_storage_async::nor_flash::NorFlashError for MemoryFlashError {
+    fn kind(&self) -> embedded_storage_async::nor_flash::NorFlashErrorKind {
+        match self {
+            Self::OutOfBounds => embedded_storage_async::nor_flash::NorFlashErrorKind::OutOfBounds,
+            Self::NotAligned => embedded_storage_async::nor_flash::NorFlashErrorKind::NotAligned,
+        }
+    }
+}
+
+impl<const SIZE: usize> embedded_storage_async::nor_flash::ErrorType for MemoryFlash<SIZE> {
+    type Error = MemoryFlashError;
+}
+
+impl<const SIZE: usize> embedded_storage_async::nor_flash::ReadNorFlash for MemoryFlash<SIZE> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let range = Self::check_range(offset, offset + bytes.len() as u32)?;
+        bytes.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize> embedded_storage_async::nor_flash::NorFlash for MemoryFlash<SIZE> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = Self::SECTOR_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let range = Self::check_range(from, to)?;
+        if !range.start.is_multiple_of(Self::ERASE_SIZE) || !range.end.is_multiple_of(Self::ERASE_SIZE) {
+            return Err(MemoryFlashError::NotAligned);
+        }
+        self.data[range].fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let range = Self::check_range(offset, offset + bytes.len() as u32)?;
+        if !range.start.is_multiple_of(Self::WRITE_SIZE) || !range.end.is_multiple_of(Self::WRITE_SIZE) {
+            return Err(MemoryFlashError::NotAligned);
+        }
+        for (data, byte) in self.data[range].iter_mut().zip(bytes) {
+            *data &= *byte;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,695 @@
+//! Keymap profiles.
+//!
+//! A keymap profile is a complete set of layers, encoders, combos and morses in the storage. Macros, forks and the
+//! behavior settings are shared by all profiles. Profile 0 uses the same storage keys as the keymap before profiles
+//! were added, so a keyboard with a single profile stores exactly the same items as before.
+//!
+//! When there're more than one profile, every profile is fully written to the storage at boot, because the default
+//! keymap isn't available after the keyboard starts. For the same reason, clearing a profile is applied at the next
+//! boot, and the keyboard reboots if the cleared profile is active.
+
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::Ordering;
+
+use embassy_sync::blocking_mutex::Mutex;
+use postcard::experimental::max_size::MaxSize;
+use rmk_types::action::{EncoderAction, KeyAction};
+use serde::{Deserialize, Serialize};
+
+use super::backend::{StorageBackend, StorageError};
+use super::request::StorageRequest;
+use super::{FlashOperationMessage, Storage, StorageData, StorageKeys, get_keymap_data_key, print_storage_error};
+use crate::boot::reboot_keyboard;
+use crate::combo::ComboConfig;
+use crate::host::storage::{EncoderKeymap, KeymapData, KeymapKey, apply_keymap_data};
+use crate::keymap::KeyMap;
+use crate::morse::Morse;
+use crate::state::{CONNECTION_TYPE, ConnectionType};
+use crate::{COMBO_MAX_NUM, MORSE_MAX_NUM, RawMutex, config};
+
+/// Max number of keymap profiles.
+///
+/// The profile takes 3 bits of the keymap keys, see [`super::get_keymap_key`], and the profiles are tracked by `u8`
+/// bit masks in [`KeymapProfileConfig`].
+pub(crate) const MAX_KEYMAP_PROFILES: u8 = 8;
+
+/// Max number of BLE profiles which can be bound to a keymap profile
+pub(crate) const MAX_BOUND_BLE_PROFILES: usize = 8;
+
+/// Keymap profile of a connection without binding
+pub(crate) const UNBOUND: u8 = 0xFF;
+
+/// Keymap profile operation requested by the host, the response is whether it succeeded
+static KEYMAP_PROFILE_OPERATION: StorageRequest<bool> = StorageRequest::new();
+
+/// Run a keymap profile operation in the storage task and wait for its outcome.
+///
+/// The message is built from the sequence number of the request. It returns `false` if the operation fails, or the
+/// pending writes can't be saved before it.
+pub(crate) async fn request_keymap_profile_operation(
+    message: impl FnOnce(Option<u32>) -> FlashOperationMessage,
+) -> bool {
+    KEYMAP_PROFILE_OPERATION.request(|seq| message(Some(seq))).await
+}
+
+/// Send the outcome of a keymap profile operation to the requester, returns the result of saving the pending writes
+pub(crate) fn respond_keymap_profile_operation(
+    seq: Option<u32>,
+    flushed: Result<(), StorageError>,
+    result: Result<(), ()>,
+) -> Result<(), StorageError> {
+    if let Some(seq) = seq {
+        KEYMAP_PROFILE_OPERATION.respond(seq, flushed.is_ok() && result.is_ok());
+    }
+    flushed
+}
+
+/// Current keymap profiles, which are read by the host
+pub(crate) static KEYMAP_PROFILES: Mutex<RawMutex, Cell<KeymapProfiles>> = Mutex::new(Cell::new(KeymapProfiles::new()));
+
+/// Persisted state of the keymap profiles
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct KeymapProfileConfig {
+    /// Active keymap profile
+    pub(crate) active: u8,
+    /// Bit mask of the profiles whose items are written to the storage
+    initialized: u8,
+    /// Bit mask of the profiles which are reset to the default keymap at the next boot.
+    ///
+    /// The default keymap is only passed to the storage at boot, so a clear can't be applied at runtime.
+    pending_clear: u8,
+    /// Profile bound to USB, or `UNBOUND`
+    pub(crate) usb: u8,
+    /// Profiles bound to the BLE profiles, or `UNBOUND`
+    pub(crate) ble: [u8; MAX_BOUND_BLE_PROFILES],
+}
+
+impl KeymapProfileConfig {
+    const fn new() -> Self {
+        Self {
+            active: 0,
+            initialized: 0,
+            pending_clear: 0,
+            usb: UNBOUND,
+            ble: [UNBOUND; MAX_BOUND_BLE_PROFILES],
+        }
+    }
+
+    /// Get the keymap profile bound to the connection
+    pub(crate) fn bound_profile(&self, connection_type: u8, ble_profile: u8) -> Option<u8> {
+        let profile = if connection_type == u8::from(ConnectionType::Usb) {
+            self.usb
+        } else {
+            *self.ble.get(ble_profile as usize)?
+        };
+        (profile != UNBOUND).then_some(profile)
+    }
+
+    /// Bind the keymap profile to the target, which is 0 for USB and `1 + n` for the BLE profile `n`.
+    ///
+    /// Binding `UNBOUND` removes the binding.
+    fn bind(&mut self, target: u8, profile: u8) -> Result<(), ()> {
+        match target {
+            0 => self.usb = profile,
+            n => *self.ble.get_mut(n as usize - 1).ok_or(())? = profile,
+        }
+        Ok(())
+    }
+
+    fn is_pending_clear(&self, profile: u8) -> bool {
+        self.pending_clear & (1 << profile) != 0
+    }
+}
+
+/// Keymap profiles of the storage
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct KeymapProfiles {
+    /// Number of the keymap profiles
+    pub(crate) num: u8,
+    pub(crate) config: KeymapProfileConfig,
+}
+
+impl KeymapProfiles {
+    pub(crate) const fn new() -> Self {
+        Self {
+            num: 1,
+            config: KeymapProfileConfig::new(),
+        }
+    }
+}
+
+impl Default for KeymapProfiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: StorageBackend, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Initialize the keymap profiles at boot.
+    ///
+    /// The default keymap is written to new profiles and the profiles to be cleared, then the profile bound to
+    /// the saved connection is activated. `reset` clears all profiles except profile 0, which is reset by
+    /// `clear_layout` already. `migrated` fills the items which are missing after a layout change.
+    pub(crate) async fn init_keymap_profiles(
+        &mut self,
+        num: u8,
+        keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+        encoder_map: &Option<&mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
+        behavior: &config::BehaviorConfig,
+        reset: bool,
+        migrated: bool,
+    ) -> Result<(), ()> {
+        let num = num.min(MAX_KEYMAP_PROFILES);
+        if num <= 1 || NUM_LAYER == 0 {
+            return Ok(());
+        }
+
+        let saved = match self
+            .fetch_keymap_profile_item(StorageKeys::KeymapProfile as u32)
+            .await?
+        {
+            Some(StorageData::KeymapProfile(config)) => Some(config),
+            _ => None,
+        };
+        let mut config = saved.unwrap_or(KeymapProfileConfig::new());
+        if reset {
+            config.pending_clear |= ((1u16 << num) - 2) as u8;
+        }
+        for profile in 0..num {
+            let bit = 1 << profile;
+            if config.pending_clear & bit != 0 {
+                info!("Resetting keymap profile {}", profile);
+                self.write_default_keymap_profile(profile, true, keymap, encoder_map, behavior)
+                    .await?;
+            } else if config.initialized & bit == 0 || migrated {
+                self.write_default_keymap_profile(profile, false, keymap, encoder_map, behavior)
+                    .await?;
+            }
+            config.initialized |= bit;
+            config.pending_clear &= !bit;
+        }
+
+        let (connection_type, ble_profile) = self.saved_connection().await;
+        if let Some(profile) = config.bound_profile(connection_type, ble_profile) {
+            config.active = profile;
+        }
+        if config.active >= num {
+            config.active = 0;
+        }
+        info!("Active keymap profile: {}", config.active);
+
+        self.keymap_profiles = KeymapProfiles { num, config };
+        if saved != Some(config) {
+            self.save_keymap_profiles().await?;
+        }
+        KEYMAP_PROFILES.lock(|p| p.set(self.keymap_profiles));
+        Ok(())
+    }
+
+    /// Switch to the keymap profile, and load it into the keymap
+    pub(crate) async fn switch_keymap_profile(
+        &mut self,
+        profile: u8,
+        keymap: Option<&RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>>,
+    ) -> Result<(), ()> {
+        if profile >= self.keymap_profiles.num {
+            warn!("Keymap profile {} doesn't exist", profile);
+            return Err(());
+        }
+        if profile == self.keymap_profiles.config.active {
+            return Ok(());
+        }
+
+        info!("Switching to keymap profile {}", profile);
+        self.keymap_profiles.config.active = profile;
+        self.save_keymap_profiles().await?;
+        if self.keymap_profiles.config.is_pending_clear(profile) {
+            // The default keymap can only be written at boot
+            reboot_keyboard();
+        } else if let Some(keymap) = keymap {
+            self.load_keymap_profile(profile, keymap).await?;
+        }
+        Ok(())
+    }
+
+    /// Copy all items of the keymap profile `src` to `dst`
+    pub(crate) async fn copy_keymap_profile(
+        &mut self,
+        src: u8,
+        dst: u8,
+        keymap: Option<&RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>>,
+    ) -> Result<(), ()> {
+        let num = self.keymap_profiles.num;
+        if src >= num || dst >= num || src == dst {
+            warn!("Invalid keymap profiles to copy: {} -> {}", src, dst);
+            return Err(());
+        }
+        if self.keymap_profiles.config.is_pending_clear(src) {
+            warn!("Keymap profile {} is reset at the next boot, it can't be copied", src);
+            return Err(());
+        }
+        info!("Copying keymap profile {} to {}", src, dst);
+
+        // Copy the keymap layer by layer to limit the memory usage
+        for layer in 0..NUM_LAYER {
+            let mut actions = [[None; COL]; ROW];
+            self.for_each_profile_item(src, |data| {
+                if let KeymapData::KeymapKey(k) = data
+                    && k.layer as usize == layer
+                    && let Some(action) = actions.get_mut(k.row as usize).and_then(|r| r.get_mut(k.col as usize))
+                {
+                    *action = Some(k.action);
+                }
+            })
+            .await?;
+            for (row, row_data) in actions.iter().enumerate() {
+                for (col, action) in row_data.iter().enumerate() {
+                    if let Some(action) = action {
+                        let keymap_key = KeymapKey {
+                            row: row as u8,
+                            col: col as u8,
+                            layer: layer as u8,
+                            action: *action,
+                        };
+                        self.store_keymap_data(dst, KeymapData::KeymapKey(keymap_key)).await?;
+                    }
+                }
+            }
+        }
+
+        let mut encoders = [[None; NUM_ENCODER]; NUM_LAYER];
+        let mut combos = [None; COMBO_MAX_NUM];
+        let mut morses: [Option<Morse>; MORSE_MAX_NUM] = core::array::from_fn(|_| None);
+        self.for_each_profile_item(src, |data| match data {
+            KeymapData::Encoder(e) => {
+                if let Some(action) = encoders
+                    .get_mut(e.layer as usize)
+                    .and_then(|l| l.get_mut(e.idx as usize))
+                {
+                    *action = Some(e.action);
+                }
+            }
+            KeymapData::Combo(idx, config) => {
+                if let Some(combo) = combos.get_mut(idx as usize) {
+                    *combo = Some(config);
+                }
+            }
+            KeymapData::Morse(idx, morse) => {
+                if let Some(item) = morses.get_mut(idx as usize) {
+                    *item = Some(morse);
+                }
+            }
+            _ => {}
+        })
+        .await?;
+        for (layer, layer_data) in encoders.iter().enumerate() {
+            for (idx, action) in layer_data.iter().enumerate() {
+                if let Some(action) = action {
+                    let encoder = EncoderKeymap {
+                        idx: idx as u8,
+                        layer: layer as u8,
+                        action: *action,
+                    };
+                    self.store_keymap_data(dst, KeymapData::Encoder(encoder)).await?;
+                }
+            }
+        }
+        for (idx, combo) in combos.into_iter().enumerate() {
+            if let Some(config) = combo {
+                self.store_keymap_data(dst, KeymapData::Combo(idx as u8, config))
+                    .await?;
+            }
+        }
+        for (idx, morse) in morses.into_iter().enumerate() {
+            if let Some(morse) = morse {
+                self.store_keymap_data(dst, KeymapData::Morse(idx as u8, morse)).await?;
+            }
+        }
+
+        // The copied profile is complete, so it doesn't need to be reset anymore
+        if self.keymap_profiles.config.is_pending_clear(dst) {
+            self.keymap_profiles.config.pending_clear &= !(1 << dst);
+            self.save_keymap_profiles().await?;
+        }
+        if dst == self.keymap_profiles.config.active
+            && let Some(keymap) = keymap
+        {
+            self.load_keymap_profile(dst, keymap).await?;
+        }
+        Ok(())
+    }
+
+    /// Reset the keymap profile to the default keymap at the next boot.
+    ///
+    /// The keyboard reboots immediately if the profile is active.
+    pub(crate) async fn clear_keymap_profile(&mut self, profile: u8) -> Result<(), ()> {
+        if profile >= self.keymap_profiles.num {
+            warn!("Keymap profile {} doesn't exist", profile);
+            return Err(());
+        }
+        info!("Keymap profile {} will be reset at the next boot", profile);
+        self.keymap_profiles.config.pending_clear |= 1 << profile;
+        self.save_keymap_profiles().await?;
+        if profile == self.keymap_profiles.config.active {
+            reboot_keyboard();
+        }
+        Ok(())
+    }
+
+    /// Bind the keymap profile to USB or a BLE profile, see [`KeymapProfileConfig::bind`]
+    pub(crate) async fn bind_keymap_profile(
+        &mut self,
+        target: u8,
+        profile: u8,
+        keymap: Option<&RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>>,
+    ) -> Result<(), ()> {
+        if profile != UNBOUND && profile >= self.keymap_profiles.num {
+            warn!("Keymap profile {} doesn't exist", profile);
+            return Err(());
+        }
+        if self.keymap_profiles.config.bind(target, profile).is_err() {
+            warn!("Invalid keymap profile binding target: {}", target);
+            return Err(());
+        }
+        self.save_keymap_profiles().await?;
+        self.follow_keymap_profile_binding(keymap).await
+    }
+
+    /// Switch to the keymap profile bound to the current connection
+    pub(crate) async fn follow_keymap_profile_binding(
+        &mut self,
+        keymap: Option<&RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>>,
+    ) -> Result<(), ()> {
+        let connection_type = CONNECTION_TYPE.load(Ordering::Acquire);
+        #[cfg(feature = "_ble")]
+        let ble_profile = crate::ble::ACTIVE_PROFILE.load(Ordering::Acquire);
+        #[cfg(not(feature = "_ble"))]
+        let ble_profile = 0;
+        match self.keymap_profiles.config.bound_profile(connection_type, ble_profile) {
+            Some(profile) => self.switch_keymap_profile(profile, keymap).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Load the items of the keymap profile into the keymap
+    async fn load_keymap_profile(
+        &mut self,
+        profile: u8,
+        keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) -> Result<(), ()> {
+        // Every profile is fully written, so all positions are overwritten.
+        // The keymap is only borrowed when an item is applied, because it's used by other tasks during the scan
+        self.for_each_profile_item(profile, |data| apply_keymap_data(&mut keymap.borrow_mut(), data))
+            .await
+    }
+
+    /// Write the default keymap, encoders, combos and morses to the keymap profile.
+    ///
+    /// If `overwrite` is false, only the missing items are written.
+    async fn write_default_keymap_profile(
+        &mut self,
+        profile: u8,
+        overwrite: bool,
+        keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+        encoder_map: &Option<&mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
+        behavior: &config::BehaviorConfig,
+    ) -> Result<(), ()> {
+        let mut keys = [[[false; COL]; ROW]; NUM_LAYER];
+        let mut encoders = [[false; NUM_ENCODER]; NUM_LAYER];
+        let mut combos = [false; COMBO_MAX_NUM];
+        let mut morses = [false; MORSE_MAX_NUM];
+        if !overwrite {
+            self.for_each_profile_item(profile, |data| {
+                let saved = match data {
+                    KeymapData::KeymapKey(k) => keys
+                        .get_mut(k.layer as usize)
+                        .and_then(|l| l.get_mut(k.row as usize))
+                        .and_then(|r| r.get_mut(k.col as usize)),
+                    KeymapData::Encoder(e) => encoders
+                        .get_mut(e.layer as usize)
+                        .and_then(|l| l.get_mut(e.idx as usize)),
+                    KeymapData::Combo(idx, _) => combos.get_mut(idx as usize),
+                    KeymapData::Morse(idx, _) => morses.get_mut(idx as usize),
+                    KeymapData::Macro(_) | KeymapData::Fork(_, _) => None,
+                };
+                if let Some(saved) = saved {
+                    *saved = true;
+                }
+            })
+            .await?;
+        }
+
+        for (layer, layer_data) in keymap.iter().enumerate() {
+            for (row, row_data) in layer_data.iter().enumerate() {
+                for (col, action) in row_data.iter().enumerate() {
+                    if !keys[layer][row][col] {
+                        let keymap_key = KeymapKey {
+                            row: row as u8,
+                            col: col as u8,
+                            layer: layer as u8,
+                            action: *action,
+                        };
+                        self.store_keymap_data(profile, KeymapData::KeymapKey(keymap_key))
+                            .await?;
+                    }
+                }
+            }
+        }
+        if let Some(encoder_map) = encoder_map {
+            for (layer, layer_data) in encoder_map.iter().enumerate() {
+                for (idx, action) in layer_data.iter().enumerate() {
+                    if !encoders[layer][idx] {
+                        let encoder = EncoderKeymap {
+                            idx: idx as u8,
+                            layer: layer as u8,
+                            action: *action,
+                        };
+                        self.store_keymap_data(profile, KeymapData::Encoder(encoder)).await?;
+                    }
+                }
+            }
+        }
+        for (idx, combo) in behavior.combo.combos.iter().enumerate() {
+            if !combos[idx] {
+                let config = combo.as_ref().map_or(ComboConfig::empty(), |c| c.config);
+                self.store_keymap_data(profile, KeymapData::Combo(idx as u8, config))
+                    .await?;
+            }
+        }
+        for (idx, saved) in morses.iter().enumerate() {
+            if !saved {
+                let morse = behavior.morse.morses.get(idx).cloned().unwrap_or_default();
+                self.store_keymap_data(profile, KeymapData::Morse(idx as u8, morse))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Visit the keymap keys, encoders, combos and morses of the keymap profile with a single scan
+    async fn for_each_profile_item(&mut self, profile: u8, mut f: impl FnMut(KeymapData)) -> Result<(), ()> {
+        self.for_each_item(|key, item| {
+            if let StorageData::VialData(data) = item
+                && !matches!(data, KeymapData::Macro(_) | KeymapData::Fork(_, _))
+                && key == get_keymap_data_key(profile, &data)
+            {
+                f(data);
+            }
+        })
+        .await
+    }
+
+    async fn store_keymap_data(&mut self, profile: u8, data: KeymapData) -> Result<(), ()> {
+        self.store_item(get_keymap_data_key(profile, &data), &StorageData::VialData(data))
+            .await
+            .map_err(print_storage_error)
+    }
+
+    async fn save_keymap_profiles(&mut self) -> Result<(), ()> {
+        KEYMAP_PROFILES.lock(|p| p.set(self.keymap_profiles));
+        self.store_item(
+            StorageKeys::KeymapProfile as u32,
+            &StorageData::KeymapProfile(self.keymap_profiles.config),
+        )
+        .await
+        .map_err(print_storage_error)
+    }
+
+    async fn fetch_keymap_profile_item(&mut self, key: u32) -> Result<Option<StorageData>, ()> {
+        self.fetch_item(key).await.map_err(print_storage_error)
+    }
+
+    /// Saved connection type and active BLE profile, which are used before the connection is started
+    async fn saved_connection(&mut self) -> (u8, u8) {
+        #[cfg(feature = "_ble")]
+        {
+            let connection_type = match self.fetch_keymap_profile_item(StorageKeys::ConnectionType as u32).await {
+                Ok(Some(StorageData::ConnectionType(connection_type))) => connection_type,
+                #[cfg(feature = "_no_usb")]
+                _ => ConnectionType::Ble.into(),
+                #[cfg(not(feature = "_no_usb"))]
+                _ => ConnectionType::Usb.into(),
+            };
+            let ble_profile = match self
+                .fetch_keymap_profile_item(StorageKeys::ActiveBleProfile as u32)
+                .await
+            {
+                Ok(Some(StorageData::ActiveBleProfile(profile))) => profile,
+                _ => 0,
+            };
+            (connection_type, ble_profile)
+        }
+
+        #[cfg(not(feature = "_ble"))]
+        (ConnectionType::Usb.into(), 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_futures::select::{Either, select};
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+    use crate::config::{BehaviorConfig, PositionalConfig, StorageConfig};
+    use crate::k;
+    use crate::storage::backend::FlashBackend;
+    use crate::storage::dummy_flash::MemoryFlash;
+
+    const FLASH_SIZE: usize = 8 * 4096;
+    type TestFlash = MemoryFlash<FLASH_SIZE>;
+    type TestStorage = Storage<FlashBackend<TestFlash>, 2, 3, 2, 0>;
+
+    const DEFAULT_KEYMAP: [[[KeyAction; 3]; 2]; 2] = [[[k!(A); 3]; 2]; 2];
+
+    fn boot(flash: TestFlash) -> TestStorage {
+        block_on(async {
+            let behavior = BehaviorConfig::default();
+            let storage_config = StorageConfig {
+                num_sectors: 8,
+                ..Default::default()
+            };
+            let mut storage = Storage::new(flash, &DEFAULT_KEYMAP, &None, &storage_config, &behavior).await;
+            storage
+                .init_keymap_profiles(3, &DEFAULT_KEYMAP, &None, &behavior, false, false)
+                .await
+                .unwrap();
+            storage
+        })
+    }
+
+    fn set_key(storage: &mut TestStorage, profile: u8, action: KeyAction) {
+        let keymap_key = KeymapKey {
+            row: 1,
+            col: 2,
+            layer: 1,
+            action,
+        };
+        block_on(storage.store_keymap_data(profile, KeymapData::KeymapKey(keymap_key))).unwrap();
+    }
+
+    fn read_key(storage: &mut TestStorage) -> KeyAction {
+        let mut keymap = DEFAULT_KEYMAP;
+        block_on(storage.read_keymap(&mut keymap, &mut None)).unwrap();
+        keymap[1][1][2]
+    }
+
+    #[test]
+    fn test_switch_and_copy_keymap_profile() {
+        let mut storage = boot(TestFlash::new());
+        assert_eq!(storage.keymap_profiles.num, 3);
+        assert_eq!(storage.keymap_profiles.config.active, 0);
+        set_key(&mut storage, 1, k!(B));
+
+        let mut layers = DEFAULT_KEYMAP;
+        let mut behavior = BehaviorConfig::default();
+        let mut positional_config = PositionalConfig::default();
+        let keymap = RefCell::new(block_on(KeyMap::new(
+            &mut layers,
+            None,
+            &mut behavior,
+            &mut positional_config,
+        )));
+
+        block_on(storage.switch_keymap_profile(1, Some(&keymap))).unwrap();
+        assert_eq!(keymap.borrow().layers[1][1][2], k!(B));
+        block_on(storage.copy_keymap_profile(1, 2, Some(&keymap))).unwrap();
+        block_on(storage.switch_keymap_profile(0, Some(&keymap))).unwrap();
+        assert_eq!(keymap.borrow().layers[1][1][2], k!(A));
+        block_on(storage.switch_keymap_profile(2, Some(&keymap))).unwrap();
+        assert_eq!(keymap.borrow().layers[1][1][2], k!(B));
+        assert!(block_on(storage.switch_keymap_profile(3, Some(&keymap))).is_err());
+        assert!(block_on(storage.copy_keymap_profile(0, 0, Some(&keymap))).is_err());
+
+        // The active profile is restored at boot
+        let mut storage = boot(storage.backend.into_inner());
+        assert_eq!(storage.keymap_profiles.config.active, 2);
+        assert_eq!(read_key(&mut storage), k!(B));
+    }
+
+    #[test]
+    fn test_clear_keymap_profile() {
+        let mut storage = boot(TestFlash::new());
+        set_key(&mut storage, 1, k!(B));
+        set_key(&mut storage, 2, k!(C));
+        block_on(storage.clear_keymap_profile(1)).unwrap();
+        assert!(storage.keymap_profiles.config.is_pending_clear(1));
+
+        // The cleared profile is reset at the next boot, other profiles are kept
+        let mut storage = boot(storage.backend.into_inner());
+        assert!(!storage.keymap_profiles.config.is_pending_clear(1));
+        block_on(storage.switch_keymap_profile(1, None)).unwrap();
+        assert_eq!(read_key(&mut storage), k!(A));
+        block_on(storage.switch_keymap_profile(2, None)).unwrap();
+        assert_eq!(read_key(&mut storage), k!(C));
+    }
+
+    #[test]
+    fn test_bind_keymap_profile() {
+        let mut storage = boot(TestFlash::new());
+        set_key(&mut storage, 2, k!(C));
+
+        // Bind profile 2 to USB, which is the current connection
+        block_on(storage.bind_keymap_profile(0, 2, None)).unwrap();
+        assert_eq!(storage.keymap_profiles.config.usb, 2);
+        assert_eq!(storage.keymap_profiles.config.active, 2);
+        assert!(block_on(storage.bind_keymap_profile(0, 3, None)).is_err());
+        assert!(block_on(storage.bind_keymap_profile(MAX_BOUND_BLE_PROFILES as u8 + 1, 1, None)).is_err());
+
+        // The bound profile is activated at boot
+        block_on(storage.bind_keymap_profile(1, 1, None)).unwrap();
+        storage.keymap_profiles.config.active = 0;
+        block_on(storage.save_keymap_profiles()).unwrap();
+        let mut storage = boot(storage.backend.into_inner());
+        assert_eq!(storage.keymap_profiles.config.active, 2);
+        assert_eq!(storage.keymap_profiles.config.bound_profile(1, 0), Some(1));
+        assert_eq!(read_key(&mut storage), k!(C));
+
+        // Remove the binding
+        block_on(storage.bind_keymap_profile(0, UNBOUND, None)).unwrap();
+        block_on(storage.switch_keymap_profile(0, None)).unwrap();
+        let storage = boot(storage.backend.into_inner());
+        assert_eq!(storage.keymap_profiles.config.active, 0);
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_keymap_profile_operation_outcome() {
+            let mut storage = boot(TestFlash::new());
+            let host = async {
+                assert!(request_keymap_profile_operation(|seq| FlashOperationMessage::SwitchKeymapProfile(1, seq)).await);
+                // Invalid profiles are reported to the host
+                assert!(!request_keymap_profile_operation(|seq| FlashOperationMessage::SwitchKeymapProfile(3, seq)).await);
+                assert!(!request_keymap_profile_operation(|seq| FlashOperationMessage::CopyKeymapProfile(0, 0, seq)).await);
+            };
+            let result = block_on(select(storage.run(None), host));
+            assert!(matches!(result, Either::Second(())));
+            assert_eq!(storage.keymap_profiles.config.active, 1);
+        }
+    }
+}
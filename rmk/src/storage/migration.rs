@@ -0,0 +1,664 @@
+//! Versioning and migration of the stored data.
+//!
+//! The storage config records the schema version of the stored data and the layout, i.e. the sizes of the keymap and
+//! the behaviors that the data was written with. When either of them differs from the firmware's at boot, the stored
+//! data is migrated instead of erased:
+//!
+//! - Keymap keys and encoder actions are stored under keys which don't depend on the keymap size.
+//!   Stored keys outside of the new keymap are ignored, and the new positions use the default keymap.
+//! - Macros are padded or truncated to the new macro space, a truncated macro is removed.
+//! - Combos and morses are re-encoded with the new max combo length and max number of patterns,
+//!   the combos which don't fit are removed, and the patterns which don't fit are dropped.
+//!
+//! The storage is only erased when the data can't be migrated: the schema version is newer than the firmware's,
+//! e.g. after downgrading the firmware, or the encoding of key actions is changed.
+
+use postcard::experimental::max_size::MaxSize;
+use rmk_types::action::KeyAction;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "host")]
+use {
+    super::backend::{SerializationError, StorageError, Value},
+    super::get_buffer_size,
+    super::{get_combo_key, get_encoder_config_key, get_keymap_key, get_morse_key},
+    crate::combo::ComboConfig,
+    crate::host::storage::KeymapData,
+    crate::morse::{Morse, MorsePattern},
+    crate::{COMBO_MAX_LENGTH, KEYMAP_PROFILE_NUM, MACRO_SPACE_SIZE, MAX_PATTERNS_PER_KEY},
+    rmk_types::action::{Action, EncoderAction, MorseProfile},
+};
+
+use super::backend::StorageBackend;
+use super::{LocalStorageConfig, Storage, StorageData, StorageKeys, print_storage_error};
+use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, MORSE_MAX_NUM};
+
+/// Version of the format of the stored data.
+///
+/// Bump it when the format of stored items changes, and add the migration from the previous version to
+/// [`Storage::migrate`]. Version 0 is the storage written by firmwares before versioning.
+pub(crate) const STORAGE_SCHEMA_VERSION: u16 = 1;
+
+/// Sizes that the stored data depends on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct StorageLayout {
+    rows: u8,
+    cols: u8,
+    layers: u8,
+    encoders: u8,
+    combo_max_num: u8,
+    combo_max_length: u8,
+    fork_max_num: u8,
+    morse_max_num: u8,
+    max_patterns_per_key: u8,
+    macro_space_size: u16,
+    /// Max encoded size of `KeyAction`, it changes when key actions are changed
+    action_size: u16,
+}
+
+impl StorageLayout {
+    /// Layout of the current firmware
+    pub(crate) fn new<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>() -> Self {
+        Self::with_size(ROW as u8, COL as u8, NUM_LAYER as u8, NUM_ENCODER as u8)
+    }
+
+    /// Layout of the keymap with the given size, the other sizes are the current firmware's
+    pub(crate) fn with_size(rows: u8, cols: u8, layers: u8, encoders: u8) -> Self {
+        Self {
+            rows,
+            cols,
+            layers,
+            encoders,
+            combo_max_num: COMBO_MAX_NUM as u8,
+            combo_max_length: crate::COMBO_MAX_LENGTH as u8,
+            fork_max_num: FORK_MAX_NUM as u8,
+            morse_max_num: MORSE_MAX_NUM as u8,
+            max_patterns_per_key: crate::MAX_PATTERNS_PER_KEY as u8,
+            macro_space_size: crate::MACRO_SPACE_SIZE as u16,
+            action_size: KeyAction::POSTCARD_MAX_SIZE as u16,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn rows(&self) -> u8 {
+        self.rows
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn cols(&self) -> u8 {
+        self.cols
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn layers(&self) -> u8 {
+        self.layers
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn encoders(&self) -> u8 {
+        self.encoders
+    }
+
+    /// FNV-1a hash of the layout
+    pub(crate) fn fingerprint(&self) -> u32 {
+        [
+            self.rows as u16,
+            self.cols as u16,
+            self.layers as u16,
+            self.encoders as u16,
+            self.combo_max_num as u16,
+            self.combo_max_length as u16,
+            self.fork_max_num as u16,
+            self.morse_max_num as u16,
+            self.max_patterns_per_key as u16,
+            self.macro_space_size,
+            self.action_size,
+        ]
+        .iter()
+        .flat_map(|field| field.to_le_bytes())
+        .fold(0x811c_9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+    }
+}
+
+impl<B: StorageBackend, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Migrate the stored data to the current schema version and layout.
+    ///
+    /// Returns error if the data can't be migrated, then the storage should be erased.
+    pub(crate) async fn migrate(
+        &mut self,
+        from: LocalStorageConfig,
+        #[cfg(feature = "host")] keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+        #[cfg(feature = "host")] encoder_map: &Option<&mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
+    ) -> Result<(), ()> {
+        let layout = StorageLayout::new::<ROW, COL, NUM_LAYER, NUM_ENCODER>();
+        if from.schema_version > STORAGE_SCHEMA_VERSION {
+            warn!(
+                "Storage schema version {} is newer than the firmware's {}, erasing storage",
+                from.schema_version, STORAGE_SCHEMA_VERSION
+            );
+            return Err(());
+        }
+        // Storage before versioning doesn't record the layout, assume that it's not changed
+        let old_layout = if from.schema_version == 0 { layout } else { from.layout };
+        if old_layout.action_size != layout.action_size {
+            warn!("Encoding of key actions is changed, erasing storage");
+            return Err(());
+        }
+        info!(
+            "Migrating storage from schema version {} to {}",
+            from.schema_version, STORAGE_SCHEMA_VERSION
+        );
+
+        #[cfg(feature = "host")]
+        {
+            if from.schema_version == 0 {
+                self.migrate_legacy_keymap(keymap, encoder_map).await?;
+            }
+            self.migrate_macros().await?;
+            if old_layout.combo_max_length != layout.combo_max_length {
+                self.migrate_combos(&old_layout).await?;
+            }
+            if old_layout.max_patterns_per_key > layout.max_patterns_per_key {
+                self.migrate_morses(&old_layout).await?;
+            }
+        }
+
+        self.store_item(
+            StorageKeys::StorageConfig as u32,
+            &StorageData::StorageConfig(LocalStorageConfig::new(true, layout)),
+        )
+        .await
+        .map_err(print_storage_error)
+    }
+
+    /// Move the keymap keys and encoder actions from the keys before versioning, which depend on the keymap size.
+    ///
+    /// Only the actions which differ from the default keymap are moved, and the old items are removed after they're
+    /// moved or skipped.
+    #[cfg(feature = "host")]
+    async fn migrate_legacy_keymap(
+        &mut self,
+        keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+        encoder_map: &Option<&mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
+    ) -> Result<(), ()> {
+        for (layer, layer_data) in keymap.iter().enumerate() {
+            for (row, row_data) in layer_data.iter().enumerate() {
+                for (col, action) in row_data.iter().enumerate() {
+                    let legacy_key = 0x1000 + (layer * COL * ROW + row * COL + col) as u32;
+                    let Some(item) = self.fetch_legacy_item(legacy_key).await? else {
+                        continue;
+                    };
+                    // Skip the stale items written with another keymap size
+                    if let StorageData::VialData(KeymapData::KeymapKey(keymap_key)) = item
+                        && (keymap_key.layer, keymap_key.row, keymap_key.col) == (layer as u8, row as u8, col as u8)
+                        && keymap_key.action != *action
+                    {
+                        self.store_migrated_item(
+                            get_keymap_key(0, &keymap_key),
+                            StorageData::VialData(KeymapData::KeymapKey(keymap_key)),
+                        )
+                        .await?;
+                    }
+                    self.remove_legacy_item(legacy_key).await?;
+                }
+            }
+        }
+
+        if let Some(encoder_map) = encoder_map {
+            for (layer, layer_data) in encoder_map.iter().enumerate() {
+                for (idx, action) in layer_data.iter().enumerate() {
+                    let legacy_key = 0x4000 + (idx + NUM_ENCODER * layer) as u32;
+                    let Some(item) = self.fetch_legacy_item(legacy_key).await? else {
+                        continue;
+                    };
+                    if let StorageData::VialData(KeymapData::Encoder(encoder)) = item
+                        && (encoder.layer, encoder.idx) == (layer as u8, idx as u8)
+                        && (encoder.action.clockwise() != action.clockwise()
+                            || encoder.action.counter_clockwise() != action.counter_clockwise())
+                    {
+                        self.store_migrated_item(
+                            get_encoder_config_key(0, encoder.idx, encoder.layer),
+                            StorageData::VialData(KeymapData::Encoder(encoder)),
+                        )
+                        .await?;
+                    }
+                    self.remove_legacy_item(legacy_key).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pad or truncate the stored macros to the current macro space
+    #[cfg(feature = "host")]
+    async fn migrate_macros(&mut self) -> Result<(), ()> {
+        let mut macros = [0; MACRO_SPACE_SIZE];
+        match self.backend.fetch::<RawItem>(StorageKeys::MacroData as u32).await {
+            // The first byte is the type of the stored data
+            Ok(Some(RawItem(data))) if data.len() != MACRO_SPACE_SIZE + 1 => resize_macros(&data[1..], &mut macros),
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                print_storage_error(e);
+                return Err(());
+            }
+        }
+        self.store_migrated_item(
+            StorageKeys::MacroData as u32,
+            StorageData::VialData(KeymapData::Macro(macros)),
+        )
+        .await
+    }
+
+    /// Re-encode the stored combos of all keymap profiles with the current max combo length
+    #[cfg(feature = "host")]
+    async fn migrate_combos(&mut self, old_layout: &StorageLayout) -> Result<(), ()> {
+        for profile in 0..KEYMAP_PROFILE_NUM as u8 {
+            for idx in 0..old_layout.combo_max_num.min(COMBO_MAX_NUM as u8) {
+                let key = get_combo_key(profile, idx);
+                let combo = match self.fetch_raw_item(key).await? {
+                    Some(RawItem(data)) => decode_combo(&data, old_layout.combo_max_length as usize),
+                    None => continue,
+                };
+                let combo = combo.unwrap_or_else(|| {
+                    warn!("Combo {} doesn't fit into the max combo length, removed", idx);
+                    ComboConfig::empty()
+                });
+                self.store_migrated_item(key, StorageData::VialData(KeymapData::Combo(idx, combo)))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-encode the stored morses of all keymap profiles with the current max number of patterns
+    #[cfg(feature = "host")]
+    async fn migrate_morses(&mut self, old_layout: &StorageLayout) -> Result<(), ()> {
+        for profile in 0..KEYMAP_PROFILE_NUM as u8 {
+            for idx in 0..old_layout.morse_max_num.min(MORSE_MAX_NUM as u8) {
+                let key = get_morse_key(profile, idx);
+                let morse = match self.fetch_raw_item(key).await? {
+                    Some(RawItem(data)) => decode_morse(&data),
+                    None => continue,
+                };
+                let morse = morse.unwrap_or_else(|| {
+                    warn!("Morse {} can't be decoded, removed", idx);
+                    Morse::default()
+                });
+                self.store_migrated_item(key, StorageData::VialData(KeymapData::Morse(idx, morse)))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch an item before versioning, the items which can't be decoded are skipped
+    #[cfg(feature = "host")]
+    async fn fetch_legacy_item(&mut self, key: u32) -> Result<Option<StorageData>, ()> {
+        match self.fetch_item(key).await {
+            Ok(item) => Ok(item),
+            Err(StorageError::Serialization) => Ok(None),
+            Err(e) => {
+                print_storage_error(e);
+                Err(())
+            }
+        }
+    }
+
+    /// Remove an item before versioning, after it's migrated or skipped
+    #[cfg(feature = "host")]
+    async fn remove_legacy_item(&mut self, key: u32) -> Result<(), ()> {
+        self.remove_item(key).await.map_err(print_storage_error)
+    }
+
+    /// Fetch the bytes of an item, the first byte is the type of the stored data
+    #[cfg(feature = "host")]
+    async fn fetch_raw_item(&mut self, key: u32) -> Result<Option<RawItem>, ()> {
+        self.backend.fetch(key).await.map_err(print_storage_error)
+    }
+
+    #[cfg(feature = "host")]
+    async fn store_migrated_item(&mut self, key: u32, item: StorageData) -> Result<(), ()> {
+        self.store_item(key, &item).await.map_err(print_storage_error)
+    }
+}
+
+/// Bytes of a stored item, which are read without decoding
+#[cfg(feature = "host")]
+struct RawItem(heapless::Vec<u8, { get_buffer_size() }>);
+
+#[cfg(feature = "host")]
+impl Value<'_> for RawItem {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        buffer
+            .get_mut(..self.0.len())
+            .ok_or(SerializationError::BufferTooSmall)?
+            .copy_from_slice(&self.0);
+        Ok(self.0.len())
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let data = heapless::Vec::from_slice(buffer).map_err(|_| SerializationError::BufferTooSmall)?;
+        Ok((Self(data), buffer.len()))
+    }
+}
+
+/// Copy macros to a macro space of another size.
+///
+/// Macros are separated by `0`, if the last macro is truncated, it's removed.
+#[cfg(feature = "host")]
+fn resize_macros(old: &[u8], new: &mut [u8]) {
+    let len = old.len().min(new.len());
+    new[..len].copy_from_slice(&old[..len]);
+    new[len..].fill(0);
+    if old.len() > new.len() && new.last().is_some_and(|b| *b != 0) {
+        let end = new.iter().rposition(|b| *b == 0).map_or(0, |end| end + 1);
+        new[end..].fill(0);
+    }
+}
+
+/// Decode a stored combo which has `old_length` actions.
+///
+/// Returns `None` if the combo can't be decoded, or it has more actions than the current max combo length.
+#[cfg(feature = "host")]
+fn decode_combo(data: &[u8], old_length: usize) -> Option<ComboConfig> {
+    // The stored combo is (idx, actions, output, layer) after the type byte
+    let (_idx, mut rest): (u8, _) = postcard::take_from_bytes(data.get(1..)?).ok()?;
+    let mut actions = [KeyAction::No; COMBO_MAX_LENGTH];
+    let mut len = 0;
+    for _ in 0..old_length {
+        let (action, unused): (KeyAction, _) = postcard::take_from_bytes(rest).ok()?;
+        rest = unused;
+        if action != KeyAction::No {
+            *actions.get_mut(len)? = action;
+            len += 1;
+        }
+    }
+    let (output, rest): (KeyAction, _) = postcard::take_from_bytes(rest).ok()?;
+    let (layer, _): (Option<u8>, _) = postcard::take_from_bytes(rest).ok()?;
+    Some(ComboConfig { actions, output, layer })
+}
+
+/// Decode a stored morse, the patterns which exceed the current max number of patterns are dropped
+#[cfg(feature = "host")]
+fn decode_morse(data: &[u8]) -> Option<Morse> {
+    // The stored morse is (idx, profile, patterns) after the type byte
+    let (_idx, rest): (u8, _) = postcard::take_from_bytes(data.get(1..)?).ok()?;
+    let (profile, rest): (MorseProfile, _) = postcard::take_from_bytes(rest).ok()?;
+    let (count, mut rest): (usize, _) = postcard::take_from_bytes(rest).ok()?;
+    let mut morse = Morse {
+        profile,
+        ..Default::default()
+    };
+    for _ in 0..count {
+        let ((pattern, action), unused): ((u16, Action), _) = postcard::take_from_bytes(rest).ok()?;
+        rest = unused;
+        if morse.actions.len() < MAX_PATTERNS_PER_KEY {
+            morse.actions.insert(MorsePattern::from_u16(pattern), action).ok()?;
+        }
+    }
+    Some(morse)
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use embassy_futures::block_on;
+    use rmk_types::keycode::KeyCode;
+    use sequential_storage::cache::NoCache;
+    use sequential_storage::map::store_item;
+
+    use super::*;
+    use crate::combo::Combo;
+    use crate::config::{BehaviorConfig, StorageConfig};
+    use crate::host::storage::KeymapKey;
+    use crate::k;
+    use crate::morse::{HOLD, TAP};
+    use crate::storage::backend::FlashBackend;
+    use crate::storage::dummy_flash::MemoryFlash;
+
+    const FLASH_SIZE: usize = 4 * 4096;
+    type TestFlash = MemoryFlash<FLASH_SIZE>;
+
+    fn storage_config() -> StorageConfig {
+        StorageConfig {
+            num_sectors: 4,
+            ..Default::default()
+        }
+    }
+
+    fn open_storage<const ROW: usize, const COL: usize, const NUM_LAYER: usize>(
+        flash: TestFlash,
+        keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+    ) -> Storage<FlashBackend<TestFlash>, ROW, COL, NUM_LAYER, 0> {
+        block_on(Storage::new(
+            flash,
+            keymap,
+            &None,
+            &storage_config(),
+            &BehaviorConfig::default(),
+        ))
+    }
+
+    fn read_keymap<const ROW: usize, const COL: usize, const NUM_LAYER: usize>(
+        storage: &mut Storage<FlashBackend<TestFlash>, ROW, COL, NUM_LAYER, 0>,
+        default: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+    ) -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
+        let mut keymap = *default;
+        block_on(storage.read_keymap(&mut keymap, &mut None)).unwrap();
+        keymap
+    }
+
+    fn store<const ROW: usize, const COL: usize, const NUM_LAYER: usize>(
+        storage: &mut Storage<FlashBackend<TestFlash>, ROW, COL, NUM_LAYER, 0>,
+        key: u32,
+        item: StorageData,
+    ) {
+        block_on(storage.store_migrated_item(key, item)).unwrap();
+    }
+
+    fn store_raw(flash: &mut TestFlash, key: u32, data: &[u8]) {
+        let mut buffer = [0; 256];
+        block_on(store_item(
+            flash,
+            0..FLASH_SIZE as u32,
+            &mut NoCache::new(),
+            &mut buffer,
+            &key,
+            &data,
+        ))
+        .unwrap();
+    }
+
+    fn keymap_key(layer: u8, row: u8, col: u8, action: KeyAction) -> StorageData {
+        StorageData::VialData(KeymapData::KeymapKey(KeymapKey {
+            row,
+            col,
+            layer,
+            action,
+        }))
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let layout = StorageLayout::new::<2, 3, 2, 0>();
+        assert_eq!(layout.fingerprint(), StorageLayout::new::<2, 3, 2, 0>().fingerprint());
+        assert_ne!(layout.fingerprint(), StorageLayout::new::<3, 2, 2, 0>().fingerprint());
+        assert_ne!(layout.fingerprint(), StorageLayout::new::<2, 3, 2, 1>().fingerprint());
+    }
+
+    #[test]
+    fn test_migrate_legacy_storage() {
+        let keymap = [[[k!(A); 3]; 2]; 2];
+        let mut flash = TestFlash::new();
+
+        // Storage config before versioning: (enable, build_hash)
+        let mut config = [StorageKeys::StorageConfig as u8; 8];
+        let len = postcard::to_slice(&(true, 0x1234_5678u32), &mut config[1..])
+            .unwrap()
+            .len();
+        store_raw(&mut flash, StorageKeys::StorageConfig as u32, &config[..len + 1]);
+        // Keymap keys were stored at `0x1000 + index in the keymap`
+        let mut buffer = [0; 32];
+        let len = keymap_key(1, 1, 2, k!(B)).serialize_into(&mut buffer).unwrap();
+        store_raw(&mut flash, 0x1000 + 3 * 2 + 3 + 2, &buffer[..len]);
+        // A stale key written with another keymap size
+        let len = keymap_key(1, 0, 0, k!(C)).serialize_into(&mut buffer).unwrap();
+        store_raw(&mut flash, 0x1000, &buffer[..len]);
+        // Macros with a smaller macro space
+        store_raw(
+            &mut flash,
+            StorageKeys::MacroData as u32,
+            &[StorageKeys::MacroData as u8, 4, 0, 5, 0],
+        );
+
+        let mut storage = open_storage(flash, &keymap);
+        let migrated = read_keymap(&mut storage, &keymap);
+        assert_eq!(migrated[1][1][2], k!(B));
+        assert_eq!(migrated[1][0][0], k!(A));
+        assert_eq!(migrated[0][0][0], k!(A));
+
+        let mut macros = [0xFF; MACRO_SPACE_SIZE];
+        block_on(storage.read_macro_cache(&mut macros)).unwrap();
+        assert_eq!(macros[..4], [4, 0, 5, 0]);
+        assert!(macros[4..].iter().all(|b| *b == 0));
+
+        let config = block_on(storage.read_storage_config()).unwrap();
+        assert!(config.is_current(&StorageLayout::new::<2, 3, 2, 0>()));
+
+        // The legacy items are removed, both the migrated and the stale ones
+        for key in [0x1000, 0x1000 + 3 * 2 + 3 + 2] {
+            assert!(block_on(storage.fetch_raw_item(key)).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_migrate_layout_change() {
+        let keymap = [[[k!(A); 3]; 2]; 2];
+        let mut storage = open_storage(TestFlash::new(), &keymap);
+        let custom = keymap_key(1, 1, 2, k!(B));
+        let StorageData::VialData(KeymapData::KeymapKey(key)) = &custom else {
+            unreachable!()
+        };
+        store(&mut storage, get_keymap_key(0, key), custom.clone());
+        let combo = ComboConfig::new([k!(C), k!(D)], k!(E), None);
+        store(
+            &mut storage,
+            get_combo_key(0, 0),
+            StorageData::VialData(KeymapData::Combo(0, combo)),
+        );
+
+        // Both the keymap and the number of layers get larger
+        let larger_keymap = [[[k!(X); 4]; 3]; 3];
+        let mut storage = open_storage(storage.backend.into_inner(), &larger_keymap);
+        let migrated = read_keymap(&mut storage, &larger_keymap);
+        assert_eq!(migrated[1][1][2], k!(B));
+        assert_eq!(migrated[0][0][0], k!(A));
+        assert_eq!(migrated[1][2][3], k!(X));
+        assert_eq!(migrated[2][0][0], k!(X));
+
+        let mut combos: [Option<Combo>; COMBO_MAX_NUM] = Default::default();
+        block_on(storage.read_combos(&mut combos)).unwrap();
+        let combo = combos[0].as_ref().unwrap();
+        assert_eq!(combo.config.actions[..2], [k!(C), k!(D)]);
+        assert_eq!(combo.config.output, k!(E));
+
+        let config = block_on(storage.read_storage_config()).unwrap();
+        assert!(config.is_current(&StorageLayout::new::<3, 4, 3, 0>()));
+    }
+
+    #[test]
+    fn test_newer_schema_is_erased() {
+        let keymap = [[[k!(A); 3]; 2]; 2];
+        let mut storage = open_storage(TestFlash::new(), &keymap);
+        store(
+            &mut storage,
+            get_keymap_key(
+                0,
+                &KeymapKey {
+                    row: 0,
+                    col: 0,
+                    layer: 0,
+                    action: k!(B),
+                },
+            ),
+            keymap_key(0, 0, 0, k!(B)),
+        );
+        let mut config = LocalStorageConfig::new(true, StorageLayout::new::<2, 3, 2, 0>());
+        config.schema_version = STORAGE_SCHEMA_VERSION + 1;
+        store(
+            &mut storage,
+            StorageKeys::StorageConfig as u32,
+            StorageData::StorageConfig(config),
+        );
+
+        let mut storage = open_storage(storage.backend.into_inner(), &keymap);
+        assert_eq!(read_keymap(&mut storage, &keymap)[0][0][0], k!(A));
+        let config = block_on(storage.read_storage_config()).unwrap();
+        assert!(config.is_current(&StorageLayout::new::<2, 3, 2, 0>()));
+    }
+
+    #[test]
+    fn test_resize_macros() {
+        let mut macros = [0xFF; 6];
+        resize_macros(&[1, 2, 0, 3], &mut macros);
+        assert_eq!(macros, [1, 2, 0, 3, 0, 0]);
+
+        // The truncated macro is removed
+        let mut macros = [0xFF; 4];
+        resize_macros(&[1, 0, 2, 3, 4, 0], &mut macros);
+        assert_eq!(macros, [1, 0, 0, 0]);
+        resize_macros(&[1, 2, 3, 0, 4, 0], &mut macros);
+        assert_eq!(macros, [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_decode_combo() {
+        let mut buffer = [0; 128];
+        buffer[0] = StorageKeys::ComboData as u8;
+
+        // Combos with a longer max length fit when they don't use the extra actions
+        let mut actions = [KeyAction::No; COMBO_MAX_LENGTH + 2];
+        actions[0] = k!(A);
+        actions[1] = k!(B);
+        let len = postcard::to_slice(&(3u8, actions, k!(C), Some(1u8)), &mut buffer[1..])
+            .unwrap()
+            .len();
+        let combo = decode_combo(&buffer[..len + 1], COMBO_MAX_LENGTH + 2).unwrap();
+        assert_eq!(combo.actions[..2], [k!(A), k!(B)]);
+        assert!(combo.actions[2..].iter().all(|a| *a == KeyAction::No));
+        assert_eq!(combo.output, k!(C));
+        assert_eq!(combo.layer, Some(1));
+
+        let actions = [k!(A); COMBO_MAX_LENGTH + 2];
+        let len = postcard::to_slice(&(3u8, actions, k!(C), None::<u8>), &mut buffer[1..])
+            .unwrap()
+            .len();
+        assert!(decode_combo(&buffer[..len + 1], COMBO_MAX_LENGTH + 2).is_none());
+    }
+
+    #[test]
+    fn test_decode_morse() {
+        let mut morse = Morse::<{ MAX_PATTERNS_PER_KEY + 2 }> {
+            profile: MorseProfile::const_default(),
+            actions: heapless::LinearMap::default(),
+        };
+        morse.actions.insert(TAP, Action::Key(KeyCode::A)).unwrap();
+        morse.actions.insert(HOLD, Action::Key(KeyCode::B)).unwrap();
+        for i in 0..MAX_PATTERNS_PER_KEY {
+            morse
+                .actions
+                .insert(MorsePattern::from_u16(0b100 + i as u16), Action::Key(KeyCode::C))
+                .unwrap();
+        }
+
+        let mut buffer = [0; 256];
+        buffer[0] = StorageKeys::MorseData as u8;
+        let len = postcard::to_slice(&(0u8, morse), &mut buffer[1..]).unwrap().len();
+        let decoded = decode_morse(&buffer[..len + 1]).unwrap();
+        assert_eq!(decoded.actions.len(), MAX_PATTERNS_PER_KEY);
+        assert_eq!(decoded.actions.get(&TAP), Some(&Action::Key(KeyCode::A)));
+        assert_eq!(decoded.actions.get(&HOLD), Some(&Action::Key(KeyCode::B)));
+    }
+}
@@ -1,35 +1,87 @@
+pub mod backend;
 pub mod dummy_flash;
+#[cfg(feature = "host")]
+pub(crate) mod keymap_profile;
+pub(crate) mod migration;
+mod request;
+pub(crate) mod runtime_state;
+pub mod stats;
+pub mod user;
 
 use core::fmt::Debug;
-use core::ops::Range;
 
 use embassy_embedded_hal::adapter::BlockingAsync;
+use embassy_futures::select::{Either, select};
 use embassy_sync::signal::Signal;
-use embassy_time::Duration;
+use embassy_time::{Duration, Timer};
 use embedded_storage::nor_flash::NorFlash;
 use embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash;
 use rmk_types::action::MorseProfile;
-use sequential_storage::Error as SSError;
-use sequential_storage::cache::NoCache;
-use sequential_storage::map::{SerializationError, Value, fetch_item, store_item};
+use sequential_storage::map::{SerializationError, Value};
 #[cfg(feature = "host")]
 use {
     crate::host::storage::{KeymapData, KeymapKey},
+    crate::keymap::KeyMap,
+    core::cell::RefCell,
+    keymap_profile::{KeymapProfileConfig, KeymapProfiles, respond_keymap_profile_operation},
     rmk_types::action::{EncoderAction, KeyAction},
 };
 
 #[cfg(feature = "_ble")]
 use crate::ble::profile::ProfileInfo;
 use crate::channel::FLASH_CHANNEL;
-use crate::config::StorageConfig;
+use crate::config::{self, PersistedStateConfig, StorageConfig};
+use crate::input_device::analog_matrix::AnalogKeyCalibration;
 #[cfg(all(feature = "_ble", feature = "split"))]
 use crate::split::ble::PeerAddress;
-use crate::{BUILD_HASH, config};
+use crate::storage::backend::{FlashBackend, StorageBackend, StorageError};
+use crate::storage::migration::StorageLayout;
+use crate::storage::request::StorageRequest;
+use crate::storage::runtime_state::RuntimeState;
+use crate::storage::stats::STORAGE_STATS_READ;
+use crate::storage::user::{USER_DATA_READ, UserDataBuffer};
 
 /// Signal to synchronize the flash operation status, usually used outside of the flash task.
 /// True if the flash operation is finished correctly, false if the flash operation is finished with error.
 pub(crate) static FLASH_OPERATION_FINISHED: Signal<crate::RawMutex, bool> = Signal::new();
 
+/// Flush waited by [`flush`], the result is sent back after the pending writes are saved
+static FLASH_FLUSHED: StorageRequest<Result<(), StorageError>> = StorageRequest::new();
+
+/// Max number of keymap writes which wait to be saved, the oldest one is saved when it's full
+#[cfg(feature = "host")]
+const PENDING_WRITES_NUM: usize = 8;
+
+/// Pending writes are saved when no flash operation is received for this time
+const WRITE_DELAY: Duration = Duration::from_secs(2);
+
+/// Save the pending writes of the storage now, it returns after they're saved, or saving them failed.
+///
+/// Keymap changes from the host are saved after the storage is idle, call it to save them immediately,
+/// e.g. before cutting the power.
+pub async fn flush() -> Result<(), StorageError> {
+    FLASH_FLUSHED
+        .request(|seq| FlashOperationMessage::Flush(Some(seq)))
+        .await
+}
+
+/// Save the pending writes before the keyboard is rebooted, without waiting forever if the storage task isn't running
+pub(crate) async fn flush_before_reboot() {
+    match embassy_time::with_timeout(Duration::from_secs(1), flush()).await {
+        Ok(Ok(())) => (),
+        _ => warn!("Failed to save the pending writes before rebooting"),
+    }
+}
+
+/// Ask the storage task to save the pending writes before the keyboard sleeps, the power might be cut while sleeping.
+///
+/// It doesn't wait for the writes to be saved, so that it can be called where waiting isn't possible.
+pub(crate) fn request_flush() {
+    if FLASH_CHANNEL.try_send(FlashOperationMessage::Flush(None)).is_err() {
+        warn!("Failed to request saving the pending writes, the flash channel is full");
+    }
+}
+
 // Message send from other tasks, which will do saving or clearing operation
 #[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug)]
@@ -71,6 +123,46 @@ pub(crate) enum FlashOperationMessage {
     PriorIdleTime(u16),
     // Default morse profile containing all morse/tap-hold settings (mode, timeouts, unilateral_tap)
     MorseDefaultProfile(MorseProfile),
+    // All behavior settings, e.g. restored from a backup
+    BehaviorConfig(BehaviorConfig),
+    // CPI of the motion sensor: (sensor id, cpi)
+    MotionSensorCpi(u8, u16),
+    // Calibrated center of the joystick: (joystick id, center of each axis)
+    JoystickCenter(u8, [i16; 3]),
+    // Calibration of the analog key: (row, col, [rest, bottom])
+    AnalogCalibration(u8, u8, [u16; 2]),
+    // Keymap profile operations, the outcome is sent to `KEYMAP_PROFILE_OPERATION` if there's a sequence number
+    // Switch to the keymap profile
+    #[cfg(feature = "host")]
+    SwitchKeymapProfile(u8, Option<u32>),
+    // Copy a keymap profile: (source, destination)
+    #[cfg(feature = "host")]
+    CopyKeymapProfile(u8, u8, Option<u32>),
+    // Reset a keymap profile to the default keymap
+    #[cfg(feature = "host")]
+    ClearKeymapProfile(u8, Option<u32>),
+    // Bind a keymap profile to a connection: (target, profile), see `KeymapProfileConfig::bind`
+    #[cfg(feature = "host")]
+    BindKeymapProfile(u8, u8, Option<u32>),
+    // Begin to stage the records of an imported backup, the records staged before are discarded
+    #[cfg(feature = "host")]
+    BeginBackupImport,
+    // Record of an imported backup, which is staged until the import is ended
+    #[cfg(feature = "host")]
+    BackupRecord(StorageData),
+    // End the import of a backup, the staged records are applied if it's true, or discarded otherwise
+    #[cfg(feature = "host")]
+    EndBackupImport(bool),
+    // User data: (key, serialized value)
+    UserData(u16, UserDataBuffer),
+    // Read the user data: (key, sequence number), the result is sent to `USER_DATA_READ`
+    ReadUserData(u16, u32),
+    // Runtime state to be saved with the pending writes
+    RuntimeState(RuntimeState),
+    // Save the pending writes now, the result is sent to `FLASH_FLUSHED` if there's a sequence number
+    Flush(Option<u32>),
+    // Read the statistics, the result is sent to `STORAGE_STATS_READ`
+    ReadStats(u32),
 }
 
 /// StorageKeys is the prefix digit stored in the flash, it's used to identify the type of the stored data.
@@ -95,6 +187,16 @@ pub(crate) enum StorageKeys {
     ForkData = 8,
     #[cfg(feature = "host")]
     MorseData = 9,
+    MotionSensorCpi = 10,
+    JoystickCenter = 11,
+    AnalogCalibration = 12,
+    #[cfg(feature = "host")]
+    KeymapProfile = 13,
+    UserData = 14,
+    EraseCycles = 15,
+    RuntimeState = 16,
+    /// Type of the behavior config saved with a version, it's saved under the key of `BehaviorConfig`
+    VersionedBehaviorConfig = 17,
     #[cfg(all(feature = "_ble", feature = "split"))]
     PeerAddress = 0xED,
     #[cfg(feature = "_ble")]
@@ -122,6 +224,15 @@ impl StorageKeys {
             8 => Some(StorageKeys::ForkData),
             #[cfg(feature = "host")]
             9 => Some(StorageKeys::MorseData),
+            10 => Some(StorageKeys::MotionSensorCpi),
+            11 => Some(StorageKeys::JoystickCenter),
+            12 => Some(StorageKeys::AnalogCalibration),
+            #[cfg(feature = "host")]
+            13 => Some(StorageKeys::KeymapProfile),
+            14 => Some(StorageKeys::UserData),
+            15 => Some(StorageKeys::EraseCycles),
+            16 => Some(StorageKeys::RuntimeState),
+            17 => Some(StorageKeys::VersionedBehaviorConfig),
             #[cfg(all(feature = "_ble", feature = "split"))]
             0xED => Some(StorageKeys::PeerAddress),
             #[cfg(feature = "_ble")]
@@ -141,8 +252,16 @@ pub(crate) enum StorageData {
     LayoutConfig(LayoutConfig),
     BehaviorConfig(BehaviorConfig),
     ConnectionType(u8),
+    MotionSensorCpi(u8, u16),
+    JoystickCenter(u8, [i16; 3]),
+    AnalogCalibration(u8, u8, [u16; 2]),
+    UserData(u16, UserDataBuffer),
+    EraseCycles(u32),
+    RuntimeState(RuntimeState),
     #[cfg(feature = "host")]
     VialData(KeymapData),
+    #[cfg(feature = "host")]
+    KeymapProfile(KeymapProfileConfig),
     #[cfg(all(feature = "_ble", feature = "split"))]
     PeerAddress(PeerAddress),
     #[cfg(feature = "_ble")]
@@ -151,12 +270,17 @@ pub(crate) enum StorageData {
     ActiveBleProfile(u8),
 }
 
-/// Get the key to retrieve the keymap key from the storage.
+/// Get the key to retrieve the keymap key of the keymap profile from the storage.
+///
+/// The key doesn't depend on the size of the keymap, so the stored keys are kept when the keymap size changes.
+/// Up to 32 layers and 8 profiles are supported, the keys of profile 0 are the same as the keys before profiles.
 #[cfg(feature = "host")]
-pub(crate) fn get_keymap_key<const ROW: usize, const COL: usize, const NUM_LAYER: usize>(
-    keymap_key: &KeymapKey,
-) -> u32 {
-    0x1000 + (keymap_key.layer as usize * COL * ROW + keymap_key.row as usize * COL + keymap_key.col as usize) as u32
+pub(crate) fn get_keymap_key(profile: u8, keymap_key: &KeymapKey) -> u32 {
+    0x0100_0000
+        + ((profile as u32) << 21)
+        + ((keymap_key.layer as u32) << 16)
+        + ((keymap_key.row as u32) << 8)
+        + keymap_key.col as u32
 }
 
 /// Get the key to retrieve the bond info from the storage.
@@ -164,16 +288,18 @@ pub(crate) fn get_bond_info_key(slot_num: u8) -> u32 {
     0x2000 + slot_num as u32
 }
 
-/// Get the key to retrieve the combo from the storage.
+/// Get the key to retrieve the combo of the keymap profile from the storage.
 #[cfg(feature = "host")]
-pub(crate) fn get_combo_key(idx: u8) -> u32 {
-    0x3000 + idx as u32
+pub(crate) fn get_combo_key(profile: u8, idx: u8) -> u32 {
+    0x3000 + ((profile as u32) << 8) + idx as u32
 }
 
-/// Get the key to retrieve the encoder config from the storage.
+/// Get the key to retrieve the encoder config of the keymap profile from the storage.
+///
+/// Like [`get_keymap_key`], the key doesn't depend on the number of encoders.
 #[cfg(feature = "host")]
-pub(crate) fn get_encoder_config_key<const NUM_ENCODER: usize>(idx: u8, layer: u8) -> u32 {
-    0x4000 + (idx as usize + NUM_ENCODER * layer as usize) as u32
+pub(crate) fn get_encoder_config_key(profile: u8, idx: u8, layer: u8) -> u32 {
+    0x0200_0000 + ((profile as u32) << 16) + ((layer as u32) << 8) + idx as u32
 }
 
 #[cfg(feature = "host")]
@@ -186,10 +312,51 @@ pub(crate) fn get_peer_address_key(peer_id: u8) -> u32 {
     0x6000 + peer_id as u32
 }
 
-/// Get the key to retrieve the tap dance from the storage.
+/// Get the key to retrieve the tap dance of the keymap profile from the storage.
+#[cfg(feature = "host")]
+pub(crate) fn get_morse_key(profile: u8, idx: u8) -> u32 {
+    0x7000 + ((profile as u32) << 8) + idx as u32
+}
+
+/// Get the key to store the keymap data of the keymap profile.
+///
+/// Macros and forks are shared by all profiles.
+#[cfg(feature = "host")]
+pub(crate) fn get_keymap_data_key(profile: u8, data: &KeymapData) -> u32 {
+    match data {
+        KeymapData::Macro(_) => StorageKeys::MacroData as u32,
+        KeymapData::KeymapKey(keymap_key) => get_keymap_key(profile, keymap_key),
+        KeymapData::Encoder(encoder) => get_encoder_config_key(profile, encoder.idx, encoder.layer),
+        KeymapData::Combo(idx, _) => get_combo_key(profile, *idx),
+        KeymapData::Fork(idx, _) => get_fork_key(*idx),
+        KeymapData::Morse(idx, _) => get_morse_key(profile, *idx),
+    }
+}
+
+/// Get the key to retrieve the CPI of the motion sensor from the storage.
+pub(crate) fn get_motion_sensor_cpi_key(id: u8) -> u32 {
+    0x8000 + id as u32
+}
+
+/// Get the key to retrieve the calibrated center of the joystick from the storage.
+pub(crate) fn get_joystick_center_key(id: u8) -> u32 {
+    0x9000 + id as u32
+}
+
+/// Get the key to retrieve the calibration of the analog key from the storage.
+pub(crate) fn get_analog_calibration_key(row: u8, col: u8) -> u32 {
+    0xA000 + ((row as u32) << 8) + col as u32
+}
+
+/// Get the key to retrieve the user data from the storage.
+pub(crate) fn get_user_data_key(key: u16) -> u32 {
+    0x0300_0000 + key as u32
+}
+
+/// Get the key to stage the record of an imported backup, until the whole backup is verified
 #[cfg(feature = "host")]
-pub(crate) fn get_morse_key(idx: u8) -> u32 {
-    0x7000 + idx as u32
+pub(crate) fn get_backup_staging_key(idx: u16) -> u32 {
+    0x0400_0000 + idx as u32
 }
 
 /// Convert postcard::Error to SerializationError
@@ -237,6 +404,14 @@ impl StorageData {
             Self::LayoutConfig(_) => StorageKeys::LayoutConfig as u32,
             Self::BehaviorConfig(_) => StorageKeys::BehaviorConfig as u32,
             Self::ConnectionType(_) => StorageKeys::ConnectionType as u32,
+            Self::MotionSensorCpi(_, _) => StorageKeys::MotionSensorCpi as u32,
+            Self::JoystickCenter(_, _) => StorageKeys::JoystickCenter as u32,
+            Self::AnalogCalibration(_, _, _) => StorageKeys::AnalogCalibration as u32,
+            Self::UserData(_, _) => StorageKeys::UserData as u32,
+            Self::EraseCycles(_) => StorageKeys::EraseCycles as u32,
+            Self::RuntimeState(_) => StorageKeys::RuntimeState as u32,
+            #[cfg(feature = "host")]
+            Self::KeymapProfile(_) => StorageKeys::KeymapProfile as u32,
             #[cfg(all(feature = "_ble", feature = "split"))]
             Self::PeerAddress(_) => StorageKeys::PeerAddress as u32,
             #[cfg(feature = "_ble")]
@@ -265,8 +440,27 @@ impl Value<'_> for StorageData {
         match self {
             Self::StorageConfig(d) => ser_storage_variant!(buffer, StorageKeys::StorageConfig, d),
             Self::LayoutConfig(d) => ser_storage_variant!(buffer, StorageKeys::LayoutConfig, d),
-            Self::BehaviorConfig(d) => ser_storage_variant!(buffer, StorageKeys::BehaviorConfig, d),
+            Self::BehaviorConfig(d) => {
+                buffer[0] = StorageKeys::VersionedBehaviorConfig as u8;
+                Ok(d.serialize_into(&mut buffer[1..])? + 1)
+            }
             Self::ConnectionType(d) => ser_storage_variant!(buffer, StorageKeys::ConnectionType, d),
+            Self::MotionSensorCpi(id, cpi) => {
+                ser_storage_variant!(buffer, StorageKeys::MotionSensorCpi, &(*id, *cpi))
+            }
+            Self::JoystickCenter(id, center) => {
+                ser_storage_variant!(buffer, StorageKeys::JoystickCenter, &(*id, *center))
+            }
+            Self::AnalogCalibration(row, col, calibration) => {
+                ser_storage_variant!(buffer, StorageKeys::AnalogCalibration, &(*row, *col, *calibration))
+            }
+            Self::UserData(key, data) => {
+                ser_storage_variant!(buffer, StorageKeys::UserData, &(*key, data.as_slice()))
+            }
+            Self::EraseCycles(d) => ser_storage_variant!(buffer, StorageKeys::EraseCycles, d),
+            Self::RuntimeState(d) => ser_storage_variant!(buffer, StorageKeys::RuntimeState, d),
+            #[cfg(feature = "host")]
+            Self::KeymapProfile(d) => ser_storage_variant!(buffer, StorageKeys::KeymapProfile, d),
             #[cfg(all(feature = "_ble", feature = "split"))]
             Self::PeerAddress(d) => ser_storage_variant!(buffer, StorageKeys::PeerAddress, d),
             #[cfg(feature = "_ble")]
@@ -290,8 +484,15 @@ impl Value<'_> for StorageData {
 
         match key {
             StorageKeys::StorageConfig => {
-                let (data, unused) =
-                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let (data, unused) = match postcard::take_from_bytes(&buffer[1..]) {
+                    Ok(result) => result,
+                    Err(_) => {
+                        // Storage config of older firmwares, which only has `enable` and the build hash
+                        let ((enable, _build_hash), unused): ((bool, u32), _) =
+                            postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                        (LocalStorageConfig::legacy(enable), unused)
+                    }
+                };
                 let size = buffer.len() - unused.len();
                 Ok((Self::StorageConfig(data), size))
             }
@@ -302,17 +503,66 @@ impl Value<'_> for StorageData {
                 Ok((Self::LayoutConfig(data), size))
             }
             StorageKeys::BehaviorConfig => {
+                // Behavior config saved before versioning
                 let (data, unused) =
                     postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
                 let size = buffer.len() - unused.len();
                 Ok((Self::BehaviorConfig(data), size))
             }
+            StorageKeys::VersionedBehaviorConfig => {
+                let (data, size) = BehaviorConfig::deserialize_from(&buffer[1..])?;
+                Ok((Self::BehaviorConfig(data), size + 1))
+            }
             StorageKeys::ConnectionType => {
                 let (data, unused) =
                     postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
                 let size = buffer.len() - unused.len();
                 Ok((Self::ConnectionType(data), size))
             }
+            StorageKeys::MotionSensorCpi => {
+                let ((id, cpi), unused) =
+                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let size = buffer.len() - unused.len();
+                Ok((Self::MotionSensorCpi(id, cpi), size))
+            }
+            StorageKeys::JoystickCenter => {
+                let ((id, center), unused) =
+                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let size = buffer.len() - unused.len();
+                Ok((Self::JoystickCenter(id, center), size))
+            }
+            StorageKeys::AnalogCalibration => {
+                let ((row, col, calibration), unused) =
+                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let size = buffer.len() - unused.len();
+                Ok((Self::AnalogCalibration(row, col, calibration), size))
+            }
+            StorageKeys::UserData => {
+                let ((key, data), unused): ((u16, &[u8]), _) =
+                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let data = UserDataBuffer::from_slice(data).map_err(|_| SerializationError::InvalidFormat)?;
+                let size = buffer.len() - unused.len();
+                Ok((Self::UserData(key, data), size))
+            }
+            StorageKeys::EraseCycles => {
+                let (data, unused) =
+                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let size = buffer.len() - unused.len();
+                Ok((Self::EraseCycles(data), size))
+            }
+            StorageKeys::RuntimeState => {
+                let (data, unused) =
+                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let size = buffer.len() - unused.len();
+                Ok((Self::RuntimeState(data), size))
+            }
+            #[cfg(feature = "host")]
+            StorageKeys::KeymapProfile => {
+                let (data, unused) =
+                    postcard::take_from_bytes(&buffer[1..]).map_err(postcard_error_to_serialization_error)?;
+                let size = buffer.len() - unused.len();
+                Ok((Self::KeymapProfile(data), size))
+            }
             #[cfg(all(feature = "_ble", feature = "split"))]
             StorageKeys::PeerAddress => {
                 let (data, unused) =
@@ -352,7 +602,38 @@ impl Value<'_> for StorageData {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub(crate) struct LocalStorageConfig {
     enable: bool,
-    build_hash: u32,
+    /// Version of the format of the stored data, see [`migration::STORAGE_SCHEMA_VERSION`]
+    schema_version: u16,
+    /// Fingerprint of `layout`
+    fingerprint: u32,
+    /// Sizes of the keymap and the behaviors that the stored data was written with
+    layout: StorageLayout,
+}
+
+impl LocalStorageConfig {
+    fn new(enable: bool, layout: StorageLayout) -> Self {
+        Self {
+            enable,
+            schema_version: migration::STORAGE_SCHEMA_VERSION,
+            fingerprint: layout.fingerprint(),
+            layout,
+        }
+    }
+
+    /// Storage config of older firmwares, whose schema version and layout are unknown
+    fn legacy(enable: bool) -> Self {
+        Self {
+            enable,
+            schema_version: 0,
+            fingerprint: 0,
+            layout: StorageLayout::default(),
+        }
+    }
+
+    /// Whether the stored data is written by a firmware with the same schema version and layout
+    fn is_current(&self, layout: &StorageLayout) -> bool {
+        self.schema_version == migration::STORAGE_SCHEMA_VERSION && self.fingerprint == layout.fingerprint()
+    }
 }
 
 #[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, postcard::experimental::max_size::MaxSize)]
@@ -362,6 +643,12 @@ pub(crate) struct LayoutConfig {
     layout_option: u32,
 }
 
+/// Version of the saved behavior config.
+///
+/// Bump it when a group of settings is appended to [`BehaviorConfig`]. The groups added after the version of a saved
+/// config are `None` when it's read, then the firmware's settings are kept.
+const BEHAVIOR_CONFIG_VERSION: u8 = 1;
+
 #[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, postcard::experimental::max_size::MaxSize)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub(crate) struct BehaviorConfig {
@@ -379,6 +666,91 @@ pub(crate) struct BehaviorConfig {
     // Interval for tapping capslock.
     // macOS has special processing of capslock, when tapping capslock, the tap interval should be another value
     pub(crate) tap_capslock_interval: u16,
+
+    // Settings added in version 1, they're saved after the fields above
+    #[serde(skip)]
+    pub(crate) v1: Option<BehaviorConfigV1>,
+}
+
+/// Behavior settings added in version 1 of the saved behavior config
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, postcard::experimental::max_size::MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct BehaviorConfigV1 {
+    // Tri-layer: [upper, lower, adjust]
+    pub(crate) tri_layer: Option<[u8; 3]>,
+    // Whether flow tap is enabled
+    pub(crate) enable_flow_tap: bool,
+    // Timeout of Caps Word in ms
+    pub(crate) caps_word_timeout: u16,
+    pub(crate) mouse_key: config::MouseKeyConfig,
+}
+
+impl From<&config::BehaviorConfig> for BehaviorConfig {
+    fn from(behavior: &config::BehaviorConfig) -> Self {
+        Self {
+            prior_idle_time: behavior.morse.prior_idle_time.as_millis() as u16,
+            morse_default_profile: behavior.morse.default_profile,
+
+            combo_timeout: behavior.combo.timeout.as_millis() as u16,
+            one_shot_timeout: behavior.one_shot.timeout.as_millis() as u16,
+            tap_interval: behavior.tap.tap_interval,
+            tap_capslock_interval: behavior.tap.tap_capslock_interval,
+
+            v1: Some(BehaviorConfigV1 {
+                tri_layer: behavior.tri_layer,
+                enable_flow_tap: behavior.morse.enable_flow_tap,
+                caps_word_timeout: behavior.caps_word.timeout.as_millis() as u16,
+                mouse_key: behavior.mouse_key,
+            }),
+        }
+    }
+}
+
+impl BehaviorConfig {
+    /// Apply the saved settings to the behavior config
+    pub(crate) fn apply_to(&self, behavior: &mut config::BehaviorConfig) {
+        behavior.morse.prior_idle_time = Duration::from_millis(self.prior_idle_time as u64);
+        behavior.morse.default_profile = self.morse_default_profile;
+
+        behavior.combo.timeout = Duration::from_millis(self.combo_timeout as u64);
+        behavior.one_shot.timeout = Duration::from_millis(self.one_shot_timeout as u64);
+        behavior.tap.tap_interval = self.tap_interval;
+        behavior.tap.tap_capslock_interval = self.tap_capslock_interval;
+
+        if let Some(v1) = &self.v1 {
+            behavior.tri_layer = v1.tri_layer;
+            behavior.morse.enable_flow_tap = v1.enable_flow_tap;
+            behavior.caps_word.timeout = Duration::from_millis(v1.caps_word_timeout as u64);
+            behavior.mouse_key = v1.mouse_key;
+        }
+    }
+
+    /// Serialize the version, followed by the fields of each version
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let version = if self.v1.is_some() { BEHAVIOR_CONFIG_VERSION } else { 0 };
+        let mut size = postcard::to_slice(&(version, self), buffer)
+            .map_err(postcard_error_to_serialization_error)?
+            .len();
+        if let Some(v1) = &self.v1 {
+            size += postcard::to_slice(v1, &mut buffer[size..])
+                .map_err(postcard_error_to_serialization_error)?
+                .len();
+        }
+        Ok(size)
+    }
+
+    /// Deserialize the fields of the saved version.
+    ///
+    /// The buffer should contain only the saved config, the fields of newer versions at the end are skipped.
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let ((version, mut config), unused): ((u8, Self), _) =
+            postcard::take_from_bytes(buffer).map_err(postcard_error_to_serialization_error)?;
+        if version >= 1 {
+            let (v1, _) = postcard::take_from_bytes(unused).map_err(postcard_error_to_serialization_error)?;
+            config.v1 = Some(v1);
+        }
+        Ok((config, buffer.len()))
+    }
 }
 
 pub fn async_flash_wrapper<F: NorFlash>(flash: F) -> BlockingAsync<F> {
@@ -389,8 +761,8 @@ pub fn async_flash_wrapper<F: NorFlash>(flash: F) -> BlockingAsync<F> {
 pub async fn new_storage_for_split_peripheral<F: AsyncNorFlash>(
     flash: F,
     storage_config: StorageConfig,
-) -> Storage<F, 0, 0, 0, 0> {
-    Storage::<F, 0, 0, 0, 0>::new(
+) -> Storage<FlashBackend<F>, 0, 0, 0, 0> {
+    Storage::<FlashBackend<F>, 0, 0, 0, 0>::new(
         flash,
         #[cfg(feature = "host")]
         &[],
@@ -403,34 +775,42 @@ pub async fn new_storage_for_split_peripheral<F: AsyncNorFlash>(
 }
 
 pub struct Storage<
-    F: AsyncNorFlash,
+    B: StorageBackend,
     const ROW: usize,
     const COL: usize,
     const NUM_LAYER: usize,
     const NUM_ENCODER: usize = 0,
 > {
-    pub(crate) flash: F,
-    pub(crate) storage_range: Range<u32>,
-    pub(crate) buffer: [u8; get_buffer_size()],
+    /// Backend where the items are saved
+    pub(crate) backend: B,
+    /// Keymap profiles, the keymap items are read and written under the active profile
+    #[cfg(feature = "host")]
+    pub(crate) keymap_profiles: KeymapProfiles,
+    /// Erase cycles before the backend is created, the erase cycles of the backend are added to it
+    pub(crate) erase_cycles_base: u32,
+    /// Erase cycles which are saved in the storage
+    pub(crate) saved_erase_cycles: u32,
+    /// Keymap writes which wait to be saved: (key, data), only the newest write of a key is kept
+    #[cfg(feature = "host")]
+    pub(crate) pending_writes: heapless::Vec<(u32, KeymapData), PENDING_WRITES_NUM>,
+    /// Number of the staged records of an imported backup, `None` if no backup is being staged
+    #[cfg(feature = "host")]
+    pub(crate) staged_backup: Option<u16>,
+    /// Runtime states which are saved and restored at boot
+    pub(crate) persisted_state: PersistedStateConfig,
+    /// Runtime state which waits to be saved
+    pub(crate) pending_state: Option<RuntimeState>,
 }
 
 /// Read out storage config, update and then save back.
 /// This macro applies to only some of the configs.
 macro_rules! update_storage_field {
-    ($f: expr, $buf: expr, $cache:expr, $key:ident, $field:ident, $range:expr) => {
-        if let Ok(Some(StorageData::$key(mut saved))) =
-            fetch_item::<u32, StorageData, _>($f, $range, $cache, $buf, &(StorageKeys::$key as u32)).await
-        {
+    ($storage: expr, $key:ident, $field:ident) => {
+        if let Ok(Some(StorageData::$key(mut saved))) = $storage.fetch_item(StorageKeys::$key as u32).await {
             saved.$field = $field;
-            store_item::<u32, StorageData, _>(
-                $f,
-                $range,
-                $cache,
-                $buf,
-                &(StorageKeys::$key as u32),
-                &StorageData::$key(saved),
-            )
-            .await
+            $storage
+                .store_item(StorageKeys::$key as u32, &StorageData::$key(saved))
+                .await
         } else {
             Ok(())
         }
@@ -438,8 +818,9 @@ macro_rules! update_storage_field {
 }
 
 impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
-    Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>
+    Storage<FlashBackend<F>, ROW, COL, NUM_LAYER, NUM_ENCODER>
 {
+    /// Create the storage on the internal flash, see [`FlashBackend::new`] for the used range of the flash
     pub async fn new(
         flash: F,
         #[cfg(feature = "host")] keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
@@ -447,54 +828,73 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         storage_config: &StorageConfig,
         behavior_config: &config::BehaviorConfig,
     ) -> Self {
-        // Check storage setting
-        assert!(
-            storage_config.num_sectors >= 2,
-            "Number of used sector for storage must larger than 1"
-        );
-
-        // If config.start_addr == 0, use last `num_sectors` sectors or sectors begin at 0x0006_0000 for nRF52
-        // Other wise, use storage config setting
-        #[cfg(feature = "_nrf_ble")]
-        let start_addr = if storage_config.start_addr == 0 {
-            0x0006_0000
-        } else {
-            storage_config.start_addr
-        };
-
-        #[cfg(not(feature = "_nrf_ble"))]
-        let start_addr = storage_config.start_addr;
-
-        // Check storage setting
-        info!(
-            "Flash capacity {} KB, RMK use {} KB({} sectors) starting from 0x{:X} as storage",
-            flash.capacity() / 1024,
-            (F::ERASE_SIZE * storage_config.num_sectors as usize) / 1024,
-            storage_config.num_sectors,
-            storage_config.start_addr,
-        );
-
-        let storage_range = if start_addr == 0 {
-            (flash.capacity() - storage_config.num_sectors as usize * F::ERASE_SIZE) as u32..flash.capacity() as u32
-        } else {
-            assert!(
-                start_addr.is_multiple_of(F::ERASE_SIZE),
-                "Storage's start addr MUST BE a multiplier of sector size"
-            );
-            start_addr as u32..(start_addr + storage_config.num_sectors as usize * F::ERASE_SIZE) as u32
-        };
+        Self::new_with_backend(
+            FlashBackend::new(flash, storage_config),
+            #[cfg(feature = "host")]
+            keymap,
+            #[cfg(feature = "host")]
+            encoder_map,
+            storage_config,
+            behavior_config,
+        )
+        .await
+    }
+}
 
+impl<B: StorageBackend, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Create the storage on the given backend, e.g. an external flash or RAM.
+    ///
+    /// The saved items are loaded, or the backend is initialized from the keymap and the configs.
+    pub async fn new_with_backend(
+        backend: B,
+        #[cfg(feature = "host")] keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
+        #[cfg(feature = "host")] encoder_map: &Option<&mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
+        storage_config: &StorageConfig,
+        behavior_config: &config::BehaviorConfig,
+    ) -> Self {
         let mut storage = Self {
-            flash,
-            storage_range,
-            buffer: [0; get_buffer_size()],
+            backend,
+            #[cfg(feature = "host")]
+            keymap_profiles: KeymapProfiles::default(),
+            erase_cycles_base: 0,
+            saved_erase_cycles: 0,
+            #[cfg(feature = "host")]
+            pending_writes: heapless::Vec::new(),
+            #[cfg(feature = "host")]
+            staged_backup: None,
+            persisted_state: storage_config.persisted_state,
+            pending_state: None,
         };
 
-        // Check whether keymap and configs have been storaged in flash
-        if !storage.check_enable().await || storage_config.clear_storage {
-            // Clear storage first
+        // Check whether keymap and configs have been storaged in flash, migrate them if they're written by a firmware
+        // with another schema version or layout
+        let layout = StorageLayout::new::<ROW, COL, NUM_LAYER, NUM_ENCODER>();
+        let mut _migrated = false;
+        let initialized = match storage.read_storage_config().await {
+            Some(config) if config.enable && !storage_config.clear_storage => {
+                config.is_current(&layout) || {
+                    _migrated = true;
+                    storage
+                        .migrate(
+                            config,
+                            #[cfg(feature = "host")]
+                            keymap,
+                            #[cfg(feature = "host")]
+                            encoder_map,
+                        )
+                        .await
+                        .is_ok()
+                }
+            }
+            _ => false,
+        };
+        if !initialized {
+            // Clear storage first, the erase cycles are kept
             debug!("Clearing storage!");
-            let _ = sequential_storage::erase_all(&mut storage.flash, storage.storage_range.clone()).await;
+            storage.erase_cycles_base += storage.read_erase_cycles().await;
+            let _ = storage.backend.erase_all().await;
 
             // Initialize storage from keymap and config
             if storage
@@ -509,19 +909,13 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                 .is_err()
             {
                 // When there's an error, `enable: false` should be saved back to storage, preventing partial initialization of storage
-                store_item(
-                    &mut storage.flash,
-                    storage.storage_range.clone(),
-                    &mut NoCache::new(),
-                    &mut storage.buffer,
-                    &(StorageKeys::StorageConfig as u32),
-                    &StorageData::StorageConfig(LocalStorageConfig {
-                        enable: false,
-                        build_hash: BUILD_HASH,
-                    }),
-                )
-                .await
-                .ok();
+                storage
+                    .store_item(
+                        StorageKeys::StorageConfig as u32,
+                        &StorageData::StorageConfig(LocalStorageConfig::new(false, layout)),
+                    )
+                    .await
+                    .ok();
             }
         } else if storage_config.clear_layout {
             #[cfg(feature = "host")]
@@ -532,28 +926,68 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             }
         }
 
+        #[cfg(feature = "host")]
+        storage
+            .init_keymap_profiles(
+                crate::KEYMAP_PROFILE_NUM as u8,
+                keymap,
+                encoder_map,
+                behavior_config,
+                storage_config.clear_layout,
+                _migrated,
+            )
+            .await
+            .ok();
+
+        storage.load_saved_state().await;
+        if let Err(e) = storage.save_erase_cycles().await {
+            print_storage_error(e);
+        }
+
         storage
     }
 
-    pub(crate) async fn run(&mut self) {
-        let mut storage_cache = NoCache::new();
+    /// Run the storage task, which saves the data received from `FLASH_CHANNEL`.
+    ///
+    /// Keymap data and the runtime state are saved after no flash operation is received for `WRITE_DELAY`,
+    /// so that repeated writes of the same key are saved only once.
+    /// The keymap is used to load the keymap profile when the profile is switched.
+    pub(crate) async fn run(
+        &mut self,
+        #[cfg(feature = "host")] keymap: Option<&RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>>,
+    ) {
         loop {
-            let info: FlashOperationMessage = FLASH_CHANNEL.receive().await;
+            let info: FlashOperationMessage = if !self.has_pending_writes() {
+                FLASH_CHANNEL.receive().await
+            } else {
+                match select(FLASH_CHANNEL.receive(), Timer::after(WRITE_DELAY)).await {
+                    Either::First(info) => info,
+                    Either::Second(_) => {
+                        debug!("Saving pending writes");
+                        if let Err(e) = self.flush_pending_writes().await {
+                            print_storage_error(e);
+                        }
+                        if let Err(e) = self.save_erase_cycles().await {
+                            print_storage_error(e);
+                        }
+                        continue;
+                    }
+                }
+            };
             debug!("Flash operation: {:?}", info);
             match match info {
                 FlashOperationMessage::LayoutOptions(layout_option) => {
                     // Read out layout options, update layer option and save back
-                    update_storage_field!(
-                        &mut self.flash,
-                        &mut self.buffer,
-                        &mut storage_cache,
-                        LayoutConfig,
-                        layout_option,
-                        self.storage_range.clone()
-                    )
+                    update_storage_field!(self, LayoutConfig, layout_option)
                 }
                 FlashOperationMessage::Reset => {
-                    sequential_storage::erase_all(&mut self.flash, self.storage_range.clone()).await
+                    #[cfg(feature = "host")]
+                    {
+                        self.pending_writes.clear();
+                        self.staged_backup = None;
+                    }
+                    self.pending_state = None;
+                    self.backend.erase_all().await
                 }
                 FlashOperationMessage::ResetLayout => {
                     info!("Ignoring ResetLayout at runtime (handled at startup via clear_layout).");
@@ -561,127 +995,120 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                 }
                 FlashOperationMessage::DefaultLayer(default_layer) => {
                     // Read out layout options, update layer option and save back
-                    update_storage_field!(
-                        &mut self.flash,
-                        &mut self.buffer,
-                        &mut storage_cache,
-                        LayoutConfig,
-                        default_layer,
-                        self.storage_range.clone()
-                    )
+                    update_storage_field!(self, LayoutConfig, default_layer)
                 }
                 #[cfg(feature = "host")]
-                FlashOperationMessage::VialMessage(vial_data) => match vial_data {
-                    KeymapData::Macro(macro_data) => {
-                        info!("Saving keyboard macro data");
-                        store_item(
-                            &mut self.flash,
-                            self.storage_range.clone(),
-                            &mut storage_cache,
-                            &mut self.buffer,
-                            &(StorageKeys::MacroData as u32),
-                            &StorageData::VialData(KeymapData::Macro(macro_data)),
-                        )
-                        .await
-                    }
-                    KeymapData::KeymapKey(keymap_key) => {
-                        let key = get_keymap_key::<ROW, COL, NUM_LAYER>(&keymap_key);
-                        let data = StorageData::VialData(KeymapData::KeymapKey(keymap_key));
-                        store_item(
-                            &mut self.flash,
-                            self.storage_range.clone(),
-                            &mut storage_cache,
-                            &mut self.buffer,
-                            &key,
-                            &data,
-                        )
-                        .await
-                    }
-                    KeymapData::Encoder(encoder_config) => {
-                        let data = KeymapData::Encoder(encoder_config);
-                        let key = get_encoder_config_key::<NUM_ENCODER>(encoder_config.idx, encoder_config.layer);
-                        store_item(
-                            &mut self.flash,
-                            self.storage_range.clone(),
-                            &mut storage_cache,
-                            &mut self.buffer,
-                            &key,
-                            &data,
-                        )
-                        .await
+                FlashOperationMessage::VialMessage(vial_data) => {
+                    // Keymap data is saved to the active keymap profile
+                    let key = get_keymap_data_key(self.keymap_profiles.config.active, &vial_data);
+                    self.queue_write(key, vial_data).await
+                }
+                // The pending writes are saved before the keymap profiles are changed, which might reboot the keyboard
+                #[cfg(feature = "host")]
+                FlashOperationMessage::SwitchKeymapProfile(profile, seq) => {
+                    let flushed = self.flush_pending_writes().await;
+                    let result = self.switch_keymap_profile(profile, keymap).await;
+                    respond_keymap_profile_operation(seq, flushed, result)
+                }
+                #[cfg(feature = "host")]
+                FlashOperationMessage::CopyKeymapProfile(src, dst, seq) => {
+                    let flushed = self.flush_pending_writes().await;
+                    let result = self.copy_keymap_profile(src, dst, keymap).await;
+                    respond_keymap_profile_operation(seq, flushed, result)
+                }
+                #[cfg(feature = "host")]
+                FlashOperationMessage::ClearKeymapProfile(profile, seq) => {
+                    let flushed = self.flush_pending_writes().await;
+                    let result = self.clear_keymap_profile(profile).await;
+                    respond_keymap_profile_operation(seq, flushed, result)
+                }
+                #[cfg(feature = "host")]
+                FlashOperationMessage::BindKeymapProfile(target, profile, seq) => {
+                    let flushed = self.flush_pending_writes().await;
+                    let result = self.bind_keymap_profile(target, profile, keymap).await;
+                    respond_keymap_profile_operation(seq, flushed, result)
+                }
+                #[cfg(feature = "host")]
+                FlashOperationMessage::BeginBackupImport => {
+                    let result = self.discard_staged_backup().await;
+                    self.staged_backup = Some(0);
+                    result
+                }
+                #[cfg(feature = "host")]
+                FlashOperationMessage::BackupRecord(data) => self.stage_backup_record(&data).await,
+                #[cfg(feature = "host")]
+                FlashOperationMessage::EndBackupImport(apply) => {
+                    if apply {
+                        self.commit_staged_backup(keymap).await
+                    } else {
+                        self.discard_staged_backup().await
                     }
-                    KeymapData::Combo(idx, config) => {
-                        let key = get_combo_key(idx);
-                        store_item(
-                            &mut self.flash,
-                            self.storage_range.clone(),
-                            &mut storage_cache,
-                            &mut self.buffer,
-                            &key,
-                            &StorageData::VialData(KeymapData::Combo(idx, config)),
-                        )
-                        .await
+                }
+                FlashOperationMessage::RuntimeState(state) => {
+                    self.pending_state = Some(state.masked(&self.persisted_state));
+                    Ok(())
+                }
+                FlashOperationMessage::Flush(seq) => {
+                    let result = self.flush_pending_writes().await;
+                    if let Some(seq) = seq {
+                        FLASH_FLUSHED.respond(seq, result);
                     }
-                    KeymapData::Fork(idx, fork) => {
-                        store_item(
-                            &mut self.flash,
-                            self.storage_range.clone(),
-                            &mut storage_cache,
-                            &mut self.buffer,
-                            &get_fork_key(idx),
-                            &StorageData::VialData(KeymapData::Fork(idx, fork)),
-                        )
+                    result
+                }
+                FlashOperationMessage::ReadStats(seq) => {
+                    let result = self.read_stats().await;
+                    STORAGE_STATS_READ.respond(seq, result.as_ref().copied().unwrap_or_default());
+                    result.map(|_| ())
+                }
+                FlashOperationMessage::MotionSensorCpi(id, cpi) => {
+                    self.store_item(get_motion_sensor_cpi_key(id), &StorageData::MotionSensorCpi(id, cpi))
                         .await
-                    }
-                    KeymapData::Morse(id, morse) => {
-                        store_item(
-                            &mut self.flash,
-                            self.storage_range.clone(),
-                            &mut storage_cache,
-                            &mut self.buffer,
-                            &get_morse_key(id),
-                            &StorageData::VialData(KeymapData::Morse(id, morse)),
-                        )
+                }
+                FlashOperationMessage::JoystickCenter(id, center) => {
+                    self.store_item(get_joystick_center_key(id), &StorageData::JoystickCenter(id, center))
                         .await
-                    }
-                },
-                FlashOperationMessage::ConnectionType(ty) => {
-                    store_item(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut storage_cache,
-                        &mut self.buffer,
-                        &(StorageKeys::ConnectionType as u32),
-                        &StorageData::ConnectionType(ty),
+                }
+                FlashOperationMessage::AnalogCalibration(row, col, calibration) => {
+                    self.store_item(
+                        get_analog_calibration_key(row, col),
+                        &StorageData::AnalogCalibration(row, col, calibration),
                     )
                     .await
                 }
+                FlashOperationMessage::UserData(key, data) => {
+                    self.store_item(get_user_data_key(key), &StorageData::UserData(key, data))
+                        .await
+                }
+                FlashOperationMessage::ReadUserData(key, seq) => {
+                    let (data, result) = match self.fetch_item(get_user_data_key(key)).await {
+                        Ok(Some(StorageData::UserData(_, data))) => (Some(data), Ok(())),
+                        Ok(_) => (None, Ok(())),
+                        Err(e) => (None, Err(e)),
+                    };
+                    USER_DATA_READ.respond(seq, data);
+                    result
+                }
+                FlashOperationMessage::ConnectionType(ty) => {
+                    let result = self
+                        .store_item(StorageKeys::ConnectionType as u32, &StorageData::ConnectionType(ty))
+                        .await;
+                    #[cfg(feature = "host")]
+                    self.follow_keymap_profile_binding(keymap).await.ok();
+                    result
+                }
                 #[cfg(all(feature = "_ble", feature = "split"))]
                 FlashOperationMessage::PeerAddress(peer) => {
                     let key = get_peer_address_key(peer.peer_id);
                     let data = StorageData::PeerAddress(peer);
-                    store_item(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut storage_cache,
-                        &mut self.buffer,
-                        &key,
-                        &data,
-                    )
-                    .await
+                    self.store_item(key, &data).await
                 }
                 #[cfg(feature = "_ble")]
                 FlashOperationMessage::ActiveBleProfile(profile) => {
                     let data = StorageData::ActiveBleProfile(profile);
-                    store_item::<u32, StorageData, _>(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut storage_cache,
-                        &mut self.buffer,
-                        &(StorageKeys::ActiveBleProfile as u32),
-                        &data,
-                    )
-                    .await
+                    let result = self.store_item(StorageKeys::ActiveBleProfile as u32, &data).await;
+                    #[cfg(feature = "host")]
+                    self.follow_keymap_profile_binding(keymap).await.ok();
+                    result
                 }
                 #[cfg(feature = "_ble")]
                 FlashOperationMessage::ClearSlot(slot_num) => {
@@ -708,180 +1135,175 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                         cccd_table: CccdTable::new([(0u16, CCCD::default()); CCCD_TABLE_SIZE]),
                     };
                     let data = StorageData::BondInfo(empty);
-                    store_item::<u32, StorageData, _>(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut storage_cache,
-                        &mut self.buffer,
-                        &get_bond_info_key(slot_num),
-                        &data,
-                    )
-                    .await
+                    self.store_item(get_bond_info_key(slot_num), &data).await
                 }
                 #[cfg(feature = "_ble")]
                 FlashOperationMessage::ProfileInfo(b) => {
                     debug!("Saving profile info: {:?}", b);
                     let data = StorageData::BondInfo(b.clone());
-                    store_item::<u32, StorageData, _>(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut storage_cache,
-                        &mut self.buffer,
-                        &get_bond_info_key(b.slot_num),
-                        &data,
-                    )
-                    .await
+                    self.store_item(get_bond_info_key(b.slot_num), &data).await
+                }
+                FlashOperationMessage::ComboTimeout(combo_timeout) => {
+                    update_storage_field!(self, BehaviorConfig, combo_timeout)
+                }
+                FlashOperationMessage::OneShotTimeout(one_shot_timeout) => {
+                    update_storage_field!(self, BehaviorConfig, one_shot_timeout)
+                }
+                FlashOperationMessage::TapInterval(tap_interval) => {
+                    update_storage_field!(self, BehaviorConfig, tap_interval)
+                }
+                FlashOperationMessage::TapCapslockInterval(tap_capslock_interval) => {
+                    update_storage_field!(self, BehaviorConfig, tap_capslock_interval)
+                }
+                FlashOperationMessage::PriorIdleTime(prior_idle_time) => {
+                    update_storage_field!(self, BehaviorConfig, prior_idle_time)
+                }
+                FlashOperationMessage::BehaviorConfig(config) => {
+                    let data = StorageData::BehaviorConfig(config);
+                    self.store_item(data.key(), &data).await
                 }
-                FlashOperationMessage::ComboTimeout(combo_timeout) => update_storage_field!(
-                    &mut self.flash,
-                    &mut self.buffer,
-                    &mut storage_cache,
-                    BehaviorConfig,
-                    combo_timeout,
-                    self.storage_range.clone()
-                ),
-                FlashOperationMessage::OneShotTimeout(one_shot_timeout) => update_storage_field!(
-                    &mut self.flash,
-                    &mut self.buffer,
-                    &mut storage_cache,
-                    BehaviorConfig,
-                    one_shot_timeout,
-                    self.storage_range.clone()
-                ),
-                FlashOperationMessage::TapInterval(tap_interval) => update_storage_field!(
-                    &mut self.flash,
-                    &mut self.buffer,
-                    &mut storage_cache,
-                    BehaviorConfig,
-                    tap_interval,
-                    self.storage_range.clone()
-                ),
-                FlashOperationMessage::TapCapslockInterval(tap_capslock_interval) => update_storage_field!(
-                    &mut self.flash,
-                    &mut self.buffer,
-                    &mut storage_cache,
-                    BehaviorConfig,
-                    tap_capslock_interval,
-                    self.storage_range.clone()
-                ),
-                FlashOperationMessage::PriorIdleTime(prior_idle_time) => update_storage_field!(
-                    &mut self.flash,
-                    &mut self.buffer,
-                    &mut storage_cache,
-                    BehaviorConfig,
-                    prior_idle_time,
-                    self.storage_range.clone()
-                ),
                 FlashOperationMessage::MorseDefaultProfile(morse_default_profile) => {
-                    update_storage_field!(
-                        &mut self.flash,
-                        &mut self.buffer,
-                        &mut storage_cache,
-                        BehaviorConfig,
-                        morse_default_profile,
-                        self.storage_range.clone()
-                    )
+                    update_storage_field!(self, BehaviorConfig, morse_default_profile)
                 }
                 #[cfg(not(feature = "_ble"))]
                 _ => Ok(()),
             } {
                 Err(e) => {
-                    print_storage_error::<F>(e);
+                    print_storage_error(e);
                     FLASH_OPERATION_FINISHED.signal(false);
                 }
                 _ => {
                     FLASH_OPERATION_FINISHED.signal(true);
                 }
             }
+            if let Err(e) = self.save_erase_cycles().await {
+                print_storage_error(e);
+            }
+        }
+    }
+
+    /// Queue the keymap data to be saved later, replacing the pending write of the same key
+    #[cfg(feature = "host")]
+    async fn queue_write(&mut self, key: u32, data: KeymapData) -> Result<(), StorageError> {
+        if let Some((_, pending)) = self.pending_writes.iter_mut().find(|(k, _)| *k == key) {
+            *pending = data;
+            return Ok(());
+        }
+        if self.pending_writes.is_full() {
+            // Save the oldest write to make room, it's kept in the queue if it can't be saved
+            let (oldest_key, oldest) = self.pending_writes[0].clone();
+            if let Err(e) = self.store_item(oldest_key, &StorageData::VialData(oldest)).await {
+                // There's no room to queue the new write, try to save it directly
+                self.store_item(key, &StorageData::VialData(data)).await.ok();
+                return Err(e);
+            }
+            self.pending_writes.remove(0);
+        }
+        let _ = self.pending_writes.push((key, data));
+        Ok(())
+    }
+
+    /// Whether there're writes waiting to be saved
+    fn has_pending_writes(&self) -> bool {
+        #[cfg(feature = "host")]
+        if !self.pending_writes.is_empty() {
+            return true;
+        }
+        self.pending_state.is_some()
+    }
+
+    /// Save the pending keymap writes in the order they're received, then the pending runtime state.
+    ///
+    /// The writes which fail are kept to be saved by the next flush, and the first error is returned.
+    pub(crate) async fn flush_pending_writes(&mut self) -> Result<(), StorageError> {
+        let mut result = Ok(());
+        #[cfg(feature = "host")]
+        {
+            let mut i = 0;
+            while i < self.pending_writes.len() {
+                let (key, data) = self.pending_writes[i].clone();
+                match self.store_item(key, &StorageData::VialData(data)).await {
+                    Ok(()) => {
+                        self.pending_writes.remove(i);
+                    }
+                    Err(e) => {
+                        result = result.and(Err(e));
+                        i += 1;
+                    }
+                }
+            }
+        }
+        if let Some(state) = self.pending_state {
+            match self
+                .store_item(StorageKeys::RuntimeState as u32, &StorageData::RuntimeState(state))
+                .await
+            {
+                Ok(()) => self.pending_state = None,
+                Err(e) => result = result.and(Err(e)),
+            }
         }
+        result
     }
 
     pub(crate) async fn read_behavior_config(
         &mut self,
         behavior_config: &mut config::BehaviorConfig,
     ) -> Result<(), ()> {
-        if let Some(StorageData::BehaviorConfig(c)) = fetch_item::<u32, StorageData, _>(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut NoCache::new(),
-            &mut self.buffer,
-            &(StorageKeys::BehaviorConfig as u32),
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?
+        if let Some(StorageData::BehaviorConfig(c)) = self
+            .fetch_item(StorageKeys::BehaviorConfig as u32)
+            .await
+            .map_err(print_storage_error)?
         {
-            behavior_config.morse.prior_idle_time = Duration::from_millis(c.prior_idle_time as u64);
-            behavior_config.morse.default_profile = c.morse_default_profile;
-
-            behavior_config.combo.timeout = Duration::from_millis(c.combo_timeout as u64);
-            behavior_config.one_shot.timeout = Duration::from_millis(c.one_shot_timeout as u64);
-            behavior_config.tap.tap_interval = c.tap_interval;
-            behavior_config.tap.tap_capslock_interval = c.tap_capslock_interval;
+            c.apply_to(behavior_config);
+            if let Some(tri_layer) = behavior_config.tri_layer
+                && tri_layer.iter().any(|&layer| layer as usize >= NUM_LAYER)
+            {
+                warn!("Saved tri-layer {:?} is out of the keymap, disabled", tri_layer);
+                behavior_config.tri_layer = None;
+            }
         }
 
         Ok(())
     }
 
+    /// Read the default layer saved in the layout config
+    pub(crate) async fn read_default_layer(&mut self) -> Option<u8> {
+        match self.fetch_item(StorageKeys::LayoutConfig as u32).await {
+            Ok(Some(StorageData::LayoutConfig(c))) => Some(c.default_layer),
+            _ => None,
+        }
+    }
+
     async fn initialize_storage_with_config(
         &mut self,
         #[cfg(feature = "host")] keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
         #[cfg(feature = "host")] encoder_map: &Option<&mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
         behavior: &config::BehaviorConfig,
     ) -> Result<(), ()> {
-        let mut cache = NoCache::new();
         // Save storage config
-        let storage_config = StorageData::StorageConfig(LocalStorageConfig {
-            enable: true,
-            build_hash: BUILD_HASH,
-        });
-        store_item(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut cache,
-            &mut self.buffer,
-            &storage_config.key(),
-            &storage_config,
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        let storage_config = StorageData::StorageConfig(LocalStorageConfig::new(
+            true,
+            StorageLayout::new::<ROW, COL, NUM_LAYER, NUM_ENCODER>(),
+        ));
+        self.store_item(storage_config.key(), &storage_config)
+            .await
+            .map_err(print_storage_error)?;
 
         // Save layout config
         let layout_config = StorageData::LayoutConfig(LayoutConfig {
             default_layer: 0,
             layout_option: 0,
         });
-        store_item(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut cache,
-            &mut self.buffer,
-            &layout_config.key(),
-            &layout_config,
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        self.store_item(layout_config.key(), &layout_config)
+            .await
+            .map_err(print_storage_error)?;
 
         // Save behavior config
-        let behavior_config = StorageData::BehaviorConfig(BehaviorConfig {
-            prior_idle_time: behavior.morse.prior_idle_time.as_millis() as u16,
-            morse_default_profile: behavior.morse.default_profile,
-
-            combo_timeout: behavior.combo.timeout.as_millis() as u16,
-            one_shot_timeout: behavior.one_shot.timeout.as_millis() as u16,
-            tap_interval: behavior.tap.tap_interval,
-            tap_capslock_interval: behavior.tap.tap_capslock_interval,
-        });
+        let behavior_config = StorageData::BehaviorConfig(BehaviorConfig::from(behavior));
 
-        store_item(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut cache,
-            &mut self.buffer,
-            &behavior_config.key(),
-            &behavior_config,
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        self.store_item(behavior_config.key(), &behavior_config)
+            .await
+            .map_err(print_storage_error)?;
 
         #[cfg(feature = "host")]
         for (layer, layer_data) in keymap.iter().enumerate() {
@@ -893,16 +1315,12 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                         layer: layer as u8,
                         action: *action,
                     };
-                    store_item(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut cache,
-                        &mut self.buffer,
-                        &get_keymap_key::<ROW, COL, NUM_LAYER>(&keymap_key),
+                    self.store_item(
+                        get_keymap_key(0, &keymap_key),
                         &StorageData::VialData(KeymapData::KeymapKey(keymap_key)),
                     )
                     .await
-                    .map_err(|e| print_storage_error::<F>(e))?;
+                    .map_err(print_storage_error)?;
                 }
             }
         }
@@ -919,16 +1337,12 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                         layer: layer as u8,
                         action: *action,
                     };
-                    store_item(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut cache,
-                        &mut self.buffer,
-                        &get_encoder_config_key::<NUM_ENCODER>(encoder.idx, encoder.layer),
+                    self.store_item(
+                        get_encoder_config_key(0, encoder.idx, encoder.layer),
                         &StorageData::VialData(KeymapData::Encoder(encoder)),
                     )
                     .await
-                    .map_err(|e| print_storage_error::<F>(e))?;
+                    .map_err(print_storage_error)?;
                 }
             }
         }
@@ -942,41 +1356,15 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         keymap: &[[[KeyAction; COL]; ROW]; NUM_LAYER],
         encoder_map: &Option<&[[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
         behavior: &config::BehaviorConfig,
-    ) -> Result<(), SSError<F::Error>> {
-        let mut cache = NoCache::new();
-
+    ) -> Result<(), StorageError> {
         let layout_config = StorageData::LayoutConfig(LayoutConfig {
             default_layer: 0,
             layout_option: 0,
         });
-        store_item(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut cache,
-            &mut self.buffer,
-            &layout_config.key(),
-            &layout_config,
-        )
-        .await?;
+        self.store_item(layout_config.key(), &layout_config).await?;
 
-        let behavior_config = StorageData::BehaviorConfig(BehaviorConfig {
-            prior_idle_time: behavior.morse.prior_idle_time.as_millis() as u16,
-            morse_default_profile: behavior.morse.default_profile,
-
-            combo_timeout: behavior.combo.timeout.as_millis() as u16,
-            one_shot_timeout: behavior.one_shot.timeout.as_millis() as u16,
-            tap_interval: behavior.tap.tap_interval,
-            tap_capslock_interval: behavior.tap.tap_capslock_interval,
-        });
-        store_item(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut cache,
-            &mut self.buffer,
-            &behavior_config.key(),
-            &behavior_config,
-        )
-        .await?;
+        let behavior_config = StorageData::BehaviorConfig(BehaviorConfig::from(behavior));
+        self.store_item(behavior_config.key(), &behavior_config).await?;
 
         // TODO: Generic reset for vial and other hosts
         for (layer, layer_data) in keymap.iter().enumerate() {
@@ -988,12 +1376,8 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                         layer: layer as u8,
                         action: *action,
                     };
-                    store_item(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut cache,
-                        &mut self.buffer,
-                        &get_keymap_key::<ROW, COL, NUM_LAYER>(&keymap_key),
+                    self.store_item(
+                        get_keymap_key(0, &keymap_key),
                         &StorageData::VialData(KeymapData::KeymapKey(keymap_key)),
                     )
                     .await?;
@@ -1006,12 +1390,8 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             for (layer, layer_data) in encoder_map.iter().enumerate() {
                 for (idx, action) in layer_data.iter().enumerate() {
                     use crate::host::storage::EncoderKeymap;
-                    store_item(
-                        &mut self.flash,
-                        self.storage_range.clone(),
-                        &mut cache,
-                        &mut self.buffer,
-                        &get_encoder_config_key::<NUM_ENCODER>(idx as u8, layer as u8),
+                    self.store_item(
+                        get_encoder_config_key(0, idx as u8, layer as u8),
                         &StorageData::VialData(KeymapData::Encoder(EncoderKeymap {
                             idx: idx as u8,
                             layer: layer as u8,
@@ -1026,35 +1406,75 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         Ok(())
     }
 
-    async fn check_enable(&mut self) -> bool {
-        if let Ok(Some(StorageData::StorageConfig(config))) = fetch_item::<u32, StorageData, _>(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut NoCache::new(),
-            &mut self.buffer,
-            &(StorageKeys::StorageConfig as u32),
-        )
-        .await
-        {
-            // if config.enable && config.build_hash == BUILD_HASH {
-            if config.enable {
-                return true;
+    /// Load saved CPI of motion sensors, which will be applied after the sensors are initialized,
+    /// saved centers of joysticks, which will be used when the boot-time calibration is skipped,
+    /// and the saved erase cycles
+    async fn load_saved_state(&mut self) {
+        use core::sync::atomic::Ordering;
+
+        use crate::input_device::joystick::{MAX_JOYSTICKS, SAVED_JOYSTICK_CENTER};
+        use crate::input_device::motion_sensor::SAVED_CPI;
+
+        let mut centers = [None; MAX_JOYSTICKS];
+        let mut erase_cycles = 0;
+        self.for_each_item(|key, item| match item {
+            StorageData::MotionSensorCpi(id, cpi) if key == get_motion_sensor_cpi_key(id) => {
+                if let Some(saved) = SAVED_CPI.get(id as usize) {
+                    saved.store(cpi, Ordering::Relaxed);
+                }
             }
+            StorageData::JoystickCenter(id, center) if key == get_joystick_center_key(id) => {
+                if let Some(saved) = centers.get_mut(id as usize) {
+                    *saved = Some(center);
+                }
+            }
+            StorageData::EraseCycles(saved) if key == StorageKeys::EraseCycles as u32 => erase_cycles = saved,
+            _ => {}
+        })
+        .await
+        .ok();
+        SAVED_JOYSTICK_CENTER.lock(|c| c.set(centers));
+        // Sectors erased at boot are counted already
+        self.erase_cycles_base += erase_cycles;
+        self.saved_erase_cycles = erase_cycles;
+    }
+
+    async fn read_storage_config(&mut self) -> Option<LocalStorageConfig> {
+        if let Ok(Some(StorageData::StorageConfig(config))) = self.fetch_item(StorageKeys::StorageConfig as u32).await {
+            return Some(config);
         }
-        false
+        None
+    }
+
+    /// Visit all items in the storage with a single scan of the flash.
+    ///
+    /// An outdated item can be visited before the newest one with the same key, so the last visited one should be used.
+    /// Items which can't be decoded, e.g. the outdated items before migration, are skipped.
+    pub(crate) async fn for_each_item(&mut self, f: impl FnMut(u32, StorageData)) -> Result<(), ()> {
+        self.backend.for_each(f).await.map_err(print_storage_error)
+    }
+
+    /// Read the item of the key from the backend
+    pub(crate) async fn fetch_item(&mut self, key: u32) -> Result<Option<StorageData>, StorageError> {
+        self.backend.fetch(key).await
+    }
+
+    /// Save the item of the key to the backend
+    pub(crate) async fn store_item(&mut self, key: u32, data: &StorageData) -> Result<(), StorageError> {
+        self.backend.store(key, data).await
+    }
+
+    /// Remove the item of the key from the backend
+    pub(crate) async fn remove_item(&mut self, key: u32) -> Result<(), StorageError> {
+        self.backend.remove(key).await
     }
 
     #[cfg(feature = "_ble")]
     pub(crate) async fn read_trouble_bond_info(&mut self, slot_num: u8) -> Result<Option<ProfileInfo>, ()> {
-        let read_data = fetch_item::<u32, StorageData, _>(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut NoCache::new(),
-            &mut self.buffer,
-            &get_bond_info_key(slot_num),
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        let read_data = self
+            .fetch_item(get_bond_info_key(slot_num))
+            .await
+            .map_err(print_storage_error)?;
 
         if let Some(StorageData::BondInfo(info)) = read_data {
             Ok(Some(info))
@@ -1063,17 +1483,35 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         }
     }
 
+    /// Read the saved calibration of the analog keys, which can be applied by [`AnalogMatrix::with_calibration`].
+    ///
+    /// [`AnalogMatrix::with_calibration`]: crate::input_device::analog_matrix::AnalogMatrix::with_calibration
+    ///
+    /// The size of the analog matrix can differ from the keymap's, e.g. on split keyboards.
+    pub async fn read_analog_calibration<const MATRIX_ROW: usize, const MATRIX_COL: usize>(
+        &mut self,
+    ) -> [[Option<AnalogKeyCalibration>; MATRIX_COL]; MATRIX_ROW] {
+        // The calibration is saved by `u8` row and column
+        const { core::assert!(MATRIX_ROW <= 256 && MATRIX_COL <= 256) };
+        let mut calibration = [[None; MATRIX_COL]; MATRIX_ROW];
+        for (row, saved) in calibration.iter_mut().enumerate() {
+            for (col, saved) in saved.iter_mut().enumerate() {
+                if let Ok(Some(StorageData::AnalogCalibration(_, _, [rest, bottom]))) =
+                    self.fetch_item(get_analog_calibration_key(row as u8, col as u8)).await
+                {
+                    *saved = Some(AnalogKeyCalibration { rest, bottom });
+                }
+            }
+        }
+        calibration
+    }
+
     #[cfg(all(feature = "_ble", feature = "split"))]
     pub async fn read_peer_address(&mut self, peer_id: u8) -> Result<Option<PeerAddress>, ()> {
-        let read_data = fetch_item::<u32, StorageData, _>(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut NoCache::new(),
-            &mut self.buffer,
-            &get_peer_address_key(peer_id),
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        let read_data = self
+            .fetch_item(get_peer_address_key(peer_id))
+            .await
+            .map_err(print_storage_error)?;
 
         if let Some(StorageData::PeerAddress(data)) = read_data {
             Ok(Some(data))
@@ -1087,36 +1525,23 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         let peer_id = peer_address.peer_id;
         let item = StorageData::PeerAddress(peer_address);
 
-        store_item(
-            &mut self.flash,
-            self.storage_range.clone(),
-            &mut NoCache::new(),
-            &mut self.buffer,
-            &get_peer_address_key(peer_id),
-            &item,
-        )
-        .await
-        .map_err(|e| print_storage_error::<F>(e))
+        self.store_item(get_peer_address_key(peer_id), &item)
+            .await
+            .map_err(print_storage_error)
     }
 }
 
-pub(crate) fn print_storage_error<F: AsyncNorFlash>(e: SSError<F::Error>) {
+pub(crate) fn print_storage_error(e: StorageError) {
     match e {
-        #[cfg(feature = "defmt")]
-        SSError::Storage { value: e } => error!("Flash error: {:?}", defmt::Debug2Format(&e)),
-        #[cfg(not(feature = "defmt"))]
-        SSError::Storage { value: _e } => error!("Flash error"),
-        SSError::FullStorage => error!("Storage is full"),
-        SSError::Corrupted {} => error!("Storage is corrupted"),
-        SSError::BufferTooBig => error!("Buffer too big"),
-        SSError::BufferTooSmall(x) => error!("Buffer too small, needs {} bytes", x),
-        SSError::SerializationError(e) => error!("Map value error: {}", e),
-        SSError::ItemTooBig => error!("Item too big"),
-        _ => error!("Unknown storage error"),
+        StorageError::Flash => error!("Flash error"),
+        StorageError::FullStorage => error!("Storage is full"),
+        StorageError::Corrupted => error!("Storage is corrupted"),
+        StorageError::ItemTooBig => error!("Item too big"),
+        StorageError::Serialization => error!("Map value error"),
     }
 }
 
-const fn get_buffer_size() -> usize {
+pub(crate) const fn get_buffer_size() -> usize {
     #[cfg(feature = "host")]
     {
         // The buffer size needed = size_of(StorageData) = MACRO_SPACE_SIZE + 8(generally)
@@ -1136,17 +1561,432 @@ const fn get_buffer_size() -> usize {
     256
 }
 
-#[macro_export]
-/// Helper macro for reading storage config
-macro_rules! read_storage {
-    ($storage: ident, $key: expr, $buf: expr) => {
-        ::sequential_storage::map::fetch_item::<u32, $crate::storage::StorageData, _>(
-            &mut $storage.flash,
-            $storage.storage_range.clone(),
-            &mut sequential_storage::cache::NoCache::new(),
-            &mut $buf,
-            $key,
-        )
-        .await
-    };
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_storage_async::nor_flash::{ErrorType, ReadNorFlash};
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+    use crate::combo::Combo;
+    use crate::fork::Fork;
+    use crate::k;
+    use crate::morse::Morse;
+    use crate::storage::backend::MemoryBackend;
+    use crate::storage::dummy_flash::MemoryFlash;
+    use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, MACRO_SPACE_SIZE, MORSE_MAX_NUM};
+
+    const FLASH_SIZE: usize = 8 * 4096;
+
+    /// Flash which counts the reads
+    #[derive(Default)]
+    struct CountingFlash {
+        flash: MemoryFlash<FLASH_SIZE>,
+        reads: usize,
+    }
+
+    impl ErrorType for CountingFlash {
+        type Error = <MemoryFlash<FLASH_SIZE> as ErrorType>::Error;
+    }
+
+    impl ReadNorFlash for CountingFlash {
+        const READ_SIZE: usize = 1;
+
+        async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            self.reads += 1;
+            self.flash.read(offset, bytes).await
+        }
+
+        fn capacity(&self) -> usize {
+            FLASH_SIZE
+        }
+    }
+
+    impl AsyncNorFlash for CountingFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 4096;
+
+        async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.flash.erase(from, to).await
+        }
+
+        async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            self.flash.write(offset, bytes).await
+        }
+    }
+
+    fn reset_reads(flash: &mut CountingFlash) -> usize {
+        let reads = flash.reads;
+        flash.reads = 0;
+        reads
+    }
+
+    #[test]
+    fn test_boot_reads() {
+        // A 4-layer, 100-key keyboard
+        let keymap = [[[k!(A); 20]; 5]; 4];
+        let storage_config = StorageConfig {
+            num_sectors: 8,
+            ..Default::default()
+        };
+        let boot = |flash| -> Storage<FlashBackend<CountingFlash>, 5, 20, 4, 0> {
+            block_on(Storage::new(
+                flash,
+                &keymap,
+                &None,
+                &storage_config,
+                &config::BehaviorConfig::default(),
+            ))
+        };
+        let storage = boot(CountingFlash::default());
+        let mut storage = boot(storage.backend.into_inner());
+        let boot_reads = reset_reads(&mut storage.backend.flash.flash);
+
+        block_on(storage.for_each_item(|_, _| {})).unwrap();
+        let scan_reads = reset_reads(&mut storage.backend.flash.flash);
+
+        let mut loaded = keymap;
+        let mut macros = [0; MACRO_SPACE_SIZE];
+        let mut combos: [Option<Combo>; COMBO_MAX_NUM] = Default::default();
+        let mut forks: heapless::Vec<Fork, FORK_MAX_NUM> = heapless::Vec::new();
+        forks.resize(FORK_MAX_NUM, Fork::default()).unwrap();
+        let mut morses: heapless::Vec<Morse, MORSE_MAX_NUM> = heapless::Vec::new();
+        morses.resize(MORSE_MAX_NUM, Morse::default()).unwrap();
+        let mut behavior = config::BehaviorConfig::default();
+        block_on(async {
+            storage.read_keymap(&mut loaded, &mut None).await.unwrap();
+            storage.read_macro_cache(&mut macros).await.unwrap();
+            storage.read_combos(&mut combos).await.unwrap();
+            storage.read_forks(&mut forks).await.unwrap();
+            storage.read_morses(&mut morses).await.unwrap();
+            storage.read_behavior_config(&mut behavior).await.unwrap();
+        });
+        let load_reads = reset_reads(&mut storage.backend.flash.flash);
+
+        // Every load scans the flash at most once, instead of once per key
+        assert!(boot_reads <= 4 * scan_reads);
+        assert!(load_reads <= 8 * scan_reads);
+
+        // Cached items are read without scanning
+        block_on(storage.read_behavior_config(&mut behavior)).unwrap();
+        assert!(reset_reads(&mut storage.backend.flash.flash) < 8);
+    }
+
+    #[test]
+    fn test_memory_backend_storage() {
+        let keymap = [[[k!(A); 2]; 1]; 1];
+        let storage_config = StorageConfig::default();
+        let boot = |backend| -> Storage<MemoryBackend<4096>, 1, 2, 1, 0> {
+            block_on(Storage::new_with_backend(
+                backend,
+                &keymap,
+                &None,
+                &storage_config,
+                &config::BehaviorConfig::default(),
+            ))
+        };
+        let mut storage = boot(MemoryBackend::new());
+        let keymap_key = KeymapKey {
+            layer: 0,
+            row: 0,
+            col: 1,
+            action: k!(B),
+        };
+        let data = StorageData::VialData(KeymapData::KeymapKey(keymap_key));
+        block_on(storage.store_item(get_keymap_key(0, &keymap_key), &data)).unwrap();
+
+        // The saved items are loaded instead of the default keymap
+        let mut storage = boot(storage.backend);
+        let mut loaded = keymap;
+        block_on(storage.read_keymap(&mut loaded, &mut None)).unwrap();
+        assert_eq!(loaded, [[[k!(A), k!(B)]]]);
+        let stats = block_on(storage.read_stats()).unwrap();
+        assert_eq!(stats.capacity, 4096);
+        assert_eq!(stats.erase_cycles, 0);
+    }
+
+    #[test]
+    fn test_flash_backend_cache_sizes() {
+        let mut default_keymap = [[[k!(A); 2]; 1]; 1];
+        let storage_config = StorageConfig {
+            num_sectors: 4,
+            ..Default::default()
+        };
+        // The range has more sectors than the page cache, the storage works without the cache
+        let flash = FlashBackend::<_, 2, { backend::cache_keys(1, 2) }>::new(
+            MemoryFlash::<{ 4 * 4096 }>::new(),
+            &storage_config,
+        );
+        let mut behavior_config = config::BehaviorConfig::default();
+        let mut positional_config = config::PositionalConfig::default();
+        let (keymap, mut storage) = block_on(crate::initialize_keymap_and_storage(
+            &mut default_keymap,
+            flash,
+            &storage_config,
+            &mut behavior_config,
+            &mut positional_config,
+        ));
+        assert!(!storage.backend.cached);
+        assert_eq!(keymap.borrow().layers[0][0][1], k!(A));
+
+        let keymap_key = KeymapKey {
+            layer: 0,
+            row: 0,
+            col: 1,
+            action: k!(B),
+        };
+        let data = StorageData::VialData(KeymapData::KeymapKey(keymap_key));
+        block_on(storage.store_item(get_keymap_key(0, &keymap_key), &data)).unwrap();
+        let mut loaded = [[[k!(A); 2]; 1]; 1];
+        block_on(storage.read_keymap(&mut loaded, &mut None)).unwrap();
+        assert_eq!(loaded, [[[k!(A), k!(B)]]]);
+    }
+
+    #[test]
+    fn test_versioned_behavior_config() {
+        let mut behavior = config::BehaviorConfig {
+            tri_layer: Some([1, 2, 3]),
+            ..Default::default()
+        };
+        behavior.morse.enable_flow_tap = true;
+        behavior.caps_word.timeout = Duration::from_secs(3);
+        behavior.mouse_key.move_delta = 10;
+        behavior.one_shot.timeout = Duration::from_millis(700);
+        let saved = BehaviorConfig::from(&behavior);
+
+        let mut buffer = [0; 128];
+        let size = StorageData::BehaviorConfig(saved).serialize_into(&mut buffer).unwrap();
+        let (StorageData::BehaviorConfig(loaded), read) = StorageData::deserialize_from(&buffer[..size]).unwrap()
+        else {
+            panic!("Expected behavior config");
+        };
+        assert_eq!(read, size);
+        let mut restored = config::BehaviorConfig::default();
+        loaded.apply_to(&mut restored);
+        assert_eq!(restored.tri_layer, Some([1, 2, 3]));
+        assert!(restored.morse.enable_flow_tap);
+        assert_eq!(restored.caps_word.timeout, Duration::from_secs(3));
+        assert_eq!(restored.mouse_key, behavior.mouse_key);
+        assert_eq!(restored.one_shot.timeout, Duration::from_millis(700));
+
+        // The config saved before versioning keeps the firmware's settings which it doesn't have
+        buffer[0] = StorageKeys::BehaviorConfig as u8;
+        let size = postcard::to_slice(&saved, &mut buffer[1..]).unwrap().len() + 1;
+        let (StorageData::BehaviorConfig(legacy), _) = StorageData::deserialize_from(&buffer[..size]).unwrap() else {
+            panic!("Expected behavior config");
+        };
+        assert!(legacy.v1.is_none());
+        let mut restored = config::BehaviorConfig::default();
+        restored.mouse_key.move_delta = 20;
+        legacy.apply_to(&mut restored);
+        assert_eq!(restored.mouse_key.move_delta, 20);
+        assert!(!restored.morse.enable_flow_tap);
+        assert_eq!(restored.one_shot.timeout, Duration::from_millis(700));
+
+        // The fields of newer versions are skipped
+        buffer[0] = StorageKeys::VersionedBehaviorConfig as u8;
+        let mut size = postcard::to_slice(&(BEHAVIOR_CONFIG_VERSION + 1, &saved), &mut buffer[1..])
+            .unwrap()
+            .len()
+            + 1;
+        size += postcard::to_slice(&saved.v1.unwrap(), &mut buffer[size..])
+            .unwrap()
+            .len();
+        buffer[size] = 0xAB;
+        let (StorageData::BehaviorConfig(newer), read) = StorageData::deserialize_from(&buffer[..size + 1]).unwrap()
+        else {
+            panic!("Expected behavior config");
+        };
+        assert_eq!(read, size + 1);
+        assert_eq!(newer.v1.unwrap().mouse_key, behavior.mouse_key);
+    }
+
+    /// Backend which fails to save the item of a key
+    #[derive(Default)]
+    struct FailingBackend {
+        backend: MemoryBackend<4096>,
+        failing_key: Option<u32>,
+    }
+
+    impl StorageBackend for FailingBackend {
+        fn capacity(&self) -> u32 {
+            self.backend.capacity()
+        }
+
+        async fn free_space(&mut self) -> Result<u32, StorageError> {
+            self.backend.free_space().await
+        }
+
+        async fn fetch<V: for<'d> Value<'d>>(&mut self, key: u32) -> Result<Option<V>, StorageError> {
+            self.backend.fetch(key).await
+        }
+
+        async fn store<V: for<'d> Value<'d>>(&mut self, key: u32, value: &V) -> Result<(), StorageError> {
+            if self.failing_key == Some(key) {
+                return Err(StorageError::Flash);
+            }
+            self.backend.store(key, value).await
+        }
+
+        async fn remove(&mut self, key: u32) -> Result<(), StorageError> {
+            self.backend.remove(key).await
+        }
+
+        async fn for_each<V: for<'d> Value<'d>>(&mut self, f: impl FnMut(u32, V)) -> Result<(), StorageError> {
+            self.backend.for_each(f).await
+        }
+
+        async fn erase_all(&mut self) -> Result<(), StorageError> {
+            self.backend.erase_all().await
+        }
+    }
+
+    #[test]
+    fn test_failed_pending_writes_are_kept() {
+        let keymap = [[[k!(A); 9]; 1]; 1];
+        let mut storage: Storage<FailingBackend, 1, 9, 1, 0> = block_on(Storage::new_with_backend(
+            FailingBackend::default(),
+            &keymap,
+            &None,
+            &StorageConfig::default(),
+            &config::BehaviorConfig::default(),
+        ));
+        let write = |col| {
+            let data = KeymapData::KeymapKey(KeymapKey {
+                layer: 0,
+                row: 0,
+                col,
+                action: k!(B),
+            });
+            (get_keymap_data_key(0, &data), data)
+        };
+        storage.backend.failing_key = Some(write(0).0);
+        for col in 0..PENDING_WRITES_NUM as u8 {
+            let (key, data) = write(col);
+            block_on(storage.queue_write(key, data)).unwrap();
+        }
+
+        // The oldest write can't be saved to make room, it's kept and the new write is saved directly
+        let (key, data) = write(8);
+        assert_eq!(block_on(storage.queue_write(key, data)), Err(StorageError::Flash));
+        assert_eq!(storage.pending_writes.len(), PENDING_WRITES_NUM);
+        assert_eq!(storage.pending_writes[0].0, write(0).0);
+
+        // The writes behind the failed one are still saved
+        assert_eq!(block_on(storage.flush_pending_writes()), Err(StorageError::Flash));
+        assert_eq!(storage.pending_writes.len(), 1);
+        let mut loaded = keymap;
+        block_on(storage.read_keymap(&mut loaded, &mut None)).unwrap();
+        assert_eq!(loaded[0][0][0], k!(A));
+        assert_eq!(loaded[0][0][1..], [k!(B); 8]);
+
+        // The failed write is saved by the next flush
+        storage.backend.failing_key = None;
+        block_on(storage.flush_pending_writes()).unwrap();
+        assert!(storage.pending_writes.is_empty());
+        block_on(storage.read_keymap(&mut loaded, &mut None)).unwrap();
+        assert_eq!(loaded, [[[k!(B); 9]; 1]; 1]);
+    }
+
+    #[test]
+    fn test_analog_calibration_keys() {
+        let keymap = [[[k!(A); 1]; 1]; 1];
+        let mut storage: Storage<MemoryBackend<4096>, 1, 1, 1, 0> = block_on(Storage::new_with_backend(
+            MemoryBackend::new(),
+            &keymap,
+            &None,
+            &StorageConfig::default(),
+            &config::BehaviorConfig::default(),
+        ));
+        // Keys of the columns after 64 don't overlap the next row's
+        for (row, col, calibration) in [(0, 64, [100, 2000]), (1, 0, [200, 3000])] {
+            let data = StorageData::AnalogCalibration(row, col, calibration);
+            block_on(storage.store_item(get_analog_calibration_key(row, col), &data)).unwrap();
+        }
+        let saved = block_on(storage.read_analog_calibration::<2, 65>());
+        assert_eq!(
+            saved[0][64],
+            Some(AnalogKeyCalibration {
+                rest: 100,
+                bottom: 2000
+            })
+        );
+        assert_eq!(
+            saved[1][0],
+            Some(AnalogKeyCalibration {
+                rest: 200,
+                bottom: 3000
+            })
+        );
+        assert_eq!(saved[0][0], None);
+    }
+
+    #[test]
+    fn test_saved_tri_layer_out_of_keymap() {
+        let keymap = [[[k!(A); 1]; 1]; 2];
+        let behavior = config::BehaviorConfig {
+            tri_layer: Some([0, 1, 2]),
+            ..Default::default()
+        };
+        let mut storage: Storage<MemoryBackend<4096>, 1, 1, 2, 0> = block_on(Storage::new_with_backend(
+            MemoryBackend::new(),
+            &keymap,
+            &None,
+            &StorageConfig::default(),
+            &behavior,
+        ));
+        let mut loaded = config::BehaviorConfig::default();
+        block_on(storage.read_behavior_config(&mut loaded)).unwrap();
+        assert_eq!(loaded.tri_layer, None);
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_write_coalescing() {
+            let keymap = [[[k!(A); 2]; 1]; 1];
+            let storage_config = StorageConfig {
+                num_sectors: 2,
+                ..Default::default()
+            };
+            let mut storage: Storage<FlashBackend<MemoryFlash<{ 2 * 4096 }>>, 1, 2, 1, 0> = block_on(Storage::new(
+                MemoryFlash::new(),
+                &keymap,
+                &None,
+                &storage_config,
+                &config::BehaviorConfig::default(),
+            ));
+            let write = |col, action| {
+                FlashOperationMessage::VialMessage(KeymapData::KeymapKey(KeymapKey {
+                    layer: 0,
+                    row: 0,
+                    col,
+                    action,
+                }))
+            };
+            let host = async {
+                // Writes of the same key are merged
+                for action in [k!(B), k!(C), k!(D)] {
+                    FLASH_CHANNEL.send(write(0, action)).await;
+                }
+                FLASH_CHANNEL.send(write(1, k!(E))).await;
+                assert_eq!(stats::read().await.pending_writes, 2);
+
+                // Saved by an explicit flush
+                flush().await.unwrap();
+                assert_eq!(stats::read().await.pending_writes, 0);
+
+                // Saved after the storage is idle
+                FLASH_CHANNEL.send(write(1, k!(F))).await;
+                assert_eq!(stats::read().await.pending_writes, 1);
+                Timer::after(WRITE_DELAY + Duration::from_millis(100)).await;
+                assert_eq!(stats::read().await.pending_writes, 0);
+            };
+            let result = block_on(select(storage.run(None), host));
+            assert!(matches!(result, Either::Second(())));
+
+            let mut loaded = keymap;
+            block_on(storage.read_keymap(&mut loaded, &mut None)).unwrap();
+            assert_eq!(loaded, [[[k!(D), k!(F)]]]);
+        }
+    }
 }
@@ -0,0 +1,47 @@
+//! Requests which are answered by the storage task.
+
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+
+use super::FlashOperationMessage;
+use crate::RawMutex;
+use crate::channel::FLASH_CHANNEL;
+
+/// A request sent to the storage task, whose response is sent back to the requester.
+///
+/// Only one request is in flight, so that the response is received by the right requester. Each request has a
+/// sequence number which is sent back with the response, so that the response of an earlier request which timed out
+/// isn't taken as the response of the current one.
+pub(crate) struct StorageRequest<T> {
+    /// Sequence number of the last request, locked while the request is in flight
+    seq: Mutex<RawMutex, u32>,
+    response: Signal<RawMutex, (u32, T)>,
+}
+
+impl<T: Send> StorageRequest<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            seq: Mutex::new(0),
+            response: Signal::new(),
+        }
+    }
+
+    /// Send the message with the sequence number of the request, and wait for the response
+    pub(crate) async fn request(&self, message: impl FnOnce(u32) -> FlashOperationMessage) -> T {
+        let mut seq = self.seq.lock().await;
+        *seq = seq.wrapping_add(1);
+        self.response.reset();
+        FLASH_CHANNEL.send(message(*seq)).await;
+        loop {
+            let (responded, response) = self.response.wait().await;
+            if responded == *seq {
+                return response;
+            }
+        }
+    }
+
+    /// Send the response of the request, the requester must be woken up even if the operation fails
+    pub(crate) fn respond(&self, seq: u32, response: T) {
+        self.response.signal((seq, response));
+    }
+}
@@ -0,0 +1,137 @@
+//! Runtime state saved across reboots.
+//!
+//! The states enabled by [`PersistedStateConfig`] are sent to the storage task by the keyboard when they change,
+//! and saved together with the pending keymap writes. They're restored when the keymap is loaded from the storage.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use super::backend::StorageBackend;
+use super::{Storage, StorageData, StorageKeys};
+use crate::config::PersistedStateConfig;
+
+/// Runtime state of the keyboard, the states which aren't persisted keep their values at boot
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct RuntimeState {
+    /// Layers toggled by `TG`, bit n is layer n
+    pub(crate) toggled_layers: u32,
+    pub(crate) combo_on: bool,
+    /// Mouse key speed preset, bit n is `MouseAccel{n}`
+    pub(crate) mouse_accel: u8,
+}
+
+impl Default for RuntimeState {
+    fn default() -> Self {
+        Self {
+            toggled_layers: 0,
+            combo_on: true,
+            mouse_accel: 0,
+        }
+    }
+}
+
+impl RuntimeState {
+    /// Keep only the persisted states, the others are reset to their values at boot
+    pub(crate) fn masked(self, config: &PersistedStateConfig) -> Self {
+        let boot = Self::default();
+        Self {
+            toggled_layers: if config.layers {
+                self.toggled_layers
+            } else {
+                boot.toggled_layers
+            },
+            combo_on: if config.combo { self.combo_on } else { boot.combo_on },
+            mouse_accel: if config.mouse_speed {
+                self.mouse_accel
+            } else {
+                boot.mouse_accel
+            },
+        }
+    }
+}
+
+impl<B: StorageBackend, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Read the saved runtime state, only the persisted states are restored
+    pub(crate) async fn read_runtime_state(&mut self) -> RuntimeState {
+        match self.fetch_item(StorageKeys::RuntimeState as u32).await {
+            Ok(Some(StorageData::RuntimeState(state))) => state.masked(&self.persisted_state),
+            _ => RuntimeState::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::config::{BehaviorConfig, PositionalConfig, StorageConfig};
+    use crate::keymap::KeyMap;
+    use crate::storage::LayoutConfig;
+    use crate::storage::backend::FlashBackend;
+    use crate::storage::dummy_flash::MemoryFlash;
+
+    #[test]
+    fn test_runtime_state() {
+        let mut layers = [[[crate::a!(No); 1]; 1]; 3];
+        let storage_config = StorageConfig {
+            num_sectors: 2,
+            persisted_state: PersistedStateConfig {
+                layers: true,
+                combo: true,
+                mouse_speed: false,
+            },
+            ..Default::default()
+        };
+        let mut storage: Storage<FlashBackend<MemoryFlash<{ 2 * 4096 }>>, 1, 1, 3, 0> = block_on(Storage::new(
+            MemoryFlash::new(),
+            &layers,
+            &None,
+            &storage_config,
+            &BehaviorConfig::default(),
+        ));
+        assert_eq!(block_on(storage.read_runtime_state()), RuntimeState::default());
+
+        let state = RuntimeState {
+            toggled_layers: 0b100,
+            combo_on: false,
+            mouse_accel: 0b10,
+        };
+        storage.pending_state = Some(state.masked(&storage.persisted_state));
+        block_on(storage.flush_pending_writes()).unwrap();
+        let layout_config = StorageData::LayoutConfig(LayoutConfig {
+            default_layer: 1,
+            layout_option: 0,
+        });
+        block_on(storage.store_item(StorageKeys::LayoutConfig as u32, &layout_config)).unwrap();
+        // The mouse speed preset isn't persisted
+        let restored = RuntimeState {
+            mouse_accel: 0,
+            ..state
+        };
+        assert_eq!(block_on(storage.read_runtime_state()), restored);
+
+        // The keymap restores the default layer and the toggled layers
+        let mut behavior = BehaviorConfig::default();
+        let mut positional_config = PositionalConfig::default();
+        let mut keymap = block_on(KeyMap::new(&mut layers, None, &mut behavior, &mut positional_config));
+        block_on(keymap.load_runtime_state(&mut storage));
+        assert_eq!(keymap.get_default_layer(), 1);
+        assert_eq!(keymap.toggled_layers(), 0b100);
+        assert_eq!(keymap.get_activated_layer(), 2);
+        assert_eq!(keymap.restored_state, restored);
+        keymap.toggle_layer(2);
+        assert_eq!(keymap.toggled_layers(), 0);
+
+        // Nothing is restored if the states aren't persisted anymore, except the default layer
+        storage.persisted_state = PersistedStateConfig::default();
+        assert_eq!(block_on(storage.read_runtime_state()), RuntimeState::default());
+        let mut keymap = block_on(KeyMap::new(&mut layers, None, &mut behavior, &mut positional_config));
+        block_on(keymap.load_runtime_state(&mut storage));
+        assert_eq!(keymap.get_default_layer(), 1);
+        assert_eq!(keymap.toggled_layers(), 0);
+    }
+}
@@ -0,0 +1,131 @@
+//! Statistics of the flash used by the storage.
+//!
+//! The erased sectors are counted over the lifetime of the storage, the count is saved to the storage itself and
+//! kept when the storage is cleared. Together with the free space, it can be read by the host to check the wear
+//! of the flash.
+
+use super::backend::{StorageBackend, StorageError};
+use super::request::StorageRequest;
+use super::{FlashOperationMessage, Storage, StorageData, StorageKeys};
+
+/// Statistics of the storage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StorageStats {
+    /// Number of sectors erased since the storage was first used.
+    ///
+    /// Writes are spread over all sectors of the storage, so each sector is erased about
+    /// `erase_cycles * sector size / capacity` times.
+    pub erase_cycles: u32,
+    /// Erased bytes which can be written before a sector must be erased again
+    pub free_space: u32,
+    /// Size of the storage in bytes
+    pub capacity: u32,
+    /// Number of keymap writes which are waiting to be saved
+    pub pending_writes: u8,
+}
+
+/// Statistics read by the storage task
+pub(crate) static STORAGE_STATS_READ: StorageRequest<StorageStats> = StorageRequest::new();
+
+/// Read the statistics of the storage.
+///
+/// The free space is measured by the storage task, so it only returns after the storage task has started.
+pub async fn read() -> StorageStats {
+    STORAGE_STATS_READ.request(FlashOperationMessage::ReadStats).await
+}
+
+impl<B: StorageBackend, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    Storage<B, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    /// Read the saved erase cycles, used before the whole storage is erased
+    pub(crate) async fn read_erase_cycles(&mut self) -> u32 {
+        match self.fetch_item(StorageKeys::EraseCycles as u32).await {
+            Ok(Some(StorageData::EraseCycles(erase_cycles))) => erase_cycles,
+            _ => 0,
+        }
+    }
+
+    /// Save the erase cycles if sectors are erased since the last save
+    pub(crate) async fn save_erase_cycles(&mut self) -> Result<(), StorageError> {
+        let erase_cycles = self.erase_cycles();
+        if erase_cycles == self.saved_erase_cycles {
+            return Ok(());
+        }
+        self.store_item(StorageKeys::EraseCycles as u32, &StorageData::EraseCycles(erase_cycles))
+            .await?;
+        self.saved_erase_cycles = erase_cycles;
+        Ok(())
+    }
+
+    /// Number of sectors erased since the storage was first used
+    pub(crate) fn erase_cycles(&self) -> u32 {
+        self.erase_cycles_base.saturating_add(self.backend.erase_cycles())
+    }
+
+    /// Read the statistics, the free space is measured by the backend
+    pub(crate) async fn read_stats(&mut self) -> Result<StorageStats, StorageError> {
+        #[cfg(feature = "host")]
+        let pending_writes = self.pending_writes.len() as u8;
+        #[cfg(not(feature = "host"))]
+        let pending_writes = 0;
+        Ok(StorageStats {
+            erase_cycles: self.erase_cycles(),
+            free_space: self.backend.free_space().await?,
+            capacity: self.backend.capacity(),
+            pending_writes,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::config::{BehaviorConfig, StorageConfig};
+    use crate::storage::backend::FlashBackend;
+    use crate::storage::dummy_flash::MemoryFlash;
+    use crate::storage::user::UserDataBuffer;
+
+    type TestStorage = Storage<FlashBackend<MemoryFlash<{ 2 * 4096 }>>, 1, 1, 1, 0>;
+
+    fn boot(flash: MemoryFlash<{ 2 * 4096 }>, clear_storage: bool) -> TestStorage {
+        let keymap = [[[crate::a!(No); 1]; 1]; 1];
+        let storage_config = StorageConfig {
+            num_sectors: 2,
+            clear_storage,
+            ..Default::default()
+        };
+        block_on(Storage::new(
+            flash,
+            &keymap,
+            &None,
+            &storage_config,
+            &BehaviorConfig::default(),
+        ))
+    }
+
+    #[test]
+    fn test_storage_stats() {
+        // A new storage is erased once
+        let mut storage = boot(MemoryFlash::new(), false);
+        let stats = block_on(storage.read_stats()).unwrap();
+        assert_eq!(stats.erase_cycles, 2);
+        assert_eq!(stats.capacity, 2 * 4096);
+        assert!(stats.free_space > 4096 && stats.free_space < 2 * 4096);
+        assert_eq!(stats.pending_writes, 0);
+
+        // Written items take the free space
+        let data = StorageData::UserData(0, UserDataBuffer::from_slice(&[0; 32]).unwrap());
+        block_on(storage.store_item(data.key(), &data)).unwrap();
+        assert!(block_on(storage.read_stats()).unwrap().free_space <= stats.free_space - 32);
+
+        // The erase cycles are kept after rebooting and clearing the storage
+        let mut storage = boot(storage.backend.into_inner(), false);
+        assert_eq!(block_on(storage.read_stats()).unwrap().erase_cycles, 2);
+        let storage = boot(storage.backend.into_inner(), true);
+        let mut storage = boot(storage.backend.into_inner(), false);
+        assert_eq!(block_on(storage.read_stats()).unwrap().erase_cycles, 4);
+    }
+}
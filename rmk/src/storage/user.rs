@@ -0,0 +1,138 @@
+//! Key-value storage for user data.
+//!
+//! Custom controllers, input processors and other user code can persist their own settings, e.g. RGB mode, CPI
+//! or display brightness, without adding new items to the storage. Values are serialized by postcard and saved
+//! under a `u16` key in the reserved user data range of the storage.
+//!
+//! Both reading and writing are handled by the storage task, so they never race with other flash operations.
+//! As a result, `read` only returns after the storage task has started.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct RgbSettings {
+//!     mode: u8,
+//!     brightness: u8,
+//! }
+//!
+//! const RGB_SETTINGS: u16 = 0;
+//!
+//! let settings: RgbSettings = rmk::storage::user::read(RGB_SETTINGS).await.unwrap_or(DEFAULT_RGB_SETTINGS);
+//! rmk::storage::user::write(RGB_SETTINGS, &settings).await.ok();
+//! ```
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::FlashOperationMessage;
+use super::request::StorageRequest;
+use crate::channel::FLASH_CHANNEL;
+
+/// Max size of a serialized user data value, in bytes
+pub const USER_DATA_MAX_SIZE: usize = 64;
+
+/// Buffer of a serialized user data value
+pub(crate) type UserDataBuffer = heapless::Vec<u8, USER_DATA_MAX_SIZE>;
+
+/// User data read by the storage task, `None` if the value isn't found
+pub(crate) static USER_DATA_READ: StorageRequest<Option<UserDataBuffer>> = StorageRequest::new();
+
+/// Error of saving the user data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UserDataError {
+    /// The value can't be serialized, or the serialized value is larger than [`USER_DATA_MAX_SIZE`]
+    Serialization,
+}
+
+/// Save the value under the key.
+///
+/// The value is saved by the storage task later.
+pub async fn write<T: Serialize>(key: u16, value: &T) -> Result<(), UserDataError> {
+    let mut buffer = [0; USER_DATA_MAX_SIZE];
+    let data = match postcard::to_slice(value, &mut buffer) {
+        Ok(data) => data,
+        Err(_) => {
+            error!("Failed to serialize user data {}", key);
+            return Err(UserDataError::Serialization);
+        }
+    };
+    let data = UserDataBuffer::from_slice(data).map_err(|_| UserDataError::Serialization)?;
+    FLASH_CHANNEL.send(FlashOperationMessage::UserData(key, data)).await;
+    Ok(())
+}
+
+/// Read the value saved under the key.
+///
+/// It returns `None` if the value isn't found, or it can't be deserialized as `T`.
+pub async fn read<T: DeserializeOwned>(key: u16) -> Option<T> {
+    let data = USER_DATA_READ
+        .request(|seq| FlashOperationMessage::ReadUserData(key, seq))
+        .await?;
+    postcard::from_bytes(&data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_futures::select::{Either, select};
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::config::{BehaviorConfig, StorageConfig};
+    use crate::storage::Storage;
+    use crate::storage::backend::FlashBackend;
+    use crate::storage::dummy_flash::MemoryFlash;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Settings {
+        mode: u8,
+        brightness: u16,
+        name: [u8; 4],
+    }
+
+    #[test]
+    fn test_user_data() {
+        let keymap = [[[crate::a!(No); 1]; 1]; 1];
+        let storage_config = StorageConfig {
+            num_sectors: 2,
+            ..Default::default()
+        };
+        let mut storage: Storage<FlashBackend<MemoryFlash<{ 2 * 4096 }>>, 1, 1, 1, 0> = block_on(Storage::new(
+            MemoryFlash::new(),
+            &keymap,
+            &None,
+            &storage_config,
+            &BehaviorConfig::default(),
+        ));
+
+        let settings = Settings {
+            mode: 3,
+            brightness: 1000,
+            name: *b"rgb1",
+        };
+        let user = async {
+            assert_eq!(read::<Settings>(0).await, None);
+            write(0, &settings).await.unwrap();
+            write(1, &5u32).await.unwrap();
+            assert_eq!(read::<Settings>(0).await, Some(settings));
+            assert_eq!(read::<u32>(1).await, Some(5));
+            // The saved value is overwritten
+            write(1, &6u32).await.unwrap();
+            assert_eq!(read::<u32>(1).await, Some(6));
+            // Too large to be saved
+            assert_eq!(
+                write(2, &[0u8; USER_DATA_MAX_SIZE].as_slice()).await,
+                Err(UserDataError::Serialization)
+            );
+            assert_eq!(read::<u32>(2).await, None);
+        };
+        let result = block_on(select(
+            storage.run(
+                #[cfg(feature = "host")]
+                None,
+            ),
+            user,
+        ));
+        assert!(matches!(result, Either::Second(())));
+    }
+}
@@ -11,6 +11,8 @@ use static_cell::StaticCell;
 use crate::channel::KEYBOARD_REPORT_CHANNEL;
 use crate::config::DeviceConfig;
 use crate::descriptor::CompositeReportType;
+#[cfg(feature = "gamepad")]
+use crate::descriptor::GamepadReport;
 use crate::hid::{HidError, HidWriterTrait, Report, RunnableHidWriter};
 use crate::state::ConnectionState;
 use crate::{CONNECTION_STATE, RawMutex};
@@ -43,12 +45,20 @@ impl From<u8> for UsbState {
 pub(crate) struct UsbKeyboardWriter<'a, 'd, D: Driver<'d>> {
     pub(crate) keyboard_writer: &'a mut HidWriter<'d, D, 8>,
     pub(crate) other_writer: &'a mut HidWriter<'d, D, 9>,
+    #[cfg(feature = "gamepad")]
+    pub(crate) gamepad_writer: &'a mut HidWriter<'d, D, { GamepadReport::SIZE }>,
 }
 impl<'a, 'd, D: Driver<'d>> UsbKeyboardWriter<'a, 'd, D> {
-    pub(crate) fn new(keyboard_writer: &'a mut HidWriter<'d, D, 8>, other_writer: &'a mut HidWriter<'d, D, 9>) -> Self {
+    pub(crate) fn new(
+        keyboard_writer: &'a mut HidWriter<'d, D, 8>,
+        other_writer: &'a mut HidWriter<'d, D, 9>,
+        #[cfg(feature = "gamepad")] gamepad_writer: &'a mut HidWriter<'d, D, { GamepadReport::SIZE }>,
+    ) -> Self {
         Self {
             keyboard_writer,
             other_writer,
+            #[cfg(feature = "gamepad")]
+            gamepad_writer,
         }
     }
 }
@@ -104,6 +114,16 @@ impl<'d, D: Driver<'d>> HidWriterTrait for UsbKeyboardWriter<'_, 'd, D> {
                     .map_err(HidError::UsbEndpointError)?;
                 Ok(n)
             }
+            #[cfg(feature = "gamepad")]
+            Report::GamepadReport(gamepad_report) => {
+                let mut buf = [0u8; GamepadReport::SIZE];
+                let n = serialize(&mut buf, &gamepad_report).map_err(|_| HidError::ReportSerializeError)?;
+                self.gamepad_writer
+                    .write(&buf[0..n])
+                    .await
+                    .map_err(HidError::UsbEndpointError)?;
+                Ok(n)
+            }
         }
     }
 }
@@ -124,9 +144,10 @@ pub(crate) fn new_usb_builder<'d, D: Driver<'d>>(driver: D, keyboard_config: Dev
     usb_config.device_protocol = 0x01;
     usb_config.composite_with_iads = true;
 
-    #[cfg(feature = "usb_log")]
+    // The gamepad interface needs more space for the configuration descriptor
+    #[cfg(any(feature = "usb_log", feature = "gamepad"))]
     const USB_BUF_SIZE: usize = 256;
-    #[cfg(not(feature = "usb_log"))]
+    #[cfg(not(any(feature = "usb_log", feature = "gamepad")))]
     const USB_BUF_SIZE: usize = 128;
 
     // Create embassy-usb DeviceBuilder using the driver and config.